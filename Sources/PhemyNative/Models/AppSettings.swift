@@ -1,7 +1,7 @@
 import Foundation
 
 enum PromptMode: String, CaseIterable, Identifiable, Codable {
-    case clean, technical, formal, casual, code, verbatim, raw, custom
+    case clean, technical, formal, casual, code, structured, verbatim, raw, custom
 
     var id: String { rawValue }
 
@@ -14,6 +14,7 @@ enum PromptMode: String, CaseIterable, Identifiable, Codable {
         case .formal:    return "Professional language, business-appropriate tone"
         case .casual:    return "Clean but conversational, friendly voice"
         case .code:      return "Structured coding task with language and requirements"
+        case .structured: return "Markdown with a title, short intro, and bulleted points"
         case .verbatim:  return "Minimal cleanup, closest to original wording"
         case .raw:       return "No LLM processing, use transcript as-is"
         case .custom:    return "Use your own custom system prompt"
@@ -27,6 +28,7 @@ enum PromptMode: String, CaseIterable, Identifiable, Codable {
         case .formal:    return "briefcase"
         case .casual:    return "face.smiling"
         case .code:      return "chevron.left.forwardslash.chevron.right"
+        case .structured: return "list.bullet.rectangle"
         case .verbatim:  return "text.quote"
         case .raw:       return "waveform"
         case .custom:    return "slider.horizontal.3"
@@ -115,9 +115,8 @@ final class PhemyCore {
         }
 
         // Check if it's an error response
-        if let errObj = try? Self.decoder.decode(ProcessingError.self, from: data),
-           !errObj.error.isEmpty {
-            return .failure(errObj.error)
+        if let errObj = try? Self.decoder.decode(RustError.self, from: data) {
+            return .failure(errObj.error.message)
         }
 
         // Try to decode as success
@@ -169,7 +168,8 @@ final class PhemyCore {
 
     func getLlmDownloadProgress() -> LlmDownloadProgress? {
         let ptr = phemy_get_llm_download_progress()
-        return decodeRustJSON(ptr, as: LlmDownloadProgress.self)
+        // JSON null when no download is running
+        return decodeRustJSON(ptr, as: LlmDownloadProgress?.self) ?? nil
     }
 
     func deleteWhisperModel(name: String) -> Bool {
@@ -226,8 +226,13 @@ struct ProcessingResult: Codable {
     var llmError: String?
 }
 
-private struct ProcessingError: Codable {
-    var error: String
+/// `{"error": {"code": "...", "message": "..."}}` returned by Rust on failure.
+private struct RustError: Codable {
+    struct Detail: Codable {
+        var code: String
+        var message: String
+    }
+    var error: Detail
 }
 
 struct WhisperModelInfo: Identifiable, Codable {
@@ -1,13 +1,96 @@
+use std::process::Command;
+
 fn main() {
-    // Generate C header using cbindgen
+    // Generate C header using cbindgen. The header hosts build against is
+    // committed as include/phemy_core.h; it's only rewritten when
+    // PHEMY_UPDATE_HEADER is set, and tests/header.rs fails while it's stale.
     let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
     let config = cbindgen::Config::from_file("cbindgen.toml")
         .unwrap_or_default();
 
-    cbindgen::Builder::new()
-        .with_crate(crate_dir)
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
         .with_config(config)
         .generate()
-        .expect("Unable to generate C bindings")
-        .write_to_file("include/phemy_core.h");
+        .expect("Unable to generate C bindings");
+    bindings.write_to_file(format!("{}/phemy_core.h", out_dir));
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=PHEMY_UPDATE_HEADER");
+    if std::env::var_os("PHEMY_UPDATE_HEADER").is_some() {
+        bindings.write_to_file("include/phemy_core.h");
+    }
+
+    build_info(&crate_dir);
+}
+
+/// Details phemy_get_build_info() reports, as PHEMY_* compile-time
+/// variables. Those that can't be found (no git checkout, no Cargo.lock)
+/// are left unset.
+fn build_info(crate_dir: &str) {
+    println!("cargo:rustc-env=PHEMY_TARGET={}", std::env::var("TARGET").unwrap());
+
+    if let Some(hash) = git(crate_dir, &["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=PHEMY_GIT_HASH={}", hash);
+        // Rebuild when a commit is made or another branch checked out. A
+        // packed branch has no file of its own; watching a missing file
+        // would rebuild every time.
+        for path in [git(crate_dir, &["rev-parse", "--git-path", "HEAD"]), head_ref_path(crate_dir)]
+            .into_iter()
+            .flatten()
+            .filter(|path| std::path::Path::new(crate_dir).join(path).exists())
+        {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    // The whisper.cpp and llama.cpp sources come with these crates, so
+    // their locked versions identify the C++ code built in
+    let lock = ["Cargo.lock", "../Cargo.lock"]
+        .into_iter()
+        .map(|name| std::path::Path::new(crate_dir).join(name))
+        .find(|path| path.exists());
+    if let Some(lock) = lock {
+        println!("cargo:rerun-if-changed={}", lock.display());
+        let lock = std::fs::read_to_string(lock).unwrap_or_default();
+        for (feature, krate, var) in [
+            ("CARGO_FEATURE_WHISPER_LOCAL", "whisper-rs", "PHEMY_WHISPER_RS_VERSION"),
+            ("CARGO_FEATURE_LLM_LOCAL", "llama-cpp-2", "PHEMY_LLAMA_CPP_2_VERSION"),
+        ] {
+            if std::env::var_os(feature).is_none() {
+                continue;
+            }
+            if let Some(version) = locked_version(&lock, krate) {
+                println!("cargo:rustc-env={}={}", var, version);
+            }
+        }
+    }
+}
+
+/// The output of a git command run in `dir`, if git is installed and it
+/// succeeds
+fn git(dir: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).current_dir(dir).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!output.is_empty()).then_some(output)
+}
+
+/// The file holding the commit of the checked out branch
+fn head_ref_path(dir: &str) -> Option<String> {
+    let head = git(dir, &["symbolic-ref", "-q", "HEAD"])?;
+    git(dir, &["rev-parse", "--git-path", &head])
+}
+
+/// The version of `krate` in Cargo.lock
+fn locked_version(lock: &str, krate: &str) -> Option<String> {
+    let name = format!("name = \"{}\"", krate);
+    let mut lines = lock.lines();
+    lines.find(|line| *line == name)?;
+    let version = lines.next()?.strip_prefix("version = \"")?;
+    Some(version.trim_end_matches('"').to_string())
 }
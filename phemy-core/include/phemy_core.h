@@ -1,5 +1,38 @@
 /* Generated by cbindgen — do not edit */
 
+/*
+ * Threading
+ *
+ * Every function may be called from any thread, and concurrently with the
+ * others, except as noted here.
+ *
+ * - Settings, status, progress and job polling calls don't wait on a
+ *   recording, transcription, download or LLM generation.
+ * - History calls share one database connection and run one at a time;
+ *   each waits for the others only as long as their SQL takes.
+ * - The local LLM loads or generates for one call at a time. Another
+ *   optimization (or deleting an LLM model) waits up to 30 seconds for it,
+ *   then fails with the "busy" error code. phemy_init with a different
+ *   directory returns false rather than wait.
+ * - phemy_init and phemy_shutdown are not meant to race other calls: make
+ *   them while nothing else is in flight. Calls made during
+ *   phemy_shutdown may fail.
+ * - The log callback, and mic level callbacks with direct delivery, must
+ *   not call phemy functions. The other callbacks may.
+ *
+ * Callbacks
+ *
+ * All callbacks (mic levels, download and paste progress, download, paste and
+ * operation completion, settings changed) arrive on one thread that phemy
+ * starts for them, in the order they were raised, unless direct delivery
+ * is requested (phemy_start_recording_ex). The log callback is the
+ * exception: it runs on whichever thread logs. If the host falls behind
+ * on mic levels or progress, the oldest pending ones are dropped;
+ * completion and settings-changed callbacks never are. Mic levels still
+ * pending when the recording stops are dropped as well. phemy_shutdown
+ * delivers what is still queued and stops the thread.
+ */
+
 #ifndef PHEMY_CORE_H
 #define PHEMY_CORE_H
 
@@ -10,30 +43,335 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Default RMS level above which a frame counts as speech
+ */
+#define DEFAULT_ENERGY_THRESHOLD 0.005
+
+/**
+ * Default audio kept before the first speech frame (2 frames)
+ */
+#define DEFAULT_PREROLL_MS 60
+
+/**
+ * Current settings file layout version
+ */
+#define SETTINGS_VERSION 3
+
+/**
+ * Maximum number of modes in `prompt_mode_chain`
+ */
+#define MAX_MODE_CHAIN_LEN 3
+
+/**
+ * Longest accepted `paste_delay_ms`
+ */
+#define MAX_PASTE_DELAY_MS 5000
+
+/**
+ * Longest accepted `history_dedupe_window_secs` (one day)
+ */
+#define MAX_DEDUPE_WINDOW_SECS 86400
+
+/**
+ * Called with the level (1 = error … 5 = trace), the target (usually the
+ * module path) and the message. Both strings are only valid during the
+ * call. May be called from any thread, including while phemy holds
+ * internal locks, so it must not call phemy functions. Null means none.
+ */
+typedef void (*LogCallback)(int32_t level, const char *target, const char *message);
+
+/**
+ * Called after settings are saved or the profile is switched, or null for none
+ */
+typedef void (*SettingsChangedCallback)(void);
+
+/**
+ * C-compatible callback type for mic level updates, or null for none.
+ * Called with (rms, peak) values on the callback thread, or
+ * with direct delivery on the audio thread, where it must return quickly
+ * and not call phemy functions: stopping the recording from there would
+ * wait on that very thread.
+ */
+typedef void (*MicLevelCallback)(float rms, float peak);
+
+/**
+ * A MicLevelCallback that also gets the `user_data` pointer given with it
+ */
+typedef void (*MicLevelUserDataCallback)(float rms, float peak, void *user_data);
+
+/**
+ * Called by phemy_stop_and_process_with_progress() as the pipeline moves
+ * through its phases: 0 stopping, 1 resampling, 2 vad, 3 transcribing,
+ * 4 optimizing, 5 saving. A phase is reported again with sub-progress in
+ * `detail_json`, which is otherwise null: `{"segments"}` after each whisper
+ * run, and `{"mode", "tokens"}` as the LLM generates. `detail_json` is only
+ * valid during the call. It is called directly on the thread doing the
+ * work, so it should return quickly.
+ */
+typedef void (*PhaseCallback)(int32_t phase, const char *detail_json, void *user_data);
+
+/**
+ * Called exactly once when a `_with_user_data` operation ends, including
+ * on error or cancellation, with the `user_data` it was started with. On
+ * success `result_json` is the operation's result, otherwise
+ * { "error": { "code", "message" } }; it is only valid during the call.
+ * It runs on the callback thread. Null means none.
+ */
+typedef void (*CompletionCallback)(bool success, const char *result_json, void *user_data);
+
+/**
+ * Called with (downloaded bytes, total bytes, fraction done) while a `_cb`
+ * download runs, at most every DOWNLOAD_CALLBACK_INTERVAL, on the callback
+ * thread. The total is 0 when the server doesn't send a length. Null means
+ * none.
+ */
+typedef void (*DownloadProgressCallback)(uint64_t downloaded_bytes, uint64_t total_bytes, double progress);
+
+/**
+ * Called exactly once when a `_cb` download ends, including on error or
+ * cancellation. `error_json` is null on success, otherwise
+ * { "error": { "code", "message" } } (code "cancelled" after
+ * phemy_cancel_downloads()); it is only valid during the call. It runs on
+ * the callback thread after the last progress call. Null means none.
+ */
+typedef void (*DownloadDoneCallback)(bool success, const char *error_json);
+
+/**
+ * Called on the callback thread after each TypeOut chunk with the number
+ * of characters typed so far and the total. Null means none.
+ */
+typedef void (*PasteProgressCallback)(uintptr_t typed, uintptr_t total);
+
+/**
+ * Called on the callback thread when a queued paste finishes.
+ * `result_json` is the `phemy_paste_text_ex` result and is only valid
+ * during the call. Null means none.
+ */
+typedef void (*PasteDoneCallback)(bool success, const char *result_json);
+
 /**
  * Initialize phemy-core with a data directory path.
+ * Pass ":memory:" to keep history in an in-memory database that is never
+ * written to disk (settings and models use the default data directory).
  * Must be called before any other function.
- * Returns true on success, true (no-op) on subsequent calls.
+ *
+ * Calling it again with the same directory is a no-op that returns true.
+ * With a different directory, pending history inserts are written, the
+ * database is closed and the local LLM unloaded, and settings, models and
+ * history then come from the new directory. This is refused (false) while
+ * recording, downloading a model or generating with the local LLM. If the
+ * database can't be opened, phemy is left uninitialized with no data
+ * directory set, as before the first call, and phemy_init can be called
+ * again. phemy_shutdown undoes initialization completely;
+ * phemy_init works again afterwards.
  */
 bool phemy_init(const char *data_dir);
 
+/**
+ * Initialize phemy-core with an encrypted history database.
+ * `key` is the database passphrase; an existing unencrypted database is
+ * converted on first use. Requires a build with the `sqlcipher` feature.
+ * Returns false if the key is wrong or encryption is unavailable.
+ */
+bool phemy_init_with_key(const char *data_dir, const char *key);
+
+/**
+ * Initialize phemy-core from a JSON configuration:
+ * `{ "data_dir"?, "in_memory"?, "db_key"?, "runtime"?: { "worker_threads"?,
+ * "current_thread"?, "thread_name"? } }`, all optional. `data_dir`,
+ * `in_memory` and `db_key` are as for phemy_init() and
+ * phemy_init_with_key(). `runtime` sets up the async runtime that
+ * transcriptions, optimizations and downloads run on: `worker_threads`
+ * (default 0 = one per core), `current_thread` to run its tasks on a
+ * single thread instead, and `thread_name`, the prefix of its thread names
+ * (default "phemy-runtime"). Calling it again with the same directory
+ * changes nothing, runtime included; switching directories also switches
+ * to the new runtime options.
+ * Returns false if the JSON is invalid or initialization fails;
+ * phemy_get_init_status() says why.
+ */
+bool phemy_init_ex(const char *config_json);
+
+/**
+ * Whether phemy_init has succeeded and phemy_shutdown hasn't been called
+ * since. Waits for a phemy_init in progress.
+ */
+bool phemy_is_initialized(void);
+
+/**
+ * Describe the last phemy_init as JSON: { "initialized", "data_dir",
+ * "db_path", "db_open", "in_memory", "encrypted", "features": [...],
+ * "warnings": [...], "error"? }. Filled in whether phemy_init succeeded or
+ * not, so a false return can be diagnosed; `error` says why it failed.
+ * `warnings` lists problems that didn't stop initialization (e.g. the
+ * database permissions couldn't be set). Before phemy_init and after
+ * phemy_shutdown, `initialized` is false and the paths are null.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_init_status(void);
+
+/**
+ * Tear down what phemy_init and later calls set up, so the library can be
+ * unloaded or initialized again. Stops any recording; cancels background
+ * jobs, model downloads, local LLM generation and queued pastes; waits for
+ * pending history inserts; closes the database and forgets the data
+ * directory; unloads the local LLM; clears the settings-changed and log
+ * callbacks; delivers the callbacks still queued and stops the callback
+ * thread and the async runtime. Whisper models are loaded per
+ * transcription, so none stay in memory. A download in progress stops
+ * before its next chunk and its partial file is removed.
+ * Blocks until done. Does nothing if not initialized, so calling it twice
+ * or before phemy_init is safe; phemy_init works again afterwards. Until
+ * then, calls that run on the async runtime (transcription, optimization,
+ * downloads) fail instead of starting a new one.
+ */
+void phemy_shutdown(void);
+
+/**
+ * Send core log messages to `cb` instead of stderr, up to `max_level`
+ * (0 = off, 1 = error, 2 = warn, 3 = info, 4 = debug, 5 = trace).
+ * `cb` gets the level, the target (module path) and the message; both
+ * strings are only valid during the call. Unlike the other callbacks it
+ * is called directly on the thread that logs, which may be any thread,
+ * including several at once, and must not call back into phemy.
+ * Pass null to go back to stderr. Can be called before phemy_init;
+ * phemy_shutdown unregisters the callback.
+ */
+void phemy_set_log_callback(LogCallback cb, int32_t max_level);
+
+/**
+ * Set the most verbose level logged, as in phemy_set_log_callback().
+ * Applies to stderr output too.
+ */
+void phemy_set_log_level(int32_t level);
+
 /**
  * Get current settings as JSON string.
  * Caller must free the returned string with phemy_free_string().
  */
 char *phemy_get_settings(void);
 
+/**
+ * Get one setting by dotted path (e.g. "llm.max_tokens") as a JSON value.
+ * Unknown keys return an error with code "not_found".
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_setting(const char *key);
+
+/**
+ * Describe every setting (type, default, allowed values, ranges, whether
+ * it needs a restart) as JSON, for building settings forms.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_settings_schema(void);
+
+/**
+ * Check a hotkey string as the user types it. Returns
+ * { "hotkey": "<canonical form>" } or { "error": {...} }.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_validate_hotkey(const char *hotkey);
+
 /**
  * Save settings from a JSON string. Returns true on success.
  */
 bool phemy_save_settings(const char *json);
 
+/**
+ * Save settings from a JSON string, reporting why they were rejected.
+ * Returns { "ok": true } or { "error": {...}, "errors": [{ "field": "...", "message": "..." }] }.
+ * A successful save may also carry "warnings" in the same shape, for values
+ * that were saved but won't take effect yet.
+ * Errors not tied to one field (unparseable JSON, write failures) use the
+ * field name "settings".
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_save_settings_ex(const char *json);
+
+/**
+ * Update only the settings fields present in `patch_json`, e.g.
+ * { "prompt_mode": "formal" }. `null` clears optional fields; unknown fields
+ * are rejected. Returns the full updated settings as JSON, or
+ * { "error": {...}, "errors": [{ "field": "...", "message": "..." }] } if
+ * nothing was saved.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_update_settings(const char *patch_json);
+
+/**
+ * Write the current settings to `path` as pretty JSON, so they can be
+ * imported on another machine with phemy_import_settings().
+ * Returns true on success.
+ */
+bool phemy_export_settings(const char *path);
+
+/**
+ * Import settings exported by phemy_export_settings(). The file is migrated
+ * from older versions and validated like any other save; nothing is changed
+ * if it is rejected. Returns the imported settings as JSON, or
+ * { "error": {...}, "errors": [{ "field": "...", "message": "..." }] }.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_import_settings(const char *path);
+
 /**
  * Reset settings to defaults and return new settings as JSON.
  * Caller must free the returned string with phemy_free_string().
  */
 char *phemy_reset_settings(void);
 
+/**
+ * Reset one settings section ("audio", "llm", "paste", "hotkey", ... or
+ * "all") to defaults, keeping everything else, and return the full settings
+ * as JSON. The vocabulary is never reset. Unknown sections return
+ * { "error": {...}, "sections": [...] }.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_reset_settings_section(const char *section);
+
+/**
+ * Store a secret ("llm_api_key" or "hf_token") outside the settings file.
+ * An empty value clears it. Settings only report whether it is set.
+ */
+bool phemy_set_secret(const char *name, const char *value);
+
+/**
+ * Remove a secret ("llm_api_key" or "hf_token").
+ */
+bool phemy_clear_secret(const char *name);
+
+/**
+ * List settings profiles as { "active": "...", "profiles": ["default", ...] }.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_list_profiles(void);
+
+/**
+ * Create a settings profile, copying the active profile's settings if
+ * `copy_current` is true and starting from defaults otherwise.
+ */
+bool phemy_create_profile(const char *name, bool copy_current);
+
+/**
+ * Delete a settings profile. The default and the active profile can't be deleted.
+ */
+bool phemy_delete_profile(const char *name);
+
+/**
+ * Switch to a settings profile and return its settings as JSON, or
+ * { "error": {...} }. The settings-changed callback fires on success.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_switch_profile(const char *name);
+
+/**
+ * Register a C function called after settings are saved or the profile is
+ * switched, so the host can reload them. Pass null to unregister.
+ * The callback runs on the callback thread.
+ */
+void phemy_set_settings_changed_callback(SettingsChangedCallback cb);
+
 /**
  * List audio input devices as JSON array.
  * Caller must free the returned string with phemy_free_string().
@@ -42,29 +380,230 @@ char *phemy_list_audio_devices(void);
 
 /**
  * Start recording. `device` may be null for default device.
- * `mic_cb` is a C function pointer called on the audio thread with (rms, peak), or null.
+ * `mic_cb` is a C function pointer called with (rms, peak), or null. It
+ * runs on the callback thread; levels the host falls behind on are dropped.
+ */
+bool phemy_start_recording(const char *device, MicLevelCallback mic_cb);
+
+/**
+ * phemy_start_recording(), calling `mic_cb` straight from the audio thread
+ * if `direct` is true, for visualizations that can't wait on the callback
+ * thread. It must then return quickly and not call phemy functions.
  */
-bool phemy_start_recording(const char *device, void (*mic_cb)(float, float));
+bool phemy_start_recording_ex(const char *device, MicLevelCallback mic_cb, bool direct);
 
 /**
- * Stop recording and return JSON with samples info.
+ * phemy_start_recording_ex() with a `user_data` pointer passed back to
+ * `mic_cb` with every level, so the host can reach its own state without a
+ * global. phemy never dereferences it. It must stay valid until
+ * phemy_stop_recording() (or another call that stops the recording)
+ * returns; no level callback runs after that.
+ */
+bool phemy_start_recording_with_user_data(const char *device, MicLevelUserDataCallback mic_cb, void *user_data, bool direct);
+
+/**
+ * Stop recording and return JSON with samples info; see
+ * phemy_stop_recording_with_samples() for the samples themselves.
  * Caller must free the returned string with phemy_free_string().
  */
 char *phemy_stop_recording(void);
 
+/**
+ * Stop recording and hand the recorded mono samples to the caller:
+ * `*out_samples` gets a buffer of `*out_len` samples (null if nothing was
+ * recorded) and `*out_rate` their sample rate. Free the buffer with
+ * phemy_free_samples(). All three pointers are required; if one is null,
+ * or the recording can't be stopped, returns false and leaves them as they
+ * were.
+ */
+bool phemy_stop_recording_with_samples(float **out_samples, uintptr_t *out_len, uint32_t *out_rate);
+
+/**
+ * Stop recording and discard the audio without processing it, e.g. when
+ * the user backs out of push-to-talk. Returns whether a recording was
+ * running; calling it when none is does nothing.
+ */
+bool phemy_cancel_recording(void);
+
 /**
  * Stop recording, transcribe, optimize, save to history, and return JSON result.
  * Always returns JSON (never null). On success: { "raw_transcript": "...", "optimized_prompt": "...", "mode": "...", "duration_secs": ... }
- * On error: { "error": "description of what went wrong" }
+ * On error: { "error": { "code": "processing" or "cancelled", "message": "..." } }
+ * Blocks until done; see phemy_stop_and_process_async() for a version that doesn't.
  * Caller must free the returned string with phemy_free_string().
  */
 char *phemy_stop_and_process(void);
 
+/**
+ * phemy_stop_and_process() with per-call options, e.g.
+ * { "skip_llm": true, "skip_history": true, "mode": "code", "language": "de",
+ * "target_app": "iTerm2" }. Every field is optional and null means none:
+ * `skip_llm` (or `skip_optimization`) returns the raw transcript with mode
+ * "raw"; `skip_history` saves nothing, and `history_id` is then omitted;
+ * `mode` and `language` replace the saved prompt mode (and mode chain) and
+ * spoken language for this call only; `target_app` is the application the
+ * prompt is for, `{{app}}` in custom prompts; `include_metrics` adds the
+ * run's `metrics` (see phemy_get_last_pipeline_metrics()) to the result.
+ * Saved settings are not changed.
+ * Unknown keys or invalid values return an error with code
+ * "invalid_argument" and leave the recording running.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_stop_and_process_ex(const char *options_json);
+
+/**
+ * phemy_stop_and_process(), reporting each phase of the pipeline to
+ * `progress_cb` with `user_data` (see PhaseCallback) as it runs on this
+ * thread. The result is still the return value. Null `progress_cb` means
+ * none.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_stop_and_process_with_progress(PhaseCallback progress_cb, void *user_data);
+
+/**
+ * Run the phemy_stop_and_process() pipeline on audio the caller recorded:
+ * resample, trim silence, transcribe, optimize and save to history.
+ * `options_json` takes the options of phemy_stop_and_process_ex(); null
+ * means none.
+ * Always returns JSON (never null), shaped like phemy_stop_and_process().
+ * Null or empty samples, a zero rate or invalid options return an error with
+ * code "invalid_argument".
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_process_samples(const float *samples, uintptr_t len, uint32_t rate, const char *options_json);
+
+/**
+ * Run text from another source (OCR, a selection) through the rest of the
+ * phemy_stop_and_process() pipeline: optimize it with the current
+ * settings, save it to history with a `duration_secs` of 0 and, with
+ * `paste`, paste the optimized prompt as phemy_paste_text() does.
+ * Always returns JSON (never null), shaped like phemy_stop_and_process();
+ * with `paste` it also has a `paste` field holding the phemy_paste_text_ex()
+ * outcome, or `{error}` if the paste failed. Null, empty or whitespace-only
+ * text returns an error with code "invalid_argument".
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_process_text(const char *text, bool paste);
+
+/**
+ * Stop recording and keep it as a session for the phemy_session_*()
+ * stages, using the settings as they are now. Returns JSON
+ * `{session_id, sample_count, sample_rate, duration_secs}`, or `{error}`
+ * if the recording couldn't be stopped or was empty. A session left
+ * unused for ten minutes is dropped.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_finish_recording(void);
+
+/**
+ * Transcribe a session's recording. Returns JSON `{raw_transcript}`
+ * (redacted as phemy_stop_and_process() would), or `{error}`.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_session_transcribe(uint64_t id);
+
+/**
+ * Optimize a session's transcript, transcribing it first if needed.
+ * Returns the phemy_optimize_prompt() JSON, or `{error}`. As in
+ * phemy_stop_and_process(), a failed LLM call falls back to the
+ * transcript rather than failing.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_session_optimize(uint64_t id);
+
+/**
+ * Save a session to history, running the stages it hasn't been through,
+ * and end it. Returns the phemy_stop_and_process() JSON, or `{error}`;
+ * on error the session is kept so the host can retry or discard it.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_session_commit(uint64_t id);
+
+/**
+ * Drop a session and its recording without saving anything. Returns false
+ * if it doesn't exist.
+ */
+bool phemy_session_discard(uint64_t id);
+
+/**
+ * Stop recording and run the rest of phemy_stop_and_process() in the
+ * background. Returns a job id for phemy_poll_job() and phemy_cancel_job().
+ */
+uint64_t phemy_stop_and_process_async(void);
+
+/**
+ * State of a job from phemy_stop_and_process_async(),
+ * phemy_stop_and_process_with_user_data() or
+ * phemy_batch_process_directory() as JSON:
+ * `{state, progress, completed?, total?, result?, error?}`. `state` is
+ * "transcribing", "optimizing", "done", "error" or "cancelled"; `progress`
+ * is a rough fraction from 0 to 1; `completed` and `total` count the files
+ * of a batch; `result` (when done) is the phemy_stop_and_process() result,
+ * or the batch's report, and `error` (on error) what went wrong.
+ * A finished job is forgotten once polled, or 10 minutes after it finished;
+ * unknown ids return an error with code "not_found".
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_poll_job(uint64_t id);
+
+/**
+ * Stop a job from phemy_stop_and_process_async() (or _with_user_data) at
+ * its next stage, or
+ * during its local LLM generation; other calls using the LLM carry on. A
+ * transcription in progress runs to the end first. Nothing is saved to
+ * history; a batch from phemy_batch_process_directory() keeps the files it
+ * already imported. Returns false if the job is unknown or already finished.
+ */
+bool phemy_cancel_job(uint64_t id);
+
+/**
+ * phemy_stop_and_process_async() with the options of
+ * phemy_stop_and_process_ex() (null for none), reporting the outcome to
+ * `done_cb` with `user_data` as well: on success with the
+ * phemy_stop_and_process() result. Returns the job id for phemy_poll_job()
+ * and phemy_cancel_job(), or 0 if the options are invalid; the recording
+ * then keeps running. `done_cb` is called exactly once either way.
+ */
+uint64_t phemy_stop_and_process_with_user_data(const char *options_json, CompletionCallback done_cb, void *user_data);
+
+/**
+ * Transcribe every WAV file in the directory at `path` into history in
+ * the background. Returns a job id for phemy_poll_job() and
+ * phemy_cancel_job(); while it runs, the job's `completed` and `total`
+ * count files. `options_json` (null for defaults) is `{ "skip_optimization"?,
+ * "mode"?, "language"?, "recursive"? }`, the first three as for
+ * phemy_stop_and_process_ex() and `recursive` to include subdirectories.
+ *
+ * Each file goes through the stop-and-process pipeline, one at a time,
+ * and its entry is dated with the file's modification time and records
+ * its path (`source_path`). Files already imported are skipped. A file
+ * that fails doesn't stop the batch; the job's result lists what happened
+ * to each: `{ "total", "imported": [{ "path", "history_id" }], "skipped":
+ * [path], "failed": [{ "path", "error" }] }`. A bad path or options make
+ * the job fail instead.
+ */
+uint64_t phemy_batch_process_directory(const char *path, const char *options_json);
+
+/**
+ * Timings of the last stop-and-process or phemy_process_samples() run as
+ * JSON (see PipelineMetrics), or JSON null before the first run and while
+ * one is starting. Also recorded for failed runs, up to where they stopped.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_last_pipeline_metrics(void);
+
 /**
  * Check if currently recording.
  */
 bool phemy_get_recording_state(void);
 
+/**
+ * Check if the active recording hit its maximum duration or silence
+ * auto-stop (see the `audio` settings). Hosts should poll this while
+ * recording and call phemy_stop_and_process() when it turns true.
+ */
+bool phemy_recording_should_stop(void);
+
 /**
  * Transcribe audio samples. Returns JSON result.
  * Caller must free the returned string with phemy_free_string().
@@ -83,7 +622,31 @@ char *phemy_list_whisper_models(void);
 bool phemy_download_whisper_model(const char *name);
 
 /**
- * Get download progress as JSON, or null if not downloading.
+ * Download a whisper model by name in the background, reporting to
+ * `progress_cb` and, once it ends, `done_cb` (see DownloadDoneCallback).
+ * Returns false, without calling either, if the name is null or not a
+ * known model or the download thread can't be started.
+ */
+bool phemy_download_whisper_model_cb(const char *name, DownloadProgressCallback progress_cb, DownloadDoneCallback done_cb);
+
+/**
+ * Like phemy_download_whisper_model_cb(), returning the download's
+ * operation id (see phemy_cancel()), or 0 where that returns false.
+ */
+uint64_t phemy_download_whisper_model_async(const char *name, DownloadProgressCallback progress_cb, DownloadDoneCallback done_cb);
+
+/**
+ * Download a whisper model by name on the async runtime, reporting the
+ * outcome to `done_cb` with `user_data` (see CompletionCallback); on
+ * success `result_json` is { "model": "<name>" }. Returns the download's
+ * operation id (see phemy_cancel()), or 0 if the name is null or not a
+ * known model; `done_cb` is still called once then. Progress is available
+ * from phemy_get_download_progress().
+ */
+uint64_t phemy_download_whisper_model_with_user_data(const char *name, CompletionCallback done_cb, void *user_data);
+
+/**
+ * Get download progress as JSON, or JSON null if not downloading.
  * Caller must free the returned string with phemy_free_string().
  */
 char *phemy_get_download_progress(void);
@@ -94,6 +657,27 @@ char *phemy_get_download_progress(void);
  */
 char *phemy_optimize_prompt(const char *transcript);
 
+/**
+ * Optimize a transcript with a per-call length override. Returns JSON.
+ * `length` is "concise", "balanced" or "detailed", or null to use the saved setting.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_optimize_prompt_ex(const char *transcript, const char *length);
+
+/**
+ * Like phemy_optimize_prompt_ex(), for a prompt that will be pasted into
+ * `target_app` (see phemy_paste_text_ex()), which custom prompts can use
+ * as `{{app}}`. Null leaves `{{app}}` empty.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_optimize_prompt_for_app(const char *transcript, const char *length, const char *target_app);
+
+/**
+ * List prompt modes and the template variables usable in custom prompts as JSON.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_list_prompt_modes(void);
+
 /**
  * List available local LLM models as JSON array.
  * Caller must free the returned string with phemy_free_string().
@@ -106,11 +690,47 @@ char *phemy_list_llm_models(void);
 bool phemy_download_llm_model(const char *name);
 
 /**
- * Get LLM model download progress as JSON, or null if not downloading.
+ * Download a local LLM model by name in the background; see
+ * phemy_download_whisper_model_cb().
+ */
+bool phemy_download_llm_model_cb(const char *name, DownloadProgressCallback progress_cb, DownloadDoneCallback done_cb);
+
+/**
+ * Download a local LLM model by name in the background; see
+ * phemy_download_whisper_model_async().
+ */
+uint64_t phemy_download_llm_model_async(const char *name, DownloadProgressCallback progress_cb, DownloadDoneCallback done_cb);
+
+/**
+ * Download a local LLM model by name on the async runtime; see
+ * phemy_download_whisper_model_with_user_data(). Progress is available
+ * from phemy_get_llm_download_progress().
+ */
+uint64_t phemy_download_llm_model_with_user_data(const char *name, CompletionCallback done_cb, void *user_data);
+
+/**
+ * Get LLM model download progress as JSON, or JSON null if not downloading.
  * Caller must free the returned string with phemy_free_string().
  */
 char *phemy_get_llm_download_progress(void);
 
+/**
+ * Stop any whisper or LLM model download before its next chunk. The
+ * download call then fails (blocking ones return false; `_cb` ones report
+ * the "cancelled" code) and the partial file is removed. To stop just one
+ * download, pass its id to phemy_cancel().
+ */
+void phemy_cancel_downloads(void);
+
+/**
+ * Stop any download of one kind of model, 0 for whisper or 1 for LLM,
+ * before its next chunk as phemy_cancel_downloads() does: the download
+ * fails with the "cancelled" code, its partial file is removed and its
+ * progress goes back to null. Returns false if none was running or the
+ * kind is unknown.
+ */
+bool phemy_cancel_download(int32_t kind);
+
 /**
  * Delete a downloaded whisper model by name. Returns true on success.
  */
@@ -121,30 +741,406 @@ bool phemy_delete_whisper_model(const char *name);
  */
 bool phemy_delete_llm_model(const char *name);
 
+/**
+ * Block until history entries from earlier phemy_stop_and_process() calls
+ * have been written. Call before shutting down.
+ */
+void phemy_flush_history(void);
+
 /**
  * Get history entries as JSON array.
  * Caller must free the returned string with phemy_free_string().
  */
 char *phemy_get_history(int32_t limit, int32_t offset);
 
+/**
+ * Query history entries matching a JSON filter, returned as a JSON array.
+ * The filter may contain `from`/`to` (RFC 3339), `mode`, `provider`,
+ * `min_duration`, `favorites_only` and `favorites_first`; null or "{}"
+ * matches everything.
+ * On an invalid filter returns an error with code "invalid_argument".
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_query_history(const char *filter_json, int32_t limit, int32_t offset);
+
+/**
+ * Full-text search history. Returns a JSON array of
+ * { "entry": {...}, "snippet": { "text", "highlights": [{ "start", "end" }] }, "score" },
+ * best match first. Highlight offsets count characters, not bytes.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_search_history(const char *query, int32_t limit, int32_t offset);
+
+/**
+ * Get the saved recording path for a history entry as `{ "path": "..." }`
+ * (a bare string with the `legacy-errors` feature). Returns an error object
+ * with code "not_found" if the entry doesn't exist or has no recording.
+ * The path is returned even if the file has since been removed; check the
+ * entry's `audio_missing` flag.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_history_audio_path(const char *id);
+
+/**
+ * Update fields of a history entry from a JSON patch, e.g.
+ * { "optimized_prompt": "...", "prompt_mode": "code" }.
+ * Returns false if the patch contains unknown fields or the entry doesn't exist.
+ */
+bool phemy_update_history_entry(const char *id, const char *json_patch);
+
 /**
  * Delete a history entry by ID. Returns true on success.
  */
 bool phemy_delete_history_entry(const char *id);
 
+/**
+ * Pin or unpin a history entry. Returns false if the entry doesn't exist.
+ */
+bool phemy_set_history_favorite(const char *id, bool favorite);
+
 /**
  * Clear all history. Returns true on success.
  */
 bool phemy_clear_history(void);
 
 /**
- * Paste text into the focused application.
+ * Clear all history except favorites. Returns true on success.
+ */
+bool phemy_clear_history_keep_favorites(void);
+
+/**
+ * Clear history (all of it, or everything but favorites with `keep_favorites`)
+ * and the entries' recordings. Returns { "deleted": n, "files_removed": m }
+ * or { "error": {...} }.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_clear_history_ex(bool keep_favorites);
+
+/**
+ * Get usage statistics as JSON, with per-day counts for the last `days` days.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_stats(int32_t days);
+
+/**
+ * Merge duplicate history entries (same transcript within the configured
+ * dedupe window). Returns a report as JSON, or { "error": {...} }.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_dedupe_history(void);
+
+/**
+ * Run database maintenance (integrity check, VACUUM, optimize) and return
+ * the report as JSON, or { "error": {...} } if the database is busy.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_db_maintenance(void);
+
+/**
+ * Back up the database to `path` while it stays in use.
+ * Returns { "success": true, "schema_version", "history_rows", "vocabulary_rows" }
+ * or { "success": false, "error": {...} }.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_backup_database(const char *path);
+
+/**
+ * Replace the database with a backup made by phemy_backup_database().
+ * The current database is left untouched if the backup is invalid.
+ * Returns the same JSON shape as phemy_backup_database(), with the
+ * restored row counts.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_restore_database(const char *path);
+
+/**
+ * Import history from a JSON export file. `strategy` is "merge" (keep
+ * existing entries with the same id) or "replace" (overwrite them).
+ * Returns the import report as JSON, or { "error": {...} }.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_import_history(const char *path, const char *strategy);
+
+/**
+ * Get recorded pipeline failures (newest first) as a JSON array.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_events(uint32_t limit, uint32_t offset);
+
+/**
+ * Get vocabulary words as JSON array.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_vocabulary(void);
+
+/**
+ * Suggest vocabulary words from history as a JSON array of `{ word, count }`:
+ * uncommon words seen at least `min_occurrences` times that aren't in the
+ * vocabulary yet, most frequent first, at most `limit` entries.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_suggest_vocabulary(uint32_t min_occurrences, uint32_t limit);
+
+/**
+ * Add a vocabulary word. Returns true if it was added, false if it was
+ * already present (case-insensitive) or on error.
+ */
+bool phemy_add_vocabulary_word(const char *word);
+
+/**
+ * Remove a vocabulary word. Returns true if it was removed.
+ */
+bool phemy_remove_vocabulary_word(const char *word);
+
+/**
+ * Paste text into the focused application, pressing the submit key
+ * afterwards if auto_submit is on. Returns false if the paste failed, was
+ * cut short (see phemy_cancel_paste), or was blocked by macOS secure input
+ * (the text is then left on the clipboard; use phemy_paste_text_ex to tell
+ * these apart).
  */
 bool phemy_paste_text(const char *text);
 
 /**
- * Free a string returned by any phemy_* function.
+ * Paste text on a background thread and return immediately. Pastes run
+ * one at a time in the order they were requested. `done_cb`, if given,
+ * is called on the callback thread with the same success value as
+ * phemy_paste_text() and the phemy_paste_text_ex() JSON, which is only
+ * valid during the call. `progress_cb`, if given, is called there too
+ * after each TypeOut chunk with (characters typed, total characters).
+ * `target_app` is as in phemy_paste_text_ex().
+ * Returns false (without calling `done_cb`) if `text` is null or the
+ * paste couldn't be queued.
+ */
+bool phemy_paste_text_async(const char *text, const char *target_app, PasteProgressCallback progress_cb, PasteDoneCallback done_cb);
+
+/**
+ * Stop a paste that is typing text out (TypeOut method) before its next
+ * chunk. Characters already typed stay; the paste reports
+ * `aborted: "cancelled"` and the submit key is not pressed.
+ */
+void phemy_cancel_paste(void);
+
+/**
+ * Like `phemy_paste_text`, but returns what happened as JSON:
+ * `{attempted, method_used, retried, clipboard_restored, clipboard_only,
+ * clipboard_retries, aborted}`, or `{error}`. `aborted` is null, or
+ * "cancelled" / "focus-changed" if a TypeOut stopped part way through.
+ * `target_app` (nullable) is the frontmost app's name or bundle id, used
+ * to pick a method from `app_paste_overrides`.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_paste_text_ex(const char *text, const char *target_app);
+
+/**
+ * Show what phemy_paste_text_ex() would deliver, without touching the
+ * clipboard or pressing any keys. Returns JSON
+ * `{text, method, auto_submit, submit_key, rules_applied}`, or `{error}`:
+ * the final text after paste post-processing, the paste method after
+ * per-app overrides, whether the submit key would be pressed and which
+ * one, and the names of the `paste_postprocess` rules that changed the
+ * text. `target_app` is as in phemy_paste_text_ex().
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_preview_paste(const char *text, const char *target_app);
+
+/**
+ * Paste a history entry's optimized prompt (or raw transcript with
+ * `use_raw`) into the focused application. Entries without an optimized
+ * prompt fall back to the raw transcript. Returns false if the entry
+ * doesn't exist or has no text, or the paste failed or was blocked as in
+ * phemy_paste_text().
+ */
+bool phemy_paste_history_entry(const char *id, bool use_raw);
+
+/**
+ * Put text on the clipboard and nothing else: no delays, no keystrokes and
+ * no clipboard restore. Returns false for null or empty text.
+ */
+bool phemy_copy_text(const char *text);
+
+/**
+ * Paste, copy or ignore text according to the `post_process_action`
+ * setting. `target_app` is as in phemy_paste_text_ex(). Returns false for
+ * null or empty text or if delivery failed.
+ */
+bool phemy_deliver_text(const char *text, const char *target_app);
+
+/**
+ * Copy a history entry's text to the clipboard without pasting.
+ * Same text selection and return value as phemy_paste_history_entry().
+ */
+bool phemy_copy_history_entry(const char *id, bool use_raw);
+
+/**
+ * Ask a running operation (see phemy_list_operations()) to stop at its
+ * next check. It then ends the way its own cancel export makes it end:
+ * a download reports "cancelled", a job polls as cancelled, a generation
+ * fails so the pipeline falls back to the raw transcript, and so on.
+ * Returns false if no operation with that id is running; cancelling one
+ * twice is harmless.
+ */
+bool phemy_cancel(uint64_t op_id);
+
+/**
+ * Running operations as a JSON array, oldest first:
+ * `[{id, kind, name?, progress, cancelled, elapsed_ms}]`. `kind` is
+ * "download", "transcription", "generation", "paste" or "job"; `name` is
+ * the model, where there is one; `progress` is a rough fraction from 0 to
+ * 1; `cancelled` means phemy_cancel() was called but it hasn't stopped yet.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_list_operations(void);
+
+/**
+ * Check the parts phemy depends on, for a diagnostics panel. Returns JSON
+ * with an `{ "ok", "detail" }` item for each of "initialized", "data_dir"
+ * (writable), "database" (open, passes a quick integrity check),
+ * "whisper_model" and "llm_model" (the configured model's file is present
+ * and support for it compiled in; `detail` has the path), "audio_input",
+ * "clipboard", "disk_space" (enough free for the configured models not yet
+ * downloaded) and "features" (compiled in), plus "ok" when all passed and
+ * "duration_ms". Loads no models and doesn't touch the network, so it
+ * returns quickly, unless `check_network` is set: then "network" says
+ * whether the model download host answers (up to 5 seconds).
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_health_check(bool check_network);
+
+/**
+ * Why the last failing phemy_* call on this thread failed, as
+ * { "code": "...", "message": "..." } with the same codes as `error`
+ * fields, or null if the last call that reports errors succeeded. Like
+ * errno, each thread has its own; exports returning false or an error
+ * JSON set it, and their successful calls clear it. Reading it changes
+ * nothing.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_last_error(void);
+
+/**
+ * Describe this build as JSON: { "version", "git_hash"?, "features": [...],
+ * "target", "profile", "whisper_rs"?, "llama_cpp_2"? }. `features` lists
+ * the optional features compiled in; `whisper_rs` and `llama_cpp_2` are
+ * the versions of the crates bundling whisper.cpp and llama.cpp. Can be
+ * called before phemy_init.
+ * Caller must free the returned string with phemy_free_string().
+ */
+char *phemy_get_build_info(void);
+
+/**
+ * Free a string returned by any phemy_* function. Null is ignored.
+ * Built with the `track-strings` feature, a pointer phemy didn't return or
+ * already freed is logged and ignored instead of corrupting the heap, and
+ * phemy_shutdown logs the strings never freed with the export that
+ * returned each.
  */
 void phemy_free_string(char *ptr);
 
+/**
+ * Free samples from phemy_stop_recording_with_samples(), passing the
+ * length it returned with them. Null is ignored.
+ */
+void phemy_free_samples(float *ptr, uintptr_t len);
+
+/**
+ * Like phemy_get_settings(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_settings_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_setting(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_setting_buf(const char *key, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_settings_schema(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_settings_schema_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_validate_hotkey(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_validate_hotkey_buf(const char *hotkey, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_list_profiles(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_list_profiles_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_list_audio_devices(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_list_audio_devices_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_list_whisper_models(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_list_whisper_models_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_download_progress(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_download_progress_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_list_prompt_modes(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_list_prompt_modes_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_list_llm_models(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_list_llm_models_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_llm_download_progress(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_llm_download_progress_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_history(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_history_buf(int32_t limit, int32_t offset, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_query_history(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_query_history_buf(const char *filter_json, int32_t limit, int32_t offset, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_search_history(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_search_history_buf(const char *query, int32_t limit, int32_t offset, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_history_audio_path(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_history_audio_path_buf(const char *id, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_stats(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_stats_buf(int32_t days, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_events(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_events_buf(uint32_t limit, uint32_t offset, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_get_vocabulary(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_get_vocabulary_buf(char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_suggest_vocabulary(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_suggest_vocabulary_buf(uint32_t min_occurrences, uint32_t limit, char *buf, uintptr_t buf_len);
+
+/**
+ * Like phemy_preview_paste(), writing into a caller-allocated buffer.
+ */
+int32_t phemy_preview_paste_buf(const char *text, const char *target_app, char *buf, uintptr_t buf_len);
+
 #endif  /* PHEMY_CORE_H */
@@ -0,0 +1,450 @@
+//! Rust API for embedding phemy-core in a Rust application (a Tauri app,
+//! say) without C strings, raw pointers or manual frees: calls return
+//! structs and `anyhow::Error`s. The `phemy_*` exports are thin wrappers
+//! over it that turn results into JSON.
+//!
+//! phemy-core keeps its state in process globals, so there is only ever one
+//! instance. Every `Handle` refers to it: `init` again with the same
+//! directory returns another handle to the same state, and with a different
+//! one switches directories as phemy_init() does.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::audio::capture::MicLevels;
+use crate::audio::device::AudioDevice;
+use crate::db::{ClearReport, HistoryEntry, HistoryFilter, SearchResult};
+use crate::health::{BuildInfo, HealthReport};
+use crate::llm::llm_model_manager::LlmModelInfo;
+use crate::llm::prompt_optimizer::OptimizationResult;
+use crate::settings::{FieldError, OptimizationLength, PromptMode, Settings};
+use crate::transcription::engine::TranscriptionResult;
+use crate::transcription::model_manager::WhisperModel;
+use crate::{db, llm, settings, transcription, PipelineOptions, ProcessResult};
+
+/// Where phemy keeps its data, and how it runs. Deserialized from the JSON
+/// given to phemy_init_ex(); missing fields take their defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Directory for settings, models, recordings and the history
+    /// database; None uses the platform data directory
+    pub data_dir: Option<PathBuf>,
+    /// Keep history in an in-memory database that is never written to disk.
+    /// Settings and models still live in `data_dir`.
+    pub in_memory: bool,
+    /// Passphrase of an encrypted history database (the `sqlcipher` feature);
+    /// an unencrypted one is converted on first use
+    pub db_key: Option<String>,
+    pub runtime: RuntimeConfig,
+}
+
+/// The async runtime that runs transcriptions, optimizations and downloads.
+/// The default is a thread per core; an app that runs one pipeline at a
+/// time can do with fewer.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    /// Worker threads; 0 = one per core. Ignored with `current_thread`.
+    pub worker_threads: usize,
+    /// Run async tasks on one thread. Blocking work (whisper, the local
+    /// LLM) still gets threads of its own.
+    pub current_thread: bool,
+    /// Prefix of the runtime's thread names; None = "phemy-runtime"
+    pub thread_name: Option<String>,
+}
+
+impl Config {
+    /// The configuration phemy_init() is given: a data directory path, or
+    /// ":memory:" for in-memory history
+    pub(crate) fn from_data_dir(data_dir: Option<&str>, db_key: Option<&str>) -> Self {
+        let in_memory = data_dir == Some(db::MEMORY_PATH);
+        Self {
+            data_dir: data_dir.filter(|_| !in_memory).map(PathBuf::from),
+            in_memory,
+            db_key: db_key.map(str::to_string),
+            ..Default::default()
+        }
+    }
+}
+
+/// Settings refused by `Handle::save_settings` or `Handle::update_settings`,
+/// with what is wrong with each field
+#[derive(Debug)]
+pub struct SettingsRejected(pub Vec<FieldError>);
+
+impl std::fmt::Display for SettingsRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&settings::describe_errors(&self.0))
+    }
+}
+
+impl std::error::Error for SettingsRejected {}
+
+/// Initialize phemy-core; see phemy_init(). Fails, leaving phemy
+/// uninitialized, if the database can't be opened, or with the current
+/// initialization in place if switching directories is refused.
+pub fn init(config: Config) -> Result<Handle> {
+    crate::init(&config)?;
+    Ok(Handle { _private: () })
+}
+
+/// See phemy_get_build_info(). Needs no `init`.
+pub fn build_info() -> BuildInfo {
+    crate::health::build_info()
+}
+
+/// Access to the initialized phemy-core, from `init`. Copies are cheap and
+/// all refer to the same state.
+#[derive(Debug, Clone, Copy)]
+pub struct Handle {
+    _private: (),
+}
+
+impl Handle {
+    /// A handle for the C exports, whose callers are trusted to have called
+    /// phemy_init() first (calls that need the database fail otherwise)
+    pub(crate) fn unchecked() -> Self {
+        Self { _private: () }
+    }
+
+    /// See phemy_health_check().
+    pub fn health_check(&self, check_network: bool) -> HealthReport {
+        crate::health::run(check_network)
+    }
+
+    /// See phemy_shutdown(). Handles still around afterwards fail until the
+    /// next `init`.
+    pub fn shutdown(self) {
+        crate::shutdown();
+    }
+
+    // Settings
+
+    /// Current settings, with the vocabulary from the database
+    pub fn settings(&self) -> Settings {
+        crate::load_settings_with_vocabulary()
+    }
+
+    /// One setting by dotted path (e.g. "llm.max_tokens"), or None if there
+    /// is no such setting
+    pub fn setting(&self, key: &str) -> Option<serde_json::Value> {
+        self.settings().get_path(key)
+    }
+
+    /// Validate and save `settings`, returning warnings about values that
+    /// were saved but won't take effect yet. Invalid settings fail with
+    /// `SettingsRejected` and change nothing.
+    pub fn save_settings(&self, settings: &Settings) -> Result<Vec<FieldError>> {
+        settings.validate().map_err(|errors| {
+            log::error!("Invalid settings: {}", settings::describe_errors(&errors));
+            SettingsRejected(errors)
+        })?;
+        crate::commit_settings(settings, true).map_err(|e| {
+            log::error!("Failed to save settings: {}", e);
+            e
+        })?;
+        Ok(settings.warnings())
+    }
+
+    /// Change only the fields in `patch` (e.g. { "prompt_mode": "formal" })
+    /// and return the updated settings. Invalid or unknown fields fail with
+    /// `SettingsRejected` and change nothing.
+    pub fn update_settings(&self, patch: &serde_json::Value) -> Result<Settings> {
+        let updated = self
+            .settings()
+            .merged(patch)
+            .and_then(|s| s.validate().map(|_| s))
+            .map_err(SettingsRejected)?;
+        crate::commit_settings(&updated, patch.get("vocabulary").is_some())?;
+        Ok(updated)
+    }
+
+    /// Save default settings and return them
+    pub fn reset_settings(&self) -> Result<Settings> {
+        let settings = Settings::default();
+        settings.save()?;
+        Ok(settings)
+    }
+
+    // Recording
+
+    pub fn list_audio_devices(&self) -> Result<Vec<AudioDevice>> {
+        crate::audio::device::list_input_devices()
+    }
+
+    /// Start recording from `device`, or the default device if None
+    pub fn start_recording(&self, device: Option<&str>) -> Result<()> {
+        self.start_recording_with_levels(device, MicLevels::None, false)
+    }
+
+    /// `start_recording`, calling `levels` with the microphone level as it
+    /// records: on the callback dispatcher thread, or straight from the
+    /// audio thread if `direct` is set
+    pub fn start_recording_with_levels(
+        &self,
+        device: Option<&str>,
+        levels: MicLevels,
+        direct: bool,
+    ) -> Result<()> {
+        let settings = Settings::load();
+        crate::audio::capture::start_recording(device, levels, direct, &settings.audio)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        crate::audio::capture::is_recording()
+    }
+
+    /// Whether the recording hit its maximum duration or silence auto-stop,
+    /// so it should be stopped
+    pub fn recording_should_stop(&self) -> bool {
+        crate::audio::capture::stop_requested()
+    }
+
+    /// Stop recording and return the samples and their sample rate
+    pub fn stop_recording(&self) -> Result<(Vec<f32>, u32)> {
+        crate::audio::capture::stop_recording()
+    }
+
+    /// Stop recording and discard it. Returns false if none was running.
+    pub fn cancel_recording(&self) -> bool {
+        crate::audio::capture::cancel_recording()
+    }
+
+    /// Stop recording, then transcribe, optimize and save to history as
+    /// `options` say; see phemy_stop_and_process_ex(). Blocks until done.
+    /// Invalid options fail before the recording is stopped.
+    pub fn stop_and_process(&self, options: &PipelineOptions) -> Result<ProcessResult> {
+        self.stop_and_process_with_phases(options, None)
+    }
+
+    /// `stop_and_process`, telling `phases` about each phase as it starts
+    /// (see `jobs::Phase`), from stopping the recording to saving
+    pub fn stop_and_process_with_phases(
+        &self,
+        options: &PipelineOptions,
+        phases: Option<crate::jobs::PhaseSink>,
+    ) -> Result<ProcessResult> {
+        options.validate().map_err(anyhow::Error::msg)?;
+        if let Some(phases) = &phases {
+            phases(crate::jobs::Phase::Stopping, None);
+        }
+        crate::run_tracked_with_phases(self.stop_recording(), options, phases)
+    }
+
+    /// Run the stop-and-process pipeline on audio recorded elsewhere
+    pub fn process_samples(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        options: &PipelineOptions,
+    ) -> Result<ProcessResult> {
+        if samples.is_empty() {
+            anyhow::bail!("No audio samples provided");
+        }
+        if sample_rate == 0 {
+            anyhow::bail!("Invalid sample rate: 0");
+        }
+        options.validate().map_err(anyhow::Error::msg)?;
+        crate::run_tracked(Ok((samples.to_vec(), sample_rate)), options)
+    }
+
+    /// Run text from elsewhere through the rest of the stop-and-process
+    /// pipeline: optimize it and save it to history, with no recording
+    pub fn process_text(&self, text: &str, options: &PipelineOptions) -> Result<ProcessResult> {
+        options.validate().map_err(anyhow::Error::msg)?;
+        crate::run_as_job(|job| crate::process_text_pipeline(text, options, job))
+    }
+
+    /// Transcribe audio without optimizing it or saving it to history
+    pub fn transcribe(&self, samples: &[f32], sample_rate: u32) -> Result<TranscriptionResult> {
+        let settings = Settings::load();
+        crate::runtime()?.block_on(transcription::engine::transcribe(samples, sample_rate, &settings))
+    }
+
+    /// Optimize a transcript with the saved settings, or `length` instead of
+    /// the saved length, for a prompt pasted into `target_app` (`{{app}}`
+    /// in custom prompts)
+    pub fn optimize(
+        &self,
+        transcript: &str,
+        length: Option<OptimizationLength>,
+        target_app: Option<&str>,
+    ) -> Result<OptimizationResult> {
+        let mut settings = Settings::load();
+        if let Some(length) = length {
+            settings.optimization_length = length;
+        }
+        crate::runtime()?.block_on(llm::prompt_optimizer::optimize(transcript, &settings, target_app))
+    }
+
+    /// Like `optimize`, in `mode` instead of the saved prompt mode and mode
+    /// chain
+    pub fn optimize_in_mode(
+        &self,
+        transcript: &str,
+        mode: PromptMode,
+        target_app: Option<&str>,
+    ) -> Result<OptimizationResult> {
+        let mut settings = Settings::load();
+        settings.prompt_mode = mode;
+        settings.prompt_mode_chain.clear();
+        crate::runtime()?.block_on(llm::prompt_optimizer::optimize(transcript, &settings, target_app))
+    }
+
+    // History
+
+    /// Newest first
+    pub fn history(&self, limit: usize, offset: usize) -> Result<Vec<HistoryEntry>> {
+        db::get_history(limit, offset)
+    }
+
+    pub fn query_history(&self, filter: &HistoryFilter, limit: usize, offset: usize) -> Result<Vec<HistoryEntry>> {
+        db::query_history(filter, limit, offset)
+    }
+
+    /// Full-text search, best match first
+    pub fn search_history(&self, query: &str, limit: usize, offset: usize) -> Result<Vec<SearchResult>> {
+        db::search_history(query, limit, offset)
+    }
+
+    pub fn history_entry(&self, id: &str) -> Result<Option<HistoryEntry>> {
+        db::get_history_entry(id)
+    }
+
+    pub fn delete_history_entry(&self, id: &str) -> Result<()> {
+        db::delete_history_entry(id)
+    }
+
+    /// Clear history, or everything but favorites, with the entries' recordings
+    pub fn clear_history(&self, keep_favorites: bool) -> Result<ClearReport> {
+        db::clear_history(keep_favorites)
+    }
+
+    /// Wait for history entries from earlier runs to be written
+    pub fn flush_history(&self) {
+        crate::flush_history_inserts();
+    }
+
+    // Models
+
+    pub fn whisper_models(&self) -> Result<Vec<WhisperModel>> {
+        transcription::model_manager::list_models()
+    }
+
+    /// Download a whisper model, blocking until done. `on_progress` gets
+    /// (downloaded bytes, total bytes, fraction done) after each chunk.
+    pub fn download_whisper_model(&self, name: &str, mut on_progress: impl FnMut(u64, u64, f64)) -> Result<()> {
+        crate::runtime()?.block_on(transcription::model_manager::download_model_with_progress(name, |p| {
+            on_progress(p.downloaded_bytes, p.total_bytes, p.progress)
+        }))
+    }
+
+    pub fn delete_whisper_model(&self, name: &str) -> Result<()> {
+        transcription::model_manager::delete_model(name)
+    }
+
+    pub fn llm_models(&self) -> Result<Vec<LlmModelInfo>> {
+        llm::llm_model_manager::list_models()
+    }
+
+    /// Download a local LLM model; see `download_whisper_model`
+    pub fn download_llm_model(&self, name: &str, mut on_progress: impl FnMut(u64, u64, f64)) -> Result<()> {
+        crate::runtime()?.block_on(llm::llm_model_manager::download_model_with_progress(name, |p| {
+            on_progress(p.downloaded_bytes, p.total_bytes, p.progress)
+        }))
+    }
+
+    pub fn delete_llm_model(&self, name: &str) -> Result<()> {
+        llm::llm_model_manager::delete_model(name)
+    }
+
+    /// Stop any model download before its next chunk; the download then
+    /// fails and its partial file is removed
+    pub fn cancel_downloads(&self) {
+        crate::ops::cancel_kind(crate::ops::OpKind::Download);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::time::Duration;
+
+    fn mock(transcript: &str, completion: &str) {
+        test_support::set_mocks(test_support::Mocks {
+            transcript: transcript.to_string(),
+            completion: completion.to_string(),
+            delay: Duration::ZERO,
+        });
+    }
+
+    #[test]
+    fn dictation_reaches_history_through_the_facade() {
+        let _lock = test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+        let phemy = init(Config {
+            data_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        })
+        .unwrap();
+        mock("hello world", "Hello, world.");
+
+        let samples = test_support::speech(16_000);
+        let result = phemy.process_samples(&samples, 16_000, &PipelineOptions::default()).unwrap();
+        assert_eq!(result.raw_transcript, "hello world");
+        assert_eq!(result.optimized_prompt, "Hello, world.");
+
+        phemy.flush_history();
+        let id = result.history_id.unwrap();
+        let entry = phemy.history_entry(&id).unwrap().unwrap();
+        assert_eq!(entry.optimized_prompt.as_deref(), Some("Hello, world."));
+        let found = phemy.search_history("world", 10, 0).unwrap();
+        assert_eq!(found[0].entry.id, id);
+
+        let skipped = PipelineOptions {
+            skip_history: true,
+            ..Default::default()
+        };
+        phemy.process_samples(&samples, 16_000, &skipped).unwrap();
+        phemy.flush_history();
+        assert_eq!(phemy.history(10, 0).unwrap().len(), 1);
+
+        assert_eq!(phemy.clear_history(false).unwrap().deleted, 1);
+        phemy.shutdown();
+    }
+
+    #[test]
+    fn settings_round_trip_and_rejections_are_typed() {
+        let _lock = test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+        let phemy = init(Config {
+            data_dir: Some(dir.path().to_path_buf()),
+            in_memory: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let patch = serde_json::json!({ "paste_delay_ms": 300 });
+        assert_eq!(phemy.update_settings(&patch).unwrap().paste_delay_ms, 300);
+        assert_eq!(phemy.setting("paste_delay_ms"), Some(serde_json::json!(300)));
+
+        let mut invalid = phemy.settings();
+        invalid.language = "German".to_string();
+        let e = phemy.save_settings(&invalid).unwrap_err();
+        let rejected = e.downcast_ref::<SettingsRejected>().expect("SettingsRejected");
+        assert_eq!(rejected.0[0].field, "language");
+        assert_eq!(phemy.settings().language, "en");
+
+        let samples = test_support::speech(16_000);
+        let options = PipelineOptions {
+            mode: Some("poetic".to_string()),
+            ..Default::default()
+        };
+        assert!(phemy.process_samples(&samples, 16_000, &options).is_err());
+        phemy.shutdown();
+    }
+}
@@ -0,0 +1,135 @@
+//! The parts of cpal that capture and device listing use, behind a trait so
+//! the `mock-audio` feature can put a fake microphone in their place.
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use super::device::{self, AudioDevice};
+
+/// Interleaved f32 samples from an open input, on the backend's audio thread
+pub type DataCallback = Box<dyn FnMut(&[f32]) + Send + 'static>;
+
+/// Failures of an open input, on the backend's audio thread
+pub type ErrorCallback = Box<dyn FnMut(StreamError) + Send + 'static>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamError {
+    /// The device went away (unplugged, say); no more samples will come
+    DeviceLost,
+    Other(String),
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeviceLost => f.write_str("Audio device is no longer available"),
+            Self::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+/// A started input, delivering samples to its callbacks until dropped
+pub struct InputStream {
+    _stream: Box<dyn std::any::Any>,
+}
+
+impl InputStream {
+    /// Wrap whatever keeps the backend's stream running
+    pub fn new(stream: impl std::any::Any) -> Self {
+        Self {
+            _stream: Box::new(stream),
+        }
+    }
+}
+
+pub trait AudioBackend {
+    fn input_devices(&self) -> anyhow::Result<Vec<AudioDevice>>;
+
+    /// The device named `name`, or the default one, in its default
+    /// configuration
+    fn input(&self, name: Option<&str>) -> anyhow::Result<Box<dyn Input>>;
+}
+
+/// An input device, ready to start
+pub trait Input {
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> u16;
+
+    /// Start capturing, passing samples and failures to the callbacks
+    fn start(self: Box<Self>, on_data: DataCallback, on_error: ErrorCallback) -> anyhow::Result<InputStream>;
+}
+
+/// The system's audio through cpal
+pub struct CpalBackend;
+
+impl AudioBackend for CpalBackend {
+    fn input_devices(&self) -> anyhow::Result<Vec<AudioDevice>> {
+        let host = cpal::default_host();
+        let default_device = host.default_input_device();
+        let default_name = default_device
+            .as_ref()
+            .and_then(|d| d.name().ok())
+            .unwrap_or_default();
+
+        let mut devices = Vec::new();
+
+        for device in host.input_devices()? {
+            if let Ok(name) = device.name() {
+                devices.push(AudioDevice {
+                    is_default: name == default_name,
+                    name,
+                });
+            }
+        }
+
+        Ok(devices)
+    }
+
+    fn input(&self, name: Option<&str>) -> anyhow::Result<Box<dyn Input>> {
+        let device = device::get_input_device(name)?;
+        let config = device.default_input_config()?;
+        Ok(Box::new(CpalInput { device, config }))
+    }
+}
+
+struct CpalInput {
+    device: cpal::Device,
+    config: cpal::SupportedStreamConfig,
+}
+
+impl Input for CpalInput {
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate().0
+    }
+
+    fn channels(&self) -> u16 {
+        self.config.channels()
+    }
+
+    fn start(self: Box<Self>, mut on_data: DataCallback, mut on_error: ErrorCallback) -> anyhow::Result<InputStream> {
+        let stream = self.device.build_input_stream(
+            &self.config.into(),
+            move |data: &[f32], _: &cpal::InputCallbackInfo| on_data(data),
+            move |err| {
+                on_error(match err {
+                    cpal::StreamError::DeviceNotAvailable => StreamError::DeviceLost,
+                    other => StreamError::Other(other.to_string()),
+                })
+            },
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(InputStream::new(stream))
+    }
+}
+
+/// Run `f` with the backend in use: the mock one while installed (see
+/// `super::mock`), otherwise cpal
+pub fn with_backend<T>(f: impl FnOnce(&dyn AudioBackend) -> T) -> T {
+    #[cfg(feature = "mock-audio")]
+    if super::mock::is_installed() {
+        return f(&super::mock::MockBackend);
+    }
+    f(&CpalBackend)
+}
@@ -1,54 +1,119 @@
-use cpal::traits::{DeviceTrait, StreamTrait};
+use std::ffi::c_void;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex,
 };
 
-use super::device;
+use super::backend::{self, InputStream, StreamError};
+use super::vad;
+use crate::ffi::UserData;
+use crate::settings::AudioSettings;
 
 static RECORDING: AtomicBool = AtomicBool::new(false);
 
-// cpal::Stream contains a raw pointer that isn't Send, so we wrap it
-struct StreamHolder(Option<cpal::Stream>);
+/// Set when the recording hit its maximum duration or silence auto-stop, or
+/// lost its device
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// The stream may be a cpal::Stream, which contains a raw pointer that isn't
+// Send, so we wrap it
+struct StreamHolder(Option<InputStream>);
 unsafe impl Send for StreamHolder {}
 unsafe impl Sync for StreamHolder {}
 
 static ACTIVE_STREAM: std::sync::LazyLock<Mutex<StreamHolder>> =
     std::sync::LazyLock::new(|| Mutex::new(StreamHolder(None)));
 
-static SAMPLES_BUF: std::sync::LazyLock<Mutex<Option<Arc<Mutex<Vec<f32>>>>>> =
+/// Mono samples of the recording, appended to by the audio callback
+type SampleBuffer = Arc<Mutex<Vec<f32>>>;
+
+static SAMPLES_BUF: std::sync::LazyLock<Mutex<Option<SampleBuffer>>> =
     std::sync::LazyLock::new(|| Mutex::new(None));
 static SAMPLE_RATE: std::sync::LazyLock<Mutex<Option<u32>>> =
     std::sync::LazyLock::new(|| Mutex::new(None));
 
-/// C-compatible callback type for mic level updates.
-/// Called from the audio thread with (rms, peak) values.
-pub type MicLevelCallback = extern "C" fn(rms: f32, peak: f32);
+/// C-compatible callback type for mic level updates, or null for none.
+/// Called with (rms, peak) values on the callback thread, or
+/// with direct delivery on the audio thread, where it must return quickly
+/// and not call phemy functions: stopping the recording from there would
+/// wait on that very thread.
+pub type MicLevelCallback = Option<extern "C" fn(rms: f32, peak: f32)>;
+
+/// A MicLevelCallback that also gets the `user_data` pointer given with it
+pub type MicLevelUserDataCallback = Option<extern "C" fn(rms: f32, peak: f32, user_data: *mut c_void)>;
+
+/// Lossy dispatcher kind of mic level callbacks
+const MIC_LEVEL: &str = "mic_level";
+
+/// Where a recording's mic levels go
+#[derive(Debug, Clone, Copy, Default)]
+pub enum MicLevels {
+    #[default]
+    None,
+    Callback(extern "C" fn(rms: f32, peak: f32)),
+    WithUserData(extern "C" fn(rms: f32, peak: f32, user_data: *mut c_void), UserData),
+}
+
+impl MicLevels {
+    pub fn with_user_data(cb: MicLevelUserDataCallback, user_data: *mut c_void) -> Self {
+        cb.map_or(Self::None, |cb| Self::WithUserData(cb, UserData(user_data)))
+    }
+
+    fn call(self, rms: f32, peak: f32) {
+        match self {
+            Self::None => {}
+            Self::Callback(cb) => cb(rms, peak),
+            Self::WithUserData(cb, user_data) => cb(rms, peak, user_data.get()),
+        }
+    }
+}
+
+impl From<MicLevelCallback> for MicLevels {
+    fn from(cb: MicLevelCallback) -> Self {
+        cb.map_or(Self::None, Self::Callback)
+    }
+}
 
 /// Start recording from the given device name (or default if null).
-/// The `mic_cb` function pointer is called on the audio thread with RMS and peak values.
+/// `levels` gets RMS and peak values through the callback dispatcher, or on
+/// the audio thread if `direct_levels` is set. None are delivered once
+/// `stop_recording` returns.
+///
+/// `audio` sets the input gain, maximum duration and silence auto-stop. The
+/// core can't end a recording on its own (the host collects the result), so
+/// when either limit is reached, or the device goes away, `stop_requested`
+/// turns true and the host should stop; samples past the maximum duration
+/// are dropped.
 pub fn start_recording(
     device_name: Option<&str>,
-    mic_cb: Option<MicLevelCallback>,
+    levels: MicLevels,
+    direct_levels: bool,
+    audio: &AudioSettings,
 ) -> anyhow::Result<()> {
     if RECORDING.load(Ordering::Relaxed) {
         return Ok(());
     }
 
-    let device = device::get_input_device(device_name)?;
-    let config = device.default_input_config()?;
+    let input = backend::with_backend(|backend| backend.input(device_name))?;
 
-    let sample_rate = config.sample_rate().0;
-    let channels = config.channels() as usize;
+    let sample_rate = input.sample_rate();
+    let channels = input.channels() as usize;
 
     let samples: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::new()));
     let samples_clone = samples.clone();
 
-    let stream = device.build_input_stream(
-        &config.into(),
-        move |data: &[f32], _: &cpal::InputCallbackInfo| {
+    let gain = audio.input_gain;
+    let max_samples = match audio.max_duration_secs {
+        0 => usize::MAX,
+        secs => secs as usize * sample_rate as usize,
+    };
+    let mut silence = vad::SilenceTracker::new(audio, sample_rate);
+    STOP_REQUESTED.store(false, Ordering::Relaxed);
+
+    let stream = input.start(
+        Box::new(move |data: &[f32]| {
             // Downmix to mono if multichannel
-            let mono: Vec<f32> = if channels > 1 {
+            let mut mono: Vec<f32> = if channels > 1 {
                 data.chunks(channels)
                     .map(|frame| frame.iter().sum::<f32>() / channels as f32)
                     .collect()
@@ -56,29 +121,46 @@ pub fn start_recording(
                 data.to_vec()
             };
 
+            if gain != 1.0 {
+                for s in &mut mono {
+                    *s = (*s * gain).clamp(-1.0, 1.0);
+                }
+            }
+
+            if let Some(tracker) = silence.as_mut() {
+                if tracker.push(&mono) && !STOP_REQUESTED.swap(true, Ordering::Relaxed) {
+                    log::info!("Silence after speech, requesting stop");
+                }
+            }
+
             // Calculate RMS and peak for visualization, invoke callback
-            if !mono.is_empty() {
-                if let Some(cb) = mic_cb {
-                    let rms =
-                        (mono.iter().map(|s| s * s).sum::<f32>() / mono.len() as f32).sqrt();
-                    let peak = mono.iter().map(|s| s.abs()).fold(0.0f32, f32::max);
-                    cb(rms, peak);
+            if !mono.is_empty() && !matches!(levels, MicLevels::None) {
+                let rms = (mono.iter().map(|s| s * s).sum::<f32>() / mono.len() as f32).sqrt();
+                let peak = mono.iter().map(|s| s.abs()).fold(0.0f32, f32::max);
+                if direct_levels {
+                    levels.call(rms, peak);
+                } else {
+                    crate::ffi::dispatch_lossy(MIC_LEVEL, move || levels.call(rms, peak));
                 }
             }
 
-            // Store samples
+            // Store samples, up to the maximum duration
             if let Ok(mut buf) = samples_clone.lock() {
-                buf.extend_from_slice(&mono);
+                let room = max_samples.saturating_sub(buf.len());
+                buf.extend_from_slice(&mono[..mono.len().min(room)]);
+                if room <= mono.len() && !STOP_REQUESTED.swap(true, Ordering::Relaxed) {
+                    log::info!("Maximum recording duration reached, requesting stop");
+                }
             }
-        },
-        |err| {
+        }),
+        Box::new(|err| {
             log::error!("Audio stream error: {}", err);
-        },
-        None,
+            if err == StreamError::DeviceLost && !STOP_REQUESTED.swap(true, Ordering::Relaxed) {
+                log::info!("Audio device lost, requesting stop");
+            }
+        }),
     )?;
 
-    stream.play()?;
-
     // Store the stream so it stays alive
     {
         let mut holder = ACTIVE_STREAM.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
@@ -103,13 +185,16 @@ pub fn stop_recording() -> anyhow::Result<(Vec<f32>, u32)> {
         let mut holder = ACTIVE_STREAM.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
         holder.0.take();
     }
+    // Levels still on their way would be stale, and their user data may be
+    // freed once this returns
+    crate::ffi::discard_lossy(MIC_LEVEL);
 
-    // Retrieve samples
+    // Retrieve samples; the stream is gone, so they can be moved out
     let samples = SAMPLES_BUF
         .lock()
         .map_err(|e| anyhow::anyhow!("{}", e))?
         .take()
-        .and_then(|arc| arc.lock().ok().map(|s| s.clone()))
+        .and_then(|arc| arc.lock().ok().map(|mut s| std::mem::take(&mut *s)))
         .unwrap_or_default();
 
     let sample_rate = SAMPLE_RATE
@@ -128,13 +213,191 @@ pub fn stop_recording() -> anyhow::Result<(Vec<f32>, u32)> {
     Ok((samples, sample_rate))
 }
 
-/// Stop recording without returning samples
-pub fn stop_recording_sync() {
-    if RECORDING.load(Ordering::Relaxed) {
-        let _ = stop_recording();
+/// Stop recording and throw the samples away. Returns whether a recording
+/// was running; either way the next `start_recording` starts afresh.
+pub fn cancel_recording() -> bool {
+    let was_recording = RECORDING.swap(false, Ordering::Relaxed);
+    ACTIVE_STREAM.lock().unwrap_or_else(|e| e.into_inner()).0.take();
+    crate::ffi::discard_lossy(MIC_LEVEL);
+    SAMPLES_BUF.lock().unwrap_or_else(|e| e.into_inner()).take();
+    SAMPLE_RATE.lock().unwrap_or_else(|e| e.into_inner()).take();
+
+    if was_recording {
+        log::info!("Recording cancelled");
     }
+    was_recording
 }
 
 pub fn is_recording() -> bool {
     RECORDING.load(Ordering::Relaxed)
 }
+
+/// Whether the active recording reached its maximum duration or silence
+/// auto-stop, or lost its device, and should be stopped by the host
+pub fn stop_requested() -> bool {
+    RECORDING.load(Ordering::Relaxed) && STOP_REQUESTED.load(Ordering::Relaxed)
+}
+
+#[cfg(all(test, feature = "mock-audio"))]
+mod tests {
+    use super::*;
+    use crate::audio::mock::{self, MockDevice, Source};
+    use crate::test_support;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::MutexGuard;
+    use std::time::{Duration, Instant};
+
+    /// Mock devices installed, with the global-state lock held, until dropped
+    struct Installed {
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl Drop for Installed {
+        fn drop(&mut self) {
+            cancel_recording();
+            mock::uninstall();
+        }
+    }
+
+    fn install(devices: Vec<MockDevice>) -> Installed {
+        let lock = test_support::lock();
+        mock::install(devices);
+        Installed { _lock: lock }
+    }
+
+    fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn sine() -> Source {
+        Source::Sine {
+            frequency: 440.0,
+            amplitude: 0.5,
+        }
+    }
+
+    static LEVELS: AtomicU32 = AtomicU32::new(0);
+    static LAST_PEAK: AtomicU32 = AtomicU32::new(0);
+
+    extern "C" fn record_level(_rms: f32, peak: f32) {
+        LAST_PEAK.store(peak.to_bits(), Ordering::Relaxed);
+        LEVELS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn records_samples_and_levels_from_the_device() {
+        let stereo = MockDevice {
+            channels: 2,
+            ..MockDevice::new("Mock Mic", sine())
+        };
+        let _devices = install(vec![stereo, MockDevice::new("Other Mic", Source::Silence)]);
+        let devices = crate::audio::device::list_input_devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert!(devices[0].is_default && devices[0].name == "Mock Mic");
+        assert!(start_recording(Some("No Such Mic"), MicLevels::None, false, &AudioSettings::default()).is_err());
+
+        LEVELS.store(0, Ordering::Relaxed);
+        start_recording(None, MicLevels::Callback(record_level), true, &AudioSettings::default()).unwrap();
+        assert!(is_recording() && mock::is_streaming());
+        wait_until("mic levels", || LEVELS.load(Ordering::Relaxed) >= 5);
+        let (samples, sample_rate) = stop_recording().unwrap();
+
+        assert!(!is_recording() && !mock::is_streaming());
+        assert_eq!(sample_rate, 16_000);
+        assert!(samples.len() >= 5 * 160, "{} samples", samples.len());
+        let peak = f32::from_bits(LAST_PEAK.load(Ordering::Relaxed));
+        assert!((peak - 0.5).abs() < 0.05, "peak {}", peak);
+        // Both channels carry the sine, so downmixing leaves it as it was
+        let expected = 0.5 * (2.0 * std::f32::consts::PI * 440.0 * 40.0 / 16_000.0).sin();
+        assert!((samples[40] - expected).abs() < 1e-4);
+    }
+
+    extern "C" fn count_level(_rms: f32, _peak: f32, user_data: *mut c_void) {
+        let count = unsafe { &*(user_data as *const AtomicU32) };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn levels_carry_user_data_and_stop_with_the_recording() {
+        let _devices = install(vec![MockDevice::new("Mock Mic", sine())]);
+        let count = Box::new(AtomicU32::new(0));
+        let user_data = &*count as *const AtomicU32 as *mut c_void;
+
+        let levels = MicLevels::with_user_data(Some(count_level), user_data);
+        start_recording(None, levels, false, &AudioSettings::default()).unwrap();
+        wait_until("mic levels", || count.load(Ordering::Relaxed) >= 3);
+        stop_recording().unwrap();
+
+        let after_stop = count.load(Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(count.load(Ordering::Relaxed), after_stop, "no level after stop_recording returned");
+        drop(count);
+    }
+
+    fn recorded() -> usize {
+        SAMPLES_BUF.lock().unwrap().as_ref().map_or(0, |buf| buf.lock().unwrap().len())
+    }
+
+    #[test]
+    fn cancelled_recordings_are_discarded() {
+        let _devices = install(vec![MockDevice::new("Mock Mic", sine())]);
+        assert!(!cancel_recording(), "nothing to cancel");
+
+        start_recording(None, MicLevels::None, false, &AudioSettings::default()).unwrap();
+        wait_until("half a second of audio", || recorded() >= 8_000);
+        assert!(cancel_recording());
+        assert!(!is_recording());
+        assert!(SAMPLES_BUF.lock().unwrap().is_none());
+        assert!(SAMPLE_RATE.lock().unwrap().is_none());
+        assert!(!cancel_recording());
+
+        // The next recording starts empty
+        start_recording(None, MicLevels::None, false, &AudioSettings::default()).unwrap();
+        wait_until("some audio", || recorded() > 0);
+        let (samples, rate) = stop_recording().unwrap();
+        assert_eq!(rate, 16_000);
+        assert!(samples.len() < 8_000, "{} samples", samples.len());
+    }
+
+    #[test]
+    fn silence_after_speech_requests_a_stop() {
+        // 300ms of speech-level tone, then silence
+        let speech: Vec<f32> = (0..4_800).map(|i| 0.3 * (i as f32 * 0.2).sin()).collect();
+        let _devices = install(vec![MockDevice::new("Mock Mic", Source::Samples(speech))]);
+        let audio = AudioSettings {
+            silence_auto_stop_ms: 300,
+            ..Default::default()
+        };
+
+        start_recording(None, MicLevels::None, false, &audio).unwrap();
+        assert!(!stop_requested());
+        wait_until("silence auto-stop", stop_requested);
+        let (samples, _) = stop_recording().unwrap();
+
+        assert!(samples.len() >= 4_800 + 4_800, "{} samples", samples.len());
+        assert!(!stop_requested(), "no recording, nothing to stop");
+    }
+
+    #[test]
+    fn lost_device_requests_a_stop_and_keeps_what_was_recorded() {
+        let _devices = install(vec![MockDevice::new("USB Mic", sine())]);
+        start_recording(Some("USB Mic"), MicLevels::None, false, &AudioSettings::default()).unwrap();
+
+        // Other stream errors are only logged
+        assert!(mock::inject_error("input overflow"));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!stop_requested() && mock::is_streaming());
+
+        assert!(mock::disconnect());
+        wait_until("device loss", stop_requested);
+        assert!(!mock::is_streaming());
+        assert!(!mock::disconnect(), "nothing left to disconnect");
+
+        let (samples, _) = stop_recording().unwrap();
+        assert!(!samples.is_empty());
+    }
+}
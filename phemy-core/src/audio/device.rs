@@ -2,33 +2,17 @@ use cpal::traits::{DeviceTrait, HostTrait};
 use serde::Serialize;
 
 #[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct AudioDevice {
     pub name: String,
     pub is_default: bool,
 }
 
 pub fn list_input_devices() -> anyhow::Result<Vec<AudioDevice>> {
-    let host = cpal::default_host();
-    let default_device = host.default_input_device();
-    let default_name = default_device
-        .as_ref()
-        .and_then(|d| d.name().ok())
-        .unwrap_or_default();
-
-    let mut devices = Vec::new();
-
-    for device in host.input_devices()? {
-        if let Ok(name) = device.name() {
-            devices.push(AudioDevice {
-                is_default: name == default_name,
-                name,
-            });
-        }
-    }
-
-    Ok(devices)
+    super::backend::with_backend(|backend| backend.input_devices())
 }
 
+/// The cpal device named `name`, or the default one
 pub fn get_input_device(name: Option<&str>) -> anyhow::Result<cpal::Device> {
     let host = cpal::default_host();
 
@@ -0,0 +1,249 @@
+//! Fake microphones for testing without audio hardware (the `mock-audio`
+//! feature). While `install`ed they replace the system's input devices: a
+//! recording from one gets a chunk of samples from its `Source` every
+//! `interval`, on a thread of its own, and `inject_error` and `disconnect`
+//! make the open stream fail the way a real one can.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::backend::{AudioBackend, DataCallback, ErrorCallback, Input, InputStream, StreamError};
+use super::device::AudioDevice;
+
+/// What a mock device records
+#[derive(Debug, Clone)]
+pub enum Source {
+    Silence,
+    Sine { frequency: f32, amplitude: f32 },
+    /// These mono samples, then silence
+    Samples(Vec<f32>),
+}
+
+impl Source {
+    /// Mono sample number `index`
+    fn sample(&self, index: usize, sample_rate: u32) -> f32 {
+        match self {
+            Self::Silence => 0.0,
+            Self::Sine { frequency, amplitude } => {
+                let t = index as f32 / sample_rate as f32;
+                amplitude * (2.0 * std::f32::consts::PI * frequency * t).sin()
+            }
+            Self::Samples(samples) => samples.get(index).copied().unwrap_or(0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockDevice {
+    pub name: String,
+    pub sample_rate: u32,
+    /// `source` is recorded on every channel
+    pub channels: u16,
+    pub source: Source,
+    /// How often the stream delivers samples (each chunk holds this much audio)
+    pub interval: Duration,
+}
+
+impl MockDevice {
+    /// A 16 kHz mono device delivering `source` every 10ms
+    pub fn new(name: &str, source: Source) -> Self {
+        Self {
+            name: name.to_string(),
+            sample_rate: 16_000,
+            channels: 1,
+            source,
+            interval: Duration::from_millis(10),
+        }
+    }
+
+    /// A mono device playing the WAV file at `path`, at its sample rate
+    pub fn wav(name: &str, path: &Path) -> anyhow::Result<Self> {
+        let (samples, sample_rate) = crate::utils::read_wav(path)?;
+        Ok(Self {
+            sample_rate,
+            ..Self::new(name, Source::Samples(samples))
+        })
+    }
+}
+
+/// Shared with the thread of an open stream
+#[derive(Default)]
+struct Control {
+    stop: AtomicBool,
+    /// Passed to the stream's error callback at its next chunk
+    error: Mutex<Option<StreamError>>,
+    /// Frames handed to the data callback so far
+    delivered: AtomicUsize,
+}
+
+struct Installed {
+    /// The first is the default device
+    devices: Vec<MockDevice>,
+    /// The most recently started stream, while it runs
+    active: Option<Arc<Control>>,
+}
+
+static INSTALLED: Mutex<Option<Installed>> = Mutex::new(None);
+
+fn installed() -> std::sync::MutexGuard<'static, Option<Installed>> {
+    INSTALLED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Use `devices` instead of the system's input devices until `uninstall`;
+/// the first is the default. Streams already open are not affected.
+pub fn install(devices: Vec<MockDevice>) {
+    *installed() = Some(Installed { devices, active: None });
+}
+
+/// Go back to the system's input devices
+pub fn uninstall() {
+    *installed() = None;
+}
+
+pub fn is_installed() -> bool {
+    installed().is_some()
+}
+
+/// Whether a mock stream is delivering samples
+pub fn is_streaming() -> bool {
+    installed()
+        .as_ref()
+        .and_then(|i| i.active.as_ref())
+        .is_some_and(|control| !control.stop.load(Ordering::Relaxed))
+}
+
+/// Frames the most recently started stream has delivered so far, so tests
+/// can wait for a recording to hold enough audio
+pub fn delivered_frames() -> usize {
+    installed()
+        .as_ref()
+        .and_then(|i| i.active.as_ref())
+        .map_or(0, |control| control.delivered.load(Ordering::Relaxed))
+}
+
+/// Report `message` as a stream error from the open stream, which keeps
+/// delivering samples. Returns false if no mock stream is open.
+pub fn inject_error(message: &str) -> bool {
+    send_error(StreamError::Other(message.to_string()))
+}
+
+/// Unplug the open stream's device: it reports `StreamError::DeviceLost`
+/// and delivers nothing more. Returns false if no mock stream is open.
+pub fn disconnect() -> bool {
+    send_error(StreamError::DeviceLost)
+}
+
+fn send_error(error: StreamError) -> bool {
+    let installed = installed();
+    let Some(control) = installed.as_ref().and_then(|i| i.active.as_ref()) else {
+        return false;
+    };
+    if control.stop.load(Ordering::Relaxed) {
+        return false;
+    }
+    *control.error.lock().unwrap_or_else(|e| e.into_inner()) = Some(error);
+    true
+}
+
+pub(super) struct MockBackend;
+
+impl AudioBackend for MockBackend {
+    fn input_devices(&self) -> anyhow::Result<Vec<AudioDevice>> {
+        let installed = installed();
+        let devices = installed.as_ref().map_or(&[][..], |i| &i.devices[..]);
+        Ok(devices
+            .iter()
+            .enumerate()
+            .map(|(i, device)| AudioDevice {
+                name: device.name.clone(),
+                is_default: i == 0,
+            })
+            .collect())
+    }
+
+    fn input(&self, name: Option<&str>) -> anyhow::Result<Box<dyn Input>> {
+        let installed = installed();
+        let devices = installed.as_ref().map_or(&[][..], |i| &i.devices[..]);
+        let device = match name {
+            Some(name) => devices
+                .iter()
+                .find(|d| d.name == name)
+                .ok_or_else(|| anyhow::anyhow!("Audio device '{}' not found", name))?,
+            None => devices
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("No default input device available"))?,
+        };
+        Ok(Box::new(device.clone()))
+    }
+}
+
+impl Input for MockDevice {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn start(self: Box<Self>, mut on_data: DataCallback, mut on_error: ErrorCallback) -> anyhow::Result<InputStream> {
+        let control = Arc::new(Control::default());
+        if let Some(installed) = installed().as_mut() {
+            installed.active = Some(control.clone());
+        }
+
+        let frames = ((self.sample_rate as f64 * self.interval.as_secs_f64()) as usize).max(1);
+        let thread_control = control.clone();
+        let thread = std::thread::Builder::new()
+            .name("phemy-mock-audio".into())
+            .spawn(move || {
+                let mut produced = 0;
+                let mut data = Vec::with_capacity(frames * self.channels as usize);
+                loop {
+                    std::thread::sleep(self.interval);
+                    if thread_control.stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let error = thread_control.error.lock().unwrap_or_else(|e| e.into_inner()).take();
+                    if let Some(error) = error {
+                        let lost = error == StreamError::DeviceLost;
+                        on_error(error);
+                        if lost {
+                            thread_control.stop.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                    }
+                    data.clear();
+                    for index in produced..produced + frames {
+                        let sample = self.source.sample(index, self.sample_rate);
+                        data.extend(std::iter::repeat_n(sample, self.channels as usize));
+                    }
+                    produced += frames;
+                    on_data(&data);
+                    thread_control.delivered.store(produced, Ordering::Relaxed);
+                }
+            })?;
+
+        Ok(InputStream::new(MockStream {
+            control,
+            thread: Some(thread),
+        }))
+    }
+}
+
+/// Stops and waits for its stream's thread when dropped
+struct MockStream {
+    control: Arc<Control>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for MockStream {
+    fn drop(&mut self) {
+        self.control.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,5 +1,8 @@
+pub mod backend;
 pub mod capture;
 pub mod device;
+#[cfg(feature = "mock-audio")]
+pub mod mock;
 pub mod resampler;
 pub mod vad;
 pub mod visualizer;
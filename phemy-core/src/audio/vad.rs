@@ -1,33 +1,41 @@
 /// Simple energy-based voice activity detection.
 /// Trims silence from the beginning and end of audio.
+use crate::settings::AudioSettings;
 
+const SAMPLE_RATE: usize = 16000;
 const FRAME_SIZE: usize = 480; // 30ms at 16kHz
-const ENERGY_THRESHOLD: f32 = 0.005;
+const FRAME_MS: u32 = 30;
 const MIN_SPEECH_FRAMES: usize = 10;
+/// Frames kept after the last speech frame
+const TRAILING_PAD_FRAMES: usize = 3;
 
-/// Trim leading and trailing silence from audio samples
-pub fn trim_silence(samples: &[f32]) -> &[f32] {
+/// Default RMS level above which a frame counts as speech
+pub const DEFAULT_ENERGY_THRESHOLD: f32 = 0.005;
+/// Default audio kept before the first speech frame (2 frames)
+pub const DEFAULT_PREROLL_MS: u32 = 60;
+
+fn rms(frame: &[f32]) -> f32 {
+    (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt()
+}
+
+/// Trim leading and trailing silence from 16kHz audio samples
+pub fn trim_silence<'a>(samples: &'a [f32], audio: &AudioSettings) -> &'a [f32] {
     if samples.is_empty() {
         return samples;
     }
 
-    let frame_energies: Vec<f32> = samples
-        .chunks(FRAME_SIZE)
-        .map(|frame| {
-            (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt()
-        })
-        .collect();
+    let frame_energies: Vec<f32> = samples.chunks(FRAME_SIZE).map(rms).collect();
 
     // Find first frame with speech
     let start_frame = frame_energies
         .iter()
-        .position(|&e| e > ENERGY_THRESHOLD)
+        .position(|&e| e > audio.vad_threshold)
         .unwrap_or(0);
 
     // Find last frame with speech
     let end_frame = frame_energies
         .iter()
-        .rposition(|&e| e > ENERGY_THRESHOLD)
+        .rposition(|&e| e > audio.vad_threshold)
         .unwrap_or(frame_energies.len().saturating_sub(1));
 
     // Require minimum speech duration
@@ -35,22 +43,161 @@ pub fn trim_silence(samples: &[f32]) -> &[f32] {
         return samples;
     }
 
-    // Add small padding (2 frames) around speech
-    let start_sample = start_frame.saturating_sub(2) * FRAME_SIZE;
-    let end_sample = ((end_frame + 3) * FRAME_SIZE).min(samples.len());
+    // Keep the pre-roll before speech and a little padding after it
+    let preroll_samples = audio.preroll_ms as usize * SAMPLE_RATE / 1000;
+    let start_sample = (start_frame * FRAME_SIZE).saturating_sub(preroll_samples);
+    let end_sample = ((end_frame + TRAILING_PAD_FRAMES) * FRAME_SIZE).min(samples.len());
 
     &samples[start_sample..end_sample]
 }
 
-/// Check if audio contains enough speech to be worth transcribing
-pub fn has_speech(samples: &[f32]) -> bool {
+/// Check if 16kHz audio contains enough speech to be worth transcribing
+pub fn has_speech(samples: &[f32], audio: &AudioSettings) -> bool {
     let speech_frames = samples
         .chunks(FRAME_SIZE)
-        .filter(|frame| {
-            let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
-            rms > ENERGY_THRESHOLD
-        })
+        .filter(|frame| rms(frame) > audio.vad_threshold)
         .count();
 
     speech_frames >= MIN_SPEECH_FRAMES
 }
+
+/// Scale samples so the loudest one is at full level. Silent input is left as is.
+pub fn normalize(samples: &[f32]) -> Vec<f32> {
+    let peak = samples.iter().map(|s| s.abs()).fold(0.0f32, f32::max);
+    if peak <= f32::EPSILON {
+        return samples.to_vec();
+    }
+    samples.iter().map(|s| s / peak).collect()
+}
+
+/// Tracks trailing silence in a live stream at any sample rate, for auto-stop.
+pub struct SilenceTracker {
+    threshold: f32,
+    frame_size: usize,
+    stop_after_frames: usize,
+    heard_speech: bool,
+    silent_frames: usize,
+    pending: Vec<f32>,
+}
+
+impl SilenceTracker {
+    /// Returns None if silence auto-stop is disabled
+    pub fn new(audio: &AudioSettings, sample_rate: u32) -> Option<Self> {
+        if audio.silence_auto_stop_ms == 0 {
+            return None;
+        }
+        Some(Self {
+            threshold: audio.vad_threshold,
+            frame_size: (sample_rate as usize * FRAME_MS as usize / 1000).max(1),
+            stop_after_frames: (audio.silence_auto_stop_ms / FRAME_MS) as usize,
+            heard_speech: false,
+            silent_frames: 0,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Feed mono samples; returns true once speech has been followed by
+    /// enough continuous silence.
+    pub fn push(&mut self, samples: &[f32]) -> bool {
+        self.pending.extend_from_slice(samples);
+        let frames = self.pending.len() / self.frame_size;
+        for frame in self.pending.chunks(self.frame_size).take(frames) {
+            if rms(frame) > self.threshold {
+                self.heard_speech = true;
+                self.silent_frames = 0;
+            } else if self.heard_speech {
+                self.silent_frames += 1;
+            }
+        }
+        self.pending.drain(..frames * self.frame_size);
+
+        self.heard_speech && self.silent_frames >= self.stop_after_frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `frames` 30ms frames of a constant level
+    fn frames(level: f32, frames: usize) -> Vec<f32> {
+        vec![level; frames * FRAME_SIZE]
+    }
+
+    /// 1s of silence, `speech_frames` of speech, then 1s of silence
+    fn utterance(speech_frames: usize) -> Vec<f32> {
+        [frames(0.0, 33), frames(0.1, speech_frames), frames(0.0, 33)].concat()
+    }
+
+    /// Trimming as it was hardcoded before the audio settings existed
+    fn trim_before_settings(samples: &[f32]) -> &[f32] {
+        let energies: Vec<f32> = samples.chunks(FRAME_SIZE).map(rms).collect();
+        let start = energies.iter().position(|&e| e > 0.005).unwrap_or(0);
+        let end = energies.iter().rposition(|&e| e > 0.005).unwrap_or(energies.len() - 1);
+        if end - start < MIN_SPEECH_FRAMES {
+            return samples;
+        }
+        &samples[start.saturating_sub(2) * FRAME_SIZE..((end + 3) * FRAME_SIZE).min(samples.len())]
+    }
+
+    #[test]
+    fn defaults_trim_like_the_old_hardcoded_values() {
+        let defaults = AudioSettings::default();
+        for samples in [utterance(20), utterance(5), frames(0.1, 40), frames(0.0, 40)] {
+            assert_eq!(trim_silence(&samples, &defaults), trim_before_settings(&samples));
+        }
+        assert!(has_speech(&utterance(10), &defaults));
+        assert!(!has_speech(&utterance(9), &defaults));
+        assert!(!has_speech(&frames(0.004, 40), &defaults));
+    }
+
+    #[test]
+    fn threshold_and_preroll_come_from_the_settings() {
+        let audio = AudioSettings {
+            vad_threshold: 0.2,
+            preroll_ms: 300,
+            ..Default::default()
+        };
+        assert!(!has_speech(&utterance(20), &audio));
+
+        let audio = AudioSettings {
+            preroll_ms: 300,
+            ..Default::default()
+        };
+        let samples = utterance(20);
+        let trimmed = trim_silence(&samples, &audio);
+        // 10 frames of pre-roll, 20 of speech and 2 of trailing padding
+        assert_eq!(trimmed.len(), 32 * FRAME_SIZE);
+    }
+
+    #[test]
+    fn normalize_scales_to_full_level() {
+        assert_eq!(normalize(&[0.25, -0.5, 0.1]), [0.5, -1.0, 0.2]);
+        assert_eq!(normalize(&[0.0, 0.0]), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn silence_tracker_stops_only_after_speech() {
+        let off = AudioSettings::default();
+        assert!(SilenceTracker::new(&off, 48_000).is_none());
+
+        let audio = AudioSettings {
+            silence_auto_stop_ms: 600,
+            ..Default::default()
+        };
+        // 30ms frames at 48kHz are 1440 samples
+        let mut tracker = SilenceTracker::new(&audio, 48_000).unwrap();
+        assert!(!tracker.push(&vec![0.0; 1440 * 40]), "silence before speech doesn't count");
+        assert!(!tracker.push(&vec![0.1; 1440 * 5]));
+        assert!(!tracker.push(&vec![0.0; 1440 * 19 + 1000]));
+        // The 20th silent frame completes across pushes
+        assert!(tracker.push(&[0.0; 440]));
+
+        // Speech resets the count
+        let mut tracker = SilenceTracker::new(&audio, 48_000).unwrap();
+        tracker.push(&vec![0.1; 1440]);
+        tracker.push(&vec![0.0; 1440 * 19]);
+        assert!(!tracker.push(&vec![0.1; 1440]));
+        assert!(!tracker.push(&vec![0.0; 1440 * 19]));
+    }
+}
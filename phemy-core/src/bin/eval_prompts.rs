@@ -0,0 +1,210 @@
+//! Run eval cases through the prompt optimizer and report how each output fares.
+//!
+//! Usage:
+//!   eval_prompts <cases.jsonl> [--modes clean,code] [--models name,name]
+//!                [--data-dir DIR] [--out PREFIX]
+//!
+//! Writes PREFIX.json and PREFIX.md (default prefix: eval-report).
+
+use std::path::PathBuf;
+use std::time::Instant;
+
+use phemy_core::llm::{eval, prompt_optimizer};
+use phemy_core::settings::{self, LlmSettings, PromptMode, Settings, DEFAULT_LLM_MODEL};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct CaseReport {
+    id: String,
+    mode: String,
+    model: String,
+    passed: bool,
+    elapsed_ms: u64,
+    /// Summed over the LLM calls for the case, from the model's tokenizer
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    output_chars: usize,
+    output_words: usize,
+    output: String,
+    checks: Vec<eval::CheckResult>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    total: usize,
+    passed: usize,
+    cases: Vec<CaseReport>,
+}
+
+struct Args {
+    cases: PathBuf,
+    modes: Vec<PromptMode>,
+    models: Vec<String>,
+    data_dir: Option<PathBuf>,
+    out: String,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let mut cases = None;
+    let mut modes = vec![PromptMode::Clean];
+    let mut models = vec![Settings::default()
+        .llm
+        .model
+        .unwrap_or_else(|| DEFAULT_LLM_MODEL.to_string())];
+    let mut data_dir = None;
+    let mut out = "eval-report".to_string();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--modes" => {
+                modes = value()?
+                    .split(',')
+                    .map(|m| {
+                        serde_json::from_value(serde_json::Value::String(m.trim().to_string()))
+                            .map_err(|_| anyhow::anyhow!("Unknown prompt mode: {}", m))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "--models" => models = value()?.split(',').map(|m| m.trim().to_string()).collect(),
+            "--data-dir" => data_dir = Some(PathBuf::from(value()?)),
+            "--out" => out = value()?,
+            _ if cases.is_none() && !arg.starts_with("--") => cases = Some(PathBuf::from(arg)),
+            _ => anyhow::bail!("Unexpected argument: {}", arg),
+        }
+    }
+
+    Ok(Args {
+        cases: cases.ok_or_else(|| anyhow::anyhow!("Usage: eval_prompts <cases.jsonl> [options]"))?,
+        modes,
+        models,
+        data_dir,
+        out,
+    })
+}
+
+fn run() -> anyhow::Result<bool> {
+    let args = parse_args()?;
+    if let Some(dir) = args.data_dir {
+        settings::set_data_dir(dir);
+    }
+
+    let cases = eval::parse_cases(&std::fs::read_to_string(&args.cases)?)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut reports = Vec::new();
+
+    for model in &args.models {
+        for mode in &args.modes {
+            let settings = Settings {
+                prompt_mode: mode.clone(),
+                llm: LlmSettings {
+                    model: Some(model.clone()),
+                    ..LlmSettings::default()
+                },
+                ..Settings::default()
+            };
+
+            for case in &cases {
+                let started = Instant::now();
+                let result = runtime.block_on(prompt_optimizer::optimize(&case.transcript, &settings, None))?;
+                let elapsed_ms = started.elapsed().as_millis() as u64;
+
+                let mut checks = eval::run_checks(case, &result.optimized_prompt);
+                if let Some(error) = &result.error {
+                    checks.push(eval::CheckResult {
+                        check: format!("optimizer succeeded ({})", error),
+                        passed: false,
+                    });
+                }
+                let passed = checks.iter().all(|c| c.passed);
+
+                println!(
+                    "[{}] {} / {:?} / {} ({}ms)",
+                    if passed { "PASS" } else { "FAIL" },
+                    case.id,
+                    mode,
+                    model,
+                    elapsed_ms
+                );
+
+                reports.push(CaseReport {
+                    id: case.id.clone(),
+                    mode: result.mode,
+                    model: model.clone(),
+                    passed,
+                    elapsed_ms,
+                    prompt_tokens: result.usage.prompt_tokens,
+                    completion_tokens: result.usage.completion_tokens,
+                    output_chars: result.optimized_prompt.chars().count(),
+                    output_words: result.optimized_prompt.split_whitespace().count(),
+                    output: result.optimized_prompt,
+                    checks,
+                });
+            }
+        }
+    }
+
+    let report = Report {
+        total: reports.len(),
+        passed: reports.iter().filter(|r| r.passed).count(),
+        cases: reports,
+    };
+
+    std::fs::write(format!("{}.json", args.out), serde_json::to_string_pretty(&report)?)?;
+    std::fs::write(format!("{}.md", args.out), to_markdown(&report))?;
+    println!(
+        "{}/{} passed — report written to {}.json and {}.md",
+        report.passed, report.total, args.out, args.out
+    );
+
+    Ok(report.passed == report.total)
+}
+
+fn to_markdown(report: &Report) -> String {
+    let mut md = format!(
+        "# Prompt eval report\n\n{}/{} cases passed\n\n\
+         | Case | Mode | Model | Result | Time (ms) | Prompt tokens | Completion tokens | Words |\n\
+         |---|---|---|---|---|---|---|---|\n",
+        report.passed, report.total
+    );
+    for case in &report.cases {
+        md.push_str(&format!(
+            "| {} | {} | {} | {} | {} | {} | {} | {} |\n",
+            case.id,
+            case.mode,
+            case.model,
+            if case.passed { "pass" } else { "FAIL" },
+            case.elapsed_ms,
+            case.prompt_tokens,
+            case.completion_tokens,
+            case.output_words
+        ));
+    }
+
+    for case in report.cases.iter().filter(|c| !c.passed) {
+        md.push_str(&format!("\n## {} ({} / {})\n\n", case.id, case.mode, case.model));
+        for check in case.checks.iter().filter(|c| !c.passed) {
+            md.push_str(&format!("- failed: {}\n", check.check));
+        }
+        md.push_str(&format!("\n```\n{}\n```\n", case.output));
+    }
+
+    md
+}
+
+fn main() {
+    let _ = env_logger::try_init();
+
+    match run() {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("eval_prompts failed: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
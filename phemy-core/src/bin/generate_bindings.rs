@@ -0,0 +1,14 @@
+//! Generate Swift and Kotlin bindings for the UniFFI interface from a built
+//! library. Build with the `uniffi` feature first, then e.g.
+//!
+//!     cargo build --release --features uniffi
+//!     cargo run --features uniffi --bin generate-bindings -- generate \
+//!         --library target/release/libphemy_core.dylib \
+//!         --language swift --language kotlin --out-dir bindings
+//!
+//! Generation reads the interface from the library's metadata, so it needs
+//! no UDL file and works the same in CI as locally.
+
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
@@ -0,0 +1,70 @@
+//! Manual paste test. Focus a text field within the countdown; each paste
+//! method then pastes a marker line into it.
+//!
+//!     cargo run --features paste-harness --bin paste_harness [method ...]
+//!
+//! Methods are the settings names (ctrl-v, ctrl-shift-v, shift-insert,
+//! type-out, middle-click); the default is every method this platform
+//! supports. Run it once per keyboard layout in this matrix, switching the
+//! layout in the OS first:
+//!
+//! | Layout          | macOS | Windows | Linux X11 | Linux Wayland |
+//! |-----------------|-------|---------|-----------|---------------|
+//! | US QWERTY       |       |         |           |               |
+//! | French AZERTY   |       |         |           |               |
+//! | German QWERTZ   |       |         |           |               |
+//! | Dvorak          |       |         |           |               |
+//! | Russian (ЙЦУКЕН)|       |         |           |               |
+//!
+//! A cell passes when every marker line appears exactly once, with no stray
+//! "v", no other shortcut triggered, and the previous clipboard restored.
+//!
+//! Also check once per platform, with a clipboard history manager running
+//! (Maccy on macOS, Win+V history on Windows, Klipper or CopyQ on Linux),
+//! that no marker line shows up in its history. `clipboard_transient` is on
+//! by default.
+
+use std::time::Duration;
+
+use phemy_core::clipboard::paste::paste_via_clipboard;
+use phemy_core::settings::{PasteMethod, Settings};
+
+const COUNTDOWN_SECS: u64 = 3;
+
+fn main() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+
+    let methods: Vec<PasteMethod> = match std::env::args().skip(1).collect::<Vec<_>>() {
+        args if args.is_empty() => PasteMethod::ALL
+            .into_iter()
+            .filter(PasteMethod::is_supported)
+            .collect(),
+        args => args
+            .iter()
+            .map(|name| {
+                serde_json::from_value(serde_json::Value::String(name.clone()))
+                    .map_err(|_| anyhow::anyhow!("Unknown paste method: {}", name))
+            })
+            .collect::<anyhow::Result<_>>()?,
+    };
+
+    for secs in (1..=COUNTDOWN_SECS).rev() {
+        println!("Focus a text field... {}", secs);
+        std::thread::sleep(Duration::from_secs(1));
+    }
+
+    for method in methods {
+        let settings = Settings {
+            paste_method: method.clone(),
+            ..Settings::default()
+        };
+        let marker = format!("phemy paste test {:?}: àé ß 日本 v\n", method);
+        match paste_via_clipboard(&marker, &settings, None) {
+            Ok(outcome) => println!("{:?}: {}", method, serde_json::to_string(&outcome)?),
+            Err(e) => println!("{:?}: FAILED {}", method, e),
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    Ok(())
+}
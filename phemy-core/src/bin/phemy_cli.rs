@@ -0,0 +1,543 @@
+//! Headless front end over the Rust API (`phemy_core::api`), for
+//! development and for running phemy on machines without a UI.
+//!
+//!     cargo run --features cli --bin phemy-cli -- [--json] <command>
+//!
+//! Results go to stdout as plain text and tables, or as JSON with `--json`;
+//! progress and errors go to stderr (errors as `{ "error": { "code",
+//! "message" } }` on stdout with `--json`). Exits with 0 on success, 1 if
+//! the command failed and 2 on bad arguments.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use phemy_core::api::{self, Handle};
+use phemy_core::db::{HistoryEntry, SearchResult};
+use phemy_core::ffi::{error_code, error_value, ErrorCode};
+use phemy_core::llm::llm_model_manager::{self, LlmModelInfo};
+use phemy_core::settings::PromptMode;
+use phemy_core::transcription::model_manager::{self, WhisperModel};
+use phemy_core::PipelineOptions;
+use serde_json::json;
+
+/// History entries fetched per query by `history export`
+const EXPORT_PAGE: usize = 500;
+
+/// Longest text shown in a history table cell
+const CELL_CHARS: usize = 60;
+
+#[derive(Parser)]
+#[command(name = "phemy-cli", version, about = "Run phemy without a UI")]
+struct Cli {
+    /// Print JSON instead of text and tables
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Directory for settings, models and history [default: the platform
+    /// data directory]
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Keep history in memory, so nothing is saved between runs
+    #[arg(long, global = true)]
+    in_memory: bool,
+
+    /// Record from a fake microphone playing this WAV file instead of the
+    /// system's devices
+    #[cfg(feature = "mock-audio")]
+    #[arg(long, global = true, value_name = "FILE")]
+    mock_mic: Option<PathBuf>,
+
+    /// Log to stderr: -v warnings, -vv info, -vvv debug
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List audio input devices
+    Devices,
+    /// List, download or delete whisper and LLM models
+    #[command(subcommand)]
+    Models(ModelsCommand),
+    /// Record from the microphone
+    Record {
+        /// How long to record; recording also stops at the configured
+        /// maximum duration or silence auto-stop
+        #[arg(long, default_value_t = 5.0)]
+        seconds: f64,
+        /// Input device name [default: the system default]
+        #[arg(long)]
+        device: Option<String>,
+        /// Save the recording as a WAV file
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Transcribe a WAV file
+    Transcribe { file: PathBuf },
+    /// Turn text into a prompt with the LLM
+    Optimize {
+        /// Prompt mode instead of the saved one (clean, code, formal, ...)
+        #[arg(long, value_parser = parse_mode)]
+        mode: Option<PromptMode>,
+        /// Application the prompt is for, as `{{app}}` in custom prompts
+        #[arg(long)]
+        app: Option<String>,
+        text: String,
+    },
+    /// List, search or export history
+    #[command(subcommand)]
+    History(HistoryCommand),
+    /// Transcribe a WAV file, optimize the transcript and save the result to
+    /// history, as stopping a recording does
+    Pipeline {
+        file: PathBuf,
+        /// Prompt mode instead of the saved one
+        #[arg(long)]
+        mode: Option<String>,
+        /// Spoken language code, or "auto", instead of the saved one
+        #[arg(long)]
+        language: Option<String>,
+        /// Skip the LLM and return the transcript
+        #[arg(long)]
+        raw: bool,
+        /// Don't save the result to history
+        #[arg(long)]
+        no_history: bool,
+        /// Include stage timings (with --json)
+        #[arg(long)]
+        metrics: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModelsCommand {
+    /// List whisper and LLM models and whether they are downloaded
+    List,
+    /// Download a model by name
+    Download { name: String },
+    /// Delete a downloaded model
+    Delete { name: String },
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// Newest entries first
+    List {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+    },
+    /// Full-text search, best match first
+    Search {
+        query: String,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Write every entry as a JSON array
+    Export {
+        /// File to write [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+fn parse_mode(name: &str) -> Result<PromptMode, String> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+        .map_err(|_| format!("unknown prompt mode '{}'", name))
+}
+
+/// What a command prints: `text` normally, `json` with --json
+struct Report {
+    text: String,
+    json: serde_json::Value,
+}
+
+impl Report {
+    fn new(value: &impl serde::Serialize, text: impl Into<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            text: text.into(),
+            json: serde_json::to_value(value)?,
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(report) if cli.json => {
+            println!("{}", serde_json::to_string_pretty(&report.json).unwrap_or_default());
+            ExitCode::SUCCESS
+        }
+        Ok(report) => {
+            if !report.text.is_empty() {
+                println!("{}", report.text);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) if cli.json => {
+            let code = error_code(&e, ErrorCode::Processing);
+            println!("{}", json!({ "error": error_value(code, &format!("{:#}", e)) }));
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> anyhow::Result<Report> {
+    let phemy = api::init(api::Config {
+        data_dir: cli.data_dir.clone(),
+        in_memory: cli.in_memory,
+        db_key: None,
+        // One command at a time needs no thread pool
+        runtime: api::RuntimeConfig {
+            current_thread: true,
+            ..Default::default()
+        },
+    })?;
+    if cli.verbose > 0 {
+        phemy_core::logging::set_level(phemy_core::logging::level_filter(1 + cli.verbose as i32));
+    }
+    #[cfg(feature = "mock-audio")]
+    if let Some(path) = &cli.mock_mic {
+        let device = phemy_core::audio::mock::MockDevice::wav("Mock Mic", path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        phemy_core::audio::mock::install(vec![device]);
+    }
+    let report = execute(&phemy, cli);
+    // Entries are written in the background; don't exit before they are
+    phemy.flush_history();
+    phemy.shutdown();
+    report
+}
+
+fn execute(phemy: &Handle, cli: &Cli) -> anyhow::Result<Report> {
+    match &cli.command {
+        Command::Devices => devices(phemy),
+        Command::Models(command) => models(phemy, command, cli.json),
+        Command::Record { seconds, device, output } => record(phemy, *seconds, device.as_deref(), output.as_deref()),
+        Command::Transcribe { file } => {
+            let (samples, sample_rate) = read_wav(file)?;
+            let result = phemy.transcribe(&samples, sample_rate)?;
+            Report::new(&result, result.text.clone())
+        }
+        Command::Optimize { mode, app, text } => {
+            let result = match mode {
+                Some(mode) => phemy.optimize_in_mode(text, mode.clone(), app.as_deref())?,
+                None => phemy.optimize(text, None, app.as_deref())?,
+            };
+            Report::new(&result, result.optimized_prompt.clone())
+        }
+        Command::History(command) => history(phemy, command),
+        Command::Pipeline { file, mode, language, raw, no_history, metrics } => {
+            let (samples, sample_rate) = read_wav(file)?;
+            let options = PipelineOptions {
+                skip_optimization: *raw,
+                skip_history: *no_history,
+                mode: mode.clone(),
+                language: language.clone(),
+                include_metrics: *metrics,
+                ..Default::default()
+            };
+            let result = phemy.process_samples(&samples, sample_rate, &options)?;
+            if let Some(llm_error) = &result.llm_error {
+                eprintln!("LLM failed, showing the transcript: {}", llm_error);
+            }
+            Report::new(&result, result.optimized_prompt.clone())
+        }
+    }
+}
+
+fn read_wav(path: &Path) -> anyhow::Result<(Vec<f32>, u32)> {
+    phemy_core::utils::read_wav(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+fn devices(phemy: &Handle) -> anyhow::Result<Report> {
+    let devices = phemy.list_audio_devices()?;
+    let text = if devices.is_empty() {
+        "No audio input devices.".to_string()
+    } else {
+        let rows = devices
+            .iter()
+            .map(|d| vec![d.name.clone(), if d.is_default { "yes" } else { "" }.to_string()])
+            .collect::<Vec<_>>();
+        table(&["NAME", "DEFAULT"], &rows)
+    };
+    Report::new(&devices, text)
+}
+
+fn models(phemy: &Handle, command: &ModelsCommand, json: bool) -> anyhow::Result<Report> {
+    match command {
+        ModelsCommand::List => {
+            let whisper = phemy.whisper_models()?;
+            let llm = phemy.llm_models()?;
+            Report::new(&json!({ "whisper": whisper, "llm": llm }), models_table(&whisper, &llm))
+        }
+        ModelsCommand::Download { name } => {
+            let mut last_percent = None;
+            let progress = |_: u64, _: u64, fraction: f64| {
+                let percent = (fraction * 100.0) as u32;
+                if !json && last_percent != Some(percent) {
+                    last_percent = Some(percent);
+                    eprint!("\rDownloading {}: {}%", name, percent);
+                }
+            };
+            let result = if model_manager::is_known_model(name) {
+                phemy.download_whisper_model(name, progress)
+            } else if llm_model_manager::is_known_model(name) {
+                phemy.download_llm_model(name, progress)
+            } else {
+                anyhow::bail!("Unknown model: {}", name);
+            };
+            if !json && last_percent.is_some() {
+                eprintln!();
+            }
+            result?;
+            Report::new(&json!({ "model": name, "downloaded": true }), format!("Downloaded {}", name))
+        }
+        ModelsCommand::Delete { name } => {
+            if model_manager::is_known_model(name) {
+                phemy.delete_whisper_model(name)?;
+            } else if llm_model_manager::is_known_model(name) {
+                phemy.delete_llm_model(name)?;
+            } else {
+                anyhow::bail!("Unknown model: {}", name);
+            }
+            Report::new(&json!({ "model": name, "deleted": true }), format!("Deleted {}", name))
+        }
+    }
+}
+
+fn models_table(whisper: &[WhisperModel], llm: &[LlmModelInfo]) -> String {
+    let row = |kind: &str, name: &str, size_mb: u64, downloaded: bool| {
+        vec![
+            kind.to_string(),
+            name.to_string(),
+            format!("{} MB", size_mb),
+            if downloaded { "yes" } else { "no" }.to_string(),
+        ]
+    };
+    let rows = whisper
+        .iter()
+        .map(|m| row("whisper", &m.name, m.size_mb, m.downloaded))
+        .chain(llm.iter().map(|m| row("llm", &m.name, m.size_mb, m.downloaded)))
+        .collect::<Vec<_>>();
+    table(&["KIND", "NAME", "SIZE", "DOWNLOADED"], &rows)
+}
+
+fn record(phemy: &Handle, seconds: f64, device: Option<&str>, output: Option<&Path>) -> anyhow::Result<Report> {
+    let duration = Duration::try_from_secs_f64(seconds).context("--seconds must be a positive number")?;
+    phemy.start_recording(device)?;
+    let started = Instant::now();
+    while started.elapsed() < duration && !phemy.recording_should_stop() {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let (samples, sample_rate) = phemy.stop_recording()?;
+
+    if let Some(path) = output {
+        let wav = phemy_core::utils::samples_to_wav(&samples, sample_rate)?;
+        std::fs::write(path, wav).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    let duration_secs = samples.len() as f64 / sample_rate as f64;
+    let mut text = format!("Recorded {:.1} s at {} Hz", duration_secs, sample_rate);
+    if let Some(path) = output {
+        text.push_str(&format!(" to {}", path.display()));
+    }
+    let summary = json!({
+        "duration_secs": duration_secs,
+        "sample_rate": sample_rate,
+        "samples": samples.len(),
+        "output": output,
+    });
+    Report::new(&summary, text)
+}
+
+fn history(phemy: &Handle, command: &HistoryCommand) -> anyhow::Result<Report> {
+    match command {
+        HistoryCommand::List { limit, offset } => {
+            let entries = phemy.history(*limit, *offset)?;
+            Report::new(&entries, history_table(&entries))
+        }
+        HistoryCommand::Search { query, limit } => {
+            let results = phemy.search_history(query, *limit, 0)?;
+            Report::new(&results, search_table(&results))
+        }
+        HistoryCommand::Export { output } => {
+            let mut entries = Vec::new();
+            loop {
+                let page = phemy.history(EXPORT_PAGE, entries.len())?;
+                let done = page.len() < EXPORT_PAGE;
+                entries.extend(page);
+                if done {
+                    break;
+                }
+            }
+            let exported = serde_json::to_string_pretty(&entries)?;
+            let summary = json!({ "entries": entries.len(), "output": output });
+            match output {
+                Some(path) => {
+                    std::fs::write(path, exported).with_context(|| format!("Failed to write {}", path.display()))?;
+                    Report::new(&summary, format!("Exported {} entries to {}", entries.len(), path.display()))
+                }
+                // The export is the output either way
+                None => Ok(Report {
+                    json: serde_json::from_str(&exported)?,
+                    text: exported,
+                }),
+            }
+        }
+    }
+}
+
+fn history_table(entries: &[HistoryEntry]) -> String {
+    if entries.is_empty() {
+        return "No history entries.".to_string();
+    }
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            let text = entry.optimized_prompt.as_deref().unwrap_or(&entry.raw_transcript);
+            vec![
+                short_id(&entry.id),
+                entry.created_at.clone(),
+                entry.prompt_mode.clone(),
+                cell(text),
+            ]
+        })
+        .collect::<Vec<_>>();
+    table(&["ID", "CREATED", "MODE", "TEXT"], &rows)
+}
+
+fn search_table(results: &[SearchResult]) -> String {
+    if results.is_empty() {
+        return "No matches.".to_string();
+    }
+    let rows = results
+        .iter()
+        .map(|result| {
+            vec![
+                short_id(&result.entry.id),
+                result.entry.created_at.clone(),
+                format!("{:.2}", result.score),
+                cell(&result.snippet.text),
+            ]
+        })
+        .collect::<Vec<_>>();
+    table(&["ID", "CREATED", "SCORE", "MATCH"], &rows)
+}
+
+/// The first 8 characters of an entry id, enough to tell entries apart
+fn short_id(id: &str) -> String {
+    id.chars().take(8).collect()
+}
+
+/// `text` on one line, cut to CELL_CHARS
+fn cell(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= CELL_CHARS {
+        return line;
+    }
+    let cut: String = line.chars().take(CELL_CHARS - 3).collect();
+    format!("{}...", cut.trim_end())
+}
+
+/// Left-aligned columns two spaces apart, under a header row
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let header = headers.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+    std::iter::once(&header)
+        .chain(rows)
+        .map(|row| {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| format!("{:width$}", value, width = width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_table_lines_up_columns() {
+        let whisper = [
+            WhisperModel { name: "tiny".into(), size_mb: 75, downloaded: true },
+            WhisperModel { name: "large-v3".into(), size_mb: 3100, downloaded: false },
+        ];
+        let llm = [LlmModelInfo {
+            name: "qwen3-4b-instruct-q4km".into(),
+            size_mb: 2700,
+            downloaded: false,
+            description: "Qwen3 4B".into(),
+        }];
+        assert_eq!(
+            models_table(&whisper, &llm),
+            "\
+KIND     NAME                    SIZE     DOWNLOADED
+whisper  tiny                    75 MB    yes
+whisper  large-v3                3100 MB  no
+llm      qwen3-4b-instruct-q4km  2700 MB  no"
+        );
+    }
+
+    #[test]
+    fn history_table_shows_one_line_per_entry() {
+        let entry = |id: &str, prompt: Option<&str>, raw: &str| -> HistoryEntry {
+            serde_json::from_value(json!({
+                "id": id,
+                "raw_transcript": raw,
+                "optimized_prompt": prompt,
+                "prompt_mode": "clean",
+                "duration_secs": 2.5,
+                "created_at": "2026-10-17T09:30:00Z",
+            }))
+            .unwrap()
+        };
+        let entries = [
+            entry("3f2a9c1e-0000-4000-8000-000000000000", Some("Fix the login bug."), "fix the uh login bug"),
+            entry(
+                "b71d04aa-0000-4000-8000-000000000000",
+                None,
+                "a raw transcript that runs on\nover two lines and well past the width of one table cell",
+            ),
+        ];
+        assert_eq!(
+            history_table(&entries),
+            "\
+ID        CREATED               MODE   TEXT
+3f2a9c1e  2026-10-17T09:30:00Z  clean  Fix the login bug.
+b71d04aa  2026-10-17T09:30:00Z  clean  a raw transcript that runs on over two lines and well pas..."
+        );
+        assert_eq!(history_table(&[]), "No history entries.");
+    }
+
+    #[test]
+    fn bad_arguments_are_rejected_before_running() {
+        assert!(Cli::try_parse_from(["phemy-cli", "optimize", "--mode", "poetic", "text"]).is_err());
+        assert!(Cli::try_parse_from(["phemy-cli", "models"]).is_err());
+        let cli = Cli::try_parse_from(["phemy-cli", "--json", "history", "list", "--limit", "5"]).unwrap();
+        assert!(cli.json);
+        assert!(matches!(cli.command, Command::History(HistoryCommand::List { limit: 5, offset: 0 })));
+    }
+}
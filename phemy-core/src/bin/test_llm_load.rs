@@ -1,4 +1,5 @@
 fn main() {
+    #[cfg(feature = "llm-local")]
     let model_path = format!(
         "{}/Library/Application Support/com.labgarge.phemy/models/llm/qwen2.5-1.5b-instruct-q4_k_m.gguf",
         std::env::var("HOME").unwrap()
@@ -0,0 +1,48 @@
+//! Which window has keyboard focus, so a long TypeOut can stop when the
+//! user switches away instead of typing the rest into the wrong app.
+
+/// An opaque token for the focused window or app, equal between two calls
+/// only if focus hasn't moved. None where we can't tell (Wayland, missing
+/// helpers), in which case callers should skip the check.
+#[cfg(target_os = "windows")]
+pub fn focused_window() -> Option<String> {
+    #[link(name = "user32")]
+    extern "system" {
+        fn GetForegroundWindow() -> *mut std::ffi::c_void;
+    }
+
+    let hwnd = unsafe { GetForegroundWindow() };
+    (!hwnd.is_null()).then(|| format!("{:p}", hwnd))
+}
+
+/// `lsappinfo front` prints the frontmost app's ASN, e.g. `ASN:0x0-0x1c01c:`
+#[cfg(target_os = "macos")]
+pub fn focused_window() -> Option<String> {
+    let output = std::process::Command::new("lsappinfo")
+        .arg("front")
+        .output()
+        .ok()?;
+    let asn = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !asn.is_empty()).then_some(asn)
+}
+
+/// X11 only, via `xprop`: `_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007`
+#[cfg(target_os = "linux")]
+pub fn focused_window() -> Option<String> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        return None;
+    }
+    let output = std::process::Command::new("xprop")
+        .args(["-root", "_NET_ACTIVE_WINDOW"])
+        .output()
+        .ok()?;
+    let line = String::from_utf8_lossy(&output.stdout);
+    let (_, id) = line.trim().split_once('#')?;
+    let id = id.trim();
+    (output.status.success() && !id.is_empty()).then(|| id.to_string())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+pub fn focused_window() -> Option<String> {
+    None
+}
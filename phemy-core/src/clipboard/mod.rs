@@ -1 +1,6 @@
+pub mod focus;
 pub mod paste;
+pub mod secure_input;
+pub mod worker;
+#[cfg(all(feature = "wayland", target_os = "linux"))]
+pub mod wayland;
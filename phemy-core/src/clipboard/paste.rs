@@ -1,110 +1,1223 @@
 use anyhow::Result;
+use arboard::{Clipboard, ImageData, Set};
 use enigo::{Direction, Enigo, Key, Keyboard, Settings as EnigoSettings};
+#[cfg(target_os = "linux")]
+use enigo::{Button, Mouse};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::Mutex;
 use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::settings::PasteMethod;
+use crate::settings::{Hotkey, HotkeyModifier, PasteMethod, PostProcessAction, Settings, SubmitKey};
+
+/// Text of the last paste, after post-processing
+static LAST_PASTED: Mutex<Option<String>> = Mutex::new(None);
+
+/// What happened during a paste
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PasteOutcome {
+    /// A paste keystroke was sent or the text was typed out
+    pub attempted: bool,
+    pub method_used: PasteMethod,
+    /// The keystroke was sent a second time by `paste_verify`
+    pub retried: bool,
+    /// The previous clipboard contents were put back. False if
+    /// `restore_clipboard` is off, there was nothing we could read (empty,
+    /// or a format we can't copy) or the restore failed; the clipboard then
+    /// still holds the pasted text.
+    pub clipboard_restored: bool,
+    /// Keystrokes were blocked (macOS secure input), so the text was only
+    /// put on the clipboard for the user to paste manually
+    pub clipboard_only: bool,
+    /// Extra attempts made because another process held the clipboard
+    pub clipboard_retries: u32,
+    /// Why a TypeOut stopped part way through; the characters already
+    /// typed stay and the submit key is not pressed
+    pub aborted: Option<PasteAbort>,
+}
+
+impl PasteOutcome {
+    /// The text went into the target app: not blocked by secure input and
+    /// not cut short
+    pub fn succeeded(&self) -> bool {
+        !self.clipboard_only && self.aborted.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PasteAbort {
+    /// `cancel_paste` was called
+    Cancelled,
+    /// Another window took focus between chunks
+    FocusChanged,
+}
+
+/// Tries per clipboard operation while another process holds the clipboard
+const CLIPBOARD_ATTEMPTS: u32 = 5;
+const CLIPBOARD_RETRY_DELAY: Duration = Duration::from_millis(40);
+
+/// Run a clipboard operation, retrying while the clipboard is held by
+/// someone else (common on Windows). Other errors are returned at once.
+/// Retries made are added to `retries`.
+pub fn retry_if_busy<T>(
+    what: &str,
+    retries: &mut u32,
+    mut op: impl FnMut() -> Result<T, arboard::Error>,
+) -> Result<T, arboard::Error> {
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(arboard::Error::ClipboardOccupied) if attempt < CLIPBOARD_ATTEMPTS => {
+                log::info!(
+                    "Clipboard busy while {}, retrying ({}/{})",
+                    what,
+                    attempt,
+                    CLIPBOARD_ATTEMPTS - 1
+                );
+                attempt += 1;
+                *retries += 1;
+                std::thread::sleep(CLIPBOARD_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The clipboard operations a paste needs, so tests can stand in a fake
+trait ClipboardIo {
+    fn get_files(&mut self) -> Result<Vec<PathBuf>, arboard::Error>;
+    fn get_image(&mut self) -> Result<ImageData<'static>, arboard::Error>;
+    fn get_text(&mut self) -> Result<String, arboard::Error>;
+    fn get_html(&mut self) -> Result<String, arboard::Error>;
+    fn set_files(&mut self, files: &[PathBuf]) -> Result<(), arboard::Error>;
+    fn set_image(&mut self, image: ImageData<'_>) -> Result<(), arboard::Error>;
+    fn set_html(&mut self, html: &str, alt_text: Option<&str>) -> Result<(), arboard::Error>;
+    /// Set text, marked transient (see `mark_transient`) if `transient`
+    fn set_text(&mut self, text: &str, transient: bool) -> Result<(), arboard::Error>;
+}
+
+impl ClipboardIo for Clipboard {
+    fn get_files(&mut self) -> Result<Vec<PathBuf>, arboard::Error> {
+        self.get().file_list()
+    }
+
+    fn get_image(&mut self) -> Result<ImageData<'static>, arboard::Error> {
+        Clipboard::get_image(self)
+    }
+
+    fn get_text(&mut self) -> Result<String, arboard::Error> {
+        Clipboard::get_text(self)
+    }
+
+    fn get_html(&mut self) -> Result<String, arboard::Error> {
+        self.get().html()
+    }
+
+    fn set_files(&mut self, files: &[PathBuf]) -> Result<(), arboard::Error> {
+        self.set().file_list(files)
+    }
+
+    fn set_image(&mut self, image: ImageData<'_>) -> Result<(), arboard::Error> {
+        Clipboard::set_image(self, image)
+    }
+
+    fn set_html(&mut self, html: &str, alt_text: Option<&str>) -> Result<(), arboard::Error> {
+        Clipboard::set_html(self, html, alt_text)
+    }
+
+    fn set_text(&mut self, text: &str, transient: bool) -> Result<(), arboard::Error> {
+        let set = self.set();
+        let set = if transient { mark_transient(set) } else { set };
+        set.text(text)
+    }
+}
+
+/// Clipboard contents saved before pasting, in the richest form we can read
+enum ClipboardBackup {
+    Files(Vec<PathBuf>),
+    Image(ImageData<'static>),
+    Html { html: String, alt_text: Option<String> },
+    Text(String),
+    /// Empty, or only formats arboard can't read; restoring would clobber it
+    Unreadable,
+}
+
+impl ClipboardBackup {
+    fn capture(clipboard: &mut impl ClipboardIo, retries: &mut u32) -> Self {
+        let what = "backing up the clipboard";
+        if let Ok(files) = retry_if_busy(what, retries, || clipboard.get_files()) {
+            if !files.is_empty() {
+                return Self::Files(files);
+            }
+        }
+        if let Ok(image) = retry_if_busy(what, retries, || clipboard.get_image()) {
+            return Self::Image(image);
+        }
+        let text = retry_if_busy(what, retries, || clipboard.get_text()).ok();
+        if let Ok(html) = retry_if_busy(what, retries, || clipboard.get_html()) {
+            return Self::Html { html, alt_text: text };
+        }
+        text.map_or(Self::Unreadable, Self::Text)
+    }
+
+    /// Put the saved contents back. Returns whether anything was restored.
+    fn restore(self, clipboard: &mut impl ClipboardIo, retries: &mut u32) -> bool {
+        let what = "restoring the clipboard";
+        let result = match self {
+            Self::Files(files) => retry_if_busy(what, retries, || clipboard.set_files(&files)),
+            Self::Image(image) => retry_if_busy(what, retries, || {
+                clipboard.set_image(ImageData {
+                    width: image.width,
+                    height: image.height,
+                    bytes: Cow::Borrowed(&image.bytes),
+                })
+            }),
+            Self::Html { html, alt_text } => retry_if_busy(what, retries, || {
+                clipboard.set_html(&html, alt_text.as_deref())
+            }),
+            Self::Text(text) => retry_if_busy(what, retries, || clipboard.set_text(&text, false)),
+            Self::Unreadable => return false,
+        };
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("Failed to restore clipboard: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Stop a TypeOut paste that is in progress before its next chunk
+pub fn cancel_paste() {
+    crate::ops::cancel_kind(crate::ops::OpKind::Paste);
+}
+
+/// Split text into pieces of at most `chars_per_chunk` user-perceived
+/// characters (grapheme clusters), so an accent is never typed apart from
+/// its letter or an emoji sequence cut in two. 0 yields the whole text as
+/// one piece.
+pub fn typeout_chunks(text: &str, chars_per_chunk: usize) -> impl Iterator<Item = &str> {
+    let chars_per_chunk = if chars_per_chunk == 0 {
+        usize::MAX
+    } else {
+        chars_per_chunk
+    };
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest
+            .grapheme_indices(true)
+            .nth(chars_per_chunk)
+            .map_or(rest.len(), |(i, _)| i);
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+/// Type text in chunks using `type_chunk`, pausing `typeout_delay_ms`
+/// between them. Runs as a paste operation (see `crate::ops`) and stops
+/// early if that is cancelled or, where we can tell, focus moves to another
+/// window. `progress` gets (characters typed, total characters) after each
+/// chunk.
+pub(super) fn type_out(
+    text: &str,
+    settings: &Settings,
+    progress: Option<&dyn Fn(usize, usize)>,
+    mut type_chunk: impl FnMut(&str) -> Result<()>,
+) -> Result<Option<PasteAbort>> {
+    let op = crate::ops::start(crate::ops::OpKind::Paste, None);
+    let total = text.chars().count();
+    let mut typed = 0;
+    let focus = super::focus::focused_window();
+    for (i, chunk) in typeout_chunks(text, settings.typeout_chars_per_chunk).enumerate() {
+        if i > 0 {
+            std::thread::sleep(Duration::from_millis(settings.typeout_delay_ms));
+        }
+        if op.is_cancelled() {
+            log::info!("TypeOut cancelled after {}/{} characters", typed, total);
+            return Ok(Some(PasteAbort::Cancelled));
+        }
+        if let Some(focus) = &focus {
+            // A failed lookup is not a change
+            if super::focus::focused_window().is_some_and(|now| now != *focus) {
+                log::info!("TypeOut stopped after {}/{} characters: focus changed", typed, total);
+                return Ok(Some(PasteAbort::FocusChanged));
+            }
+        }
+        type_chunk(chunk)?;
+        typed += chunk.chars().count();
+        op.set_progress(typed as f64 / total as f64);
+        if let Some(progress) = progress {
+            progress(typed, total);
+        }
+    }
+    Ok(None)
+}
+
+/// Everything a paste will do, decided up front without touching the
+/// clipboard or the keyboard
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PastePlan {
+    /// The text that will be pasted, after `paste_postprocess`
+    pub text: String,
+    /// `paste_method` after `app_paste_overrides`, or Ctrl+V if the chosen
+    /// method isn't supported here
+    pub method: PasteMethod,
+    /// The submit key will be pressed after the paste
+    pub auto_submit: bool,
+    pub submit_key: SubmitKey,
+    /// `paste_postprocess` rules that changed the text, in the order they ran
+    pub rules_applied: Vec<&'static str>,
+}
+
+/// Decide how `text` would be pasted. `target_app` is the frontmost app as
+/// reported by the host; it selects an `app_paste_overrides` entry.
+/// `previous` is the text of the last paste (see `prepare_for_paste`).
+pub fn plan_paste(
+    text: &str,
+    settings: &Settings,
+    target_app: Option<&str>,
+    previous: Option<&str>,
+) -> PastePlan {
+    let mut method = settings.paste_method_for(target_app).clone();
+    if !method.is_supported() {
+        method = PasteMethod::CtrlV;
+    }
+    let (text, rules_applied) =
+        crate::postprocess::prepare_for_paste(text, &settings.paste_postprocess, previous);
+    let skip = method == PasteMethod::TypeOut && settings.auto_submit_skip_type_out;
+    PastePlan {
+        auto_submit: settings.auto_submit && !skip && !text.is_empty(),
+        submit_key: settings.submit_key.clone(),
+        text,
+        method,
+        rules_applied,
+    }
+}
+
+/// The plan `paste_and_maybe_submit` would follow right now
+pub fn preview_paste(text: &str, settings: &Settings, target_app: Option<&str>) -> PastePlan {
+    let last_pasted = LAST_PASTED.lock().unwrap_or_else(|e| e.into_inner());
+    plan_paste(text, settings, target_app, last_pasted.as_deref())
+}
+
+/// Paste text using the paste settings, then press the submit key if
+/// `auto_submit` is on. Multi-line text is pasted in one go, so only the
+/// final key press sends it. See `plan_paste` for how the text and method
+/// are chosen. `progress` is as in `type_out`.
+pub fn paste_and_maybe_submit(
+    text: &str,
+    settings: &Settings,
+    target_app: Option<&str>,
+    progress: Option<&dyn Fn(usize, usize)>,
+) -> Result<PasteOutcome> {
+    // Not held while pasting, so `preview_paste` doesn't wait on a TypeOut
+    let previous = LAST_PASTED.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let plan = plan_paste(text, settings, target_app, previous.as_deref());
+    execute_plan(&plan, settings, progress, || {
+        *LAST_PASTED.lock().unwrap_or_else(|e| e.into_inner()) = Some(plan.text.clone());
+    })
+}
+
+/// Carry out a plan. `delivered` is called once the text is in the target
+/// app, before the submit key is pressed.
+fn execute_plan(
+    plan: &PastePlan,
+    settings: &Settings,
+    progress: Option<&dyn Fn(usize, usize)>,
+    delivered: impl FnOnce(),
+) -> Result<PasteOutcome> {
+    let mut settings = settings.clone();
+    settings.paste_method = plan.method.clone();
+
+    let outcome = paste_via_clipboard(&plan.text, &settings, progress)?;
+    if !outcome.attempted || outcome.aborted.is_some() {
+        return Ok(outcome);
+    }
+    delivered();
+
+    if plan.auto_submit {
+        std::thread::sleep(Duration::from_millis(settings.submit_delay_ms));
+        #[cfg(all(feature = "wayland", target_os = "linux"))]
+        if super::wayland::is_wayland_session() {
+            super::wayland::submit(&plan.submit_key)?;
+            return Ok(outcome);
+        }
+        simulate_submit(&plan.submit_key)?;
+    }
+    Ok(outcome)
+}
 
 /// Paste text into the currently focused application via clipboard.
 ///
 /// Strategy:
-/// 1. Back up current clipboard contents
+/// 1. Back up current clipboard contents (files, image, HTML or text),
+///    unless `restore_clipboard` is off
 /// 2. Set clipboard to our text via arboard
-/// 3. Simulate paste keystroke
-/// 4. Restore original clipboard contents (best-effort)
+/// 3. Simulate paste keystroke. With `paste_verify`, retry it once after
+///    `paste_verify_window_ms` if it failed or the clipboard didn't hold our
+///    text when it was sent.
+/// 4. Wait `clipboard_restore_delay_ms` (at least the verify window) for the
+///    target app to read it, then restore the original contents (best-effort)
 pub fn paste_via_clipboard(
     text: &str,
-    method: &PasteMethod,
-    delay_ms: u64,
-) -> Result<()> {
+    settings: &Settings,
+    progress: Option<&dyn Fn(usize, usize)>,
+) -> Result<PasteOutcome> {
+    #[cfg(all(feature = "wayland", target_os = "linux"))]
+    if super::wayland::is_wayland_session() {
+        return super::wayland::paste(text, settings, progress);
+    }
+
+    let mut method = &settings.paste_method;
+    if !method.is_supported() {
+        log::warn!("Paste method {:?} is not supported here, using Ctrl+V", method);
+        method = &PasteMethod::CtrlV;
+    }
+    if text.is_empty() {
+        return Ok(PasteOutcome {
+            attempted: false,
+            method_used: method.clone(),
+            retried: false,
+            clipboard_restored: false,
+            clipboard_only: false,
+            clipboard_retries: 0,
+            aborted: None,
+        });
+    }
+
     // Small delay for focus to return to previous app
-    std::thread::sleep(Duration::from_millis(delay_ms));
+    std::thread::sleep(Duration::from_millis(settings.paste_delay_ms));
+
+    if let Some(app) = super::secure_input::enabled_by() {
+        log::warn!(
+            "Secure input is enabled by {}; paste blocked, text left on the clipboard",
+            app
+        );
+        copy_to_clipboard(text)?;
+        return Ok(PasteOutcome {
+            attempted: false,
+            method_used: method.clone(),
+            retried: false,
+            clipboard_restored: false,
+            clipboard_only: true,
+            clipboard_retries: 0,
+            aborted: None,
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    if *method == PasteMethod::MiddleClick {
+        let clipboard_retries = paste_via_primary_selection(text, settings)?;
+        return Ok(PasteOutcome {
+            attempted: true,
+            method_used: method.clone(),
+            retried: false,
+            clipboard_restored: false,
+            clipboard_only: false,
+            clipboard_retries,
+            aborted: None,
+        });
+    }
 
     // Back up current clipboard contents (best-effort)
-    let mut clipboard = arboard::Clipboard::new()
-        .map_err(|e| anyhow::anyhow!("Failed to access clipboard: {}", e))?;
-    let previous_text = clipboard.get_text().ok();
+    let mut clipboard_retries = 0;
+    let mut clipboard =
+        retry_if_busy("opening the clipboard", &mut clipboard_retries, Clipboard::new)
+            .map_err(|e| anyhow::anyhow!("Failed to access clipboard: {}", e))?;
+    paste_with_backup(
+        &mut clipboard,
+        text,
+        settings,
+        method,
+        progress,
+        clipboard_retries,
+        simulate_paste,
+    )
+}
 
-    clipboard
-        .set_text(text)
-        .map_err(|e| anyhow::anyhow!("Failed to set clipboard text: {}", e))?;
+/// Steps 1–4 of `paste_via_clipboard` on an open clipboard, sending paste
+/// keystrokes with `send_paste`
+fn paste_with_backup(
+    clipboard: &mut impl ClipboardIo,
+    text: &str,
+    settings: &Settings,
+    method: &PasteMethod,
+    progress: Option<&dyn Fn(usize, usize)>,
+    mut clipboard_retries: u32,
+    mut send_paste: impl FnMut(&PasteMethod) -> Result<()>,
+) -> Result<PasteOutcome> {
+    let backup = settings
+        .restore_clipboard
+        .then(|| ClipboardBackup::capture(clipboard, &mut clipboard_retries));
+
+    set_paste_text(clipboard, text, settings, &mut clipboard_retries)?;
 
     std::thread::sleep(Duration::from_millis(50));
 
+    let mut retried = false;
+    // Time since the last keystroke already spent in the verify window
+    let mut waited_ms = 0;
     match method {
         PasteMethod::TypeOut => {
             let mut enigo = Enigo::new(&EnigoSettings::default())
                 .map_err(|e| anyhow::anyhow!("Failed to create enigo: {}", e))?;
-            enigo
-                .text(text)
-                .map_err(|e| anyhow::anyhow!("Failed to type text: {}", e))?;
+            let typed = type_out(text, settings, progress, |chunk| {
+                enigo
+                    .text(chunk)
+                    .map_err(|e| anyhow::anyhow!("Failed to type text: {}", e))
+            });
+            if !matches!(typed, Ok(None)) {
+                // Don't leave the text on the clipboard after a cancel
+                let clipboard_restored =
+                    backup.is_some_and(|backup| backup.restore(clipboard, &mut clipboard_retries));
+                return Ok(PasteOutcome {
+                    attempted: true,
+                    method_used: method.clone(),
+                    retried: false,
+                    clipboard_restored,
+                    clipboard_only: false,
+                    clipboard_retries,
+                    aborted: typed?,
+                });
+            }
+        }
+        _ if settings.paste_verify => {
+            // A clipboard manager or slow app can replace our text before the
+            // keystroke lands; there is nothing to paste then
+            let holds_text = retry_if_busy("checking the clipboard", &mut clipboard_retries, || {
+                clipboard.get_text()
+            })
+            .is_ok_and(|current| current == text);
+            let result = send_paste(method);
+            if let Err(e) = &result {
+                log::warn!("Paste keystroke failed: {}", e);
+            }
+            std::thread::sleep(Duration::from_millis(settings.paste_verify_window_ms));
+            waited_ms = settings.paste_verify_window_ms;
+
+            if result.is_err() || !holds_text {
+                log::info!("Retrying paste");
+                set_paste_text(clipboard, text, settings, &mut clipboard_retries)?;
+                std::thread::sleep(Duration::from_millis(50));
+                send_paste(method)?;
+                retried = true;
+                waited_ms = 0;
+            }
         }
         _ => {
-            simulate_paste(method)?;
+            send_paste(method)?;
+        }
+    }
+
+    // Restore original clipboard contents once the paste has been consumed.
+    // Best-effort — don't fail the paste if this doesn't work.
+    let clipboard_restored = match backup {
+        Some(backup) => {
+            std::thread::sleep(Duration::from_millis(
+                settings.clipboard_restore_delay_ms.saturating_sub(waited_ms),
+            ));
+            let restored = backup.restore(clipboard, &mut clipboard_retries);
+            if !restored {
+                log::debug!("Clipboard not restored; it still holds the pasted text");
+            }
+            restored
+        }
+        None => false,
+    };
+
+    Ok(PasteOutcome {
+        attempted: true,
+        method_used: method.clone(),
+        retried,
+        clipboard_restored,
+        clipboard_only: false,
+        clipboard_retries,
+        aborted: None,
+    })
+}
+
+/// Put the text to paste on the clipboard, marked transient if
+/// `clipboard_transient` is on
+fn set_paste_text(
+    clipboard: &mut impl ClipboardIo,
+    text: &str,
+    settings: &Settings,
+    retries: &mut u32,
+) -> Result<()> {
+    retry_if_busy("setting the clipboard", retries, || {
+        clipboard.set_text(text, settings.clipboard_transient)
+    })
+    .map_err(|e| anyhow::anyhow!("Failed to set clipboard text: {}", e))
+}
+
+/// Ask clipboard history managers to skip this write:
+/// `org.nspasteboard.TransientType` on macOS
+#[cfg(target_os = "macos")]
+fn mark_transient(set: Set<'_>) -> Set<'_> {
+    use arboard::SetExtApple;
+    set.exclude_from_history()
+}
+
+/// `ExcludeClipboardContentFromMonitorProcessing` on Windows, which also
+/// keeps it out of Win+V history and the cloud clipboard
+#[cfg(target_os = "windows")]
+fn mark_transient(set: Set<'_>) -> Set<'_> {
+    use arboard::SetExtWindows;
+    set.exclude_from_monitoring()
+}
+
+/// `x-kde-passwordManagerHint` on Linux, honored by Klipper and CopyQ
+#[cfg(target_os = "linux")]
+fn mark_transient(set: Set<'_>) -> Set<'_> {
+    use arboard::SetExtLinux;
+    set.exclude_from_history()
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+fn mark_transient(set: Set<'_>) -> Set<'_> {
+    set
+}
+
+/// Put text on the primary selection and middle-click at the mouse pointer.
+/// The regular clipboard is left alone. Returns the clipboard retries made.
+#[cfg(target_os = "linux")]
+fn paste_via_primary_selection(text: &str, settings: &Settings) -> Result<u32> {
+    use arboard::{LinuxClipboardKind, SetExtLinux};
+
+    let mut retries = 0;
+    let mut clipboard = retry_if_busy("opening the clipboard", &mut retries, Clipboard::new)
+        .map_err(|e| anyhow::anyhow!("Failed to access clipboard: {}", e))?;
+    retry_if_busy("setting the primary selection", &mut retries, || {
+        let set = clipboard.set().clipboard(LinuxClipboardKind::Primary);
+        let set = if settings.clipboard_transient {
+            mark_transient(set)
+        } else {
+            set
+        };
+        set.text(text)
+    })
+    .map_err(|e| anyhow::anyhow!("Failed to set primary selection: {}", e))?;
+
+    std::thread::sleep(Duration::from_millis(50));
+
+    let mut enigo = Enigo::new(&EnigoSettings::default())
+        .map_err(|e| anyhow::anyhow!("Failed to create enigo: {}", e))?;
+    enigo
+        .button(Button::Middle, Direction::Click)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    // We only serve the selection while `clipboard` is alive, so give the
+    // target time to read it
+    std::thread::sleep(Duration::from_millis(settings.clipboard_restore_delay_ms));
+    Ok(retries)
+}
+
+/// Paste, copy or do nothing with finished text, per `post_process_action`
+pub fn deliver_text(text: &str, settings: &Settings, target_app: Option<&str>) -> Result<()> {
+    match settings.post_process_action {
+        PostProcessAction::Paste => {
+            paste_and_maybe_submit(text, settings, target_app, None).map(|_| ())
         }
+        PostProcessAction::CopyOnly => copy_to_clipboard(text),
+        PostProcessAction::None => Ok(()),
     }
+}
 
-    // Restore original clipboard contents after a short delay for paste to complete
-    if let Some(prev) = previous_text {
-        std::thread::sleep(Duration::from_millis(100));
-        // Best-effort restore — don't fail the paste if this doesn't work
-        let _ = clipboard.set_text(prev);
+/// Put text on the clipboard without pasting it.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    #[cfg(all(feature = "wayland", target_os = "linux"))]
+    if super::wayland::is_wayland_session() {
+        return super::wayland::copy_text(text);
     }
 
+    let mut retries = 0;
+    let mut clipboard = retry_if_busy("opening the clipboard", &mut retries, Clipboard::new)
+        .map_err(|e| anyhow::anyhow!("Failed to access clipboard: {}", e))?;
+    retry_if_busy("setting the clipboard", &mut retries, || clipboard.set_text(text))
+        .map_err(|e| anyhow::anyhow!("Failed to set clipboard text: {}", e))?;
     Ok(())
 }
 
+/// Check that the clipboard can be reached, without touching its contents.
+/// Returns what provides it.
+pub fn check_clipboard() -> Result<&'static str> {
+    #[cfg(all(feature = "wayland", target_os = "linux"))]
+    if super::wayland::is_wayland_session() {
+        return super::wayland::check_clipboard();
+    }
+
+    Clipboard::new().map_err(|e| anyhow::anyhow!("Failed to access clipboard: {}", e))?;
+    Ok("arboard")
+}
+
+fn simulate_submit(key: &SubmitKey) -> Result<()> {
+    let mut enigo = Enigo::new(&EnigoSettings::default())
+        .map_err(|e| anyhow::anyhow!("Failed to create enigo: {}", e))?;
+    press_combo(&mut enigo, &key.key_combo())
+}
+
+/// Key in the Insert position. Mac keyboards have Help there.
+#[cfg(target_os = "macos")]
+const INSERT_KEY: Key = Key::Help;
+#[cfg(not(target_os = "macos"))]
+const INSERT_KEY: Key = Key::Insert;
+
+/// Click the V of the paste shortcut by key code rather than by character,
+/// so non-QWERTY layouts don't turn it into another shortcut or a typed
+/// "v". Falls back to the character if that fails, and on Linux, where
+/// xdo can only send characters.
+fn click_v(enigo: &mut Enigo) -> Result<()> {
+    // kVK_ANSI_V
+    #[cfg(target_os = "macos")]
+    let by_code = enigo.raw(0x09, Direction::Click);
+    // VK_V; Windows matches shortcuts by virtual key whatever the layout
+    #[cfg(target_os = "windows")]
+    let by_code = enigo.key(Key::Other(0x56), Direction::Click);
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    match by_code {
+        Ok(()) => return Ok(()),
+        Err(e) => log::warn!("Paste key by key code failed, sending 'v': {}", e),
+    }
+
+    enigo
+        .key(Key::Unicode('v'), Direction::Click)
+        .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
 fn simulate_paste(method: &PasteMethod) -> Result<()> {
+    let combo = method
+        .key_combo()
+        .map_err(|e| anyhow::anyhow!("Invalid paste combo: {}", e))?
+        .ok_or_else(|| anyhow::anyhow!("Paste method {:?} has no key combo", method))?;
     let mut enigo = Enigo::new(&EnigoSettings::default())
         .map_err(|e| anyhow::anyhow!("Failed to create enigo: {}", e))?;
+    press_combo(&mut enigo, &combo)
+}
 
-    let modifier = if cfg!(target_os = "macos") {
-        Key::Meta
-    } else {
-        Key::Control
+/// The key presses a combo needs, so tests can record them
+trait KeySink {
+    fn press_key(&mut self, key: Key, direction: Direction) -> Result<()>;
+    /// Click the V of a paste shortcut (see `click_v`)
+    fn click_v(&mut self) -> Result<()>;
+}
+
+impl KeySink for Enigo {
+    fn press_key(&mut self, key: Key, direction: Direction) -> Result<()> {
+        self.key(key, direction).map_err(|e| anyhow::anyhow!("{}", e))
+    }
+
+    fn click_v(&mut self) -> Result<()> {
+        click_v(self)
+    }
+}
+
+/// Hold the modifiers in order, click the key, then release the modifiers
+/// in reverse order. Modifiers already down are released even if a later
+/// step fails, so none stays held.
+fn press_combo(keys: &mut impl KeySink, combo: &Hotkey) -> Result<()> {
+    let mut held = Vec::new();
+    let mut result = Ok(());
+    for modifier in &combo.modifiers {
+        let key = modifier_key(*modifier);
+        if let Err(e) = keys.press_key(key, Direction::Press) {
+            result = Err(e);
+            break;
+        }
+        held.push(key);
+    }
+    if result.is_ok() {
+        result = click_combo_key(keys, &combo.key);
+    }
+    for key in held.into_iter().rev() {
+        if let Err(e) = keys.press_key(key, Direction::Release) {
+            result = result.and(Err(e));
+        }
+    }
+    result
+}
+
+fn modifier_key(modifier: HotkeyModifier) -> Key {
+    match modifier {
+        HotkeyModifier::Ctrl => Key::Control,
+        HotkeyModifier::Alt => Key::Alt,
+        HotkeyModifier::Shift => Key::Shift,
+        HotkeyModifier::Super => Key::Meta,
+    }
+}
+
+/// Click a key by its canonical hotkey name (see `normalize_hotkey`)
+fn click_combo_key(keys: &mut impl KeySink, name: &str) -> Result<()> {
+    const F_KEYS: [Key; 20] = [
+        Key::F1,
+        Key::F2,
+        Key::F3,
+        Key::F4,
+        Key::F5,
+        Key::F6,
+        Key::F7,
+        Key::F8,
+        Key::F9,
+        Key::F10,
+        Key::F11,
+        Key::F12,
+        Key::F13,
+        Key::F14,
+        Key::F15,
+        Key::F16,
+        Key::F17,
+        Key::F18,
+        Key::F19,
+        Key::F20,
+    ];
+
+    let key = match name {
+        "V" => return keys.click_v(),
+        "Space" => Key::Space,
+        "Enter" => Key::Return,
+        "Tab" => Key::Tab,
+        "Escape" => Key::Escape,
+        "Backspace" => Key::Backspace,
+        "Delete" => Key::Delete,
+        "Insert" => INSERT_KEY,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Up" => Key::UpArrow,
+        "Down" => Key::DownArrow,
+        "Left" => Key::LeftArrow,
+        "Right" => Key::RightArrow,
+        "CapsLock" => Key::CapsLock,
+        "Minus" => Key::Unicode('-'),
+        "Plus" => Key::Unicode('+'),
+        "Equal" => Key::Unicode('='),
+        "Comma" => Key::Unicode(','),
+        "Period" => Key::Unicode('.'),
+        "Slash" => Key::Unicode('/'),
+        "Backslash" => Key::Unicode('\\'),
+        "Semicolon" => Key::Unicode(';'),
+        "Quote" => Key::Unicode('\''),
+        "Backquote" => Key::Unicode('`'),
+        "BracketLeft" => Key::Unicode('['),
+        "BracketRight" => Key::Unicode(']'),
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                // A letter or digit
+                (Some(c), None) => Key::Unicode(c.to_ascii_lowercase()),
+                _ => name
+                    .strip_prefix('F')
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .and_then(|n| F_KEYS.get(n.wrapping_sub(1)).copied())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Key {} can't be pressed in a paste combo", name)
+                    })?,
+            }
+        }
     };
+    keys.press_key(key, Direction::Click)
+}
 
-    match method {
-        PasteMethod::CtrlV => {
-            enigo
-                .key(modifier, Direction::Press)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            enigo
-                .key(Key::Unicode('v'), Direction::Click)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            enigo
-                .key(modifier, Direction::Release)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-        }
-        PasteMethod::CtrlShiftV => {
-            enigo
-                .key(modifier, Direction::Press)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            enigo
-                .key(Key::Shift, Direction::Press)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            enigo
-                .key(Key::Unicode('v'), Direction::Click)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            enigo
-                .key(Key::Shift, Direction::Release)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            enigo
-                .key(modifier, Direction::Release)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-        }
-        PasteMethod::ShiftInsert => {
-            enigo
-                .key(Key::Shift, Direction::Press)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            enigo
-                .key(Key::Other(0xFF63), Direction::Click) // Insert key
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            enigo
-                .key(Key::Shift, Direction::Release)
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-        }
-        PasteMethod::TypeOut => unreachable!(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// In-memory clipboard that logs every write, sharing the log with the
+    /// fake keystroke sender so the test sees the order of both
+    #[derive(Default)]
+    struct FakeClipboard {
+        text: Option<String>,
+        html: Option<String>,
+        /// Replaces the next text set, like a clipboard manager would
+        steal_next_set: Option<String>,
+        log: Rc<RefCell<Vec<String>>>,
     }
 
-    Ok(())
+    impl ClipboardIo for FakeClipboard {
+        fn get_files(&mut self) -> Result<Vec<PathBuf>, arboard::Error> {
+            Err(arboard::Error::ContentNotAvailable)
+        }
+
+        fn get_image(&mut self) -> Result<ImageData<'static>, arboard::Error> {
+            Err(arboard::Error::ContentNotAvailable)
+        }
+
+        fn get_text(&mut self) -> Result<String, arboard::Error> {
+            self.log.borrow_mut().push("read".into());
+            self.text.clone().ok_or(arboard::Error::ContentNotAvailable)
+        }
+
+        fn get_html(&mut self) -> Result<String, arboard::Error> {
+            self.html.clone().ok_or(arboard::Error::ContentNotAvailable)
+        }
+
+        fn set_files(&mut self, _files: &[PathBuf]) -> Result<(), arboard::Error> {
+            unreachable!("no files are backed up")
+        }
+
+        fn set_image(&mut self, _image: ImageData<'_>) -> Result<(), arboard::Error> {
+            unreachable!("no image is backed up")
+        }
+
+        fn set_html(&mut self, html: &str, alt_text: Option<&str>) -> Result<(), arboard::Error> {
+            self.log.borrow_mut().push(format!("set html {}", html));
+            self.html = Some(html.to_string());
+            self.text = alt_text.map(str::to_string);
+            Ok(())
+        }
+
+        fn set_text(&mut self, text: &str, _transient: bool) -> Result<(), arboard::Error> {
+            self.log.borrow_mut().push(format!("set {}", text));
+            self.html = None;
+            let stolen = self.steal_next_set.take();
+            self.text = Some(stolen.unwrap_or_else(|| text.to_string()));
+            Ok(())
+        }
+    }
+
+    fn quick_settings() -> Settings {
+        Settings {
+            clipboard_restore_delay_ms: 0,
+            paste_verify_window_ms: 0,
+            ..Settings::default()
+        }
+    }
+
+    fn paste(clipboard: &mut FakeClipboard, settings: &Settings) -> PasteOutcome {
+        let log = clipboard.log.clone();
+        let send_paste = |_: &PasteMethod| {
+            log.borrow_mut().push("paste".into());
+            Ok(())
+        };
+        paste_with_backup(
+            clipboard,
+            "dictated",
+            settings,
+            &PasteMethod::CtrlV,
+            None,
+            0,
+            send_paste,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn backs_up_before_pasting_and_restores_after() {
+        let mut clipboard = FakeClipboard {
+            text: Some("copied".into()),
+            ..Default::default()
+        };
+        let outcome = paste(&mut clipboard, &quick_settings());
+
+        assert!(outcome.clipboard_restored);
+        assert_eq!(
+            *clipboard.log.borrow(),
+            ["read", "set dictated", "paste", "set copied"]
+        );
+        assert_eq!(clipboard.text.as_deref(), Some("copied"));
+    }
+
+    #[test]
+    fn html_is_restored_with_its_text() {
+        let mut clipboard = FakeClipboard {
+            text: Some("bold".into()),
+            html: Some("<b>bold</b>".into()),
+            ..Default::default()
+        };
+        let outcome = paste(&mut clipboard, &quick_settings());
+
+        assert!(outcome.clipboard_restored);
+        assert_eq!(
+            *clipboard.log.borrow(),
+            ["read", "set dictated", "paste", "set html <b>bold</b>"]
+        );
+        assert_eq!(clipboard.text.as_deref(), Some("bold"));
+    }
+
+    #[test]
+    fn empty_clipboard_keeps_the_pasted_text() {
+        let mut clipboard = FakeClipboard::default();
+        let outcome = paste(&mut clipboard, &quick_settings());
+
+        assert!(!outcome.clipboard_restored);
+        assert_eq!(*clipboard.log.borrow(), ["read", "set dictated", "paste"]);
+        assert_eq!(clipboard.text.as_deref(), Some("dictated"));
+    }
+
+    #[test]
+    fn restore_off_neither_reads_nor_restores() {
+        let mut clipboard = FakeClipboard {
+            text: Some("copied".into()),
+            ..Default::default()
+        };
+        let settings = Settings {
+            restore_clipboard: false,
+            ..quick_settings()
+        };
+        let outcome = paste(&mut clipboard, &settings);
+
+        assert!(!outcome.clipboard_restored);
+        assert_eq!(*clipboard.log.borrow(), ["set dictated", "paste"]);
+    }
+
+    #[test]
+    fn verify_retry_restores_only_after_the_second_paste() {
+        let mut clipboard = FakeClipboard {
+            text: Some("copied".into()),
+            steal_next_set: Some("from a clipboard manager".into()),
+            ..Default::default()
+        };
+        let settings = Settings {
+            paste_verify: true,
+            ..quick_settings()
+        };
+        let outcome = paste(&mut clipboard, &settings);
+
+        assert!(outcome.retried && outcome.clipboard_restored);
+        assert_eq!(
+            *clipboard.log.borrow(),
+            [
+                "read",
+                "set dictated",
+                "read",
+                "paste",
+                "set dictated",
+                "paste",
+                "set copied"
+            ]
+        );
+    }
+
+    #[test]
+    fn markdown_is_pasted_verbatim() {
+        let markdown = "# Title\n\nIntro.\n\n- first\n  - nested\n- second\n";
+        let settings = quick_settings();
+        let plan = plan_paste(markdown, &settings, None, None);
+        assert_eq!(plan.text, markdown);
+
+        let mut clipboard = FakeClipboard::default();
+        paste_with_backup(&mut clipboard, &plan.text, &settings, &plan.method, None, 0, |_| Ok(()))
+            .unwrap();
+        assert_eq!(clipboard.log.borrow()[1], format!("set {}", markdown));
+    }
+
+    #[test]
+    fn busy_clipboard_is_retried_until_it_frees_up() {
+        let mut calls = 0;
+        let mut retries = 0;
+        let result = retry_if_busy("testing", &mut retries, || {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(arboard::Error::ClipboardOccupied),
+                _ => Ok("copied"),
+            }
+        });
+        assert_eq!(result.unwrap(), "copied");
+        assert_eq!((calls, retries), (3, 2));
+    }
+
+    #[test]
+    fn other_clipboard_errors_are_not_retried() {
+        let mut calls = 0;
+        let mut retries = 0;
+        let result: Result<(), _> = retry_if_busy("testing", &mut retries, || {
+            calls += 1;
+            Err(arboard::Error::ContentNotAvailable)
+        });
+        assert!(matches!(result, Err(arboard::Error::ContentNotAvailable)));
+        assert_eq!((calls, retries), (1, 0));
+    }
+
+    #[test]
+    fn busy_clipboard_retries_stop_after_the_attempt_limit() {
+        let mut calls = 0;
+        // Retries add to those already made by earlier operations
+        let mut retries = 3;
+        let result: Result<(), _> = retry_if_busy("testing", &mut retries, || {
+            calls += 1;
+            Err(arboard::Error::ClipboardOccupied)
+        });
+        assert!(matches!(result, Err(arboard::Error::ClipboardOccupied)));
+        assert_eq!(calls, CLIPBOARD_ATTEMPTS);
+        assert_eq!(retries, 3 + CLIPBOARD_ATTEMPTS - 1);
+    }
+
+    /// Records key presses; the press of `fail_on` fails
+    #[derive(Default)]
+    struct FakeKeys {
+        log: Vec<String>,
+        fail_on: Option<&'static str>,
+    }
+
+    impl FakeKeys {
+        fn record(&mut self, event: String) -> Result<()> {
+            if self.fail_on == Some(event.as_str()) {
+                anyhow::bail!("{} failed", event);
+            }
+            self.log.push(event);
+            Ok(())
+        }
+    }
+
+    impl KeySink for FakeKeys {
+        fn press_key(&mut self, key: Key, direction: Direction) -> Result<()> {
+            self.record(format!("{:?} {:?}", direction, key))
+        }
+
+        fn click_v(&mut self) -> Result<()> {
+            self.record("Click V".to_string())
+        }
+    }
+
+    #[test]
+    fn combos_hold_modifiers_around_the_key() {
+        let mut keys = FakeKeys::default();
+        let combo = Hotkey::new(&[HotkeyModifier::Shift, HotkeyModifier::Ctrl], "V");
+        press_combo(&mut keys, &combo).unwrap();
+        assert_eq!(
+            keys.log,
+            ["Press Control", "Press Shift", "Click V", "Release Shift", "Release Control"]
+        );
+
+        let mut keys = FakeKeys::default();
+        press_combo(&mut keys, &Hotkey::new(&[HotkeyModifier::Alt], "F5")).unwrap();
+        assert_eq!(keys.log, ["Press Alt", "Click F5", "Release Alt"]);
+
+        let mut keys = FakeKeys::default();
+        press_combo(&mut keys, &SubmitKey::Enter.key_combo()).unwrap();
+        assert_eq!(keys.log, ["Click Return"]);
+    }
+
+    #[test]
+    fn held_modifiers_are_released_when_a_later_press_fails() {
+        use HotkeyModifier::{Alt, Ctrl, Shift};
+        let combo = Hotkey::new(&[Ctrl, Alt, Shift], "V");
+        let mut keys = FakeKeys {
+            fail_on: Some("Press Shift"),
+            ..Default::default()
+        };
+        assert!(press_combo(&mut keys, &combo).is_err());
+        assert_eq!(keys.log, ["Press Control", "Press Alt", "Release Alt", "Release Control"]);
+
+        let mut keys = FakeKeys {
+            fail_on: Some("Click V"),
+            ..Default::default()
+        };
+        assert!(press_combo(&mut keys, &combo).is_err());
+        assert_eq!(
+            keys.log,
+            [
+                "Press Control",
+                "Press Alt",
+                "Press Shift",
+                "Release Shift",
+                "Release Alt",
+                "Release Control"
+            ]
+        );
+
+        let mut keys = FakeKeys::default();
+        let unpressable = Hotkey::new(&[HotkeyModifier::Ctrl], "F24");
+        assert!(press_combo(&mut keys, &unpressable).is_err());
+        assert_eq!(keys.log, ["Press Control", "Release Control"]);
+    }
+
+    #[test]
+    fn custom_combos_press_their_parsed_keys() {
+        let press = |combo: &str| {
+            let method = PasteMethod::CustomCombo(combo.to_string());
+            let mut keys = FakeKeys::default();
+            press_combo(&mut keys, &method.key_combo().unwrap().unwrap()).unwrap();
+            keys.log
+        };
+        assert_eq!(
+            press("Cmd+Option+Shift+V"),
+            [
+                "Press Alt",
+                "Press Shift",
+                "Press Meta",
+                "Click V",
+                "Release Meta",
+                "Release Shift",
+                "Release Alt"
+            ]
+        );
+        assert_eq!(
+            press("Ctrl+Win+V"),
+            ["Press Control", "Press Meta", "Click V", "Release Meta", "Release Control"]
+        );
+        assert!(PasteMethod::CustomCombo("Ctrl+Bogus".to_string()).key_combo().is_err());
+    }
+
+    fn chunks(text: &str, chars_per_chunk: usize) -> Vec<&str> {
+        typeout_chunks(text, chars_per_chunk).collect()
+    }
+
+    #[test]
+    fn typeout_chunks_have_the_configured_size() {
+        assert_eq!(chunks("abcdefgh", 4), ["abcd", "efgh"]);
+        assert_eq!(chunks("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(chunks("abc", 1), ["a", "b", "c"]);
+        assert_eq!(chunks("abc", 10), ["abc"]);
+        assert_eq!(chunks("abc def", 0), ["abc def"]);
+        assert!(chunks("", 3).is_empty());
+        assert!(chunks("", 0).is_empty());
+    }
+
+    #[test]
+    fn typeout_chunks_never_split_a_character() {
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        let thumbs = "\u{1f44d}\u{1f3fd}";
+        let flag = "\u{1f1ef}\u{1f1f5}";
+        let accented = "e\u{301}";
+        let text = format!("{}{}{}{}\u{65e5}\u{672c}\u{8a9e}", family, thumbs, flag, accented);
+        assert_eq!(
+            chunks(&text, 1),
+            [family, thumbs, flag, accented, "\u{65e5}", "\u{672c}", "\u{8a9e}"]
+        );
+        assert_eq!(
+            chunks(&text, 2),
+            [
+                format!("{}{}", family, thumbs),
+                format!("{}{}", flag, accented),
+                "\u{65e5}\u{672c}".to_string(),
+                "\u{8a9e}".to_string(),
+            ]
+        );
+        for size in 0..=8 {
+            assert_eq!(chunks(&text, size).concat(), text, "{}", size);
+        }
+    }
+
+    #[test]
+    fn type_out_stops_once_its_operation_is_cancelled() {
+        use crate::ops::{self, OpKind};
+
+        let settings = Settings {
+            typeout_chars_per_chunk: 1,
+            typeout_delay_ms: 0,
+            ..quick_settings()
+        };
+        let mut typed = String::new();
+        let aborted = type_out("abc", &settings, None, |chunk| {
+            typed.push_str(chunk);
+            let op = ops::list().into_iter().find(|op| op.kind == OpKind::Paste).unwrap();
+            assert!(ops::cancel(op.id));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(aborted, Some(PasteAbort::Cancelled));
+        assert_eq!(typed, "a");
+        assert!(ops::list().iter().all(|op| op.kind != OpKind::Paste));
+    }
 }
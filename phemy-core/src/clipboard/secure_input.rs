@@ -0,0 +1,54 @@
+//! macOS Secure Keyboard Entry. While a password field or an app such as
+//! 1Password has it on, synthetic key events are silently dropped, so a
+//! paste keystroke would appear to succeed while nothing happens.
+
+/// Name of the app holding secure input, "an unknown app" if we can't tell,
+/// or None if secure input is off (always None outside macOS)
+#[cfg(target_os = "macos")]
+pub fn enabled_by() -> Option<String> {
+    #[link(name = "Carbon", kind = "framework")]
+    extern "C" {
+        fn IsSecureEventInputEnabled() -> u8;
+    }
+
+    if unsafe { IsSecureEventInputEnabled() } == 0 {
+        return None;
+    }
+    Some(owner_app().unwrap_or_else(|| "an unknown app".to_string()))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn enabled_by() -> Option<String> {
+    None
+}
+
+/// The window server records the owning process in the session dictionary,
+/// which ioreg prints as `"kCGSSessionSecureInputPID"=1234`
+#[cfg(target_os = "macos")]
+fn owner_app() -> Option<String> {
+    use std::process::Command;
+
+    let ioreg = Command::new("ioreg")
+        .args(["-l", "-w", "0", "-d", "1"])
+        .output()
+        .ok()?;
+    let ioreg = String::from_utf8_lossy(&ioreg.stdout);
+    let (_, rest) = ioreg.split_once("\"kCGSSessionSecureInputPID\"=")?;
+    let pid: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    if pid.is_empty() {
+        return None;
+    }
+
+    let ps = Command::new("ps")
+        .args(["-p", &pid, "-o", "comm="])
+        .output()
+        .ok()?;
+    let path = String::from_utf8_lossy(&ps.stdout).trim().to_string();
+    // comm is the executable path; the app bundle name reads better
+    let name = path
+        .split('/')
+        .find_map(|part| part.strip_suffix(".app"))
+        .or_else(|| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())?;
+    Some(name.to_string())
+}
@@ -0,0 +1,342 @@
+//! Clipboard and paste for Wayland sessions, where enigo's synthetic keys
+//! are ignored and arboard loses the clipboard once our window is
+//! unfocused. Uses helper binaries instead:
+//!
+//! - `wl-copy` / `wl-paste` (wl-clipboard) for the clipboard
+//! - `ydotool` (needs `ydotoold` running) or `wtype` (virtual-keyboard
+//!   protocol, wlroots compositors) for key presses and typing
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use super::paste::PasteOutcome;
+use crate::settings::{Hotkey, HotkeyModifier, PasteMethod, Settings, SubmitKey};
+
+/// Whether we are running in a Wayland session
+pub fn is_wayland_session() -> bool {
+    std::env::var("XDG_SESSION_TYPE").is_ok_and(|t| t.eq_ignore_ascii_case("wayland"))
+        || std::env::var_os("WAYLAND_DISPLAY").is_some_and(|d| !d.is_empty())
+}
+
+/// Clipboard types to back up, richest first
+const BACKUP_TYPES: &[&str] = &[
+    "text/uri-list",
+    "image/png",
+    "text/html",
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+];
+
+fn find_helper(name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+fn require_helper(name: &str, package: &str) -> Result<PathBuf> {
+    find_helper(name).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Pasting on Wayland needs `{}` (package {}), which was not found in PATH",
+            name,
+            package
+        )
+    })
+}
+
+/// Whether the clipboard helpers are installed
+pub fn check_clipboard() -> Result<&'static str> {
+    require_helper("wl-copy", "wl-clipboard")?;
+    require_helper("wl-paste", "wl-clipboard")?;
+    Ok("wl-clipboard")
+}
+
+fn run(program: &PathBuf, args: &[&str]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .with_context(|| format!("Failed to run {:?}", program))?;
+    anyhow::ensure!(status.success(), "{:?} exited with {}", program, status);
+    Ok(())
+}
+
+/// Put `data` on the clipboard (or the primary selection) as `mime`
+fn copy(data: &[u8], mime: &str, primary: bool) -> Result<()> {
+    let wl_copy = require_helper("wl-copy", "wl-clipboard")?;
+    // wl-copy forks to serve the clipboard, so the parent exits once it has read stdin
+    let mut child = Command::new(&wl_copy)
+        .args(["--type", mime])
+        .args(primary.then_some("--primary"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .context("Failed to run wl-copy")?;
+    child
+        .stdin
+        .take()
+        .context("wl-copy stdin unavailable")?
+        .write_all(data)?;
+    let status = child.wait()?;
+    anyhow::ensure!(status.success(), "wl-copy exited with {}", status);
+    Ok(())
+}
+
+/// Put text on the clipboard
+pub fn copy_text(text: &str) -> Result<()> {
+    copy(text.as_bytes(), "text/plain;charset=utf-8", false)
+}
+
+/// Current clipboard contents in the richest type we know, if any
+fn backup() -> Option<(String, Vec<u8>)> {
+    let wl_paste = find_helper("wl-paste")?;
+    let types = Command::new(&wl_paste).arg("--list-types").output().ok()?;
+    let types = String::from_utf8_lossy(&types.stdout);
+    let mime = BACKUP_TYPES
+        .iter()
+        .find(|wanted| types.lines().any(|t| t.trim() == **wanted))?;
+
+    let output = Command::new(&wl_paste)
+        .args(["--no-newline", "--type", mime])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| (mime.to_string(), output.stdout))
+}
+
+/// Linux input event code and xkb keysym name (for wtype) of a key, by its
+/// canonical hotkey name
+fn key_codes(name: &str) -> Option<(u16, String)> {
+    const NAMED: &[(&str, u16, &str)] = &[
+        ("Space", 57, "space"),
+        ("Enter", 28, "Return"),
+        ("Tab", 15, "Tab"),
+        ("Escape", 1, "Escape"),
+        ("Backspace", 14, "BackSpace"),
+        ("Delete", 111, "Delete"),
+        ("Insert", 110, "Insert"),
+        ("Home", 102, "Home"),
+        ("End", 107, "End"),
+        ("PageUp", 104, "Prior"),
+        ("PageDown", 109, "Next"),
+        ("Up", 103, "Up"),
+        ("Down", 108, "Down"),
+        ("Left", 105, "Left"),
+        ("Right", 106, "Right"),
+        ("Minus", 12, "minus"),
+        ("Plus", 78, "plus"),
+        ("Equal", 13, "equal"),
+        ("Comma", 51, "comma"),
+        ("Period", 52, "period"),
+        ("Slash", 53, "slash"),
+        ("Backslash", 43, "backslash"),
+        ("Semicolon", 39, "semicolon"),
+        ("Quote", 40, "apostrophe"),
+        ("Backquote", 41, "grave"),
+        ("BracketLeft", 26, "bracketleft"),
+        ("BracketRight", 27, "bracketright"),
+        ("CapsLock", 58, "Caps_Lock"),
+    ];
+    // A to Z and 0 to 9; event codes follow the QWERTY rows
+    const LETTERS: [u16; 26] = [
+        30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17,
+        45, 21, 44,
+    ];
+    const DIGITS: [u16; 10] = [11, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    if let Some((_, code, keysym)) = NAMED.iter().find(|(named, ..)| *named == name) {
+        return Some((*code, keysym.to_string()));
+    }
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        let code = match c {
+            'A'..='Z' => LETTERS[(c as u8 - b'A') as usize],
+            '0'..='9' => DIGITS[(c as u8 - b'0') as usize],
+            _ => return None,
+        };
+        return Some((code, c.to_ascii_lowercase().to_string()));
+    }
+    let number: u16 = name.strip_prefix('F')?.parse().ok()?;
+    let code = match number {
+        1..=10 => 58 + number,
+        11 | 12 => 76 + number,
+        13..=24 => 170 + number,
+        _ => return None,
+    };
+    Some((code, name.to_string()))
+}
+
+/// Press a key combination, via ydotool or wtype: modifiers down in order,
+/// the key, then modifiers up in reverse order
+fn send_keys(combo: &Hotkey) -> Result<()> {
+    let (key_code, keysym) = key_codes(&combo.key)
+        .ok_or_else(|| anyhow::anyhow!("Key {} can't be pressed on Wayland", combo.key))?;
+
+    if let Some(ydotool) = find_helper("ydotool") {
+        // Linux input event codes
+        let modifier_code = |m: &HotkeyModifier| match m {
+            HotkeyModifier::Ctrl => 29,
+            HotkeyModifier::Alt => 56,
+            HotkeyModifier::Shift => 42,
+            HotkeyModifier::Super => 125,
+        };
+        let mut events: Vec<String> = combo
+            .modifiers
+            .iter()
+            .map(|m| format!("{}:1", modifier_code(m)))
+            .collect();
+        events.push(format!("{}:1", key_code));
+        events.push(format!("{}:0", key_code));
+        events.extend(
+            combo
+                .modifiers
+                .iter()
+                .rev()
+                .map(|m| format!("{}:0", modifier_code(m))),
+        );
+
+        let mut args = vec!["key"];
+        args.extend(events.iter().map(String::as_str));
+        return run(&ydotool, &args);
+    }
+
+    if let Some(wtype) = find_helper("wtype") {
+        let modifier_name = |m: &HotkeyModifier| match m {
+            HotkeyModifier::Ctrl => "ctrl",
+            HotkeyModifier::Alt => "alt",
+            HotkeyModifier::Shift => "shift",
+            HotkeyModifier::Super => "logo",
+        };
+        let mut args = Vec::new();
+        for m in &combo.modifiers {
+            args.extend(["-M", modifier_name(m)]);
+        }
+        args.extend(["-k", keysym.as_str()]);
+        for m in combo.modifiers.iter().rev() {
+            args.extend(["-m", modifier_name(m)]);
+        }
+        return run(&wtype, &args);
+    }
+
+    anyhow::bail!(
+        "Simulating keys on Wayland needs `ydotool` (with ydotoold running) or `wtype`; neither was found in PATH"
+    )
+}
+
+/// Type text directly, via ydotool or wtype
+fn type_text(text: &str) -> Result<()> {
+    if let Some(ydotool) = find_helper("ydotool") {
+        return run(&ydotool, &["type", "--", text]);
+    }
+    if let Some(wtype) = find_helper("wtype") {
+        return run(&wtype, &["--", text]);
+    }
+    anyhow::bail!("Typing text on Wayland needs `ydotool` (with ydotoold running) or `wtype`; neither was found in PATH")
+}
+
+/// Wayland counterpart of `paste_via_clipboard`
+pub fn paste(
+    text: &str,
+    settings: &Settings,
+    progress: Option<&dyn Fn(usize, usize)>,
+) -> Result<PasteOutcome> {
+    let outcome = |attempted, retried, clipboard_restored| PasteOutcome {
+        attempted,
+        method_used: settings.paste_method.clone(),
+        retried,
+        clipboard_restored,
+        clipboard_only: false,
+        clipboard_retries: 0,
+        aborted: None,
+    };
+    if text.is_empty() {
+        return Ok(outcome(false, false, false));
+    }
+
+    std::thread::sleep(Duration::from_millis(settings.paste_delay_ms));
+
+    if settings.paste_method == PasteMethod::TypeOut {
+        let aborted = super::paste::type_out(text, settings, progress, type_text)?;
+        return Ok(PasteOutcome {
+            aborted,
+            ..outcome(true, false, false)
+        });
+    }
+
+    if settings.paste_method == PasteMethod::MiddleClick {
+        copy(text.as_bytes(), "text/plain;charset=utf-8", true)?;
+        std::thread::sleep(Duration::from_millis(50));
+        let ydotool = find_helper("ydotool").ok_or_else(|| {
+            anyhow::anyhow!("Middle-click paste on Wayland needs `ydotool` (with ydotoold running), which was not found in PATH")
+        })?;
+        // 0xC2: press and release the middle button
+        run(&ydotool, &["click", "0xC2"])?;
+        return Ok(outcome(true, false, false));
+    }
+
+    let combo = settings
+        .paste_method
+        .key_combo()
+        .map_err(|e| anyhow::anyhow!("Invalid paste combo: {}", e))?
+        .ok_or_else(|| anyhow::anyhow!("Paste method {:?} has no key combo", settings.paste_method))?;
+    let backup = if settings.restore_clipboard {
+        backup()
+    } else {
+        None
+    };
+    copy_text(text)?;
+    std::thread::sleep(Duration::from_millis(50));
+
+    let press = || send_keys(&combo);
+
+    // We can't tell whether the target read the clipboard, so paste_verify
+    // only retries a keystroke that failed to send
+    let mut retried = false;
+    let mut waited_ms = 0;
+    match press() {
+        Err(e) if settings.paste_verify => {
+            log::warn!("Paste keystroke failed, retrying: {}", e);
+            std::thread::sleep(Duration::from_millis(settings.paste_verify_window_ms));
+            press()?;
+            retried = true;
+        }
+        result => {
+            result?;
+            if settings.paste_verify {
+                std::thread::sleep(Duration::from_millis(settings.paste_verify_window_ms));
+                waited_ms = settings.paste_verify_window_ms;
+            }
+        }
+    }
+
+    let clipboard_restored = match backup {
+        Some((mime, data)) => {
+            std::thread::sleep(Duration::from_millis(
+                settings
+                    .clipboard_restore_delay_ms
+                    .saturating_sub(waited_ms),
+            ));
+            match copy(&data, &mime, false) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::warn!("Failed to restore clipboard: {}", e);
+                    false
+                }
+            }
+        }
+        None => false,
+    };
+
+    Ok(outcome(true, retried, clipboard_restored))
+}
+
+/// Wayland counterpart of the submit key press
+pub fn submit(key: &SubmitKey) -> Result<()> {
+    send_keys(&key.key_combo())
+}
@@ -0,0 +1,126 @@
+//! Background thread that runs pastes one at a time, so the caller (usually
+//! the UI thread) doesn't sit through the paste delays and concurrent
+//! requests queue up instead of interleaving keystrokes.
+
+use std::ffi::{c_char, CString};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+use super::paste;
+use crate::settings::Settings;
+
+/// Called on the callback thread when a queued paste finishes.
+/// `result_json` is the `phemy_paste_text_ex` result and is only valid
+/// during the call. Null means none.
+pub type PasteDoneCallback = Option<extern "C" fn(success: bool, result_json: *const c_char)>;
+
+/// Called on the callback thread after each TypeOut chunk with the number
+/// of characters typed so far and the total. Null means none.
+pub type PasteProgressCallback = Option<extern "C" fn(typed: usize, total: usize)>;
+
+struct Job {
+    text: String,
+    target_app: Option<String>,
+    progress: PasteProgressCallback,
+    done: PasteDoneCallback,
+}
+
+struct Worker {
+    sender: Sender<Job>,
+    handle: JoinHandle<()>,
+}
+
+static WORKER: Mutex<Option<Worker>> = Mutex::new(None);
+
+/// Set while shutting down; queued pastes are dropped instead of run
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Queue a paste. Starts the paste thread on first use.
+pub fn enqueue(
+    text: String,
+    target_app: Option<String>,
+    progress: PasteProgressCallback,
+    done: PasteDoneCallback,
+) -> anyhow::Result<()> {
+    let mut worker = WORKER.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+    if worker.is_none() {
+        STOPPING.store(false, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let handle = std::thread::Builder::new()
+            .name("phemy-paste".into())
+            .spawn(move || {
+                for job in receiver {
+                    run(job);
+                }
+            })?;
+        *worker = Some(Worker { sender, handle });
+    }
+
+    let job = Job {
+        text,
+        target_app,
+        progress,
+        done,
+    };
+    worker
+        .as_ref()
+        .expect("worker started above")
+        .sender
+        .send(job)
+        .map_err(|_| anyhow::anyhow!("Paste thread has stopped"))
+}
+
+fn run(job: Job) {
+    let result = if STOPPING.load(Ordering::Relaxed) {
+        Err(anyhow::anyhow!("Paste cancelled: shutting down"))
+    } else {
+        // Settings are read when the paste runs, not when it was queued
+        let progress = job.progress.map(|progress| {
+            move |typed, total| crate::ffi::dispatch_lossy("paste_progress", move || progress(typed, total))
+        });
+        paste::paste_and_maybe_submit(
+            &job.text,
+            &Settings::load(),
+            job.target_app.as_deref(),
+            progress.as_ref().map(|p| p as &dyn Fn(usize, usize)),
+        )
+    };
+
+    let (success, json) = match result {
+        Ok(outcome) => (
+            outcome.succeeded(),
+            serde_json::to_string(&outcome).unwrap_or_default(),
+        ),
+        Err(e) => {
+            log::error!("Failed to paste text: {}", e);
+            (false, serde_json::json!({ "error": e.to_string() }).to_string())
+        }
+    };
+
+    if let Some(done) = job.done {
+        let json = CString::new(json).unwrap_or_default();
+        crate::ffi::dispatch(move || done(success, json.as_ptr()));
+    }
+}
+
+/// Stop the paste thread: the running paste is cancelled at its next
+/// checkpoint, queued ones report an error, and the thread is joined.
+pub fn shutdown() {
+    let worker = match WORKER.lock() {
+        Ok(mut worker) => worker.take(),
+        Err(_) => return,
+    };
+    let Some(Worker { sender, handle }) = worker else {
+        return;
+    };
+
+    STOPPING.store(true, Ordering::Relaxed);
+    paste::cancel_paste();
+    drop(sender);
+    if handle.join().is_err() {
+        log::error!("Paste thread panicked");
+    }
+    STOPPING.store(false, Ordering::Relaxed);
+}
@@ -0,0 +1,104 @@
+//! SQLCipher support for encrypting history at rest.
+//!
+//! Only available with the `sqlcipher` cargo feature, which swaps the bundled
+//! SQLite for SQLCipher.
+
+use anyhow::Result;
+use rusqlite::Connection;
+use std::path::Path;
+
+/// Open `path` with `key`, converting an existing plaintext database first.
+#[cfg(feature = "sqlcipher")]
+pub fn open_encrypted(path: &Path, key: &str) -> Result<Connection> {
+    if path.exists() && is_plaintext(path) {
+        encrypt_existing(path, key)?;
+    }
+
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "key", key)?;
+    check_key(&conn, path)?;
+    Ok(conn)
+}
+
+#[cfg(not(feature = "sqlcipher"))]
+pub fn open_encrypted(_path: &Path, _key: &str) -> Result<Connection> {
+    anyhow::bail!("Database encryption not compiled (enable 'sqlcipher' feature)")
+}
+
+/// Open the encrypted file at `path` read-only with `key`. Unlike
+/// `open_encrypted`, a plaintext file is refused rather than converted,
+/// so a backup being restored is never rewritten.
+#[cfg(feature = "sqlcipher")]
+pub fn open_read_only(path: &Path, key: &str) -> Result<Connection> {
+    anyhow::ensure!(
+        !is_plaintext(path),
+        "{:?} is not encrypted; only backups made while encryption is on can be restored",
+        path
+    );
+
+    let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    conn.pragma_update(None, "key", key)?;
+    check_key(&conn, path)?;
+    Ok(conn)
+}
+
+#[cfg(not(feature = "sqlcipher"))]
+pub fn open_read_only(_path: &Path, _key: &str) -> Result<Connection> {
+    anyhow::bail!("Database encryption not compiled (enable 'sqlcipher' feature)")
+}
+
+/// Fail with "Wrong database key" unless `conn` can read its first page.
+#[cfg(feature = "sqlcipher")]
+fn check_key(conn: &Connection, path: &Path) -> Result<()> {
+    // The key is only checked when the first page is read
+    match conn.query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0)) {
+        Ok(_) => Ok(()),
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::NotADatabase => {
+            anyhow::bail!("Wrong database key for {:?}", path)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether the file at `path` is a readable, unencrypted SQLite database.
+#[cfg(feature = "sqlcipher")]
+fn is_plaintext(path: &Path) -> bool {
+    Connection::open(path)
+        .and_then(|conn| conn.query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0)))
+        .is_ok()
+}
+
+/// Re-encrypt a plaintext database in place via `sqlcipher_export`.
+/// The original is only replaced once the encrypted copy is complete.
+#[cfg(feature = "sqlcipher")]
+fn encrypt_existing(path: &Path, key: &str) -> Result<()> {
+    log::info!("Encrypting existing database at {:?}", path);
+
+    let encrypted_path = path.with_extension("db.encrypting");
+    let _ = std::fs::remove_file(&encrypted_path);
+
+    {
+        let conn = Connection::open(path)?;
+        // Fold any WAL content into the main file before copying
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        conn.execute(
+            "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+            rusqlite::params![encrypted_path.to_string_lossy(), key],
+        )?;
+        conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+        conn.execute_batch(&format!("PRAGMA encrypted.user_version = {}", version))?;
+        conn.execute_batch("DETACH DATABASE encrypted")?;
+    }
+
+    std::fs::rename(&encrypted_path, path)?;
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(suffix);
+        let _ = std::fs::remove_file(sidecar);
+    }
+
+    log::info!("Database encrypted");
+    Ok(())
+}
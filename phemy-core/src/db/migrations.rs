@@ -0,0 +1,419 @@
+//! Schema migrations, tracked with `PRAGMA user_version`.
+//!
+//! Each migration moves the schema from `version - 1` to `version`. All
+//! pending migrations run in a single transaction, so a failure leaves the
+//! database exactly as it was.
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, Transaction};
+
+enum Step {
+    Sql(&'static str),
+    Rust(fn(&Transaction) -> Result<()>),
+}
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    step: Step,
+}
+
+/// Ordered list of migrations. Append new ones; never edit or reorder existing ones.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        step: Step::Sql(
+            "CREATE TABLE IF NOT EXISTS history (
+                id TEXT PRIMARY KEY,
+                raw_transcript TEXT NOT NULL,
+                optimized_prompt TEXT,
+                prompt_mode TEXT NOT NULL DEFAULT 'clean',
+                llm_provider TEXT,
+                duration_secs REAL NOT NULL DEFAULT 0.0,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS vocabulary (
+                id TEXT PRIMARY KEY,
+                word TEXT NOT NULL UNIQUE,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_history_created_at ON history(created_at DESC);",
+        ),
+    },
+    Migration {
+        version: 2,
+        description: "history metadata, favorites, recordings and vocabulary index",
+        step: Step::Rust(history_metadata),
+    },
+    Migration {
+        version: 3,
+        description: "numeric created_at for ordering and range queries",
+        step: Step::Rust(created_at_unix),
+    },
+    Migration {
+        version: 4,
+        description: "Unicode-aware word counts and character counts",
+        step: Step::Rust(text_counts),
+    },
+    Migration {
+        version: 5,
+        description: "pipeline failure events",
+        step: Step::Sql(
+            "CREATE TABLE IF NOT EXISTS events (
+                id TEXT PRIMARY KEY,
+                kind TEXT NOT NULL,
+                message TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                created_at_unix INTEGER NOT NULL,
+                duration_secs REAL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_events_created_at_unix ON events(created_at_unix DESC);",
+        ),
+    },
+    Migration {
+        version: 6,
+        description: "full-text search index for history",
+        // The index keeps its own copy of the text keyed by id: history has no
+        // INTEGER PRIMARY KEY, so its rowids aren't stable across VACUUM.
+        step: Step::Sql(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS history_fts USING fts5(
+                id UNINDEXED,
+                raw_transcript,
+                optimized_prompt,
+                tokenize = 'unicode61 remove_diacritics 2'
+            );
+
+            INSERT INTO history_fts (id, raw_transcript, optimized_prompt)
+            SELECT id, raw_transcript, COALESCE(optimized_prompt, '') FROM history;
+
+            CREATE TRIGGER IF NOT EXISTS history_fts_insert AFTER INSERT ON history BEGIN
+                INSERT INTO history_fts (id, raw_transcript, optimized_prompt)
+                VALUES (new.id, new.raw_transcript, COALESCE(new.optimized_prompt, ''));
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS history_fts_delete AFTER DELETE ON history BEGIN
+                DELETE FROM history_fts WHERE id = old.id;
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS history_fts_update
+            AFTER UPDATE OF raw_transcript, optimized_prompt ON history BEGIN
+                UPDATE history_fts
+                SET raw_transcript = new.raw_transcript,
+                    optimized_prompt = COALESCE(new.optimized_prompt, '')
+                WHERE id = old.id;
+            END;",
+        ),
+    },
+    Migration {
+        version: 7,
+        description: "source file of entries imported from audio files",
+        step: Step::Sql(
+            "ALTER TABLE history ADD COLUMN source_path TEXT;
+
+            CREATE INDEX IF NOT EXISTS idx_history_source_path ON history(source_path)
+            WHERE source_path IS NOT NULL;",
+        ),
+    },
+];
+
+/// Latest schema version known to this build
+pub const SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Schema version recorded in the database
+pub fn user_version(conn: &Connection) -> Result<u32> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Bring the database schema up to `SCHEMA_VERSION`.
+pub fn run(conn: &mut Connection) -> Result<()> {
+    let current = user_version(conn)?;
+    anyhow::ensure!(
+        current <= SCHEMA_VERSION,
+        "Database schema version {} is newer than this build supports ({})",
+        current,
+        SCHEMA_VERSION
+    );
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    for migration in pending {
+        log::info!(
+            "Applying database migration {} ({})",
+            migration.version,
+            migration.description
+        );
+        match &migration.step {
+            Step::Sql(sql) => tx.execute_batch(sql).map_err(anyhow::Error::from),
+            Step::Rust(f) => f(&tx),
+        }
+        .with_context(|| {
+            format!(
+                "Database migration {} ({}) failed",
+                migration.version, migration.description
+            )
+        })?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+    }
+    tx.commit()?;
+
+    log::info!("Database schema at version {}", SCHEMA_VERSION);
+    Ok(())
+}
+
+fn history_metadata(tx: &Transaction) -> Result<()> {
+    // Databases opened by pre-release builds may already have some of these
+    add_column_if_missing(tx, "history", "elapsed_ms", "INTEGER")?;
+    add_column_if_missing(tx, "history", "updated_at", "TEXT")?;
+    add_column_if_missing(tx, "history", "favorite", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "history", "word_count", "INTEGER")?;
+    add_column_if_missing(tx, "history", "audio_path", "TEXT")?;
+    tx.execute_batch(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_vocabulary_word_nocase
+         ON vocabulary(word COLLATE NOCASE);",
+    )?;
+    backfill_word_counts(tx)
+}
+
+fn created_at_unix(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "history", "created_at_unix", "INTEGER NOT NULL DEFAULT 0")?;
+
+    let mut stmt = tx.prepare("SELECT id, created_at FROM history")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (id, created_at) in &rows {
+        match chrono::DateTime::parse_from_rfc3339(created_at) {
+            Ok(t) => {
+                tx.execute(
+                    "UPDATE history SET created_at_unix = ?2 WHERE id = ?1",
+                    rusqlite::params![id, t.timestamp()],
+                )?;
+            }
+            Err(e) => log::warn!("History entry {} has unparseable created_at {:?}: {}", id, created_at, e),
+        }
+    }
+
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_history_created_at_unix ON history(created_at_unix DESC);
+         DROP INDEX IF EXISTS idx_history_created_at;",
+    )?;
+    Ok(())
+}
+
+fn text_counts(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "history", "char_count", "INTEGER NOT NULL DEFAULT 0")?;
+
+    // Recount every row: word counts from version 2 split on whitespace only
+    let mut stmt = tx.prepare("SELECT id, raw_transcript FROM history")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (id, transcript) in &rows {
+        tx.execute(
+            "UPDATE history SET word_count = ?2, char_count = ?3 WHERE id = ?1",
+            rusqlite::params![
+                id,
+                crate::text::count_words(transcript) as i64,
+                crate::text::count_chars(transcript) as i64,
+            ],
+        )?;
+    }
+    if !rows.is_empty() {
+        log::info!("Recounted words and characters for {} history entries", rows.len());
+    }
+    Ok(())
+}
+
+/// Add a column to an existing table unless it is already present
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))?;
+        log::info!("Added column {}.{}", table, column);
+    }
+    Ok(())
+}
+
+/// Compute `word_count` for rows stored before the column existed
+fn backfill_word_counts(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT id, raw_transcript FROM history WHERE word_count IS NULL")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (id, transcript) in &rows {
+        conn.execute(
+            "UPDATE history SET word_count = ?2 WHERE id = ?1",
+            rusqlite::params![id, crate::text::count_words(transcript) as i64],
+        )?;
+    }
+    if !rows.is_empty() {
+        log::info!("Backfilled word counts for {} history entries", rows.len());
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database as the first release left it: no user_version, no
+    /// metadata columns
+    fn v0_fixture() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE history (
+                id TEXT PRIMARY KEY,
+                raw_transcript TEXT NOT NULL,
+                optimized_prompt TEXT,
+                prompt_mode TEXT NOT NULL DEFAULT 'clean',
+                llm_provider TEXT,
+                duration_secs REAL NOT NULL DEFAULT 0.0,
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE vocabulary (
+                id TEXT PRIMARY KEY,
+                word TEXT NOT NULL UNIQUE,
+                created_at TEXT NOT NULL
+            );
+            INSERT INTO history VALUES
+                ('a', 'héllo wörld again', 'Hello world.', 'clean', 'local', 2.5,
+                 '2024-05-01T10:00:00+00:00'),
+                ('b', 'second entry', NULL, 'code', NULL, 1.0, 'not a date');
+            INSERT INTO vocabulary VALUES ('v', 'Phemy', '2024-05-01T10:00:00+00:00');",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+        let names = stmt.query_map([], |row| row.get(1)).unwrap();
+        names.collect::<Result<_, _>>().unwrap()
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        let sql = format!("SELECT COUNT(*) FROM {}", table);
+        conn.query_row(&sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn v0_database_upgrades_through_every_migration() {
+        let mut conn = v0_fixture();
+        run(&mut conn).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), SCHEMA_VERSION);
+
+        let history = columns(&conn, "history");
+        let added = [
+            "elapsed_ms",
+            "updated_at",
+            "favorite",
+            "word_count",
+            "audio_path",
+            "created_at_unix",
+            "char_count",
+        ];
+        for column in added {
+            assert!(history.contains(&column.to_string()), "no history.{}", column);
+        }
+
+        let (words, chars, unix, favorite): (i64, i64, i64, bool) = conn
+            .query_row(
+                "SELECT word_count, char_count, created_at_unix, favorite
+                 FROM history WHERE id = 'a'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!((words, chars, unix, favorite), (3, 17, 1_714_557_600, false));
+        // An unparseable date is kept, sorting as the epoch
+        let unix: i64 = conn
+            .query_row("SELECT created_at_unix FROM history WHERE id = 'b'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(unix, 0);
+
+        // Existing rows are in the search index
+        let search = "SELECT id FROM history_fts WHERE history_fts MATCH 'hello'";
+        let found: String = conn.query_row(search, [], |row| row.get(0)).unwrap();
+        assert_eq!(found, "a");
+        assert!(columns(&conn, "events").contains(&"kind".to_string()));
+        assert_eq!(count(&conn, "vocabulary"), 1);
+    }
+
+    #[test]
+    fn running_again_changes_nothing() {
+        let mut conn = v0_fixture();
+        run(&mut conn).unwrap();
+        run(&mut conn).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert_eq!(count(&conn, "history_fts"), 2);
+    }
+
+    #[test]
+    fn prerelease_columns_are_kept() {
+        let mut conn = v0_fixture();
+        conn.execute_batch(
+            "ALTER TABLE history ADD COLUMN favorite INTEGER NOT NULL DEFAULT 0;
+             UPDATE history SET favorite = 1 WHERE id = 'b';",
+        )
+        .unwrap();
+        run(&mut conn).unwrap();
+        let favorite: bool = conn
+            .query_row("SELECT favorite FROM history WHERE id = 'b'", [], |row| row.get(0))
+            .unwrap();
+        assert!(favorite);
+    }
+
+    #[test]
+    fn failed_migration_leaves_the_database_untouched() {
+        let mut conn = v0_fixture();
+        // A plain table where migration 6 expects to create the search index
+        conn.execute_batch("CREATE TABLE history_fts (unrelated TEXT);").unwrap();
+        let before = columns(&conn, "history");
+
+        let err = run(&mut conn).unwrap_err();
+        assert!(format!("{:#}", err).contains("Database migration 6"), "{:#}", err);
+        assert_eq!(user_version(&conn).unwrap(), 0);
+        assert_eq!(columns(&conn, "history"), before);
+        assert!(columns(&conn, "events").is_empty());
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let mut conn = v0_fixture();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        let err = run(&mut conn).unwrap_err();
+        assert!(err.to_string().contains("newer than this build"), "{}", err);
+    }
+
+    #[test]
+    fn init_reports_a_failed_migration() {
+        let env = crate::test_support::env();
+        let path = env.path().join("history.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch("CREATE TABLE history_fts (unrelated TEXT);").unwrap();
+        drop(conn);
+
+        let err = crate::db::init(&path, None).unwrap_err();
+        assert!(format!("{:#}", err).contains("failed"), "{:#}", err);
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), 0);
+    }
+}
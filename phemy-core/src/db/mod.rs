@@ -0,0 +1,1980 @@
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+mod encryption;
+mod migrations;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+pub struct Database {
+    pub conn: Mutex<Connection>,
+    /// SQLCipher key the database was opened with; backups are encrypted with it too
+    key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct HistoryEntry {
+    pub id: String,
+    pub raw_transcript: String,
+    pub optimized_prompt: Option<String>,
+    pub prompt_mode: String,
+    pub llm_provider: Option<String>,
+    pub duration_secs: f64,
+    pub created_at: String,
+    /// `created_at` as Unix seconds, used for ordering and range queries
+    #[serde(default)]
+    pub created_at_unix: i64,
+    /// Time spent optimizing the transcript with the LLM
+    pub elapsed_ms: Option<u64>,
+    /// Set when the entry is edited after creation
+    pub updated_at: Option<String>,
+    /// Pinned by the user
+    #[serde(default)]
+    pub favorite: bool,
+    /// Saved recording for this entry, if recordings are kept
+    pub audio_path: Option<String>,
+    /// Set when `audio_path` points to a file that no longer exists
+    #[serde(default)]
+    pub audio_missing: bool,
+    /// Words in `raw_transcript` (see `text::count_words`)
+    #[serde(default)]
+    pub word_count: u64,
+    /// Characters in `raw_transcript`
+    #[serde(default)]
+    pub char_count: u64,
+    /// Audio file the entry was transcribed from, for entries imported from
+    /// files rather than recorded
+    #[serde(default)]
+    pub source_path: Option<String>,
+}
+
+/// How long a statement waits for a lock held by another connection
+const BUSY_TIMEOUT_MS: u64 = 5000;
+
+/// Global database instance
+static DB: std::sync::LazyLock<Mutex<Option<Database>>> =
+    std::sync::LazyLock::new(|| Mutex::new(None));
+
+/// Path that `init` treats as a request for an in-memory database
+pub const MEMORY_PATH: &str = ":memory:";
+
+/// Initialize the database at the given path.
+/// With `key`, the database is opened (or converted to) SQLCipher encryption.
+/// A path of `:memory:` opens an in-memory database instead (see `init_in_memory`).
+/// Returns warnings about problems that didn't stop the database opening.
+pub fn init(db_path: &PathBuf, key: Option<&str>) -> Result<Vec<String>> {
+    if db_path.as_os_str() == MEMORY_PATH {
+        return init_in_memory().map(|_| Vec::new());
+    }
+    let mut warnings = Vec::new();
+
+    if let Some(parent) = db_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let conn = match key {
+        Some(key) => encryption::open_encrypted(db_path, key)?,
+        None => Connection::open(db_path)?,
+    };
+
+    // WAL lets readers run alongside the history insert; the busy timeout
+    // waits out short lock contention instead of failing with "database is locked"
+    let journal_mode: String =
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    if !journal_mode.eq_ignore_ascii_case("wal") {
+        log::warn!("Database journal mode is {} (WAL unavailable)", journal_mode);
+        warnings.push(format!("Database journal mode is {} (WAL unavailable)", journal_mode));
+    }
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    conn.busy_timeout(std::time::Duration::from_millis(BUSY_TIMEOUT_MS))?;
+
+    install(conn, key)?;
+
+    // Set restrictive permissions (owner-only read/write) on the database file
+    #[cfg(unix)]
+    {
+        let perms = std::fs::Permissions::from_mode(0o600);
+        if let Err(e) = std::fs::set_permissions(db_path, perms) {
+            log::warn!("Failed to set database file permissions: {}", e);
+            warnings.push(format!("Database file permissions could not be set: {}", e));
+        }
+    }
+
+    log::info!("Database initialized at {:?}", db_path);
+    Ok(warnings)
+}
+
+/// Initialize an in-memory database. Nothing is written to disk and all
+/// data is lost when the database is replaced or the process exits.
+pub fn init_in_memory() -> Result<()> {
+    install(Connection::open_in_memory()?, None)?;
+    log::info!("In-memory database initialized");
+    Ok(())
+}
+
+/// Migrate `conn` and make it the global database.
+fn install(mut conn: Connection, key: Option<&str>) -> Result<()> {
+    // Rows removed by INSERT OR REPLACE must fire the delete trigger that
+    // keeps the search index in sync
+    conn.pragma_update(None, "recursive_triggers", true)?;
+
+    migrations::run(&mut conn)?;
+
+    let mut db = DB.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+    *db = Some(Database {
+        conn: Mutex::new(conn),
+        key: key.map(str::to_owned),
+    });
+    Ok(())
+}
+
+/// Close the global database. Calls fail with "Database not initialized"
+/// until `init` is called again.
+pub fn close() {
+    let mut db = DB.lock().unwrap_or_else(|e| e.into_inner());
+    if db.take().is_some() {
+        log::info!("Database closed");
+    }
+}
+
+/// Get a reference to the global database.
+///
+/// Every history call waits on this lock, so `f` must only run SQL and
+/// file cleanup: no loading settings, blocking on the async runtime or
+/// taking other phemy locks.
+fn with_db<T, F: FnOnce(&Database) -> Result<T>>(f: F) -> Result<T> {
+    let guard = DB.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let db = guard
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Database not initialized"))?;
+    f(db)
+}
+
+/// What `insert_history` did with an entry
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum InsertOutcome {
+    Inserted,
+    /// An entry with the same transcript already existed within the dedupe
+    /// window; it was updated with the new optimized prompt instead.
+    Merged { id: String },
+}
+
+/// Insert a history entry, retrying once if the first attempt fails.
+///
+/// With `dedupe_window_secs`, an existing entry with the same raw transcript
+/// created within that many seconds before this one is updated instead.
+pub fn insert_history(entry: &HistoryEntry, dedupe_window_secs: Option<u64>) -> Result<InsertOutcome> {
+    let insert = || {
+        with_db(|db| {
+            let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+            if let Some(window) = dedupe_window_secs {
+                if let Some(id) = find_recent_duplicate(&conn, entry, window)? {
+                    merge_into(&conn, &id, entry)?;
+                    return Ok(InsertOutcome::Merged { id });
+                }
+            }
+            insert_history_row(&conn, entry, "INSERT")?;
+            Ok(InsertOutcome::Inserted)
+        })
+    };
+
+    let outcome = insert().or_else(|e| {
+        log::warn!("History insert failed, retrying once: {}", e);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        insert()
+    })?;
+
+    if let InsertOutcome::Merged { id } = &outcome {
+        log::info!("Merged duplicate history entry into {}", id);
+    }
+    Ok(outcome)
+}
+
+/// Id of the entry `insert_history` would merge `entry` into with a dedupe
+/// window of `window_secs`, if any.
+pub fn find_history_duplicate(entry: &HistoryEntry, window_secs: u64) -> Result<Option<String>> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        find_recent_duplicate(&conn, entry, window_secs)
+    })
+}
+
+/// Merge `entry` into the existing entry `id`, as `insert_history` does
+/// with a duplicate.
+pub fn merge_history(id: &str, entry: &HistoryEntry) -> Result<()> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        merge_into(&conn, id, entry)
+    })?;
+    log::info!("Merged duplicate history entry into {}", id);
+    Ok(())
+}
+
+/// Find the newest entry with the same transcript created within `window_secs` before `entry`.
+fn find_recent_duplicate(conn: &Connection, entry: &HistoryEntry, window_secs: u64) -> Result<Option<String>> {
+    let cutoff = entry.created_at_unix - window_secs as i64;
+
+    let id = conn
+        .query_row(
+            "SELECT id FROM history
+             WHERE raw_transcript = ?1 AND created_at_unix >= ?2 AND id != ?3
+             ORDER BY created_at_unix DESC, rowid DESC LIMIT 1",
+            rusqlite::params![entry.raw_transcript, cutoff, entry.id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(id)
+}
+
+/// Update an existing entry with a newer run's output. The existing
+/// recording is kept; the new one is only used if there wasn't one.
+fn merge_into(conn: &Connection, id: &str, entry: &HistoryEntry) -> Result<()> {
+    let existing_audio: Option<String> =
+        conn.query_row("SELECT audio_path FROM history WHERE id = ?1", [id], |row| row.get(0))?;
+
+    conn.execute(
+        "UPDATE history SET
+            optimized_prompt = ?2,
+            prompt_mode = ?3,
+            llm_provider = ?4,
+            elapsed_ms = ?5,
+            audio_path = COALESCE(audio_path, ?6),
+            updated_at = ?7
+         WHERE id = ?1",
+        rusqlite::params![
+            id,
+            entry.optimized_prompt,
+            entry.prompt_mode,
+            entry.llm_provider,
+            entry.elapsed_ms.map(|ms| ms as i64),
+            entry.audio_path,
+            chrono::Utc::now().to_rfc3339(),
+        ],
+    )?;
+
+    if let (Some(_), Some(new_audio)) = (existing_audio, &entry.audio_path) {
+        remove_recordings(std::slice::from_ref(new_audio));
+    }
+    Ok(())
+}
+
+/// Insert a full history row. `verb` is the INSERT variant to use
+/// (e.g. "INSERT OR IGNORE"); returns the number of rows written.
+fn insert_history_row(conn: &Connection, entry: &HistoryEntry, verb: &str) -> Result<usize> {
+    let written = conn.execute(
+        &format!(
+            "{} INTO history ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            verb, HISTORY_COLUMNS
+        ),
+        rusqlite::params![
+            entry.id,
+            entry.raw_transcript,
+            entry.optimized_prompt,
+            entry.prompt_mode,
+            entry.llm_provider,
+            entry.duration_secs,
+            entry.created_at,
+            entry.elapsed_ms.map(|ms| ms as i64),
+            entry.updated_at,
+            entry.favorite,
+            entry.audio_path,
+            entry.created_at_unix,
+            entry.word_count as i64,
+            entry.char_count as i64,
+            entry.source_path,
+        ],
+    )?;
+    Ok(written)
+}
+
+/// Columns selected for a `HistoryEntry`, in the order `history_entry_from_row` reads them
+const HISTORY_COLUMNS: &str =
+    "id, raw_transcript, optimized_prompt, prompt_mode, llm_provider, duration_secs, created_at, elapsed_ms, updated_at, favorite, audio_path, created_at_unix, word_count, char_count, source_path";
+
+fn history_entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<HistoryEntry> {
+    let audio_path: Option<String> = row.get(10)?;
+    Ok(HistoryEntry {
+        id: row.get(0)?,
+        raw_transcript: row.get(1)?,
+        optimized_prompt: row.get(2)?,
+        prompt_mode: row.get(3)?,
+        llm_provider: row.get(4)?,
+        duration_secs: row.get(5)?,
+        created_at: row.get(6)?,
+        created_at_unix: row.get(11)?,
+        elapsed_ms: row.get::<_, Option<i64>>(7)?.map(|ms| ms as u64),
+        updated_at: row.get(8)?,
+        favorite: row.get(9)?,
+        audio_missing: audio_path
+            .as_ref()
+            .is_some_and(|p| !std::path::Path::new(p).exists()),
+        audio_path,
+        word_count: row.get::<_, Option<i64>>(12)?.unwrap_or(0) as u64,
+        char_count: row.get::<_, Option<i64>>(13)?.unwrap_or(0) as u64,
+        source_path: row.get(14)?,
+    })
+}
+
+/// Whether an entry was already imported from the audio file at `path`
+pub fn history_has_source(path: &str) -> Result<bool> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM history WHERE source_path = ?1)",
+            [path],
+            |row| row.get(0),
+        )?)
+    })
+}
+
+/// Get a single history entry by ID.
+pub fn get_history_entry(id: &str) -> Result<Option<HistoryEntry>> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let entry = conn
+            .query_row(
+                &format!("SELECT {} FROM history WHERE id = ?1", HISTORY_COLUMNS),
+                [id],
+                history_entry_from_row,
+            )
+            .optional()?;
+        Ok(entry)
+    })
+}
+
+pub fn get_history(limit: usize, offset: usize) -> Result<Vec<HistoryEntry>> {
+    query_history(&HistoryFilter::default(), limit, offset)
+}
+
+/// Criteria for `query_history`. Unset fields don't filter.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryFilter {
+    /// Only entries created at or after this time
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only entries created at or before this time
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    pub mode: Option<String>,
+    pub provider: Option<String>,
+    pub min_duration: Option<f64>,
+    pub favorites_only: bool,
+    /// Sort favorites before other entries (otherwise newest first only)
+    pub favorites_first: bool,
+}
+
+impl HistoryFilter {
+    /// Build the WHERE clause (empty if nothing is filtered) and its parameters.
+    fn where_clause(&self) -> (String, Vec<Box<dyn rusqlite::ToSql>>) {
+        let mut conditions: Vec<&str> = Vec::new();
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+
+        if let Some(from) = &self.from {
+            conditions.push("created_at_unix >= ?");
+            params.push(Box::new(from.timestamp()));
+        }
+        if let Some(to) = &self.to {
+            conditions.push("created_at_unix <= ?");
+            params.push(Box::new(to.timestamp()));
+        }
+        if let Some(mode) = &self.mode {
+            conditions.push("prompt_mode = ? COLLATE NOCASE");
+            params.push(Box::new(mode.clone()));
+        }
+        if let Some(provider) = &self.provider {
+            conditions.push("llm_provider = ?");
+            params.push(Box::new(provider.clone()));
+        }
+        if let Some(min) = self.min_duration {
+            conditions.push("duration_secs >= ?");
+            params.push(Box::new(min));
+        }
+        if self.favorites_only {
+            conditions.push("favorite = 1");
+        }
+
+        if conditions.is_empty() {
+            (String::new(), params)
+        } else {
+            (format!("WHERE {}", conditions.join(" AND ")), params)
+        }
+    }
+}
+
+/// Get history entries matching `filter`, newest first.
+pub fn query_history(filter: &HistoryFilter, limit: usize, offset: usize) -> Result<Vec<HistoryEntry>> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let (where_clause, mut params) = filter.where_clause();
+        params.push(Box::new(limit as i64));
+        params.push(Box::new(offset as i64));
+
+        let order = if filter.favorites_first {
+            "favorite DESC, created_at_unix DESC, rowid DESC"
+        } else {
+            "created_at_unix DESC, rowid DESC"
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM history {} ORDER BY {} LIMIT ? OFFSET ?",
+            HISTORY_COLUMNS, where_clause, order
+        ))?;
+
+        let entries = stmt
+            .query_map(
+                rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
+                history_entry_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    })
+}
+
+/// Marks around matched terms in FTS snippets; stripped before returning
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub entry: HistoryEntry,
+    pub snippet: Snippet,
+    /// Relevance; higher is better
+    pub score: f64,
+}
+
+/// A short excerpt around the matched terms.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    /// Matched ranges in `text`, as character (not byte) offsets
+    pub highlights: Vec<Highlight>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Highlight {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Full-text search over raw transcripts and optimized prompts, best match first.
+///
+/// Every word in `query` must match; the last one also matches as a prefix so
+/// results update while typing. FTS syntax in `query` is not interpreted.
+pub fn search_history(query: &str, limit: usize, offset: usize) -> Result<Vec<SearchResult>> {
+    let Some(fts_query) = fts_query(query) else {
+        return Ok(Vec::new());
+    };
+
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let columns = HISTORY_COLUMNS
+            .split(", ")
+            .map(|column| format!("h.{}", column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}, snippet(history_fts, -1, '{}', '{}', '…', 16), bm25(history_fts)
+             FROM history_fts JOIN history h ON h.id = history_fts.id
+             WHERE history_fts MATCH ?1
+             ORDER BY bm25(history_fts) LIMIT ?2 OFFSET ?3",
+            columns, MATCH_START, MATCH_END
+        ))?;
+
+        let column_count = HISTORY_COLUMNS.split(", ").count();
+        let results = stmt
+            .query_map(rusqlite::params![fts_query, limit as i64, offset as i64], |row| {
+                let snippet: String = row.get(column_count)?;
+                let rank: f64 = row.get(column_count + 1)?;
+                Ok(SearchResult {
+                    entry: history_entry_from_row(row)?,
+                    snippet: parse_snippet(&snippet),
+                    // bm25() is lower for better matches
+                    score: -rank,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(results)
+    })
+}
+
+/// Build an FTS5 query from user input: each whitespace-separated term quoted
+/// (so operators are literal and the tokenizer handles punctuation and
+/// unspaced scripts), the last one as a prefix. None if there are no terms.
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .filter(|term| term.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    let last = terms.len().checked_sub(1)?;
+
+    Some(
+        terms
+            .iter()
+            .enumerate()
+            .map(|(i, term)| if i == last { format!("{}*", term) } else { term.clone() })
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Strip the match markers from an FTS snippet, recording where they were.
+fn parse_snippet(marked: &str) -> Snippet {
+    let mut text = String::with_capacity(marked.len());
+    let mut highlights = Vec::new();
+    let mut chars = 0;
+    let mut start = None;
+
+    for c in marked.chars() {
+        match c {
+            MATCH_START => start = Some(chars),
+            MATCH_END => {
+                if let Some(start) = start.take() {
+                    highlights.push(Highlight { start, end: chars });
+                }
+            }
+            _ => {
+                text.push(c);
+                chars += 1;
+            }
+        }
+    }
+    Snippet { text, highlights }
+}
+
+/// Fields of a history entry that can be edited after creation.
+/// Unset fields are left unchanged.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HistoryUpdate {
+    pub optimized_prompt: Option<String>,
+    pub prompt_mode: Option<String>,
+    pub favorite: Option<bool>,
+}
+
+impl HistoryUpdate {
+    const FIELDS: &'static [&'static str] = &["optimized_prompt", "prompt_mode", "favorite"];
+
+    /// Parse a JSON patch, rejecting any fields that can't be updated.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("History update must be a JSON object"))?;
+
+        let unknown: Vec<&str> = object
+            .keys()
+            .map(|k| k.as_str())
+            .filter(|k| !Self::FIELDS.contains(k))
+            .collect();
+        anyhow::ensure!(
+            unknown.is_empty(),
+            "Unknown history fields: {}",
+            unknown.join(", ")
+        );
+
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// Apply `update` to the entry with `id`. Returns false if no such entry exists.
+pub fn update_history_entry(id: &str, update: &HistoryUpdate) -> Result<bool> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let updated = conn.execute(
+            "UPDATE history SET
+                optimized_prompt = COALESCE(?2, optimized_prompt),
+                prompt_mode = COALESCE(?3, prompt_mode),
+                favorite = COALESCE(?4, favorite),
+                updated_at = ?5
+             WHERE id = ?1",
+            rusqlite::params![
+                id,
+                update.optimized_prompt,
+                update.prompt_mode,
+                update.favorite,
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(updated > 0)
+    })
+}
+
+pub fn delete_history_entry(id: &str) -> Result<()> {
+    let audio_paths = with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let paths = audio_paths_where(&conn, "id = ?1", [id])?;
+        conn.execute("DELETE FROM history WHERE id = ?1", [id])?;
+        Ok(paths)
+    })?;
+    remove_recordings(&audio_paths);
+    Ok(())
+}
+
+fn audio_paths_where<P: rusqlite::Params>(conn: &Connection, condition: &str, params: P) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT audio_path FROM history WHERE audio_path IS NOT NULL AND {}",
+        condition
+    ))?;
+    let paths = stmt
+        .query_map(params, |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(paths)
+}
+
+/// Delete recording files for removed history entries. Only files inside
+/// the managed recordings directory are touched. Returns how many were deleted.
+fn remove_recordings(paths: &[String]) -> usize {
+    let Ok(dir) = crate::utils::recordings_dir().and_then(|d| Ok(d.canonicalize()?)) else {
+        return 0;
+    };
+
+    let mut removed = 0;
+    for path in paths {
+        let Ok(path) = std::path::Path::new(path).canonicalize() else {
+            continue; // Already removed
+        };
+        if !path.starts_with(&dir) {
+            log::debug!("Not deleting recording outside managed directory: {:?}", path);
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) => log::warn!("Failed to delete recording {:?}: {}", path, e),
+        }
+    }
+    removed
+}
+
+/// Pin or unpin a history entry. Returns false if no such entry exists.
+pub fn set_favorite(id: &str, favorite: bool) -> Result<bool> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let updated = conn.execute(
+            "UPDATE history SET favorite = ?2 WHERE id = ?1",
+            rusqlite::params![id, favorite],
+        )?;
+        Ok(updated > 0)
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClearReport {
+    /// History entries deleted
+    pub deleted: usize,
+    /// Recording files deleted
+    pub files_removed: usize,
+}
+
+/// Delete all history, or everything except favorites if `keep_favorites` is set,
+/// along with the entries' recordings.
+pub fn clear_history(keep_favorites: bool) -> Result<ClearReport> {
+    let condition = if keep_favorites { "favorite = 0" } else { "1 = 1" };
+    let (deleted, audio_paths) = with_db(|db| {
+        let mut conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let tx = conn.transaction()?;
+        let paths = audio_paths_where(&tx, condition, [])?;
+
+        // Clear the search index up front so the per-row delete trigger has nothing to scan
+        if keep_favorites {
+            tx.execute(
+                "DELETE FROM history_fts WHERE id IN (SELECT id FROM history WHERE favorite = 0)",
+                [],
+            )?;
+        } else {
+            tx.execute("DELETE FROM history_fts", [])?;
+        }
+        let deleted = tx.execute(&format!("DELETE FROM history WHERE {}", condition), [])?;
+        tx.commit()?;
+        Ok((deleted, paths))
+    })?;
+
+    let files_removed = remove_recordings(&audio_paths);
+    log::info!("Cleared {} history entries and {} recordings", deleted, files_removed);
+    Ok(ClearReport { deleted, files_removed })
+}
+
+// ============================================================
+// Statistics
+// ============================================================
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageStats {
+    pub total_dictations: u64,
+    pub total_audio_minutes: f64,
+    pub total_words: u64,
+    pub total_chars: u64,
+    pub average_duration_secs: f64,
+    /// Day with the most dictations, if there are any
+    pub busiest_day: Option<DayCount>,
+    /// Consecutive days with at least one dictation, ending today (or
+    /// yesterday, if nothing has been dictated yet today)
+    pub current_streak_days: u32,
+    pub longest_streak_days: u32,
+    /// One entry per day for the requested window, oldest first, zero-filled
+    pub per_day: Vec<DayCount>,
+    pub database_size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DayCount {
+    /// Local date (YYYY-MM-DD)
+    pub date: String,
+    pub count: u64,
+}
+
+/// Compute usage statistics, with per-day counts for the last `days` days.
+pub fn get_stats(days: usize) -> Result<UsageStats> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let (total, total_secs, total_words, total_chars): (i64, f64, i64, i64) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(duration_secs), 0.0), COALESCE(SUM(word_count), 0),
+                    COALESCE(SUM(char_count), 0)
+             FROM history",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+
+        // Dictations per local day, oldest first
+        let mut stmt = conn.prepare(
+            "SELECT date(created_at_unix, 'unixepoch', 'localtime') AS day, COUNT(*) FROM history
+             GROUP BY day ORDER BY day",
+        )?;
+        let daily = stmt
+            .query_map([], |row| {
+                Ok(DayCount {
+                    date: row.get(0)?,
+                    count: row.get::<_, i64>(1)? as u64,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let today = chrono::Local::now().date_naive();
+        let (current_streak_days, longest_streak_days) = streaks(&daily, today);
+
+        let per_day = (0..days)
+            .rev()
+            .map(|ago| {
+                let date = (today - chrono::Days::new(ago as u64)).format("%Y-%m-%d").to_string();
+                let count = daily.iter().find(|d| d.date == date).map_or(0, |d| d.count);
+                DayCount { date, count }
+            })
+            .collect();
+
+        Ok(UsageStats {
+            total_dictations: total as u64,
+            total_audio_minutes: total_secs / 60.0,
+            total_words: total_words as u64,
+            total_chars: total_chars as u64,
+            average_duration_secs: if total > 0 { total_secs / total as f64 } else { 0.0 },
+            busiest_day: daily.iter().max_by_key(|d| d.count).cloned(),
+            current_streak_days,
+            longest_streak_days,
+            per_day,
+            database_size_bytes: size_bytes(&conn)?,
+        })
+    })
+}
+
+/// (current, longest) runs of consecutive active days. `daily` must be sorted by date.
+fn streaks(daily: &[DayCount], today: chrono::NaiveDate) -> (u32, u32) {
+    let dates: Vec<chrono::NaiveDate> = daily
+        .iter()
+        .filter_map(|d| chrono::NaiveDate::parse_from_str(&d.date, "%Y-%m-%d").ok())
+        .collect();
+
+    let mut longest = 0;
+    let mut run = 0;
+    let mut prev: Option<chrono::NaiveDate> = None;
+    for &date in &dates {
+        run = match prev {
+            Some(p) if date.signed_duration_since(p).num_days() == 1 => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        prev = Some(date);
+    }
+
+    // The last run counts as current only if it reaches today or yesterday
+    let current = match prev {
+        Some(last) if today.signed_duration_since(last).num_days() <= 1 => run,
+        _ => 0,
+    };
+
+    (current, longest)
+}
+
+// ============================================================
+// Deduplication
+// ============================================================
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DedupeReport {
+    /// Number of groups of duplicates that were merged
+    pub groups_merged: usize,
+    /// Number of older duplicate entries removed
+    pub entries_removed: usize,
+}
+
+/// Merge existing duplicates: entries with the same raw transcript created
+/// within `window_secs` of the previous one. The newest entry of each group
+/// is kept; it inherits the favorite flag and recording from the others.
+pub fn dedupe_history(window_secs: u64) -> Result<DedupeReport> {
+    struct Row {
+        id: String,
+        raw_transcript: String,
+        created_at_unix: i64,
+        favorite: bool,
+        audio_path: Option<String>,
+    }
+
+    let mut report = DedupeReport::default();
+    let orphaned_audio = with_db(|db| {
+        let mut conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let rows = {
+            let mut stmt = conn.prepare(
+                "SELECT id, raw_transcript, created_at_unix, favorite, audio_path FROM history
+                 ORDER BY raw_transcript, created_at_unix, rowid",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok(Row {
+                    id: row.get(0)?,
+                    raw_transcript: row.get(1)?,
+                    created_at_unix: row.get(2)?,
+                    favorite: row.get(3)?,
+                    audio_path: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+
+        // Split into groups of same-transcript entries, each within the window of the previous
+        let mut groups: Vec<Vec<&Row>> = Vec::new();
+        for row in &rows {
+            match groups.last_mut() {
+                Some(group)
+                    if group.last().is_some_and(|prev| {
+                        prev.raw_transcript == row.raw_transcript
+                            && row.created_at_unix - prev.created_at_unix <= window_secs as i64
+                    }) =>
+                {
+                    group.push(row)
+                }
+                _ => groups.push(vec![row]),
+            }
+        }
+
+        let mut orphaned_audio = Vec::new();
+        let tx = conn.transaction()?;
+        for group in groups.iter().filter(|g| g.len() > 1) {
+            let (keep, older) = group.split_last().expect("group has entries");
+            let favorite = group.iter().any(|r| r.favorite);
+            let mut audio_path = keep.audio_path.clone();
+
+            for row in older {
+                match (&audio_path, &row.audio_path) {
+                    (None, Some(path)) => audio_path = Some(path.clone()),
+                    (_, Some(path)) => orphaned_audio.push(path.clone()),
+                    _ => {}
+                }
+                tx.execute("DELETE FROM history WHERE id = ?1", [&row.id])?;
+                report.entries_removed += 1;
+            }
+
+            tx.execute(
+                "UPDATE history SET favorite = ?2, audio_path = ?3 WHERE id = ?1",
+                rusqlite::params![keep.id, favorite, audio_path],
+            )?;
+            report.groups_merged += 1;
+        }
+        tx.commit()?;
+        Ok(orphaned_audio)
+    })?;
+
+    remove_recordings(&orphaned_audio);
+    log::info!(
+        "Deduplicated history: {} groups merged, {} entries removed",
+        report.groups_merged,
+        report.entries_removed
+    );
+    Ok(report)
+}
+
+// ============================================================
+// Maintenance
+// ============================================================
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceReport {
+    pub size_before_bytes: u64,
+    pub size_after_bytes: u64,
+    /// "ok", or the problems reported by `PRAGMA integrity_check`
+    pub integrity: String,
+    pub duration_ms: u64,
+}
+
+fn size_bytes(conn: &Connection) -> Result<u64> {
+    let page_count: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+    let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+    Ok((page_count * page_size) as u64)
+}
+
+/// Current size of the database in bytes.
+pub fn database_size_bytes() -> Result<u64> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        size_bytes(&conn)
+    })
+}
+
+/// Result of `PRAGMA quick_check`: "ok", or the problems it found. Much
+/// faster than the full check in `maintenance`.
+pub fn quick_check() -> Result<String> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let mut stmt = conn.prepare("PRAGMA quick_check")?;
+        let problems = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(problems.join("; "))
+    })
+}
+
+/// Check integrity, reclaim space from deleted rows, and refresh query
+/// planner statistics. Fails immediately if the connection is in use.
+pub fn maintenance() -> Result<MaintenanceReport> {
+    with_db(|db| {
+        let conn = db.conn.try_lock().map_err(|e| match e {
+            std::sync::TryLockError::WouldBlock => {
+                anyhow::anyhow!("Database is busy; try maintenance again later")
+            }
+            std::sync::TryLockError::Poisoned(e) => anyhow::anyhow!("{}", e),
+        })?;
+
+        let started = std::time::Instant::now();
+        let size_before_bytes = size_bytes(&conn)?;
+
+        let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+        let integrity = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?
+            .join("; ");
+        drop(stmt);
+
+        conn.execute_batch("VACUUM; PRAGMA optimize;")?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+
+        let report = MaintenanceReport {
+            size_before_bytes,
+            size_after_bytes: size_bytes(&conn)?,
+            integrity,
+            duration_ms: started.elapsed().as_millis() as u64,
+        };
+        log::info!(
+            "Database maintenance: {} -> {} bytes, integrity {}",
+            report.size_before_bytes,
+            report.size_after_bytes,
+            report.integrity
+        );
+        Ok(report)
+    })
+}
+
+// ============================================================
+// Backup
+// ============================================================
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupReport {
+    pub schema_version: u32,
+    pub history_rows: u64,
+    pub vocabulary_rows: u64,
+}
+
+fn row_counts(conn: &Connection) -> Result<(u64, u64)> {
+    let history: i64 = conn.query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0))?;
+    let vocabulary: i64 = conn.query_row("SELECT COUNT(*) FROM vocabulary", [], |row| row.get(0))?;
+    Ok((history as u64, vocabulary as u64))
+}
+
+/// Copy the live database to `path` with SQLite's online backup API.
+///
+/// The copy is written next to `path` first and renamed into place, so an
+/// existing backup is only replaced by a complete one. An encrypted database
+/// is backed up encrypted with the same key.
+pub fn backup_to(path: &Path) -> Result<BackupReport> {
+    let tmp_path = path.with_extension("tmp");
+
+    let report = with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let _ = std::fs::remove_file(&tmp_path);
+        match &db.key {
+            // SQLCipher only backs up between databases that are both keyed
+            Some(key) => {
+                let mut dest = encryption::open_encrypted(&tmp_path, key)?;
+                rusqlite::backup::Backup::new(&conn, &mut dest)?.run_to_completion(
+                    100,
+                    std::time::Duration::ZERO,
+                    None,
+                )?;
+            }
+            None => conn.backup(rusqlite::DatabaseName::Main, &tmp_path, None)?,
+        }
+
+        let (history_rows, vocabulary_rows) = row_counts(&conn)?;
+        Ok(BackupReport {
+            schema_version: migrations::user_version(&conn)?,
+            history_rows,
+            vocabulary_rows,
+        })
+    });
+
+    let report = report.and_then(|report| {
+        std::fs::rename(&tmp_path, path)?;
+        Ok(report)
+    });
+    if report.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    let report = report?;
+
+    log::info!(
+        "Backed up database to {:?} ({} history entries, {} vocabulary words)",
+        path,
+        report.history_rows,
+        report.vocabulary_rows
+    );
+    Ok(report)
+}
+
+/// Replace the live database with the backup at `path`.
+///
+/// The backup is validated and migrated in memory first; the live database
+/// is only written once that succeeds, and the final copy into it is a
+/// single SQLite backup, so a failure leaves the current data untouched.
+/// While encryption is on, only backups encrypted with the current key are
+/// accepted.
+pub fn restore_from(path: &Path) -> Result<BackupReport> {
+    anyhow::ensure!(path.is_file(), "Backup file not found: {}", path.display());
+
+    let key = with_db(|db| Ok(db.key.clone()))?;
+    let source = match &key {
+        Some(key) => encryption::open_read_only(path, key)?,
+        None => Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?,
+    };
+    let backup_version = validate_backup(&source)
+        .map_err(|e| anyhow::anyhow!("Not a valid phemy backup: {:#}", e))?;
+
+    let mut staged = Connection::open_in_memory()?;
+    // Keyed so SQLCipher allows the copies in from the backup and out to the live database
+    if let Some(key) = &key {
+        staged.pragma_update(None, "key", key)?;
+    }
+    rusqlite::backup::Backup::new(&source, &mut staged)?.run_to_completion(
+        100,
+        std::time::Duration::ZERO,
+        None,
+    )?;
+    drop(source);
+    migrations::run(&mut staged)?;
+
+    let report = with_db(|db| {
+        let mut conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        rusqlite::backup::Backup::new(&staged, &mut conn)?.run_to_completion(
+            100,
+            std::time::Duration::ZERO,
+            None,
+        )?;
+
+        let (history_rows, vocabulary_rows) = row_counts(&conn)?;
+        Ok(BackupReport {
+            schema_version: migrations::user_version(&conn)?,
+            history_rows,
+            vocabulary_rows,
+        })
+    })?;
+
+    log::info!(
+        "Restored database from {:?} (schema {} -> {}, {} history entries, {} vocabulary words)",
+        path,
+        backup_version,
+        report.schema_version,
+        report.history_rows,
+        report.vocabulary_rows
+    );
+    Ok(report)
+}
+
+/// Check that `conn` holds a phemy database this build can open; returns its schema version.
+fn validate_backup(conn: &Connection) -> Result<u32> {
+    let integrity: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    anyhow::ensure!(integrity == "ok", "integrity check failed: {}", integrity);
+
+    let version = migrations::user_version(conn)?;
+    anyhow::ensure!(
+        version <= migrations::SCHEMA_VERSION,
+        "schema version {} is newer than this build supports ({})",
+        version,
+        migrations::SCHEMA_VERSION
+    );
+
+    for table in ["history", "vocabulary"] {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+            [table],
+            |row| row.get(0),
+        )?;
+        anyhow::ensure!(exists, "missing table {}", table);
+    }
+    Ok(version)
+}
+
+// ============================================================
+// Import
+// ============================================================
+
+/// How `import_history` treats entries whose id already exists
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStrategy {
+    /// Keep the existing entry and skip the imported one
+    Merge,
+    /// Overwrite the existing entry with the imported one
+    Replace,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: usize,
+    pub invalid: usize,
+    pub errors: Vec<ImportError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportError {
+    /// Position of the entry in the imported array
+    pub index: usize,
+    pub id: Option<String>,
+    pub error: String,
+}
+
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// Import history from a JSON array of entries (the format returned by
+/// `get_history`). Invalid entries are reported and skipped; they don't
+/// abort the rest of the import.
+pub fn import_history(json: &str, strategy: ImportStrategy) -> Result<ImportReport> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(json)
+        .map_err(|e| anyhow::anyhow!("History import must be a JSON array: {}", e))?;
+
+    let mut report = ImportReport::default();
+    let mut valid = Vec::new();
+
+    for (index, value) in values.into_iter().enumerate() {
+        let id = value.get("id").and_then(|v| v.as_str()).map(|s| s.to_string());
+        match parse_import_entry(value) {
+            Ok(entry) => valid.push((index, entry)),
+            Err(e) => {
+                report.invalid += 1;
+                report.errors.push(ImportError { index, id, error: e.to_string() });
+            }
+        }
+    }
+
+    let verb = match strategy {
+        ImportStrategy::Merge => "INSERT OR IGNORE",
+        ImportStrategy::Replace => "INSERT OR REPLACE",
+    };
+
+    with_db(|db| {
+        let mut conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        for batch in valid.chunks(IMPORT_BATCH_SIZE) {
+            let tx = conn.transaction()?;
+            for (index, entry) in batch {
+                match insert_history_row(&tx, entry, verb) {
+                    Ok(0) => report.skipped += 1,
+                    Ok(_) => report.imported += 1,
+                    Err(e) => {
+                        report.invalid += 1;
+                        report.errors.push(ImportError {
+                            index: *index,
+                            id: Some(entry.id.clone()),
+                            error: e.to_string(),
+                        });
+                    }
+                }
+            }
+            tx.commit()?;
+        }
+        Ok(())
+    })?;
+
+    log::info!(
+        "Imported history: {} imported, {} skipped, {} invalid",
+        report.imported,
+        report.skipped,
+        report.invalid
+    );
+    Ok(report)
+}
+
+fn parse_import_entry(value: serde_json::Value) -> Result<HistoryEntry> {
+    let mut entry: HistoryEntry = serde_json::from_value(value)?;
+    Uuid::parse_str(&entry.id).map_err(|e| anyhow::anyhow!("Invalid id: {}", e))?;
+    entry.created_at_unix = chrono::DateTime::parse_from_rfc3339(&entry.created_at)
+        .map_err(|e| anyhow::anyhow!("Invalid created_at: {}", e))?
+        .timestamp();
+    // Counts in exported files may come from an older counter
+    entry.word_count = crate::text::count_words(&entry.raw_transcript) as u64;
+    entry.char_count = crate::text::count_chars(&entry.raw_transcript) as u64;
+    if let Some(updated_at) = &entry.updated_at {
+        chrono::DateTime::parse_from_rfc3339(updated_at)
+            .map_err(|e| anyhow::anyhow!("Invalid updated_at: {}", e))?;
+    }
+    Ok(entry)
+}
+
+// ============================================================
+// Events
+// ============================================================
+
+/// Most events kept; older ones are pruned as new ones are logged
+const MAX_EVENTS: i64 = 1000;
+
+/// Category of a failed or degraded pipeline run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    NoSpeech,
+    TranscriptionError,
+    LlmFallback,
+    HistoryInsertFailed,
+}
+
+/// A recorded pipeline failure. Never holds audio or transcript text.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub id: String,
+    pub kind: EventKind,
+    pub message: String,
+    pub created_at: String,
+    /// Length of the recording, if one was captured
+    pub duration_secs: Option<f64>,
+}
+
+/// Record a pipeline event, pruning the oldest beyond `MAX_EVENTS`.
+/// `message` must be an error description, not user content.
+pub fn log_event(kind: EventKind, message: &str, duration_secs: Option<f64>) -> Result<()> {
+    let kind_str = serde_json::to_value(kind)?
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let now = chrono::Utc::now();
+
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        conn.execute(
+            "INSERT INTO events (id, kind, message, created_at, created_at_unix, duration_secs)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                Uuid::new_v4().to_string(),
+                kind_str,
+                message,
+                now.to_rfc3339(),
+                now.timestamp(),
+                duration_secs,
+            ],
+        )?;
+        conn.execute(
+            "DELETE FROM events WHERE rowid NOT IN
+             (SELECT rowid FROM events ORDER BY created_at_unix DESC, rowid DESC LIMIT ?1)",
+            [MAX_EVENTS],
+        )?;
+        Ok(())
+    })
+}
+
+/// Get events, newest first.
+pub fn get_events(limit: usize, offset: usize) -> Result<Vec<Event>> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let mut stmt = conn.prepare(
+            "SELECT id, kind, message, created_at, duration_secs FROM events
+             ORDER BY created_at_unix DESC, rowid DESC LIMIT ?1 OFFSET ?2",
+        )?;
+        let rows = stmt
+            .query_map(rusqlite::params![limit as i64, offset as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, Option<f64>>(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let events = rows
+            .into_iter()
+            .filter_map(|(id, kind, message, created_at, duration_secs)| {
+                match serde_json::from_value(serde_json::Value::String(kind.clone())) {
+                    Ok(kind) => Some(Event { id, kind, message, created_at, duration_secs }),
+                    Err(_) => {
+                        log::warn!("Skipping event {} with unknown kind {:?}", id, kind);
+                        None
+                    }
+                }
+            })
+            .collect();
+        Ok(events)
+    })
+}
+
+// ============================================================
+// Vocabulary
+// ============================================================
+
+/// Add a word to the vocabulary. Words are trimmed and compared
+/// case-insensitively; returns false if the word was already present.
+pub fn add_vocabulary_word(word: &str) -> Result<bool> {
+    let word = word.trim();
+    anyhow::ensure!(!word.is_empty(), "Vocabulary word cannot be empty");
+
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO vocabulary (id, word, created_at) VALUES (?1, ?2, ?3)",
+            rusqlite::params![
+                Uuid::new_v4().to_string(),
+                word,
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(inserted > 0)
+    })
+}
+
+/// Remove a word from the vocabulary (case-insensitive).
+/// Returns false if the word was not present.
+pub fn remove_vocabulary_word(word: &str) -> Result<bool> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let deleted = conn.execute(
+            "DELETE FROM vocabulary WHERE word = ?1 COLLATE NOCASE",
+            [word.trim()],
+        )?;
+        Ok(deleted > 0)
+    })
+}
+
+/// List vocabulary words in the order they were added.
+pub fn list_vocabulary() -> Result<Vec<String>> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let mut stmt = conn.prepare("SELECT word FROM vocabulary ORDER BY created_at, rowid")?;
+        let words = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(words)
+    })
+}
+
+pub fn clear_vocabulary() -> Result<()> {
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        conn.execute("DELETE FROM vocabulary", [])?;
+        Ok(())
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VocabularySuggestion {
+    pub word: String,
+    pub count: u64,
+}
+
+/// Suggest vocabulary words from history: uncommon words that occur at least
+/// `min_occurrences` times across raw transcripts and aren't in the vocabulary
+/// yet, most frequent first.
+///
+/// Words are counted case-insensitively; each suggestion uses the spelling
+/// seen most often (preferring capitalized forms on ties).
+pub fn suggest_vocabulary(min_occurrences: u64, limit: usize) -> Result<Vec<VocabularySuggestion>> {
+    use std::collections::{HashMap, HashSet};
+
+    with_db(|db| {
+        let conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let mut stmt = conn.prepare("SELECT word FROM vocabulary")?;
+        let known: HashSet<String> = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|word| word.map(|w| w.to_lowercase()))
+            .collect::<Result<_, _>>()?;
+
+        // lowercase word -> (total, count per spelling)
+        let mut counts: HashMap<String, (u64, HashMap<String, u64>)> = HashMap::new();
+
+        // Single streaming pass; transcripts are borrowed from the row, not collected
+        let mut stmt = conn.prepare("SELECT raw_transcript FROM history")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let transcript = row.get_ref(0)?.as_str()?;
+            for word in crate::text::words(transcript) {
+                if !crate::text::is_vocabulary_candidate(word) {
+                    continue;
+                }
+                let key = word.to_lowercase();
+                if known.contains(&key) {
+                    continue;
+                }
+                let (total, forms) = counts.entry(key).or_default();
+                *total += 1;
+                *forms.entry(word.to_string()).or_default() += 1;
+            }
+        }
+
+        let mut suggestions: Vec<VocabularySuggestion> = counts
+            .into_values()
+            .filter(|(total, _)| *total >= min_occurrences)
+            .filter_map(|(count, forms)| {
+                let word = forms
+                    .into_iter()
+                    .max_by(|(a, a_count), (b, b_count)| {
+                        a_count
+                            .cmp(b_count)
+                            .then_with(|| a.chars().any(char::is_uppercase).cmp(&b.chars().any(char::is_uppercase)))
+                            .then_with(|| b.cmp(a))
+                    })?
+                    .0;
+                Some(VocabularySuggestion { word, count })
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.word.cmp(&b.word)));
+        suggestions.truncate(limit);
+        Ok(suggestions)
+    })
+}
+
+/// Replace the vocabulary with `words`, keeping existing entries (and their
+/// added order) for words that are still present. All or nothing: on error
+/// the vocabulary is left as it was.
+pub fn replace_vocabulary(words: &[String]) -> Result<()> {
+    let wanted: Vec<String> = words.iter().map(|w| w.trim().to_lowercase()).collect();
+
+    with_db(|db| {
+        let mut conn = db.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        let tx = conn.transaction()?;
+        let current = {
+            let mut stmt = tx.prepare("SELECT word FROM vocabulary")?;
+            let rows = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+
+        for word in current.iter().filter(|w| !wanted.contains(&w.to_lowercase())) {
+            tx.execute("DELETE FROM vocabulary WHERE word = ?1 COLLATE NOCASE", [word])?;
+        }
+        let now = chrono::Utc::now().to_rfc3339();
+        for word in words.iter().map(|w| w.trim()).filter(|w| !w.is_empty()) {
+            tx.execute(
+                "INSERT OR IGNORE INTO vocabulary (id, word, created_at) VALUES (?1, ?2, ?3)",
+                rusqlite::params![Uuid::new_v4().to_string(), word, now],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    })
+}
+
+pub fn new_history_entry(
+    raw_transcript: String,
+    optimized_prompt: Option<String>,
+    prompt_mode: String,
+    llm_provider: Option<String>,
+    duration_secs: f64,
+    elapsed_ms: Option<u64>,
+) -> HistoryEntry {
+    let now = chrono::Utc::now();
+    HistoryEntry {
+        id: Uuid::new_v4().to_string(),
+        word_count: crate::text::count_words(&raw_transcript) as u64,
+        char_count: crate::text::count_chars(&raw_transcript) as u64,
+        raw_transcript,
+        optimized_prompt,
+        prompt_mode,
+        llm_provider,
+        duration_secs,
+        created_at: now.to_rfc3339(),
+        created_at_unix: now.timestamp(),
+        elapsed_ms,
+        updated_at: None,
+        favorite: false,
+        audio_path: None,
+        audio_missing: false,
+        source_path: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn entry(transcript: &str, prompt: &str) -> HistoryEntry {
+        new_history_entry(
+            transcript.to_string(),
+            Some(prompt.to_string()),
+            "clean".to_string(),
+            None,
+            1.0,
+            None,
+        )
+    }
+
+    #[test]
+    fn duplicate_is_merged_under_the_existing_id() {
+        let _env = test_support::env();
+        let first = entry("same words", "first");
+        insert_history(&first, None).unwrap();
+
+        let second = entry("same words", "second");
+        let id = find_history_duplicate(&second, 60).unwrap();
+        assert_eq!(id.as_deref(), Some(first.id.as_str()));
+
+        merge_history(&first.id, &second).unwrap();
+        let merged = get_history_entry(&first.id).unwrap().expect("merged entry");
+        assert_eq!(merged.optimized_prompt.as_deref(), Some("second"));
+        assert!(get_history_entry(&second.id).unwrap().is_none());
+        assert_eq!(get_history(10, 0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn no_duplicate_outside_the_window_or_for_other_text() {
+        let _env = test_support::env();
+        let mut old = entry("same words", "old");
+        old.created_at_unix -= 600;
+        insert_history(&old, None).unwrap();
+
+        assert_eq!(find_history_duplicate(&entry("same words", "new"), 60).unwrap(), None);
+        assert_eq!(find_history_duplicate(&entry("other words", "new"), 3600).unwrap(), None);
+    }
+
+    #[test]
+    fn empty_filter_has_no_where_clause() {
+        let (clause, params) = HistoryFilter::default().where_clause();
+        assert_eq!(clause, "");
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn where_clause_has_one_condition_per_field() {
+        let filter = HistoryFilter {
+            from: Some(chrono::DateTime::from_timestamp(100, 0).unwrap()),
+            to: Some(chrono::DateTime::from_timestamp(200, 0).unwrap()),
+            mode: Some("code".to_string()),
+            provider: Some("local".to_string()),
+            min_duration: Some(2.5),
+            favorites_only: true,
+            favorites_first: true,
+        };
+        let (clause, params) = filter.where_clause();
+        assert_eq!(
+            clause,
+            "WHERE created_at_unix >= ? AND created_at_unix <= ? \
+             AND prompt_mode = ? COLLATE NOCASE AND llm_provider = ? \
+             AND duration_secs >= ? AND favorite = 1"
+        );
+        assert_eq!(params.len(), 5);
+    }
+
+    #[test]
+    fn query_history_applies_each_filter() {
+        let _env = test_support::env();
+        let now = chrono::Utc::now().timestamp();
+        let add = |mode: &str, provider: Option<&str>, duration: f64, age: i64, favorite: bool| {
+            let mut entry = new_history_entry(
+                format!("{} entry", mode),
+                None,
+                mode.to_string(),
+                provider.map(str::to_string),
+                duration,
+                None,
+            );
+            entry.created_at_unix = now - age;
+            entry.favorite = favorite;
+            insert_history(&entry, None).unwrap();
+            entry.id
+        };
+        let old_code = add("code", Some("local"), 5.0, 8 * 86_400, false);
+        let new_code = add("code", None, 1.0, 60, false);
+        let clean = add("clean", Some("local"), 3.0, 120, true);
+        let (old_code, new_code, clean) = (old_code.as_str(), new_code.as_str(), clean.as_str());
+
+        let ids = |filter: HistoryFilter| -> Vec<String> {
+            query_history(&filter, 10, 0).unwrap().into_iter().map(|e| e.id).collect()
+        };
+        let week_ago = chrono::DateTime::from_timestamp(now - 7 * 86_400, 0).unwrap();
+
+        assert_eq!(ids(HistoryFilter::default()), [new_code, clean, old_code]);
+        let last_week_code = HistoryFilter {
+            from: Some(week_ago),
+            mode: Some("CODE".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(last_week_code), [new_code]);
+        let before_last_week = HistoryFilter { to: Some(week_ago), ..Default::default() };
+        assert_eq!(ids(before_last_week), [old_code]);
+        let local = HistoryFilter { provider: Some("local".to_string()), ..Default::default() };
+        assert_eq!(ids(local), [clean, old_code]);
+        let long = HistoryFilter { min_duration: Some(3.0), ..Default::default() };
+        assert_eq!(ids(long), [clean, old_code]);
+        let favorites = HistoryFilter { favorites_only: true, ..Default::default() };
+        assert_eq!(ids(favorites), [clean]);
+        let favorites_first = HistoryFilter { favorites_first: true, ..Default::default() };
+        assert_eq!(ids(favorites_first), [clean, new_code, old_code]);
+
+        let page = query_history(&HistoryFilter::default(), 1, 1).unwrap();
+        assert_eq!(page[0].id, clean);
+    }
+
+    #[test]
+    fn filter_json_rejects_bad_dates_and_unknown_fields() {
+        let parse = serde_json::from_str::<HistoryFilter>;
+        let filter = parse(r#"{ "from": "2024-05-01T00:00:00Z", "mode": "code" }"#).unwrap();
+        assert_eq!(filter.from.unwrap().timestamp(), 1_714_521_600);
+        assert!(parse(r#"{ "from": "last week" }"#).is_err());
+        assert!(parse(r#"{ "to": "2024-13-01T00:00:00Z" }"#).is_err());
+        assert!(parse(r#"{ "modes": "code" }"#).is_err());
+    }
+
+    fn count_rows(conn: &Connection) -> usize {
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0))
+            .unwrap();
+        count as usize
+    }
+
+    #[test]
+    fn concurrent_inserts_and_reads_lose_nothing() {
+        const WRITERS: usize = 4;
+        const PER_WRITER: usize = 50;
+
+        let env = test_support::env();
+        let path = env.path().join("history.db");
+        init(&path, None).unwrap();
+
+        // Another connection, as a second process reading the file would use
+        let outside = Connection::open(&path).unwrap();
+        outside.busy_timeout(std::time::Duration::from_millis(BUSY_TIMEOUT_MS)).unwrap();
+        let mode: String = outside.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(mode, "wal");
+
+        let outside = std::thread::scope(|scope| {
+            let writers: Vec<_> = (0..WRITERS)
+                .map(|w| {
+                    scope.spawn(move || {
+                        for i in 0..PER_WRITER {
+                            let entry = entry(&format!("writer {} entry {}", w, i), "prompt");
+                            insert_history(&entry, None).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            let reader = scope.spawn(move || {
+                while !writers.iter().all(|w| w.is_finished()) {
+                    get_history(20, 0).unwrap();
+                    search_history("entry", 20, 0).unwrap();
+                    count_rows(&outside);
+                }
+                outside
+            });
+            reader.join().unwrap()
+        });
+
+        assert_eq!(get_history(1000, 0).unwrap().len(), WRITERS * PER_WRITER);
+        assert_eq!(count_rows(&outside), WRITERS * PER_WRITER);
+    }
+
+    #[test]
+    fn new_entries_carry_both_timestamps() {
+        let entry = entry("now", "now");
+        let parsed = chrono::DateTime::parse_from_rfc3339(&entry.created_at).unwrap();
+        assert_eq!(parsed.timestamp(), entry.created_at_unix);
+    }
+
+    #[test]
+    fn mixed_offsets_order_and_filter_by_instant() {
+        let _env = test_support::env();
+        // Listed newest first as strings; as instants the second is newest
+        // and the first oldest
+        let at = |created_at: &str| {
+            let mut value = serde_json::to_value(entry(created_at, "")).unwrap();
+            value["created_at"] = created_at.into();
+            value
+        };
+        let entries = [
+            at("2024-05-01T12:30:00+02:00"),
+            at("2024-05-01T11:00:00Z"),
+            at("2024-05-01T09:45:00-01:00"),
+        ];
+        let json = serde_json::to_string(&entries).unwrap();
+        let report = import_history(&json, ImportStrategy::Merge).unwrap();
+        assert_eq!(report.imported, 3);
+
+        let transcripts = |filter: HistoryFilter| -> Vec<String> {
+            let entries = query_history(&filter, 10, 0).unwrap();
+            entries.into_iter().map(|e| e.raw_transcript).collect()
+        };
+        assert_eq!(
+            transcripts(HistoryFilter::default()),
+            ["2024-05-01T11:00:00Z", "2024-05-01T09:45:00-01:00", "2024-05-01T12:30:00+02:00"]
+        );
+
+        let time = |s: &str| Some(chrono::DateTime::parse_from_rfc3339(s).unwrap().to_utc());
+        let around_c = HistoryFilter {
+            from: time("2024-05-01T10:40:00Z"),
+            to: time("2024-05-01T12:50:00+02:00"),
+            ..Default::default()
+        };
+        assert_eq!(transcripts(around_c), ["2024-05-01T09:45:00-01:00"]);
+
+        // The original string is kept for display
+        let first = &get_history(1, 0).unwrap()[0];
+        assert_eq!(first.created_at, "2024-05-01T11:00:00Z");
+    }
+
+    #[test]
+    fn dedupe_groups_and_keeps_by_instant_across_offsets() {
+        let _env = test_support::env();
+        // As strings the +02:00 entry is newest; as instants it is oldest
+        // (10:30Z, then 10:45Z, then 10:59Z)
+        let at = |created_at: &str| {
+            let mut value = serde_json::to_value(entry("same words", created_at)).unwrap();
+            value["created_at"] = created_at.into();
+            value
+        };
+        let entries = [
+            at("2024-05-01T12:30:00+02:00"),
+            at("2024-05-01T09:45:00-01:00"),
+            at("2024-05-01T10:59:00Z"),
+        ];
+        let json = serde_json::to_string(&entries).unwrap();
+        import_history(&json, ImportStrategy::Merge).unwrap();
+
+        let report = dedupe_history(20 * 60).unwrap();
+        assert_eq!(report.groups_merged, 1);
+        assert_eq!(report.entries_removed, 2);
+        let kept = get_history(10, 0).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].optimized_prompt.as_deref(), Some("2024-05-01T10:59:00Z"));
+    }
+
+    #[test]
+    fn stats_sum_the_stored_counts() {
+        let _env = test_support::env();
+        insert_history(&entry("one two three", ""), None).unwrap();
+        insert_history(&entry("我爱你 ok", ""), None).unwrap();
+
+        let stats = get_stats(7).unwrap();
+        assert_eq!(stats.total_dictations, 2);
+        assert_eq!(stats.total_words, 3 + 4);
+        assert_eq!(stats.total_chars, 13 + 6);
+
+        let json = serde_json::to_value(&get_history(1, 0).unwrap()[0]).unwrap();
+        assert_eq!((json["word_count"].as_u64(), json["char_count"].as_u64()), (Some(4), Some(6)));
+    }
+
+    #[test]
+    fn replacing_the_vocabulary_keeps_surviving_words_in_place() {
+        let _env = test_support::env();
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        replace_vocabulary(&words(&["Phemy", "Qwen", "WAL"])).unwrap();
+        replace_vocabulary(&words(&["wal", " Tauri ", "phemy", ""])).unwrap();
+        assert_eq!(list_vocabulary().unwrap(), ["Phemy", "WAL", "Tauri"]);
+    }
+
+    #[test]
+    fn failed_vocabulary_replace_changes_nothing() {
+        let _env = test_support::env();
+        replace_vocabulary(&["Phemy".to_string(), "Qwen".to_string()]).unwrap();
+        with_db(|db| {
+            let conn = db.conn.lock().unwrap();
+            conn.execute_batch(
+                "CREATE TEMP TRIGGER reject_boom BEFORE INSERT ON vocabulary
+                 WHEN NEW.word = 'boom' BEGIN SELECT RAISE(ABORT, 'rejected'); END",
+            )?;
+            Ok(())
+        })
+        .unwrap();
+
+        // The delete of "Qwen" and insert of "Tauri" come before the failure
+        let words = ["Phemy".to_string(), "Tauri".to_string(), "boom".to_string()];
+        assert!(replace_vocabulary(&words).is_err());
+        assert_eq!(list_vocabulary().unwrap(), ["Phemy", "Qwen"]);
+    }
+
+    #[test]
+    fn suggestions_rank_uncommon_words_not_yet_in_the_vocabulary() {
+        let _env = test_support::env();
+        for transcript in [
+            "deploy the Kubernetes cluster with Terraform",
+            "kubernetes again, and the Kubernetes docs",
+            "Terraform plan then apply",
+            "ask Phemy about Phemy",
+            "Grafana once",
+        ] {
+            insert_history(&entry(transcript, ""), None).unwrap();
+        }
+        add_vocabulary_word("phemy").unwrap();
+
+        let suggestions = suggest_vocabulary(2, 10).unwrap();
+        let found: Vec<_> = suggestions.iter().map(|s| (s.word.as_str(), s.count)).collect();
+        // Most frequent first, in the most used (then capitalized) spelling
+        assert_eq!(found, [("Kubernetes", 3), ("Terraform", 2)]);
+        let json = serde_json::to_value(&suggestions[0]).unwrap();
+        assert_eq!(json, serde_json::json!({ "word": "Kubernetes", "count": 3 }));
+
+        assert_eq!(suggest_vocabulary(1, 1).unwrap()[0].word, "Kubernetes");
+        let once: Vec<_> = suggest_vocabulary(1, 10).unwrap().into_iter().map(|s| s.word).collect();
+        assert!(once.contains(&"Grafana".to_string()) && once.contains(&"deploy".to_string()));
+        assert!(!once.iter().any(|w| w.eq_ignore_ascii_case("phemy") || w == "the"));
+    }
+
+    #[test]
+    fn fts_query_quotes_terms_and_prefixes_the_last() {
+        assert_eq!(fts_query("deploy clu").as_deref(), Some(r#""deploy" "clu"*"#));
+        assert_eq!(fts_query(r#"a OR "b" -- ?"#).as_deref(), Some(r#""a" "OR" """b"""*"#));
+        assert_eq!(fts_query("  ... "), None);
+    }
+
+    #[test]
+    fn snippet_markers_become_character_offsets() {
+        let (start, end) = (MATCH_START, MATCH_END);
+        let marked = format!("ünïcode {start}wörds{end} and {start}more{end}");
+        let snippet = parse_snippet(&marked);
+        assert_eq!(snippet.text, "ünïcode wörds and more");
+        assert_eq!(
+            snippet.highlights,
+            [Highlight { start: 8, end: 13 }, Highlight { start: 18, end: 22 }]
+        );
+    }
+
+    /// The highlighted parts of a search result's snippet
+    fn highlighted(result: &SearchResult) -> Vec<String> {
+        let chars: Vec<char> = result.snippet.text.chars().collect();
+        let highlights = &result.snippet.highlights;
+        highlights.iter().map(|h| chars[h.start..h.end].iter().collect()).collect()
+    }
+
+    #[test]
+    fn search_needs_every_term_and_ranks_better_matches_first() {
+        let _env = test_support::env();
+        let once = entry("deploy the cluster tonight and then rest for a while", "");
+        let twice = entry("cluster deploy: deploy the cluster", "");
+        insert_history(&once, None).unwrap();
+        insert_history(&twice, None).unwrap();
+        insert_history(&entry("deploy only", ""), None).unwrap();
+
+        let results = search_history("cluster deploy", 10, 0).unwrap();
+        let ids: Vec<_> = results.iter().map(|r| r.entry.id.as_str()).collect();
+        assert_eq!(ids, [twice.id.as_str(), once.id.as_str()]);
+        assert!(results[0].score > results[1].score);
+        assert_eq!(highlighted(&results[1]), ["deploy", "cluster"]);
+
+        // The last term matches as a prefix while typing
+        assert_eq!(search_history("deploy clu", 10, 0).unwrap().len(), 2);
+        assert!(search_history("--", 10, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_handles_non_ascii_text() {
+        let _env = test_support::env();
+        let (before, after) = ("längerer Text ".repeat(20), "ende ".repeat(20));
+        let long = format!("{before} Größenänderung des Fensters {after}");
+        insert_history(&entry(&long, "Café crème"), None).unwrap();
+        insert_history(&entry("日本語のテキスト", ""), None).unwrap();
+
+        let results = search_history("größenänderung", 10, 0).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(highlighted(&results[0]), ["Größenänderung"]);
+        assert!(results[0].snippet.text.contains('…'));
+
+        // Diacritics are folded, and the optimized prompt is searched too
+        let results = search_history("cafe creme", 10, 0).unwrap();
+        assert_eq!(highlighted(&results[0]), ["Café", "crème"]);
+
+        assert_eq!(search_history("日本語", 10, 0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn memory_path_opens_a_fresh_database_without_a_file() {
+        let env = test_support::env();
+        let cwd_before: Vec<_> = std::fs::read_dir(".").unwrap().collect();
+
+        init(&PathBuf::from(MEMORY_PATH), None).unwrap();
+        let kept = entry("kept in memory", "");
+        insert_history(&kept, None).unwrap();
+        assert_eq!(get_history(10, 0).unwrap().len(), 1);
+        assert!(get_history_entry(&kept.id).unwrap().is_some());
+        assert_eq!(search_history("memory", 10, 0).unwrap().len(), 1);
+        delete_history_entry(&kept.id).unwrap();
+        assert!(get_history(10, 0).unwrap().is_empty());
+
+        // Reopening starts over instead of sharing the previous connection
+        insert_history(&entry("gone after reopening", ""), None).unwrap();
+        init(&PathBuf::from(MEMORY_PATH), None).unwrap();
+        assert!(get_history(10, 0).unwrap().is_empty());
+
+        // No database file appears in the working or data directory
+        assert_eq!(std::fs::read_dir(".").unwrap().count(), cwd_before.len());
+        let entries = std::fs::read_dir(env.path()).unwrap();
+        assert!(entries.map(|e| e.unwrap().path()).all(|p| p.is_dir()));
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn encrypted_database_backs_up_encrypted_and_restores() {
+        let env = test_support::env();
+        init(&env.path().join("history.db"), Some("secret")).unwrap();
+        insert_history(&entry("kept words", "kept"), None).unwrap();
+
+        let backup = env.path().join("backup.db");
+        let report = backup_to(&backup).unwrap();
+        assert_eq!(report.history_rows, 1);
+
+        // The backup can't be read without the key
+        let plain = Connection::open(&backup).unwrap();
+        assert!(plain
+            .query_row("SELECT count(*) FROM history", [], |row| row.get::<_, i64>(0))
+            .is_err());
+        drop(plain);
+
+        insert_history(&entry("later words", "later"), None).unwrap();
+        let report = restore_from(&backup).unwrap();
+        assert_eq!(report.history_rows, 1);
+        assert_eq!(get_history(10, 0).unwrap()[0].raw_transcript, "kept words");
+
+        // A plaintext backup is refused, not converted in place
+        let plaintext = env.path().join("plaintext.db");
+        let conn = Connection::open(&plaintext).unwrap();
+        conn.execute_batch("CREATE TABLE history (id TEXT)").unwrap();
+        drop(conn);
+        let err = restore_from(&plaintext).unwrap_err();
+        assert!(err.to_string().contains("not encrypted"), "{:#}", err);
+        assert!(Connection::open(&plaintext)
+            .unwrap()
+            .query_row("SELECT count(*) FROM history", [], |row| row.get::<_, i64>(0))
+            .is_ok());
+    }
+}
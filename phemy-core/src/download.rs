@@ -0,0 +1,133 @@
+//! Streaming, checksummed model download shared by the whisper and LLM
+//! model managers. Progress goes to a hook after every chunk; each manager
+//! feeds its polling state from it, and callers can pass on their own.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A download stopped by its cancel flag
+#[derive(Debug)]
+pub struct Cancelled(pub String);
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Download of '{}' cancelled", self.0)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Whether `e`, or anything it wraps, is a `Cancelled` error
+pub fn is_cancelled(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Cancelled>())
+}
+
+/// Download `url` to `dest` and check it against `expected_sha256`.
+///
+/// `on_progress` gets (downloaded bytes, total bytes, fraction done) after
+/// each chunk; total is 0 and the fraction 0.0 when the server doesn't
+/// send a length. `cancel` is checked between chunks. On cancellation or
+/// a checksum mismatch the partial file is removed.
+pub async fn download_verified(
+    name: &str,
+    url: &str,
+    dest: &Path,
+    expected_sha256: &str,
+    cancel: &AtomicBool,
+    mut on_progress: impl FnMut(u64, u64, f64),
+) -> Result<()> {
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let client = reqwest::Client::new();
+    let response = client.get(url).send().await?;
+
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "Failed to download model '{}': HTTP {}",
+            name,
+            response.status()
+        );
+    }
+
+    let total_bytes = response.content_length().unwrap_or(0);
+    let mut downloaded_bytes: u64 = 0;
+    let mut hasher = Sha256::new();
+
+    let mut file = tokio::fs::File::create(dest).await?;
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        if cancel.load(Ordering::Relaxed) {
+            drop(file);
+            let _ = tokio::fs::remove_file(dest).await;
+            return Err(Cancelled(name.to_string()).into());
+        }
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        hasher.update(&chunk);
+        downloaded_bytes += chunk.len() as u64;
+
+        let progress = if total_bytes > 0 {
+            downloaded_bytes as f64 / total_bytes as f64
+        } else {
+            0.0
+        };
+        on_progress(downloaded_bytes, total_bytes, progress);
+    }
+
+    file.flush().await?;
+
+    // Verify SHA256 checksum
+    let actual_sha256 = format!("{:x}", hasher.finalize());
+    if actual_sha256 != expected_sha256 {
+        // Remove the corrupted file
+        let _ = tokio::fs::remove_file(dest).await;
+        anyhow::bail!(
+            "SHA256 mismatch for model '{}': expected {}, got {}",
+            name,
+            expected_sha256,
+            actual_sha256
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::{self, OpKind};
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    #[test]
+    fn cancelling_its_operation_stops_a_download_and_removes_the_file() {
+        // Sends half the body, then the rest a little later
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/model.bin", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nabcd");
+            std::thread::sleep(Duration::from_millis(200));
+            let _ = stream.write_all(b"efgh");
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("model.bin");
+
+        let op = ops::start(OpKind::Download, Some("test"));
+        let id = op.id();
+        let download = download_verified("test", &url, &dest, "", op.cancel_flag(), |_, _, _| {
+            assert!(ops::cancel(id));
+            assert!(ops::cancel(id), "cancelling twice is harmless");
+        });
+        let result = tokio::runtime::Runtime::new().unwrap().block_on(download);
+
+        assert!(is_cancelled(&result.unwrap_err()));
+        assert!(!dest.exists());
+    }
+}
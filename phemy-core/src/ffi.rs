@@ -1,8 +1,17 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Condvar, Mutex};
+use std::thread::{JoinHandle, ThreadId};
 
 /// Convert a C string pointer to a Rust &str.
 /// Returns None if the pointer is null or the string is invalid UTF-8.
+///
+/// # Safety
+/// `ptr` must be null or point to a NUL-terminated string that stays valid
+/// and unchanged for `'a`.
 pub unsafe fn c_str_to_str<'a>(ptr: *const c_char) -> Option<&'a str> {
     if ptr.is_null() {
         return None;
@@ -16,20 +25,564 @@ pub unsafe fn c_str_to_str<'a>(ptr: *const c_char) -> Option<&'a str> {
     }
 }
 
-/// Convert a Rust string to a heap-allocated C string.
+/// Convert a Rust string to a heap-allocated C string. NUL bytes, which a
+/// C string can't hold, are dropped with a warning rather than failing the
+/// call; JSON escapes them, so only plain strings lose them.
 /// The caller must free this with phemy_free_string().
+#[cfg_attr(feature = "track-strings", track_caller)]
 pub fn str_to_c_char(s: &str) -> *mut c_char {
-    match CString::new(s) {
-        Ok(cs) => cs.into_raw(),
-        Err(_) => std::ptr::null_mut(),
+    let cs = CString::new(s).unwrap_or_else(|_| {
+        log::warn!("Dropping NUL bytes from a string returned to the host");
+        CString::new(s.replace('\0', "")).unwrap_or_default()
+    });
+    let ptr = cs.into_raw();
+    #[cfg(feature = "track-strings")]
+    tracking::register(ptr, std::panic::Location::caller());
+    ptr
+}
+
+/// Free a string from `str_to_c_char`. With the `track-strings` feature,
+/// pointers that aren't outstanding (already freed, or not ours) are logged
+/// and left alone instead of corrupting the heap.
+///
+/// # Safety
+/// `ptr` must be null or a string from `str_to_c_char` that hasn't been
+/// freed yet, and must not be used afterwards.
+pub unsafe fn free_c_char(ptr: *mut c_char) {
+    if ptr.is_null() {
+        return;
+    }
+    #[cfg(feature = "track-strings")]
+    if !tracking::release(ptr, "phemy_free_string") {
+        return;
+    }
+    drop(CString::from_raw(ptr));
+}
+
+/// Hand `samples` to the host as a pointer and length for `free_samples`,
+/// without copying them unless the vector has spare capacity. Empty
+/// samples come out as null.
+pub fn samples_into_raw(samples: Vec<f32>) -> (*mut f32, usize) {
+    if samples.is_empty() {
+        return (std::ptr::null_mut(), 0);
+    }
+    let len = samples.len();
+    (Box::into_raw(samples.into_boxed_slice()) as *mut f32, len)
+}
+
+/// Free samples from `samples_into_raw`, given the length it returned.
+///
+/// # Safety
+/// `ptr` must be null or come from `samples_into_raw` with `len`, not freed
+/// yet, and must not be used afterwards.
+pub unsafe fn free_samples(ptr: *mut f32, len: usize) {
+    if ptr.is_null() {
+        return;
+    }
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+/// Copy a string returned by one of the exports into the caller's buffer and
+/// free it, for hosts that can't safely call phemy_free_string() (e.g. a
+/// different C runtime on Windows). Returns the size needed in bytes,
+/// including the NUL terminator. The string is written only if it fits in
+/// `buf_len` bytes; with a null or short `buf` nothing is written and the
+/// caller can retry with a buffer of the returned size. Returns -1 if `s` is
+/// null or too long to report.
+///
+/// # Safety
+/// `s` must be null or come from `str_to_c_char`, and must not be used
+/// afterwards. `buf` must be null or valid for writes of `buf_len` bytes.
+pub unsafe fn copy_to_buf(s: *mut c_char, buf: *mut c_char, buf_len: usize) -> i32 {
+    if s.is_null() {
+        return -1;
+    }
+    #[cfg(feature = "track-strings")]
+    if !tracking::release(s, "copy_to_buf") {
+        return -1;
+    }
+    let s = CString::from_raw(s);
+    let bytes = s.as_bytes_with_nul();
+    if !buf.is_null() && bytes.len() <= buf_len {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr().cast::<c_char>(), buf, bytes.len());
     }
+    i32::try_from(bytes.len()).unwrap_or(-1)
 }
 
 /// Serialize a value to JSON and return as a C string.
 /// The caller must free this with phemy_free_string().
+#[cfg_attr(feature = "track-strings", track_caller)]
 pub fn to_json_c_char<T: serde::Serialize>(value: &T) -> *mut c_char {
     match serde_json::to_string(value) {
-        Ok(json) => str_to_c_char(&json),
-        Err(_) => std::ptr::null_mut(),
+        Ok(json) => {
+            clear_last_error();
+            str_to_c_char(&json)
+        }
+        Err(e) => legacy_error_c_char(None, ErrorCode::Internal, &format!("Failed to serialize result: {}", e)),
+    }
+}
+
+/// Stable error codes reported in `{ "error": { "code", "message" } }`.
+/// Hosts can branch on the code; the message is for people and may change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// A required argument was null, not UTF-8 or malformed
+    InvalidArgument,
+    /// Settings failed validation
+    InvalidSettings,
+    /// No such history entry, setting, job, profile or model
+    NotFound,
+    /// Reading or writing a file failed
+    Io,
+    /// The history database failed
+    Database,
+    /// Recording or listing audio devices failed
+    Audio,
+    /// Whisper transcription failed
+    Transcription,
+    /// Prompt optimization or the LLM models failed
+    Llm,
+    /// Pasting or clipboard access failed
+    Clipboard,
+    /// Stop-and-process failed; the message says at which stage
+    Processing,
+    /// The operation was cancelled
+    Cancelled,
+    /// Another call is using what this one needs (the local LLM); retry later
+    Busy,
+    /// Downloading a model failed
+    Download,
+    /// Anything else
+    Internal,
+}
+
+/// `code`, or `Busy` or `Cancelled` if `e` is a `utils::Busy`,
+/// `download::Cancelled` or `ops::Cancelled` error
+pub fn error_code(e: &anyhow::Error, code: ErrorCode) -> ErrorCode {
+    if crate::utils::is_busy(e) {
+        ErrorCode::Busy
+    } else if crate::download::is_cancelled(e) || crate::ops::is_cancelled(e) {
+        ErrorCode::Cancelled
+    } else {
+        code
+    }
+}
+
+/// The value of an `error` field: `{ "code", "message" }`, or just the
+/// message with the `legacy-errors` feature.
+pub fn error_value(code: ErrorCode, message: &str) -> serde_json::Value {
+    if cfg!(feature = "legacy-errors") {
+        return serde_json::Value::String(message.to_string());
+    }
+    serde_json::json!({ "code": code, "message": message })
+}
+
+/// Return `{ "error": { "code": "...", "message": "..." } }` as a C string.
+/// The caller must free this with phemy_free_string().
+#[cfg_attr(feature = "track-strings", track_caller)]
+pub fn error_json_c_char(code: ErrorCode, message: &str) -> *mut c_char {
+    let json = to_json_c_char(&serde_json::json!({ "error": error_value(code, message) }));
+    set_last_error(code, message);
+    json
+}
+
+/// Like `error_json_c_char`, for exports that returned `legacy` (null, or a
+/// fallback such as "[]") on failure before errors were JSON. The
+/// `legacy-errors` feature keeps returning `legacy`.
+#[cfg_attr(feature = "track-strings", track_caller)]
+pub fn legacy_error_c_char(legacy: Option<&str>, code: ErrorCode, message: &str) -> *mut c_char {
+    if cfg!(feature = "legacy-errors") {
+        set_last_error(code, message);
+        return legacy.map_or(std::ptr::null_mut(), str_to_c_char);
+    }
+    error_json_c_char(code, message)
+}
+
+thread_local! {
+    /// The error from the last failing call on this thread, for
+    /// phemy_get_last_error()
+    static LAST_ERROR: RefCell<Option<(ErrorCode, String)>> = const { RefCell::new(None) };
+}
+
+/// Record the error phemy_get_last_error() reports on this thread
+pub fn set_last_error(code: ErrorCode, message: &str) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((code, message.to_string())));
+}
+
+pub fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+pub fn last_error() -> Option<(ErrorCode, String)> {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
+
+/// For exports returning a bool: clear the last error and return true
+pub fn succeeded() -> bool {
+    clear_last_error();
+    true
+}
+
+/// For exports returning a bool: log `e` with its causes, record it as the
+/// last error (as `code` unless it is a busy or cancelled error) and
+/// return false
+pub fn failed(code: ErrorCode, what: &str, e: &anyhow::Error) -> bool {
+    let message = format!("{}: {:#}", what, e);
+    log::error!("{}", message);
+    set_last_error(error_code(e, code), &message);
+    false
+}
+
+/// For exports returning a bool: record a bad argument and return false
+pub fn invalid_argument(message: &str) -> bool {
+    set_last_error(ErrorCode::InvalidArgument, message);
+    false
+}
+
+/// Log the strings handed to the host and not yet freed, with where each
+/// was allocated; a no-op without the `track-strings` feature
+pub fn report_leaked_strings() {
+    #[cfg(feature = "track-strings")]
+    tracking::report_leaks();
+}
+
+/// Outstanding strings for the `track-strings` feature, by address, with
+/// the export (file and line) that returned each
+#[cfg(feature = "track-strings")]
+mod tracking {
+    use std::collections::HashMap;
+    use std::os::raw::c_char;
+    use std::panic::Location;
+    use std::sync::Mutex;
+
+    static OUTSTANDING: std::sync::LazyLock<Mutex<HashMap<usize, &'static Location<'static>>>> =
+        std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+    pub fn register(ptr: *mut c_char, origin: &'static Location<'static>) {
+        OUTSTANDING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(ptr as usize, origin);
+    }
+
+    /// Forget `ptr` before it is freed by `by`; false if it isn't outstanding
+    pub fn release(ptr: *mut c_char, by: &str) -> bool {
+        let known = OUTSTANDING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&(ptr as usize))
+            .is_some();
+        if !known {
+            log::error!(
+                "{} given {:p}, which phemy didn't return or was already freed; ignoring it",
+                by,
+                ptr
+            );
+        }
+        known
+    }
+
+    pub fn report_leaks() {
+        let outstanding = OUTSTANDING.lock().unwrap_or_else(|e| e.into_inner());
+        if outstanding.is_empty() {
+            return;
+        }
+        let mut by_origin: HashMap<String, usize> = HashMap::new();
+        for origin in outstanding.values() {
+            *by_origin.entry(origin.to_string()).or_default() += 1;
+        }
+        let mut by_origin: Vec<_> = by_origin.into_iter().collect();
+        by_origin.sort();
+        log::warn!("{} strings returned to the host were never freed", outstanding.len());
+        for (origin, count) in by_origin {
+            log::warn!("  {} from {}", count, origin);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_outstanding(ptr: *mut c_char) -> bool {
+        OUTSTANDING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(&(ptr as usize))
+    }
+}
+
+// ============================================================
+// Callback dispatcher
+// ============================================================
+
+/// Most callbacks of one lossy kind (mic levels, progress) waiting at once;
+/// the oldest is dropped to make room for a new one
+const LOSSY_QUEUE_LEN: usize = 32;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+enum Message {
+    Call(Callback),
+    /// Run the oldest waiting callback of this kind, if any is left
+    Lossy(&'static str),
+}
+
+struct Dispatcher {
+    sender: Sender<Message>,
+    handle: JoinHandle<()>,
+}
+
+static DISPATCHER: Mutex<Option<Dispatcher>> = Mutex::new(None);
+
+static LOSSY: std::sync::LazyLock<Mutex<HashMap<&'static str, VecDeque<Callback>>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The kind of the lossy callback running, if any, and the thread it runs on
+static RUNNING_LOSSY: Mutex<Option<(&'static str, ThreadId)>> = Mutex::new(None);
+static LOSSY_DONE: Condvar = Condvar::new();
+
+/// A pointer the host gave with a callback, passed back to it with each
+/// call. phemy never dereferences it.
+#[derive(Debug, Clone, Copy)]
+pub struct UserData(pub *mut c_void);
+
+// Only handed back to the host's callbacks, on whichever thread they run;
+// the host vouches for that when it passes the pointer
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    /// The pointer. Closures should call this rather than read `.0`, which
+    /// would capture the bare pointer instead of the Send wrapper.
+    pub fn get(self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Call a host callback on the dispatcher thread, after those queued
+/// before it, so hosts get every callback on one thread. Starts the thread
+/// on first use.
+pub fn dispatch(callback: impl FnOnce() + Send + 'static) {
+    send(Message::Call(Box::new(callback)));
+}
+
+/// `dispatch` for high-rate callbacks of `kind` (e.g. "mic_level"): if
+/// the host falls behind, only the newest LOSSY_QUEUE_LEN of that kind
+/// are kept.
+pub fn dispatch_lossy(kind: &'static str, callback: impl FnOnce() + Send + 'static) {
+    {
+        let mut lossy = LOSSY.lock().unwrap_or_else(|e| e.into_inner());
+        let queue = lossy.entry(kind).or_default();
+        if queue.len() >= LOSSY_QUEUE_LEN {
+            queue.pop_front();
+        }
+        queue.push_back(Box::new(callback));
+    }
+    send(Message::Lossy(kind));
+}
+
+fn send(message: Message) {
+    let mut dispatcher = DISPATCHER.lock().unwrap_or_else(|e| e.into_inner());
+    if dispatcher.is_none() {
+        let (sender, receiver) = mpsc::channel::<Message>();
+        match std::thread::Builder::new()
+            .name("phemy-callbacks".into())
+            .spawn(move || {
+                for message in receiver {
+                    run(message);
+                }
+            }) {
+            Ok(handle) => *dispatcher = Some(Dispatcher { sender, handle }),
+            Err(e) => {
+                // Late beats never
+                drop(dispatcher);
+                log::error!("Failed to start callback thread: {}", e);
+                run(message);
+                return;
+            }
+        }
+    }
+    let sender = &dispatcher.as_ref().expect("dispatcher started above").sender;
+    if let Err(mpsc::SendError(message)) = sender.send(message) {
+        drop(dispatcher);
+        run(message);
+    }
+}
+
+fn run(message: Message) {
+    match message {
+        Message::Call(callback) => callback(),
+        Message::Lossy(kind) => {
+            let callback = {
+                let mut lossy = LOSSY.lock().unwrap_or_else(|e| e.into_inner());
+                let callback = lossy.get_mut(kind).and_then(VecDeque::pop_front);
+                if callback.is_some() {
+                    // Marked before LOSSY is released, so `discard_lossy`
+                    // can't miss it
+                    *RUNNING_LOSSY.lock().unwrap_or_else(|e| e.into_inner()) = Some((kind, std::thread::current().id()));
+                }
+                callback
+            };
+            if let Some(callback) = callback {
+                callback();
+                *RUNNING_LOSSY.lock().unwrap_or_else(|e| e.into_inner()) = None;
+                LOSSY_DONE.notify_all();
+            }
+        }
+    }
+}
+
+/// Drop the callbacks of `kind` still waiting and wait for one running, so
+/// none is called after this returns (e.g. with user data the host is
+/// about to free). Called from a callback, that one is not waited for.
+pub fn discard_lossy(kind: &'static str) {
+    if let Some(queue) = LOSSY.lock().unwrap_or_else(|e| e.into_inner()).get_mut(kind) {
+        queue.clear();
+    }
+    let current = std::thread::current().id();
+    let running = RUNNING_LOSSY.lock().unwrap_or_else(|e| e.into_inner());
+    let _running = LOSSY_DONE
+        .wait_while(running, |running| {
+            running.is_some_and(|(running, thread)| running == kind && thread != current)
+        })
+        .unwrap_or_else(|e| e.into_inner());
+}
+
+/// Run the callbacks still queued and stop the dispatcher thread. Called
+/// from a callback, the thread exits once that callback returns instead of
+/// being waited for. A later callback starts a new thread.
+pub fn stop_dispatcher() {
+    let dispatcher = DISPATCHER.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let Some(Dispatcher { sender, handle }) = dispatcher else {
+        return;
+    };
+    drop(sender);
+    if handle.thread().id() == std::thread::current().id() {
+        return;
+    }
+    if handle.join().is_err() {
+        log::error!("Callback thread panicked");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::sync::Arc;
+
+    #[cfg(feature = "track-strings")]
+    #[test]
+    fn tracked_strings_survive_double_and_foreign_frees() {
+        let ptr = str_to_c_char("{}");
+        assert!(tracking::is_outstanding(ptr));
+        unsafe { free_c_char(ptr) };
+        assert!(!tracking::is_outstanding(ptr));
+        // Would be a double free without tracking
+        unsafe { free_c_char(ptr) };
+
+        let foreign = CString::new("host").unwrap();
+        unsafe { free_c_char(foreign.as_ptr() as *mut c_char) };
+        assert_eq!(foreign.to_str().unwrap(), "host");
+
+        let copied = str_to_c_char("abc");
+        assert_eq!(unsafe { copy_to_buf(copied, std::ptr::null_mut(), 0) }, 4);
+        assert_eq!(unsafe { copy_to_buf(copied, std::ptr::null_mut(), 0) }, -1);
+    }
+
+    #[test]
+    fn copy_to_buf_writes_only_when_the_string_fits() {
+        // Exactly len + 1 bytes: the string and its terminator
+        let mut buf = [0x7f as c_char; 4];
+        let needed = unsafe { copy_to_buf(str_to_c_char("abc"), buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(needed, 4);
+        assert_eq!(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap(), "abc");
+
+        // One byte short: nothing is written
+        let mut buf = [0x7f as c_char; 3];
+        let needed = unsafe { copy_to_buf(str_to_c_char("abc"), buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(needed, 4);
+        assert_eq!(buf, [0x7f as c_char; 3]);
+
+        let needed = unsafe { copy_to_buf(str_to_c_char("abc"), std::ptr::null_mut(), 0) };
+        assert_eq!(needed, 4);
+        let needed = unsafe { copy_to_buf(str_to_c_char(""), std::ptr::null_mut(), 0) };
+        assert_eq!(needed, 1);
+        let needed = unsafe { copy_to_buf(std::ptr::null_mut(), std::ptr::null_mut(), 0) };
+        assert_eq!(needed, -1);
+    }
+
+    #[test]
+    fn strings_with_nul_bytes_still_reach_the_host() {
+        let take = |ptr: *mut c_char| {
+            assert!(!ptr.is_null());
+            let s = unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_string();
+            unsafe { free_c_char(ptr) };
+            s
+        };
+        assert_eq!(take(str_to_c_char("turn\0 on\0")), "turn on");
+
+        // JSON escapes them, so results keep them
+        let transcript = "caf\u{e9}\0 \u{1f642} \u{feff}\u{7f}";
+        let json = take(to_json_c_char(&serde_json::json!({ "raw_transcript": transcript })));
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["raw_transcript"], transcript);
+    }
+
+    #[test]
+    fn callbacks_run_in_order_on_one_thread() {
+        let _lock = test_support::lock();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..10 {
+            let calls = calls.clone();
+            dispatch(move || calls.lock().unwrap().push((i, std::thread::current().id())));
+        }
+        stop_dispatcher();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.iter().map(|(i, _)| *i).collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+        assert!(calls.iter().all(|(_, thread)| *thread == calls[0].1));
+        assert_ne!(calls[0].1, std::thread::current().id());
+    }
+
+    #[test]
+    fn lossy_callbacks_keep_the_newest_when_the_host_falls_behind() {
+        let _lock = test_support::lock();
+        let (release, blocked) = mpsc::channel::<()>();
+        dispatch(move || blocked.recv().unwrap());
+
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let done = Arc::new(Mutex::new(false));
+        for i in 0..100 {
+            let levels = levels.clone();
+            dispatch_lossy("test_level", move || levels.lock().unwrap().push(i));
+        }
+        let finished = done.clone();
+        dispatch(move || *finished.lock().unwrap() = true);
+        release.send(()).unwrap();
+        stop_dispatcher();
+
+        assert_eq!(*levels.lock().unwrap(), (100 - LOSSY_QUEUE_LEN..100).collect::<Vec<_>>());
+        assert!(*done.lock().unwrap(), "reliable callbacks are never dropped");
+    }
+
+    #[test]
+    fn discarding_lossy_callbacks_waits_for_the_running_one() {
+        let _lock = test_support::lock();
+        let (started, running) = mpsc::channel::<()>();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..5 {
+            let calls = calls.clone();
+            let started = started.clone();
+            dispatch_lossy("test_discard", move || {
+                let _ = started.send(());
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                calls.lock().unwrap().push(i);
+            });
+        }
+
+        running.recv().unwrap();
+        discard_lossy("test_discard");
+        assert_eq!(*calls.lock().unwrap(), [0], "the running one finished, the rest never ran");
+        stop_dispatcher();
+        assert_eq!(*calls.lock().unwrap(), [0]);
     }
 }
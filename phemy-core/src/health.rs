@@ -0,0 +1,368 @@
+//! Quick diagnostics for a settings panel (phemy_health_check): whether each
+//! part phemy depends on looks usable, without loading models or, unless
+//! asked, touching the network. Also what was built (phemy_get_build_info).
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::llm::{client, llm_model_manager};
+use crate::settings::{LlmProvider, Settings};
+use crate::transcription::model_manager;
+
+/// Where the network check connects: the host models are downloaded from
+const NETWORK_CHECK_URL: &str = "https://huggingface.co";
+
+const NETWORK_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn ok(detail: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn failed(detail: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            detail: detail.into(),
+        }
+    }
+
+    fn from_result(result: anyhow::Result<String>) -> Self {
+        match result {
+            Ok(detail) => Self::ok(detail),
+            Err(e) => Self::failed(format!("{:#}", e)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// Every check passed
+    pub ok: bool,
+    pub initialized: Check,
+    pub data_dir: Check,
+    pub database: Check,
+    pub whisper_model: Check,
+    pub llm_model: Check,
+    pub audio_input: Check,
+    pub clipboard: Check,
+    pub disk_space: Check,
+    /// Always ok; lists the optional features this library was built with
+    pub features: Check,
+    /// Only with `check_network`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<Check>,
+    pub duration_ms: u64,
+}
+
+/// Run every check. Loads no models; connects to the model download host
+/// only with `check_network`.
+pub fn run(check_network: bool) -> HealthReport {
+    let started = Instant::now();
+    let settings = Settings::load();
+    let features = crate::compiled_features();
+
+    let mut report = HealthReport {
+        ok: false,
+        initialized: check_initialized(),
+        data_dir: Check::from_result(check_data_dir()),
+        database: Check::from_result(check_database()),
+        whisper_model: Check::from_result(check_whisper_model(&settings)),
+        llm_model: Check::from_result(check_llm_model(&settings)),
+        audio_input: Check::from_result(check_audio_input()),
+        clipboard: Check::from_result(crate::clipboard::paste::check_clipboard().map(|via| format!("Available ({})", via))),
+        disk_space: Check::from_result(check_disk_space(&settings)),
+        features: Check::ok(if features.is_empty() { "none".to_string() } else { features.join(", ") }),
+        network: check_network.then(|| Check::from_result(check_network_reachable())),
+        duration_ms: 0,
+    };
+    report.ok = [
+        &report.initialized,
+        &report.data_dir,
+        &report.database,
+        &report.whisper_model,
+        &report.llm_model,
+        &report.audio_input,
+        &report.clipboard,
+        &report.disk_space,
+        &report.features,
+    ]
+    .into_iter()
+    .chain(report.network.as_ref())
+    .all(|check| check.ok);
+    report.duration_ms = started.elapsed().as_millis() as u64;
+    report
+}
+
+/// What this library is, for bug reports (phemy_get_build_info)
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Commit built from; absent when not built from a git checkout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_hash: Option<&'static str>,
+    pub features: Vec<&'static str>,
+    /// Target triple, e.g. "aarch64-apple-darwin"
+    pub target: &'static str,
+    /// "debug" or "release"
+    pub profile: &'static str,
+    /// Version of the whisper-rs crate, which bundles whisper.cpp; with
+    /// `whisper-local` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub whisper_rs: Option<&'static str>,
+    /// Version of the llama-cpp-2 crate, which bundles llama.cpp; with
+    /// `llm-local` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llama_cpp_2: Option<&'static str>,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: option_env!("PHEMY_GIT_HASH"),
+        features: crate::compiled_features(),
+        target: env!("PHEMY_TARGET"),
+        profile: if cfg!(debug_assertions) { "debug" } else { "release" },
+        whisper_rs: option_env!("PHEMY_WHISPER_RS_VERSION"),
+        llama_cpp_2: option_env!("PHEMY_LLAMA_CPP_2_VERSION"),
+    }
+}
+
+fn check_initialized() -> Check {
+    let status = crate::INIT_STATUS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    match status {
+        Some(status) if status.initialized => Check::ok(match status.data_dir {
+            Some(dir) => format!("Initialized with data directory {}", dir),
+            None => "Initialized".to_string(),
+        }),
+        Some(status) => Check::failed(status.error.unwrap_or_else(|| "Initialization failed".to_string())),
+        None => Check::failed("phemy_init has not been called"),
+    }
+}
+
+/// The data directory from phemy_init, or the default one used before it
+fn data_dir() -> PathBuf {
+    crate::settings::get_data_dir().unwrap_or_else(|| {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("phemy")
+    })
+}
+
+/// Create and remove a file in the data directory
+fn check_data_dir() -> anyhow::Result<String> {
+    let dir = data_dir();
+    let probe = dir.join(format!(".health-check-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(&probe, b"ok"))
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| anyhow::anyhow!("{} is not writable: {}", dir.display(), e))?;
+    Ok(format!("{} is writable", dir.display()))
+}
+
+fn check_database() -> anyhow::Result<String> {
+    let result = crate::db::quick_check()?;
+    anyhow::ensure!(result == "ok", "Integrity check failed: {}", result);
+    let size = crate::db::database_size_bytes()?;
+    Ok(format!("Open, integrity ok, {} KB", size / 1024))
+}
+
+fn check_whisper_model(settings: &Settings) -> anyhow::Result<String> {
+    let path = model_manager::get_model_path(&settings.whisper_model)?;
+    anyhow::ensure!(
+        path.exists(),
+        "Whisper model '{}' is not downloaded ({})",
+        settings.whisper_model,
+        path.display()
+    );
+    anyhow::ensure!(
+        cfg!(feature = "whisper-local"),
+        "Whisper model '{}' is at {}, but this build lacks the whisper-local feature",
+        settings.whisper_model,
+        path.display()
+    );
+    Ok(format!("'{}' at {}", settings.whisper_model, path.display()))
+}
+
+/// The model file for the configured mode is present and the provider is
+/// compiled in; the model is not loaded
+fn check_llm_model(settings: &Settings) -> anyhow::Result<String> {
+    let name = client::model_name(settings, &settings.prompt_mode);
+    match settings.llm.provider {
+        LlmProvider::Local => {
+            let path = llm_model_manager::get_model_path(name)?;
+            anyhow::ensure!(
+                path.exists(),
+                "LLM model '{}' is not downloaded ({})",
+                name,
+                path.display()
+            );
+            anyhow::ensure!(
+                cfg!(feature = "llm-local"),
+                "LLM model '{}' is at {}, but this build lacks the llm-local feature",
+                name,
+                path.display()
+            );
+            Ok(format!("'{}' at {}", name, path.display()))
+        }
+    }
+}
+
+fn check_audio_input() -> anyhow::Result<String> {
+    let devices = crate::audio::device::list_input_devices()?;
+    anyhow::ensure!(!devices.is_empty(), "No input devices found");
+    let default = devices.iter().find(|d| d.is_default).unwrap_or(&devices[0]);
+    Ok(format!("{} device(s), default '{}'", devices.len(), default.name))
+}
+
+/// Enough free space for the configured models that aren't downloaded yet
+fn check_disk_space(settings: &Settings) -> anyhow::Result<String> {
+    let dir = crate::utils::models_dir()?;
+    let free_mb = free_space_bytes(&dir)? / (1024 * 1024);
+
+    let llm_name = client::model_name(settings, &settings.prompt_mode);
+    let needed_mb: u64 = model_manager::list_models()?
+        .into_iter()
+        .filter(|m| m.name == settings.whisper_model && !m.downloaded)
+        .map(|m| m.size_mb)
+        .chain(
+            llm_model_manager::list_models()?
+                .into_iter()
+                .filter(|m| m.name == llm_name && !m.downloaded)
+                .map(|m| m.size_mb),
+        )
+        .sum();
+
+    anyhow::ensure!(
+        free_mb >= needed_mb,
+        "{} MB free in {}, but the configured models need {} MB",
+        free_mb,
+        dir.display(),
+        needed_mb
+    );
+    Ok(format!("{} MB free in {}", free_mb, dir.display()))
+}
+
+#[cfg(unix)]
+fn free_space_bytes(path: &Path) -> anyhow::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(windows)]
+fn free_space_bytes(path: &Path) -> anyhow::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            free_to_caller: *mut u64,
+            total: *mut u64,
+            total_free: *mut u64,
+        ) -> i32;
+    }
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut free = 0u64;
+    if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut free, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(free)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn free_space_bytes(_path: &Path) -> anyhow::Result<u64> {
+    anyhow::bail!("Free space can't be checked on this platform")
+}
+
+fn check_network_reachable() -> anyhow::Result<String> {
+    let runtime = crate::runtime()?;
+    let status = runtime.block_on(async {
+        reqwest::Client::builder()
+            .timeout(NETWORK_CHECK_TIMEOUT)
+            .build()?
+            .head(NETWORK_CHECK_URL)
+            .send()
+            .await
+            .map(|response| response.status())
+    })?;
+    Ok(format!("{} answered with HTTP {}", NETWORK_CHECK_URL, status.as_u16()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn reports_each_subsystem_without_loading_anything() {
+        let _env = test_support::env();
+        let settings = Settings::load();
+        let whisper = model_manager::get_model_path(&settings.whisper_model).unwrap();
+
+        let report = run(false);
+        assert!(report.data_dir.ok, "{:?}", report.data_dir);
+        assert!(report.database.ok, "{:?}", report.database);
+        assert!(!report.whisper_model.ok);
+        assert!(report.whisper_model.detail.contains("not downloaded"), "{}", report.whisper_model.detail);
+        assert!(report.whisper_model.detail.contains(&whisper.display().to_string()));
+        assert!(!report.llm_model.ok);
+        assert!(report.network.is_none());
+        assert!(!report.ok);
+
+        std::fs::write(&whisper, b"model").unwrap();
+        let report = run(false);
+        assert_eq!(report.whisper_model.ok, cfg!(feature = "whisper-local"), "{:?}", report.whisper_model);
+        assert!(!report.whisper_model.detail.contains("not downloaded"));
+
+        let json = serde_json::to_value(run(false)).unwrap();
+        assert!(json["clipboard"]["ok"].is_boolean());
+        assert!(json["disk_space"]["detail"].is_string());
+        assert!(json.get("network").is_none());
+    }
+
+    #[test]
+    fn build_info_describes_this_build() {
+        let json = serde_json::to_value(build_info()).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!json["target"].as_str().unwrap().is_empty());
+        assert_eq!(json["profile"], if cfg!(debug_assertions) { "debug" } else { "release" });
+        assert_eq!(json["features"].as_array().unwrap().len(), crate::compiled_features().len());
+        assert_eq!(json.get("whisper_rs").is_some(), cfg!(feature = "whisper-local"));
+    }
+
+    #[cfg(feature = "mock-audio")]
+    #[test]
+    fn audio_input_follows_the_available_devices() {
+        use crate::audio::mock::{self, MockDevice, Source};
+
+        let _env = test_support::env();
+        mock::install(Vec::new());
+        let report = run(false);
+        assert!(!report.audio_input.ok);
+        assert_eq!(report.audio_input.detail, "No input devices found");
+
+        mock::install(vec![MockDevice::new("Test Mic", Source::Silence)]);
+        let report = run(false);
+        mock::uninstall();
+        assert!(report.audio_input.ok);
+        assert_eq!(report.audio_input.detail, "1 device(s), default 'Test Mic'");
+    }
+}
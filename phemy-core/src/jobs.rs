@@ -0,0 +1,247 @@
+//! Registry of background pipeline jobs (phemy_stop_and_process_async).
+//! A job reports its stage as it runs; the host polls it by id. Finished
+//! jobs are kept until polled once or for `RESULT_TTL`. While running, a
+//! job is also an operation (see `ops`) under the same id.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// How long a finished job's result is kept if nobody polls it
+const RESULT_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Transcribing,
+    Optimizing,
+    Done,
+    Error,
+    Cancelled,
+}
+
+impl JobState {
+    fn is_finished(self) -> bool {
+        matches!(self, Self::Done | Self::Error | Self::Cancelled)
+    }
+}
+
+/// Pipeline phase passed to a job's phase sink (see `Job::with_phases`).
+/// The numbers are the ones the C API reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Phase {
+    Stopping = 0,
+    Resampling = 1,
+    Vad = 2,
+    Transcribing = 3,
+    Optimizing = 4,
+    Saving = 5,
+}
+
+/// Gets each phase a job enters, and sub-progress within one, with
+/// optional JSON detail
+pub type PhaseSink = Arc<dyn Fn(Phase, Option<&serde_json::Value>) + Send + Sync>;
+
+/// What `poll` reports
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub state: JobState,
+    /// Rough fraction of the pipeline done, by stage
+    pub progress: f64,
+    /// Items done and in all, for jobs that work through several (files in
+    /// a batch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Entry {
+    status: JobStatus,
+    finished_at: Option<Instant>,
+}
+
+static JOBS: std::sync::LazyLock<Mutex<HashMap<u64, Entry>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Signalled whenever a job finishes
+static FINISHED: Condvar = Condvar::new();
+
+/// Handle held by the code running a job
+pub struct Job {
+    pub id: u64,
+    cancel: Arc<AtomicBool>,
+    /// Registration as an operation; None for `untracked` jobs
+    op: Option<crate::ops::Op>,
+    phases: Option<PhaseSink>,
+}
+
+impl Job {
+    /// Report the phases of the work to `sink` as it runs
+    pub fn with_phases(self, sink: Option<PhaseSink>) -> Self {
+        Self { phases: sink, ..self }
+    }
+
+    /// Tell the phase sink, if there is one, about `phase`
+    pub fn report_phase(&self, phase: Phase, detail: Option<serde_json::Value>) {
+        if let Some(sink) = &self.phases {
+            sink(phase, detail.as_ref());
+        }
+    }
+
+    /// Move on to `state`; ignored once cancelled
+    pub fn set_state(&self, state: JobState, progress: f64) {
+        if let Some(op) = &self.op {
+            op.set_progress(progress);
+        }
+        let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = jobs.get_mut(&self.id) {
+            if !entry.status.state.is_finished() {
+                entry.status.state = state;
+                entry.status.progress = progress;
+            }
+        }
+    }
+
+    /// Record that `completed` of `total` items are done, which also sets
+    /// the progress; ignored once finished
+    pub fn set_items(&self, completed: usize, total: usize) {
+        let progress = if total == 0 { 0.0 } else { completed as f64 / total as f64 };
+        if let Some(op) = &self.op {
+            op.set_progress(progress);
+        }
+        let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = jobs.get_mut(&self.id) {
+            if !entry.status.state.is_finished() {
+                entry.status.progress = progress;
+                entry.status.completed = Some(completed);
+                entry.status.total = Some(total);
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Flag set when the job is cancelled, for work that checks it as it goes
+    pub fn cancel_flag(&self) -> &AtomicBool {
+        &self.cancel
+    }
+
+    /// Fail with `Cancelled` if `cancel` was called; for checks between stages
+    pub fn check_cancelled(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            return Err(crate::ops::Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Forget the job without recording an outcome, for a caller that
+    /// takes the result itself
+    pub fn discard(self) {
+        JOBS.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+    }
+
+    /// Record the outcome. A job cancelled part way through ends up
+    /// cancelled whatever `result` is.
+    pub fn finish(self, result: anyhow::Result<serde_json::Value>) {
+        let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = jobs.get_mut(&self.id) {
+            let status = &mut entry.status;
+            match result {
+                _ if self.is_cancelled() => status.state = JobState::Cancelled,
+                Ok(result) => {
+                    status.state = JobState::Done;
+                    status.progress = 1.0;
+                    status.result = Some(result);
+                }
+                Err(e) => {
+                    status.state = JobState::Error;
+                    status.error = Some(e.to_string());
+                }
+            }
+            entry.finished_at = Some(Instant::now());
+        }
+        FINISHED.notify_all();
+    }
+}
+
+/// Register a new job, starting in the transcribing state
+pub fn create() -> Job {
+    let op = crate::ops::start(crate::ops::OpKind::Job, None);
+    let id = op.id();
+    let cancel = op.shared_cancel_flag();
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    jobs.retain(|_, entry| entry.finished_at.is_none_or(|at| at.elapsed() < RESULT_TTL));
+    jobs.insert(
+        id,
+        Entry {
+            status: JobStatus {
+                state: JobState::Transcribing,
+                progress: 0.0,
+                completed: None,
+                total: None,
+                result: None,
+                error: None,
+            },
+            finished_at: None,
+        },
+    );
+    Job {
+        id,
+        cancel,
+        op: Some(op),
+        phases: None,
+    }
+}
+
+/// A job that isn't registered, for running the pipeline where nobody polls
+/// or cancels it: `set_state` and `finish` do nothing
+pub fn untracked() -> Job {
+    Job {
+        id: 0,
+        cancel: Arc::new(AtomicBool::new(false)),
+        op: None,
+        phases: None,
+    }
+}
+
+/// Current status of a job, or None if the id is unknown, was already
+/// collected or expired. A finished job is removed once polled.
+pub fn poll(id: u64) -> Option<JobStatus> {
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    let status = jobs.get(&id)?.status.clone();
+    if status.state.is_finished() {
+        jobs.remove(&id);
+    }
+    Some(status)
+}
+
+/// Block until a job finishes and collect its status
+pub fn wait(id: u64) -> Option<JobStatus> {
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        match jobs.get(&id) {
+            None => return None,
+            Some(entry) if entry.status.state.is_finished() => {
+                return jobs.remove(&id).map(|entry| entry.status);
+            }
+            Some(_) => jobs = FINISHED.wait(jobs).unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+}
+
+/// Ask a running job to stop at its next checkpoint. Returns false if the
+/// job is unknown or already finished.
+pub fn cancel(id: u64) -> bool {
+    crate::ops::kind(id) == Some(crate::ops::OpKind::Job) && crate::ops::cancel(id)
+}
@@ -1,532 +1,5020 @@
+pub mod api;
 pub mod audio;
 pub mod clipboard;
 pub mod db;
+pub mod download;
 pub mod ffi;
+pub mod health;
+pub mod jobs;
 pub mod llm;
+pub mod logging;
+pub mod ops;
+pub mod postprocess;
+pub mod secrets;
+pub mod session;
 pub mod settings;
+pub mod text;
 pub mod transcription;
+#[cfg(test)]
+mod test_support;
+#[cfg(feature = "uniffi")]
+pub mod uniffi_api;
 pub mod utils;
 
 use std::ffi::CString;
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use ffi::{c_str_to_str, str_to_c_char, to_json_c_char};
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
 
-/// Tokio runtime for async operations
-static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+use ffi::{c_str_to_str, error_json_c_char, error_value, legacy_error_c_char, str_to_c_char, to_json_c_char, ErrorCode};
 
-/// Guard against double-initialization
-static INIT: OnceLock<bool> = OnceLock::new();
+/// Tokio runtime for async operations, created on first use. Stopped by
+/// phemy_shutdown and not recreated until the next phemy_init.
+static RUNTIME: Mutex<RuntimeState> = Mutex::new(RuntimeState::NotStarted);
 
-fn runtime() -> &'static tokio::runtime::Runtime {
-    RUNTIME.get_or_init(|| {
-        tokio::runtime::Runtime::new().expect("Failed to create tokio runtime")
-    })
+enum RuntimeState {
+    NotStarted,
+    Running(AsyncRuntime),
+    ShutDown,
 }
 
-// ============================================================
-// Init
-// ============================================================
+/// A started runtime and the options it was built with
+struct AsyncRuntime {
+    runtime: Arc<tokio::runtime::Runtime>,
+    config: api::RuntimeConfig,
+    /// With `current_thread`, the thread driving the runtime, which returns
+    /// when the sender is dropped
+    driver: Option<(tokio::sync::oneshot::Sender<()>, std::thread::JoinHandle<()>)>,
+}
 
-/// Initialize phemy-core with a data directory path.
-/// Must be called before any other function.
-/// Returns true on success, true (no-op) on subsequent calls.
-#[no_mangle]
-pub extern "C" fn phemy_init(data_dir: *const c_char) -> bool {
-    let _ = env_logger::try_init();
+impl AsyncRuntime {
+    fn start(config: &api::RuntimeConfig) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        let mut builder = if config.current_thread {
+            tokio::runtime::Builder::new_current_thread()
+        } else {
+            tokio::runtime::Builder::new_multi_thread()
+        };
+        if !config.current_thread && config.worker_threads > 0 {
+            builder.worker_threads(config.worker_threads);
+        }
+        let name = config.thread_name.clone().unwrap_or_else(|| "phemy-runtime".to_string());
+        let thread_name = name.clone();
+        let threads = std::sync::atomic::AtomicUsize::new(0);
+        builder.thread_name_fn(move || {
+            let n = threads.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            format!("{}-{}", thread_name, n)
+        });
+        let runtime = Arc::new(builder.enable_all().build().context("Failed to start the async runtime")?);
+
+        // A current-thread runtime only runs spawned tasks while something
+        // blocks on it, so one thread does that for as long as it's running
+        let driver = if config.current_thread {
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let driven = runtime.clone();
+            let handle = std::thread::Builder::new()
+                .name(name)
+                .spawn(move || {
+                    let _ = driven.block_on(stopped);
+                })
+                .context("Failed to start the async runtime thread")?;
+            Some((stop, handle))
+        } else {
+            None
+        };
 
-    // Prevent double-initialization
-    if INIT.get().is_some() {
-        log::debug!("phemy_init called again — already initialized, skipping");
-        return true;
+        log::debug!("Started async runtime: {:?}", config);
+        Ok(Self {
+            runtime,
+            config: config.clone(),
+            driver,
+        })
     }
 
-    let dir = match unsafe { c_str_to_str(data_dir) } {
-        Some(s) => PathBuf::from(s),
-        None => {
-            dirs::data_dir()
-                .unwrap_or_else(|| PathBuf::from("."))
-                .join("phemy")
+    /// Shut the runtime down, or have it shut down once the calls still
+    /// using it return
+    fn stop(self) {
+        if let Some((stop, handle)) = self.driver {
+            drop(stop);
+            let _ = handle.join();
         }
-    };
+        match Arc::try_unwrap(self.runtime) {
+            Ok(runtime) => runtime.shutdown_timeout(RUNTIME_SHUTDOWN_TIMEOUT),
+            // A blocking call such as a download is still returning
+            Err(runtime) => {
+                log::warn!("Async runtime still in use; it stops when the last call returns");
+                stop_runtime_when_released(runtime);
+            }
+        }
+    }
+}
+
+/// How long phemy_shutdown waits for runtime tasks to finish
+const RUNTIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
-    settings::set_data_dir(dir.clone());
+/// Timings of the last pipeline run, for phemy_get_last_pipeline_metrics().
+/// Cleared when a run starts.
+static LAST_METRICS: Mutex<Option<PipelineMetrics>> = Mutex::new(None);
 
-    let db_path = dir.join("phemy.db");
-    match db::init(&db_path) {
-        Ok(_) => {
-            let _ = INIT.set(true);
-            true
+/// Database path of the current initialization, or None before phemy_init
+/// and after phemy_shutdown. Held while initializing.
+static INIT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// What the last phemy_init found, for phemy_get_init_status(). None before
+/// the first phemy_init and after phemy_shutdown.
+static INIT_STATUS: Mutex<Option<InitStatus>> = Mutex::new(None);
+
+/// History inserts spawned by stop-and-process that may still be running
+static PENDING_HISTORY: std::sync::LazyLock<std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>> =
+    std::sync::LazyLock::new(|| std::sync::Mutex::new(Vec::new()));
+
+/// The runtime, started with the default options if phemy_init hasn't
+/// started it. Fails after phemy_shutdown.
+fn runtime() -> anyhow::Result<Arc<tokio::runtime::Runtime>> {
+    let mut state = RUNTIME.lock().unwrap_or_else(|e| e.into_inner());
+    match &*state {
+        RuntimeState::Running(running) => Ok(running.runtime.clone()),
+        RuntimeState::ShutDown => anyhow::bail!("phemy-core is shut down; call phemy_init first"),
+        RuntimeState::NotStarted => {
+            let running = AsyncRuntime::start(&api::RuntimeConfig::default())?;
+            let runtime = running.runtime.clone();
+            *state = RuntimeState::Running(running);
+            Ok(runtime)
         }
-        Err(e) => {
-            log::error!("Failed to initialize database: {}", e);
-            false
+    }
+}
+
+/// Start the runtime with `config`, replacing a running one built with
+/// other options
+fn start_runtime(config: &api::RuntimeConfig) -> anyhow::Result<()> {
+    let mut state = RUNTIME.lock().unwrap_or_else(|e| e.into_inner());
+    if let RuntimeState::Running(running) = &*state {
+        if running.config == *config {
+            return Ok(());
         }
     }
+    let started = AsyncRuntime::start(config)?;
+    let previous = std::mem::replace(&mut *state, RuntimeState::Running(started));
+    drop(state);
+    if let RuntimeState::Running(previous) = previous {
+        previous.stop();
+    }
+    Ok(())
+}
+
+/// Stop `runtime` on a thread of its own once the calls still using it
+/// return. Dropping the last handle inside one of them would panic.
+fn stop_runtime_when_released(mut runtime: Arc<tokio::runtime::Runtime>) {
+    let spawned = std::thread::Builder::new()
+        .name("phemy-runtime-stop".into())
+        .spawn(move || loop {
+            match Arc::try_unwrap(runtime) {
+                Ok(runtime) => return runtime.shutdown_timeout(RUNTIME_SHUTDOWN_TIMEOUT),
+                Err(still_used) => runtime = still_used,
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        });
+    if let Err(e) = spawned {
+        log::error!("Failed to start the runtime shutdown thread: {}", e);
+    }
 }
 
 // ============================================================
-// Settings
+// Init
 // ============================================================
 
-/// Get current settings as JSON string.
-/// Caller must free the returned string with phemy_free_string().
+/// Initialize phemy-core with a data directory path.
+/// Pass ":memory:" to keep history in an in-memory database that is never
+/// written to disk (settings and models use the default data directory).
+/// Must be called before any other function.
+///
+/// Calling it again with the same directory is a no-op that returns true.
+/// With a different directory, pending history inserts are written, the
+/// database is closed and the local LLM unloaded, and settings, models and
+/// history then come from the new directory. This is refused (false) while
+/// recording, downloading a model or generating with the local LLM. If the
+/// database can't be opened, phemy is left uninitialized with no data
+/// directory set, as before the first call, and phemy_init can be called
+/// again. phemy_shutdown undoes initialization completely;
+/// phemy_init works again afterwards.
 #[no_mangle]
-pub extern "C" fn phemy_get_settings() -> *mut c_char {
-    let settings = settings::Settings::load();
-    to_json_c_char(&settings)
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_init(data_dir: *const c_char) -> bool {
+    init_succeeded(api::init(api::Config::from_data_dir(unsafe { c_str_to_str(data_dir) }, None)))
 }
 
-/// Save settings from a JSON string. Returns true on success.
+/// Initialize phemy-core with an encrypted history database.
+/// `key` is the database passphrase; an existing unencrypted database is
+/// converted on first use. Requires a build with the `sqlcipher` feature.
+/// Returns false if the key is wrong or encryption is unavailable.
 #[no_mangle]
-pub extern "C" fn phemy_save_settings(json: *const c_char) -> bool {
-    let json_str = match unsafe { c_str_to_str(json) } {
-        Some(s) => s,
-        None => return false,
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_init_with_key(data_dir: *const c_char, key: *const c_char) -> bool {
+    let key = match unsafe { c_str_to_str(key) } {
+        Some(k) if !k.is_empty() => k,
+        _ => {
+            log::error!("phemy_init_with_key requires a non-empty key");
+            return ffi::invalid_argument("phemy_init_with_key requires a non-empty key");
+        }
     };
+    init_succeeded(api::init(api::Config::from_data_dir(unsafe { c_str_to_str(data_dir) }, Some(key))))
+}
 
-    let settings: settings::Settings = match serde_json::from_str(json_str) {
-        Ok(s) => s,
-        Err(e) => {
-            log::error!("Failed to parse settings JSON: {}", e);
-            return false;
+/// Initialize phemy-core from a JSON configuration:
+/// `{ "data_dir"?, "in_memory"?, "db_key"?, "runtime"?: { "worker_threads"?,
+/// "current_thread"?, "thread_name"? } }`, all optional. `data_dir`,
+/// `in_memory` and `db_key` are as for phemy_init() and
+/// phemy_init_with_key(). `runtime` sets up the async runtime that
+/// transcriptions, optimizations and downloads run on: `worker_threads`
+/// (default 0 = one per core), `current_thread` to run its tasks on a
+/// single thread instead, and `thread_name`, the prefix of its thread names
+/// (default "phemy-runtime"). Calling it again with the same directory
+/// changes nothing, runtime included; switching directories also switches
+/// to the new runtime options.
+/// Returns false if the JSON is invalid or initialization fails;
+/// phemy_get_init_status() says why.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_init_ex(config_json: *const c_char) -> bool {
+    let config = match unsafe { c_str_to_str(config_json) }.map(serde_json::from_str::<api::Config>) {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            log::error!("phemy_init_ex: invalid configuration: {}", e);
+            set_init_error(format!("Invalid configuration: {}", e));
+            return ffi::invalid_argument(&format!("Invalid configuration: {}", e));
+        }
+        None => {
+            log::error!("phemy_init_ex requires a configuration");
+            set_init_error("Missing configuration".to_string());
+            return ffi::invalid_argument("Missing configuration");
         }
     };
+    init_succeeded(api::init(config))
+}
 
-    match settings.save() {
-        Ok(_) => true,
+/// The return value of the phemy_init variants. `init` has logged any
+/// error already.
+fn init_succeeded(result: anyhow::Result<api::Handle>) -> bool {
+    match result {
+        Ok(_) => ffi::succeeded(),
         Err(e) => {
-            log::error!("Failed to save settings: {}", e);
+            ffi::set_last_error(ffi::error_code(&e, ErrorCode::Internal), &format!("{:#}", e));
             false
         }
     }
 }
 
-/// Reset settings to defaults and return new settings as JSON.
-/// Caller must free the returned string with phemy_free_string().
-#[no_mangle]
-pub extern "C" fn phemy_reset_settings() -> *mut c_char {
-    let settings = settings::Settings::default();
-    let _ = settings.save();
-    to_json_c_char(&settings)
-}
+fn init(config: &api::Config) -> anyhow::Result<()> {
+    logging::install();
 
-// ============================================================
-// Audio
-// ============================================================
+    // In-memory history without a directory keeps settings and models in
+    // the default data directory
+    let dir = config.data_dir.clone().unwrap_or_else(|| {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("phemy")
+    });
 
-/// List audio input devices as JSON array.
-/// Caller must free the returned string with phemy_free_string().
-#[no_mangle]
-pub extern "C" fn phemy_list_audio_devices() -> *mut c_char {
-    match audio::device::list_input_devices() {
-        Ok(devices) => to_json_c_char(&devices),
-        Err(e) => {
-            log::error!("Failed to list audio devices: {}", e);
-            str_to_c_char("[]")
+    let db_path = if config.in_memory {
+        PathBuf::from(db::MEMORY_PATH)
+    } else {
+        dir.join("phemy.db")
+    };
+
+    let mut current = INIT.lock().unwrap_or_else(|e| e.into_inner());
+    match current.as_ref() {
+        Some(path) if *path == db_path => {
+            log::debug!("phemy_init called again — already initialized, skipping");
+            return Ok(());
+        }
+        Some(path) => {
+            // Refusing leaves the current initialization, and its status, in place
+            if let Err(e) = check_can_reinit().and_then(|_| {
+                // Before closing anything, so a busy model leaves this
+                // initialization intact
+                llm::local::try_unload(Duration::ZERO)
+            }) {
+                log::error!("Cannot switch data directory: {}", e);
+                let error = format!("Cannot switch data directory to {:?}: {}", dir, e);
+                set_init_error(error.clone());
+                return Err(anyhow::Error::msg(error));
+            }
+            log::info!("Switching database from {:?} to {:?}", path, db_path);
+            flush_history_inserts();
+            db::close();
+            settings::clear_data_dir();
+            *current = None;
         }
+        None => {}
     }
-}
 
-/// Start recording. `device` may be null for default device.
-/// `mic_cb` is a C function pointer called on the audio thread with (rms, peak), or null.
-#[no_mangle]
-pub extern "C" fn phemy_start_recording(
-    device: *const c_char,
-    mic_cb: Option<extern "C" fn(f32, f32)>,
-) -> bool {
-    let device_name = unsafe { c_str_to_str(device) };
-    match audio::capture::start_recording(device_name, mic_cb) {
-        Ok(_) => true,
+    let mut status = InitStatus {
+        initialized: false,
+        data_dir: Some(dir.to_string_lossy().to_string()),
+        db_path: Some(db_path.to_string_lossy().to_string()),
+        db_open: false,
+        in_memory: config.in_memory,
+        encrypted: config.db_key.is_some(),
+        features: compiled_features(),
+        warnings: Vec::new(),
+        error: None,
+    };
+    let result = match db::init(&db_path, config.db_key.as_deref()) {
+        Ok(warnings) => match start_runtime(&config.runtime) {
+            Ok(()) => {
+                // Only now, so a failed init leaves no data directory
+                // behind for settings and models to use
+                settings::set_data_dir(dir.clone());
+                migrate_settings_vocabulary();
+                warm_up_llm();
+                *current = Some(db_path);
+                status.initialized = true;
+                status.db_open = true;
+                status.warnings = warnings;
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("{:#}", e);
+                db::close();
+                status.error = Some(format!("{:#}", e));
+                Err(e)
+            }
+        },
         Err(e) => {
-            log::error!("Failed to start recording: {}", e);
-            false
+            log::error!("Failed to initialize database: {:#}", e);
+            status.error = Some(format!("Failed to initialize database: {:#}", e));
+            Err(e.context("Failed to initialize database"))
         }
-    }
+    };
+    *INIT_STATUS.lock().unwrap_or_else(|e| e.into_inner()) = Some(status);
+    result
 }
 
-/// Stop recording and return JSON with samples info.
-/// Caller must free the returned string with phemy_free_string().
+/// Whether phemy_init has succeeded and phemy_shutdown hasn't been called
+/// since. Waits for a phemy_init in progress.
 #[no_mangle]
-pub extern "C" fn phemy_stop_recording() -> *mut c_char {
-    match audio::capture::stop_recording() {
-        Ok((samples, rate)) => {
-            #[derive(serde::Serialize)]
-            struct StopResult {
-                sample_count: usize,
-                sample_rate: u32,
-                duration_secs: f64,
-            }
-            let result = StopResult {
-                sample_count: samples.len(),
-                sample_rate: rate,
-                duration_secs: samples.len() as f64 / rate as f64,
-            };
-            to_json_c_char(&result)
-        }
-        Err(e) => {
-            log::error!("Failed to stop recording: {}", e);
-            std::ptr::null_mut()
-        }
-    }
+pub extern "C" fn phemy_is_initialized() -> bool {
+    INIT.lock().unwrap_or_else(|e| e.into_inner()).is_some()
 }
 
-/// Stop recording, transcribe, optimize, save to history, and return JSON result.
-/// Always returns JSON (never null). On success: { "raw_transcript": "...", "optimized_prompt": "...", "mode": "...", "duration_secs": ... }
-/// On error: { "error": "description of what went wrong" }
+/// Describe the last phemy_init as JSON: { "initialized", "data_dir",
+/// "db_path", "db_open", "in_memory", "encrypted", "features": [...],
+/// "warnings": [...], "error"? }. Filled in whether phemy_init succeeded or
+/// not, so a false return can be diagnosed; `error` says why it failed.
+/// `warnings` lists problems that didn't stop initialization (e.g. the
+/// database permissions couldn't be set). Before phemy_init and after
+/// phemy_shutdown, `initialized` is false and the paths are null.
 /// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_stop_and_process() -> *mut c_char {
-    match stop_and_process_inner() {
-        Ok(json) => json,
-        Err(e) => {
-            log::error!("stop_and_process failed: {}", e);
-            #[derive(serde::Serialize)]
-            struct ErrorResult { error: String }
-            to_json_c_char(&ErrorResult { error: format!("{}", e) })
-        }
-    }
+pub extern "C" fn phemy_get_init_status() -> *mut c_char {
+    let status = INIT_STATUS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    to_json_c_char(&status.unwrap_or_else(|| InitStatus {
+        features: compiled_features(),
+        ..Default::default()
+    }))
 }
 
-fn stop_and_process_inner() -> anyhow::Result<*mut c_char> {
-    // 1. Stop recording → get samples
-    let (samples, sample_rate) = audio::capture::stop_recording()?;
+/// What phemy_get_init_status() reports
+#[derive(Debug, Clone, Default, serde::Serialize)]
+struct InitStatus {
+    initialized: bool,
+    data_dir: Option<String>,
+    db_path: Option<String>,
+    db_open: bool,
+    in_memory: bool,
+    encrypted: bool,
+    features: Vec<&'static str>,
+    warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
 
-    if samples.is_empty() {
-        anyhow::bail!("No audio samples captured");
+/// Record why phemy_init failed before getting started (refusing to switch
+/// directories, say), keeping the status of an initialization still in place
+fn set_init_error(error: String) {
+    let mut status = INIT_STATUS.lock().unwrap_or_else(|e| e.into_inner());
+    status
+        .get_or_insert_with(|| InitStatus {
+            features: compiled_features(),
+            ..Default::default()
+        })
+        .error = Some(error);
+}
+
+/// Optional cargo features this library was built with
+fn compiled_features() -> Vec<&'static str> {
+    [
+        ("whisper-local", cfg!(feature = "whisper-local")),
+        ("llm-local", cfg!(feature = "llm-local")),
+        ("sqlcipher", cfg!(feature = "sqlcipher")),
+        ("wayland", cfg!(feature = "wayland")),
+        ("uniffi", cfg!(feature = "uniffi")),
+        ("legacy-errors", cfg!(feature = "legacy-errors")),
+        ("track-strings", cfg!(feature = "track-strings")),
+        ("mock-audio", cfg!(feature = "mock-audio")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+/// Fail if switching the data directory would pull it out from under a
+/// recording or download
+fn check_can_reinit() -> anyhow::Result<()> {
+    if audio::capture::is_recording() {
+        anyhow::bail!("a recording is in progress");
+    }
+    if let Some(progress) = transcription::model_manager::get_download_progress() {
+        anyhow::bail!("whisper model '{}' is downloading", progress.model);
+    }
+    if let Some(progress) = llm::llm_model_manager::get_download_progress() {
+        anyhow::bail!("LLM model '{}' is downloading", progress.model);
     }
+    Ok(())
+}
 
-    let duration_secs = samples.len() as f64 / sample_rate as f64;
+/// Load the LLM in the background if `llm.warmup` is set.
+fn warm_up_llm() {
     let settings = settings::Settings::load();
-
-    // 2. Transcribe
-    let transcript = match runtime()
-        .block_on(transcription::engine::transcribe(&samples, sample_rate, &settings))
-    {
-        Ok(result) => result.text,
-        Err(e) => return Err(e),
+    if !settings.llm.warmup {
+        return;
+    }
+    let runtime = match runtime() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::warn!("LLM warmup failed: {:#}", e);
+            return;
+        }
     };
+    runtime.spawn_blocking(move || {
+        if let Err(e) = llm::client::warm_up(&settings, &settings.prompt_mode) {
+            log::warn!("LLM warmup failed: {:#}", e);
+        }
+    });
+}
 
-    if transcript.trim().is_empty() {
-        anyhow::bail!("No speech detected in recording");
+/// Copy vocabulary saved in settings.json (before it moved to the database)
+/// into the vocabulary table, if the table is still empty.
+fn migrate_settings_vocabulary() {
+    let settings = settings::Settings::load();
+    if settings.vocabulary.is_empty() {
+        return;
     }
-
-    // 3. Optimize (unless raw mode)
-    let opt_result = match runtime().block_on(llm::prompt_optimizer::optimize(&transcript, &settings)) {
-        Ok(result) => result,
-        Err(e) => {
-            log::warn!("Optimization failed, using raw transcript: {}", e);
-            llm::prompt_optimizer::OptimizationResult {
-                raw_transcript: transcript.clone(),
-                optimized_prompt: transcript.clone(),
-                mode: format!("{:?}", settings.prompt_mode).to_lowercase(),
-                provider: None,
+    match db::list_vocabulary() {
+        Ok(words) if words.is_empty() => {
+            if let Err(e) = db::replace_vocabulary(&settings.vocabulary) {
+                log::error!("Failed to migrate vocabulary from settings: {}", e);
             }
         }
-    };
+        Ok(_) => {}
+        Err(e) => log::error!("Failed to read vocabulary: {}", e),
+    }
+}
 
-    // 4. Save to history
-    let entry = db::new_history_entry(
-        opt_result.raw_transcript.clone(),
-        Some(opt_result.optimized_prompt.clone()),
-        opt_result.mode.clone(),
-        opt_result.provider.clone(),
-        duration_secs,
-    );
-    if let Err(e) = db::insert_history(&entry) {
-        log::error!("Failed to save history: {}", e);
+// ============================================================
+// Shutdown
+// ============================================================
+
+/// Tear down what phemy_init and later calls set up, so the library can be
+/// unloaded or initialized again. Stops any recording; cancels background
+/// jobs, model downloads, local LLM generation and queued pastes; waits for
+/// pending history inserts; closes the database and forgets the data
+/// directory; unloads the local LLM; clears the settings-changed and log
+/// callbacks; delivers the callbacks still queued and stops the callback
+/// thread and the async runtime. Whisper models are loaded per
+/// transcription, so none stay in memory. A download in progress stops
+/// before its next chunk and its partial file is removed.
+/// Blocks until done. Does nothing if not initialized, so calling it twice
+/// or before phemy_init is safe; phemy_init works again afterwards. Until
+/// then, calls that run on the async runtime (transcription, optimization,
+/// downloads) fail instead of starting a new one.
+#[no_mangle]
+pub extern "C" fn phemy_shutdown() {
+    api::Handle::unchecked().shutdown();
+}
+
+fn shutdown() {
+    if INIT.lock().unwrap_or_else(|e| e.into_inner()).take().is_none() {
+        log::debug!("phemy_shutdown called while not initialized, skipping");
+        return;
     }
+    INIT_STATUS.lock().unwrap_or_else(|e| e.into_inner()).take();
 
-    // 5. Return JSON result
-    #[derive(serde::Serialize)]
-    struct ProcessResult {
-        raw_transcript: String,
-        optimized_prompt: String,
-        mode: String,
-        duration_secs: f64,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        llm_error: Option<String>,
+    audio::capture::cancel_recording();
+    ops::cancel_all();
+    session::clear();
+    clipboard::worker::shutdown();
+
+    flush_history_inserts();
+    db::close();
+    settings::clear_data_dir();
+
+    llm::local::set_idle_unload(0);
+    llm::local::unload();
+
+    if let Ok(mut cb) = SETTINGS_CHANGED_CB.lock() {
+        *cb = None;
     }
+    ffi::stop_dispatcher();
 
-    // Detect if optimization was skipped (raw == optimized and mode isn't "raw")
-    let llm_error = if opt_result.raw_transcript == opt_result.optimized_prompt
-        && opt_result.mode.to_lowercase() != "raw"
-    {
-        opt_result.provider.as_ref().and_then(|p| {
-            if p.contains("failed") {
-                Some(p.clone())
-            } else {
-                None
-            }
-        })
-    } else {
-        None
-    };
+    let runtime = std::mem::replace(
+        &mut *RUNTIME.lock().unwrap_or_else(|e| e.into_inner()),
+        RuntimeState::ShutDown,
+    );
+    if let RuntimeState::Running(runtime) = runtime {
+        runtime.stop();
+    }
 
-    Ok(to_json_c_char(&ProcessResult {
-        raw_transcript: opt_result.raw_transcript,
-        optimized_prompt: opt_result.optimized_prompt,
-        mode: opt_result.mode,
-        duration_secs,
-        llm_error,
-    }))
+    ffi::report_leaked_strings();
+    log::info!("phemy-core shut down");
+    logging::clear_callback();
 }
 
-/// Check if currently recording.
+// ============================================================
+// Logging
+// ============================================================
+
+/// Send core log messages to `cb` instead of stderr, up to `max_level`
+/// (0 = off, 1 = error, 2 = warn, 3 = info, 4 = debug, 5 = trace).
+/// `cb` gets the level, the target (module path) and the message; both
+/// strings are only valid during the call. Unlike the other callbacks it
+/// is called directly on the thread that logs, which may be any thread,
+/// including several at once, and must not call back into phemy.
+/// Pass null to go back to stderr. Can be called before phemy_init;
+/// phemy_shutdown unregisters the callback.
 #[no_mangle]
-pub extern "C" fn phemy_get_recording_state() -> bool {
-    audio::capture::is_recording()
+pub extern "C" fn phemy_set_log_callback(
+    cb: logging::LogCallback,
+    max_level: i32,
+) {
+    logging::set_callback(cb, logging::level_filter(max_level));
+}
+
+/// Set the most verbose level logged, as in phemy_set_log_callback().
+/// Applies to stderr output too.
+#[no_mangle]
+pub extern "C" fn phemy_set_log_level(level: i32) {
+    logging::install();
+    logging::set_level(logging::level_filter(level));
 }
 
 // ============================================================
-// Transcription
+// Settings
 // ============================================================
 
-/// Transcribe audio samples. Returns JSON result.
+/// Get current settings as JSON string.
 /// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_transcribe(
-    samples: *const f32,
-    len: usize,
-    rate: u32,
-) -> *mut c_char {
-    if samples.is_null() || len == 0 {
-        return std::ptr::null_mut();
-    }
-
-    let samples = unsafe { std::slice::from_raw_parts(samples, len) };
-    let settings = settings::Settings::load();
+pub extern "C" fn phemy_get_settings() -> *mut c_char {
+    to_json_c_char(&api::Handle::unchecked().settings())
+}
 
-    match runtime().block_on(transcription::engine::transcribe(samples, rate, &settings)) {
-        Ok(result) => to_json_c_char(&result),
-        Err(e) => {
-            log::error!("Transcription failed: {}", e);
-            std::ptr::null_mut()
-        }
+/// Get one setting by dotted path (e.g. "llm.max_tokens") as a JSON value.
+/// Unknown keys return an error with code "not_found".
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_setting(key: *const c_char) -> *mut c_char {
+    let key = match unsafe { c_str_to_str(key) } {
+        Some(k) => k,
+        None => return legacy_error_c_char(None, ErrorCode::InvalidArgument, "key is required"),
+    };
+    match api::Handle::unchecked().setting(key) {
+        Some(value) => to_json_c_char(&value),
+        None => legacy_error_c_char(None, ErrorCode::NotFound, &format!("Unknown setting {:?}", key)),
     }
 }
 
-/// List available whisper models as JSON array.
+/// Describe every setting (type, default, allowed values, ranges, whether
+/// it needs a restart) as JSON, for building settings forms.
 /// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_list_whisper_models() -> *mut c_char {
-    match transcription::model_manager::list_models() {
-        Ok(models) => to_json_c_char(&models),
-        Err(e) => {
-            log::error!("Failed to list whisper models: {}", e);
-            str_to_c_char("[]")
-        }
-    }
+pub extern "C" fn phemy_get_settings_schema() -> *mut c_char {
+    to_json_c_char(&settings::schema())
 }
 
-/// Download a whisper model by name. Blocking.
+/// Check a hotkey string as the user types it. Returns
+/// { "hotkey": "<canonical form>" } or { "error": {...} }.
+/// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_download_whisper_model(name: *const c_char) -> bool {
-    let name = match unsafe { c_str_to_str(name) } {
-        Some(s) => s,
-        None => return false,
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_validate_hotkey(hotkey: *const c_char) -> *mut c_char {
+    #[derive(serde::Serialize)]
+    struct ValidHotkey {
+        hotkey: String,
+    }
+
+    let hotkey = match unsafe { c_str_to_str(hotkey) } {
+        Some(h) => h,
+        None => return error_json_c_char(ErrorCode::InvalidArgument, "hotkey is required"),
     };
+    match settings::normalize_hotkey(hotkey) {
+        Ok(hotkey) => to_json_c_char(&ValidHotkey { hotkey }),
+        Err(message) => error_json_c_char(ErrorCode::InvalidArgument, &message),
+    }
+}
 
-    match runtime().block_on(transcription::model_manager::download_model(name)) {
-        Ok(_) => true,
-        Err(e) => {
-            log::error!("Failed to download model: {}", e);
+/// Save settings from a JSON string. Returns true on success.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_save_settings(json: *const c_char) -> bool {
+    match save_settings_json(unsafe { c_str_to_str(json) }) {
+        Ok(_) => ffi::succeeded(),
+        Err((code, errors)) => {
+            ffi::set_last_error(code, &settings::describe_errors(&errors));
             false
         }
     }
 }
 
-/// Get download progress as JSON, or null if not downloading.
+/// Save settings from a JSON string, reporting why they were rejected.
+/// Returns { "ok": true } or { "error": {...}, "errors": [{ "field": "...", "message": "..." }] }.
+/// A successful save may also carry "warnings" in the same shape, for values
+/// that were saved but won't take effect yet.
+/// Errors not tied to one field (unparseable JSON, write failures) use the
+/// field name "settings".
 /// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_get_download_progress() -> *mut c_char {
-    match transcription::model_manager::get_download_progress() {
-        Some(progress) => to_json_c_char(&progress),
-        None => std::ptr::null_mut(),
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_save_settings_ex(json: *const c_char) -> *mut c_char {
+    #[derive(serde::Serialize)]
+    struct SaveResult {
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        ok: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<serde_json::Value>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<settings::FieldError>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<settings::FieldError>,
+    }
+
+    match save_settings_json(unsafe { c_str_to_str(json) }) {
+        Ok(settings) => to_json_c_char(&SaveResult {
+            ok: true,
+            error: None,
+            errors: Vec::new(),
+            warnings: settings.warnings(),
+        }),
+        Err((code, errors)) => {
+            let message = settings::describe_errors(&errors);
+            let json = to_json_c_char(&SaveResult {
+                ok: false,
+                error: settings_error_value(code, &errors),
+                errors,
+                warnings: Vec::new(),
+            });
+            ffi::set_last_error(code, &message);
+            json
+        }
     }
 }
 
-// ============================================================
-// LLM
-// ============================================================
+/// Parse, validate and save settings JSON, for phemy_save_settings() and
+/// phemy_save_settings_ex(). On failure, returns the error code and what
+/// was wrong; nothing has been changed then.
+pub(crate) fn save_settings_json(
+    json: Option<&str>,
+) -> Result<settings::Settings, (ErrorCode, Vec<settings::FieldError>)> {
+    let general = |message: String| vec![settings::FieldError::general(message)];
 
-/// Optimize a transcript into a polished prompt. Returns JSON.
+    let json = json.ok_or_else(|| {
+        (ErrorCode::InvalidArgument, general("Settings JSON is required".to_string()))
+    })?;
+    let settings = settings::from_json(json).map_err(|e| {
+        log::error!("Failed to parse settings JSON: {}", e);
+        (ErrorCode::InvalidArgument, general(format!("Invalid settings JSON: {}", e)))
+    })?;
+    api::Handle::unchecked().save_settings(&settings).map_err(settings_rejection)?;
+    Ok(settings)
+}
+
+/// The error code and field errors for a failed `Handle::save_settings` or
+/// `Handle::update_settings`
+fn settings_rejection(e: anyhow::Error) -> (ErrorCode, Vec<settings::FieldError>) {
+    match e.downcast::<api::SettingsRejected>() {
+        Ok(api::SettingsRejected(errors)) => (ErrorCode::InvalidSettings, errors),
+        Err(e) => (ErrorCode::Io, vec![settings::FieldError::general(e.to_string())]),
+    }
+}
+
+/// Save validated `settings`, then store their vocabulary in the database
+/// if `vocabulary` is set. The vocabulary is only replaced once the file
+/// is written, so a failed save leaves both as they were.
+fn commit_settings(settings: &settings::Settings, vocabulary: bool) -> anyhow::Result<()> {
+    settings.save()?;
+    if vocabulary {
+        if let Err(e) = db::replace_vocabulary(&settings.vocabulary) {
+            log::error!("Failed to save vocabulary: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Update only the settings fields present in `patch_json`, e.g.
+/// { "prompt_mode": "formal" }. `null` clears optional fields; unknown fields
+/// are rejected. Returns the full updated settings as JSON, or
+/// { "error": {...}, "errors": [{ "field": "...", "message": "..." }] } if
+/// nothing was saved.
 /// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_optimize_prompt(transcript: *const c_char) -> *mut c_char {
-    let transcript = match unsafe { c_str_to_str(transcript) } {
-        Some(s) => s,
-        None => return std::ptr::null_mut(),
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_update_settings(patch_json: *const c_char) -> *mut c_char {
+    let failed = |code: ErrorCode, errors: Vec<settings::FieldError>| {
+        log::error!("Settings update rejected: {}", settings::describe_errors(&errors));
+        settings_errors_json(code, errors)
     };
 
-    let settings = settings::Settings::load();
-    match runtime().block_on(llm::prompt_optimizer::optimize(transcript, &settings)) {
-        Ok(result) => to_json_c_char(&result),
+    let patch: serde_json::Value = match unsafe { c_str_to_str(patch_json) }.map(serde_json::from_str) {
+        Some(Ok(patch)) => patch,
+        Some(Err(e)) => {
+            return failed(
+                ErrorCode::InvalidArgument,
+                vec![settings::FieldError::general(format!("Invalid patch JSON: {}", e))],
+            )
+        }
+        None => {
+            return failed(
+                ErrorCode::InvalidArgument,
+                vec![settings::FieldError::general("Patch JSON is required")],
+            )
+        }
+    };
+
+    match api::Handle::unchecked().update_settings(&patch) {
+        Ok(updated) => to_json_c_char(&updated),
         Err(e) => {
-            log::error!("Optimization failed: {}", e);
-            std::ptr::null_mut()
+            let (code, errors) = settings_rejection(e);
+            failed(code, errors)
         }
     }
 }
 
-/// List available local LLM models as JSON array.
-/// Caller must free the returned string with phemy_free_string().
+/// Write the current settings to `path` as pretty JSON, so they can be
+/// imported on another machine with phemy_import_settings().
+/// Returns true on success.
 #[no_mangle]
-pub extern "C" fn phemy_list_llm_models() -> *mut c_char {
-    match llm::llm_model_manager::list_models() {
-        Ok(models) => to_json_c_char(&models),
-        Err(e) => {
-            log::error!("Failed to list LLM models: {}", e);
-            str_to_c_char("[]")
-        }
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_export_settings(path: *const c_char) -> bool {
+    let path = match unsafe { c_str_to_str(path) } {
+        Some(p) => p,
+        None => return ffi::invalid_argument("path is required"),
+    };
+
+    let result = serde_json::to_string_pretty(&load_settings_with_vocabulary().for_export())
+        .map_err(anyhow::Error::from)
+        .and_then(|json| Ok(std::fs::write(path, json)?));
+
+    match result {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Io, "Failed to export settings", &e),
     }
 }
 
-/// Download a local LLM model by name. Blocking.
+/// Import settings exported by phemy_export_settings(). The file is migrated
+/// from older versions and validated like any other save; nothing is changed
+/// if it is rejected. Returns the imported settings as JSON, or
+/// { "error": {...}, "errors": [{ "field": "...", "message": "..." }] }.
+/// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_download_llm_model(name: *const c_char) -> bool {
-    let name = match unsafe { c_str_to_str(name) } {
-        Some(s) => s,
-        None => return false,
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_import_settings(path: *const c_char) -> *mut c_char {
+    let failed = |code: ErrorCode, errors: Vec<settings::FieldError>| {
+        log::error!("Settings import rejected: {}", settings::describe_errors(&errors));
+        settings_errors_json(code, errors)
+    };
+
+    let path = match unsafe { c_str_to_str(path) } {
+        Some(p) => p,
+        None => {
+            return failed(
+                ErrorCode::InvalidArgument,
+                vec![settings::FieldError::general("path is required")],
+            )
+        }
     };
 
-    match runtime().block_on(llm::llm_model_manager::download_model(name)) {
-        Ok(_) => true,
+    let imported = match std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|contents| Ok(serde_json::from_str::<serde_json::Value>(&contents)?))
+        .and_then(settings::try_migrate)
+    {
+        Ok(imported) => imported,
         Err(e) => {
-            log::error!("Failed to download LLM model: {}", e);
-            false
+            return failed(
+                ErrorCode::Io,
+                vec![settings::FieldError::general(format!("Failed to read settings file: {}", e))],
+            )
         }
+    };
+    if let Err(errors) = imported.validate() {
+        return failed(ErrorCode::InvalidSettings, errors);
+    }
+
+    match commit_settings(&imported, true) {
+        Ok(_) => to_json_c_char(&imported),
+        Err(e) => failed(ErrorCode::Io, vec![settings::FieldError::general(e.to_string())]),
+    }
+}
+
+/// Settings from disk with the vocabulary filled in from the database.
+fn load_settings_with_vocabulary() -> settings::Settings {
+    let mut settings = settings::Settings::load();
+    if let Ok(words) = db::list_vocabulary() {
+        settings.vocabulary = words;
+    }
+    settings
+}
+
+/// Return { "error": {...}, "errors": [...] } as a C string.
+fn settings_errors_json(code: ErrorCode, errors: Vec<settings::FieldError>) -> *mut c_char {
+    #[derive(serde::Serialize)]
+    struct SettingsErrors {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<serde_json::Value>,
+        errors: Vec<settings::FieldError>,
     }
+    let message = settings::describe_errors(&errors);
+    let json = to_json_c_char(&SettingsErrors {
+        error: settings_error_value(code, &errors),
+        errors,
+    });
+    ffi::set_last_error(code, &message);
+    json
+}
+
+/// The `error` summarizing per-field settings errors; absent with the
+/// `legacy-errors` feature, where only `errors` was reported.
+fn settings_error_value(code: ErrorCode, errors: &[settings::FieldError]) -> Option<serde_json::Value> {
+    (!cfg!(feature = "legacy-errors")).then(|| error_value(code, &settings::describe_errors(errors)))
 }
 
-/// Get LLM model download progress as JSON, or null if not downloading.
+/// Reset settings to defaults and return new settings as JSON.
 /// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_get_llm_download_progress() -> *mut c_char {
-    match llm::llm_model_manager::get_download_progress() {
-        Some(progress) => to_json_c_char(&progress),
-        None => std::ptr::null_mut(),
+pub extern "C" fn phemy_reset_settings() -> *mut c_char {
+    to_json_c_char(&api::Handle::unchecked().reset_settings().unwrap_or_default())
+}
+
+/// Reset one settings section ("audio", "llm", "paste", "hotkey", ... or
+/// "all") to defaults, keeping everything else, and return the full settings
+/// as JSON. The vocabulary is never reset. Unknown sections return
+/// { "error": {...}, "sections": [...] }.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_reset_settings_section(section: *const c_char) -> *mut c_char {
+    #[derive(serde::Serialize)]
+    struct UnknownSection {
+        error: serde_json::Value,
+        sections: Vec<&'static str>,
+    }
+
+    let section = unsafe { c_str_to_str(section) }.unwrap_or_default();
+    if !settings::section_names().contains(&section) {
+        let message = format!("Unknown settings section {:?}", section);
+        let json = to_json_c_char(&UnknownSection {
+            error: error_value(ErrorCode::InvalidArgument, &message),
+            sections: settings::section_names(),
+        });
+        ffi::set_last_error(ErrorCode::InvalidArgument, &message);
+        return json;
+    }
+
+    let reset = match load_settings_with_vocabulary().reset_section(section) {
+        Ok(reset) => reset,
+        Err(e) => return error_json_c_char(ErrorCode::Internal, &e.to_string()),
+    };
+    if let Err(errors) = reset.validate() {
+        return settings_errors_json(ErrorCode::InvalidSettings, errors);
+    }
+    match reset.save() {
+        Ok(_) => to_json_c_char(&reset),
+        Err(e) => {
+            log::error!("Failed to reset settings section {}: {}", section, e);
+            error_json_c_char(ErrorCode::Io, &e.to_string())
+        }
     }
 }
 
-/// Delete a downloaded whisper model by name. Returns true on success.
+/// Store a secret ("llm_api_key" or "hf_token") outside the settings file.
+/// An empty value clears it. Settings only report whether it is set.
 #[no_mangle]
-pub extern "C" fn phemy_delete_whisper_model(name: *const c_char) -> bool {
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_set_secret(name: *const c_char, value: *const c_char) -> bool {
+    let (name, value) = match unsafe { (c_str_to_str(name), c_str_to_str(value)) } {
+        (Some(n), Some(v)) => (n, v),
+        _ => return ffi::invalid_argument("name and value are required"),
+    };
+    match secrets::Secret::from_name(name).and_then(|secret| secrets::set(secret, value)) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Internal, "Failed to store secret", &e),
+    }
+}
+
+/// Remove a secret ("llm_api_key" or "hf_token").
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_clear_secret(name: *const c_char) -> bool {
     let name = match unsafe { c_str_to_str(name) } {
-        Some(s) => s,
-        None => return false,
+        Some(n) => n,
+        None => return ffi::invalid_argument("name is required"),
     };
+    match secrets::Secret::from_name(name).and_then(secrets::clear) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Internal, "Failed to clear secret", &e),
+    }
+}
 
-    match transcription::model_manager::delete_model(name) {
-        Ok(_) => true,
+/// List settings profiles as { "active": "...", "profiles": ["default", ...] }.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_list_profiles() -> *mut c_char {
+    match settings::list_profiles() {
+        Ok(profiles) => to_json_c_char(&profiles),
         Err(e) => {
-            log::error!("Failed to delete whisper model: {}", e);
-            false
+            log::error!("Failed to list profiles: {}", e);
+            error_json_c_char(ErrorCode::Io, &e.to_string())
         }
     }
 }
 
-/// Delete a downloaded LLM model by name. Returns true on success.
+/// Create a settings profile, copying the active profile's settings if
+/// `copy_current` is true and starting from defaults otherwise.
 #[no_mangle]
-pub extern "C" fn phemy_delete_llm_model(name: *const c_char) -> bool {
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_create_profile(name: *const c_char, copy_current: bool) -> bool {
     let name = match unsafe { c_str_to_str(name) } {
-        Some(s) => s,
-        None => return false,
+        Some(n) => n,
+        None => return ffi::invalid_argument("name is required"),
+    };
+    match settings::create_profile(name, copy_current) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Io, "Failed to create profile", &e),
+    }
+}
+
+/// Delete a settings profile. The default and the active profile can't be deleted.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_delete_profile(name: *const c_char) -> bool {
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(n) => n,
+        None => return ffi::invalid_argument("name is required"),
     };
+    match settings::delete_profile(name) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::NotFound, "Failed to delete profile", &e),
+    }
+}
 
-    match llm::llm_model_manager::delete_model(name) {
-        Ok(_) => true,
+/// Switch to a settings profile and return its settings as JSON, or
+/// { "error": {...} }. The settings-changed callback fires on success.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_switch_profile(name: *const c_char) -> *mut c_char {
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(n) => n,
+        None => return error_json_c_char(ErrorCode::InvalidArgument, "name is required"),
+    };
+    match settings::switch_profile(name) {
+        Ok(_) => to_json_c_char(&load_settings_with_vocabulary()),
         Err(e) => {
-            log::error!("Failed to delete LLM model: {}", e);
-            false
+            log::error!("Failed to switch profile: {}", e);
+            error_json_c_char(ErrorCode::NotFound, &e.to_string())
         }
     }
 }
 
+/// Called after settings are saved or the profile is switched, or null for none
+pub type SettingsChangedCallback = Option<extern "C" fn()>;
+
+static SETTINGS_CHANGED_CB: std::sync::Mutex<SettingsChangedCallback> = std::sync::Mutex::new(None);
+
+/// Register a C function called after settings are saved or the profile is
+/// switched, so the host can reload them. Pass null to unregister.
+/// The callback runs on the callback thread.
+#[no_mangle]
+pub extern "C" fn phemy_set_settings_changed_callback(cb: SettingsChangedCallback) {
+    static REGISTER: std::sync::Once = std::sync::Once::new();
+    REGISTER.call_once(|| {
+        settings::add_listener(|_| {
+            let cb = SETTINGS_CHANGED_CB.lock().ok().and_then(|cb| *cb);
+            if let Some(cb) = cb {
+                ffi::dispatch(move || cb());
+            }
+        });
+    });
+    if let Ok(mut current) = SETTINGS_CHANGED_CB.lock() {
+        *current = cb;
+    }
+}
+
 // ============================================================
-// History
+// Audio
 // ============================================================
 
-/// Get history entries as JSON array.
+/// List audio input devices as JSON array.
 /// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_get_history(limit: i32, offset: i32) -> *mut c_char {
-    match db::get_history(limit as usize, offset as usize) {
-        Ok(entries) => to_json_c_char(&entries),
+pub extern "C" fn phemy_list_audio_devices() -> *mut c_char {
+    match api::Handle::unchecked().list_audio_devices() {
+        Ok(devices) => to_json_c_char(&devices),
         Err(e) => {
-            log::error!("Failed to get history: {}", e);
-            str_to_c_char("[]")
+            log::error!("Failed to list audio devices: {}", e);
+            legacy_error_c_char(Some("[]"), ErrorCode::Audio, &e.to_string())
         }
     }
 }
 
-/// Delete a history entry by ID. Returns true on success.
+/// Start recording. `device` may be null for default device.
+/// `mic_cb` is a C function pointer called with (rms, peak), or null. It
+/// runs on the callback thread; levels the host falls behind on are dropped.
 #[no_mangle]
-pub extern "C" fn phemy_delete_history_entry(id: *const c_char) -> bool {
-    let id = match unsafe { c_str_to_str(id) } {
-        Some(s) => s,
-        None => return false,
-    };
+pub extern "C" fn phemy_start_recording(
+    device: *const c_char,
+    mic_cb: audio::capture::MicLevelCallback,
+) -> bool {
+    phemy_start_recording_ex(device, mic_cb, false)
+}
+
+/// phemy_start_recording(), calling `mic_cb` straight from the audio thread
+/// if `direct` is true, for visualizations that can't wait on the callback
+/// thread. It must then return quickly and not call phemy functions.
+#[no_mangle]
+pub extern "C" fn phemy_start_recording_ex(
+    device: *const c_char,
+    mic_cb: audio::capture::MicLevelCallback,
+    direct: bool,
+) -> bool {
+    start_recording(device, mic_cb.into(), direct)
+}
+
+/// phemy_start_recording_ex() with a `user_data` pointer passed back to
+/// `mic_cb` with every level, so the host can reach its own state without a
+/// global. phemy never dereferences it. It must stay valid until
+/// phemy_stop_recording() (or another call that stops the recording)
+/// returns; no level callback runs after that.
+#[no_mangle]
+pub extern "C" fn phemy_start_recording_with_user_data(
+    device: *const c_char,
+    mic_cb: audio::capture::MicLevelUserDataCallback,
+    user_data: *mut c_void,
+    direct: bool,
+) -> bool {
+    start_recording(device, audio::capture::MicLevels::with_user_data(mic_cb, user_data), direct)
+}
+
+fn start_recording(device: *const c_char, levels: audio::capture::MicLevels, direct: bool) -> bool {
+    let device_name = unsafe { c_str_to_str(device) };
+    match api::Handle::unchecked().start_recording_with_levels(device_name, levels, direct) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Audio, "Failed to start recording", &e),
+    }
+}
 
-    match db::delete_history_entry(id) {
-        Ok(_) => true,
+/// Stop recording and return JSON with samples info; see
+/// phemy_stop_recording_with_samples() for the samples themselves.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_stop_recording() -> *mut c_char {
+    match api::Handle::unchecked().stop_recording() {
+        Ok((samples, rate)) => {
+            #[derive(serde::Serialize)]
+            struct StopResult {
+                sample_count: usize,
+                sample_rate: u32,
+                duration_secs: f64,
+            }
+            let result = StopResult {
+                sample_count: samples.len(),
+                sample_rate: rate,
+                duration_secs: samples.len() as f64 / rate as f64,
+            };
+            to_json_c_char(&result)
+        }
         Err(e) => {
-            log::error!("Failed to delete history entry: {}", e);
-            false
+            log::error!("Failed to stop recording: {}", e);
+            legacy_error_c_char(None, ErrorCode::Audio, &e.to_string())
         }
     }
 }
 
-/// Clear all history. Returns true on success.
+/// Stop recording and hand the recorded mono samples to the caller:
+/// `*out_samples` gets a buffer of `*out_len` samples (null if nothing was
+/// recorded) and `*out_rate` their sample rate. Free the buffer with
+/// phemy_free_samples(). All three pointers are required; if one is null,
+/// or the recording can't be stopped, returns false and leaves them as they
+/// were.
 #[no_mangle]
-pub extern "C" fn phemy_clear_history() -> bool {
-    match db::clear_history() {
-        Ok(_) => true,
-        Err(e) => {
-            log::error!("Failed to clear history: {}", e);
-            false
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_stop_recording_with_samples(
+    out_samples: *mut *mut f32,
+    out_len: *mut usize,
+    out_rate: *mut u32,
+) -> bool {
+    if out_samples.is_null() || out_len.is_null() || out_rate.is_null() {
+        return ffi::invalid_argument("out_samples, out_len and out_rate are required");
+    }
+    match api::Handle::unchecked().stop_recording() {
+        Ok((samples, rate)) => {
+            let (ptr, len) = ffi::samples_into_raw(samples);
+            unsafe {
+                *out_samples = ptr;
+                *out_len = len;
+                *out_rate = rate;
+            }
+            ffi::succeeded()
         }
+        Err(e) => ffi::failed(ErrorCode::Audio, "Failed to stop recording", &e),
     }
 }
 
-// ============================================================
-// Clipboard
-// ============================================================
+/// Stop recording and discard the audio without processing it, e.g. when
+/// the user backs out of push-to-talk. Returns whether a recording was
+/// running; calling it when none is does nothing.
+#[no_mangle]
+pub extern "C" fn phemy_cancel_recording() -> bool {
+    api::Handle::unchecked().cancel_recording()
+}
 
-/// Paste text into the focused application.
+/// Stop recording, transcribe, optimize, save to history, and return JSON result.
+/// Always returns JSON (never null). On success: { "raw_transcript": "...", "optimized_prompt": "...", "mode": "...", "duration_secs": ... }
+/// On error: { "error": { "code": "processing" or "cancelled", "message": "..." } }
+/// Blocks until done; see phemy_stop_and_process_async() for a version that doesn't.
+/// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_paste_text(text: *const c_char) -> bool {
+pub extern "C" fn phemy_stop_and_process() -> *mut c_char {
+    phemy_stop_and_process_ex(std::ptr::null())
+}
+
+/// phemy_stop_and_process() with per-call options, e.g.
+/// { "skip_llm": true, "skip_history": true, "mode": "code", "language": "de",
+/// "target_app": "iTerm2" }. Every field is optional and null means none:
+/// `skip_llm` (or `skip_optimization`) returns the raw transcript with mode
+/// "raw"; `skip_history` saves nothing, and `history_id` is then omitted;
+/// `mode` and `language` replace the saved prompt mode (and mode chain) and
+/// spoken language for this call only; `target_app` is the application the
+/// prompt is for, `{{app}}` in custom prompts; `include_metrics` adds the
+/// run's `metrics` (see phemy_get_last_pipeline_metrics()) to the result.
+/// Saved settings are not changed.
+/// Unknown keys or invalid values return an error with code
+/// "invalid_argument" and leave the recording running.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_stop_and_process_ex(options_json: *const c_char) -> *mut c_char {
+    match pipeline_options(unsafe { c_str_to_str(options_json) }) {
+        Ok(opts) => process_result_json(api::Handle::unchecked().stop_and_process(&opts)),
+        Err(message) => error_json_c_char(ErrorCode::InvalidArgument, &message),
+    }
+}
+
+/// Called by phemy_stop_and_process_with_progress() as the pipeline moves
+/// through its phases: 0 stopping, 1 resampling, 2 vad, 3 transcribing,
+/// 4 optimizing, 5 saving. A phase is reported again with sub-progress in
+/// `detail_json`, which is otherwise null: `{"segments"}` after each whisper
+/// run, and `{"mode", "tokens"}` as the LLM generates. `detail_json` is only
+/// valid during the call. It is called directly on the thread doing the
+/// work, so it should return quickly.
+pub type PhaseCallback =
+    Option<extern "C" fn(phase: i32, detail_json: *const c_char, user_data: *mut c_void)>;
+
+/// phemy_stop_and_process(), reporting each phase of the pipeline to
+/// `progress_cb` with `user_data` (see PhaseCallback) as it runs on this
+/// thread. The result is still the return value. Null `progress_cb` means
+/// none.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_stop_and_process_with_progress(
+    progress_cb: PhaseCallback,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    let phases = progress_cb.map(|cb| {
+        let user_data = ffi::UserData(user_data);
+        let sink: jobs::PhaseSink = Arc::new(move |phase: jobs::Phase, detail: Option<&serde_json::Value>| {
+            let detail = detail.map(|detail| CString::new(detail.to_string()).unwrap_or_default());
+            cb(phase as i32, detail.as_ref().map_or(std::ptr::null(), |d| d.as_ptr()), user_data.get());
+        });
+        sink
+    });
+    let opts = PipelineOptions::default();
+    process_result_json(api::Handle::unchecked().stop_and_process_with_phases(&opts, phases))
+}
+
+/// Run the phemy_stop_and_process() pipeline on audio the caller recorded:
+/// resample, trim silence, transcribe, optimize and save to history.
+/// `options_json` takes the options of phemy_stop_and_process_ex(); null
+/// means none.
+/// Always returns JSON (never null), shaped like phemy_stop_and_process().
+/// Null or empty samples, a zero rate or invalid options return an error with
+/// code "invalid_argument".
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_process_samples(
+    samples: *const f32,
+    len: usize,
+    rate: u32,
+    options_json: *const c_char,
+) -> *mut c_char {
+    if samples.is_null() || len == 0 {
+        return error_json_c_char(ErrorCode::InvalidArgument, "No audio samples provided");
+    }
+    if rate == 0 {
+        return error_json_c_char(ErrorCode::InvalidArgument, "Invalid sample rate: 0");
+    }
+    let opts = match pipeline_options(unsafe { c_str_to_str(options_json) }) {
+        Ok(opts) => opts,
+        Err(message) => return error_json_c_char(ErrorCode::InvalidArgument, &message),
+    };
+
+    let samples = unsafe { std::slice::from_raw_parts(samples, len) };
+    process_result_json(api::Handle::unchecked().process_samples(samples, rate, &opts))
+}
+
+/// Parse and check pipeline options JSON; None means the defaults
+fn pipeline_options(json: Option<&str>) -> Result<PipelineOptions, String> {
+    let opts = match json {
+        Some(json) => {
+            serde_json::from_str::<PipelineOptions>(json).map_err(|e| format!("Invalid options: {}", e))?
+        }
+        None => PipelineOptions::default(),
+    };
+    opts.validate()?;
+    Ok(opts)
+}
+
+/// A blocking pipeline run's result as JSON
+fn process_result_json(result: anyhow::Result<ProcessResult>) -> *mut c_char {
+    match result {
+        Ok(result) => to_json_c_char(&result),
+        Err(e) if ops::is_cancelled(&e) => error_json_c_char(ErrorCode::Cancelled, "Cancelled"),
+        Err(e) => error_json_c_char(ErrorCode::Processing, &e.to_string()),
+    }
+}
+
+/// Run the pipeline on a stopped recording as a job on this thread, so
+/// phemy_shutdown() can cancel it. Fails with `ops::Cancelled` if it was.
+fn run_tracked(recording: anyhow::Result<(Vec<f32>, u32)>, opts: &PipelineOptions) -> anyhow::Result<ProcessResult> {
+    run_tracked_with_phases(recording, opts, None)
+}
+
+/// `run_tracked`, reporting the pipeline's phases to `phases`
+fn run_tracked_with_phases(
+    recording: anyhow::Result<(Vec<f32>, u32)>,
+    opts: &PipelineOptions,
+    phases: Option<jobs::PhaseSink>,
+) -> anyhow::Result<ProcessResult> {
+    run_as_job_with_phases(phases, |job| {
+        recording.and_then(|(samples, sample_rate)| process_pipeline(&samples, sample_rate, opts, job, None))
+    })
+}
+
+/// Run `run` as a job on this thread, as `run_tracked` does
+fn run_as_job<T>(run: impl FnOnce(&jobs::Job) -> anyhow::Result<T>) -> anyhow::Result<T> {
+    run_as_job_with_phases(None, run)
+}
+
+fn run_as_job_with_phases<T>(
+    phases: Option<jobs::PhaseSink>,
+    run: impl FnOnce(&jobs::Job) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let job = jobs::create().with_phases(phases);
+    let result = run(&job);
+    let cancelled = job.is_cancelled();
+    job.discard();
+    if cancelled {
+        return Err(ops::Cancelled.into());
+    }
+    if let Err(e) = &result {
+        log::error!("Processing failed: {}", e);
+    }
+    result
+}
+
+/// Run text from another source (OCR, a selection) through the rest of the
+/// phemy_stop_and_process() pipeline: optimize it with the current
+/// settings, save it to history with a `duration_secs` of 0 and, with
+/// `paste`, paste the optimized prompt as phemy_paste_text() does.
+/// Always returns JSON (never null), shaped like phemy_stop_and_process();
+/// with `paste` it also has a `paste` field holding the phemy_paste_text_ex()
+/// outcome, or `{error}` if the paste failed. Null, empty or whitespace-only
+/// text returns an error with code "invalid_argument".
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_process_text(text: *const c_char, paste: bool) -> *mut c_char {
     let text = match unsafe { c_str_to_str(text) } {
-        Some(s) => s,
-        None => return false,
+        Some(s) if !s.trim().is_empty() => s,
+        _ => return error_json_c_char(ErrorCode::InvalidArgument, "No text provided"),
     };
 
+    let result = match api::Handle::unchecked().process_text(text, &PipelineOptions::default()) {
+        Ok(result) if paste => result,
+        result => return process_result_json(result),
+    };
     let settings = settings::Settings::load();
-    match clipboard::paste::paste_via_clipboard(
-        text,
-        &settings.paste_method,
-        settings.paste_delay_ms,
-    ) {
-        Ok(_) => true,
+    let paste = match clipboard::paste::paste_and_maybe_submit(&result.optimized_prompt, &settings, None, None) {
+        Ok(outcome) => serde_json::to_value(outcome).unwrap_or_default(),
         Err(e) => {
-            log::error!("Failed to paste text: {}", e);
-            false
+            log::error!("Failed to paste processed text: {}", e);
+            serde_json::json!({ "error": error_value(ErrorCode::Clipboard, &e.to_string()) })
         }
+    };
+
+    #[derive(serde::Serialize)]
+    struct PastedResult {
+        #[serde(flatten)]
+        result: ProcessResult,
+        paste: serde_json::Value,
     }
+    to_json_c_char(&PastedResult { result, paste })
 }
 
-// ============================================================
-// Memory management
-// ============================================================
+// Staged pipeline: the steps of phemy_stop_and_process() one call at a
+// time, so the host can show the transcript while the LLM runs. Each stage
+// runs the ones before it if they haven't run yet and keeps its result, so
+// calling one twice is cheap. The calls block like phemy_stop_and_process();
+// a stage on one session can't run while another is. Stages report an
+// unknown, committed, discarded or expired session with code "not_found".
 
-/// Free a string returned by any phemy_* function.
+/// Stop recording and keep it as a session for the phemy_session_*()
+/// stages, using the settings as they are now. Returns JSON
+/// `{session_id, sample_count, sample_rate, duration_secs}`, or `{error}`
+/// if the recording couldn't be stopped or was empty. A session left
+/// unused for ten minutes is dropped.
+/// Caller must free the returned string with phemy_free_string().
 #[no_mangle]
-pub extern "C" fn phemy_free_string(ptr: *mut c_char) {
-    if !ptr.is_null() {
-        unsafe {
-            let _ = CString::from_raw(ptr);
+pub extern "C" fn phemy_finish_recording() -> *mut c_char {
+    let (samples, sample_rate) = match api::Handle::unchecked().stop_recording() {
+        Ok(recording) => recording,
+        Err(e) => {
+            log::error!("Failed to stop recording: {}", e);
+            return error_json_c_char(ErrorCode::Audio, &e.to_string());
+        }
+    };
+    if samples.is_empty() {
+        record_event(db::EventKind::NoSpeech, "No audio samples captured", None);
+        return error_json_c_char(ErrorCode::Processing, "No audio samples captured");
+    }
+
+    let duration_secs = samples.len() as f64 / sample_rate as f64;
+    let sample_count = samples.len();
+    let session_id = session::create(session::Session {
+        samples,
+        sample_rate,
+        settings: settings::Settings::load(),
+        options: PipelineOptions::default(),
+        transcript: None,
+        optimization: None,
+        metrics: PipelineMetrics {
+            recording_secs: duration_secs,
+            ..Default::default()
+        },
+    });
+    to_json_c_char(&serde_json::json!({
+        "session_id": session_id,
+        "sample_count": sample_count,
+        "sample_rate": sample_rate,
+        "duration_secs": duration_secs,
+    }))
+}
+
+/// Transcribe a session's recording. Returns JSON `{raw_transcript}`
+/// (redacted as phemy_stop_and_process() would), or `{error}`.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_session_transcribe(id: u64) -> *mut c_char {
+    session_stage(id, false, |session, job| {
+        let transcript = session_transcript(session, job)?;
+        let redaction = &session.settings.redaction;
+        let transcript = if redaction.any_enabled() {
+            postprocess::redact(&transcript, redaction)
+        } else {
+            transcript
+        };
+        Ok(serde_json::json!({ "raw_transcript": transcript }))
+    })
+}
+
+/// Optimize a session's transcript, transcribing it first if needed.
+/// Returns the phemy_optimize_prompt() JSON, or `{error}`. As in
+/// phemy_stop_and_process(), a failed LLM call falls back to the
+/// transcript rather than failing.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_session_optimize(id: u64) -> *mut c_char {
+    session_stage(id, false, session_optimization)
+}
+
+/// Save a session to history, running the stages it hasn't been through,
+/// and end it. Returns the phemy_stop_and_process() JSON, or `{error}`;
+/// on error the session is kept so the host can retry or discard it.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_session_commit(id: u64) -> *mut c_char {
+    session_stage(id, true, |session, job| {
+        let opt_result = session_optimization(session, job)?;
+        let started = Instant::now();
+        let audio = PipelineAudio::Recording(&session.samples, session.sample_rate);
+        let duration_secs = session.metrics.recording_secs;
+        let result = commit_stage(
+            opt_result,
+            duration_secs,
+            &session.settings,
+            &session.options,
+            job,
+            audio,
+            &mut session.metrics,
+        );
+        session.metrics.total_ms += started.elapsed().as_millis() as u64;
+        *LAST_METRICS.lock().unwrap_or_else(|e| e.into_inner()) = Some(session.metrics.clone());
+        result.map(|result| ProcessResult {
+            metrics: session.options.include_metrics.then(|| session.metrics.clone()),
+            ..result
+        })
+    })
+}
+
+/// Drop a session and its recording without saving anything. Returns false
+/// if it doesn't exist.
+#[no_mangle]
+pub extern "C" fn phemy_session_discard(id: u64) -> bool {
+    session::discard(id)
+}
+
+/// Run `stage` on session `id` as a job, so phemy_shutdown() can cancel it,
+/// and return its result as JSON. With `end`, the session is dropped once
+/// the stage succeeds.
+fn session_stage<T: serde::Serialize>(
+    id: u64,
+    end: bool,
+    stage: impl FnOnce(&mut session::Session, &jobs::Job) -> anyhow::Result<T>,
+) -> *mut c_char {
+    let mut session = match session::take(id) {
+        Ok(session) => session,
+        Err(session::Unavailable::NotFound) => {
+            return error_json_c_char(ErrorCode::NotFound, &format!("Session {} not found", id))
+        }
+        Err(session::Unavailable::InUse) => {
+            return error_json_c_char(ErrorCode::Busy, &format!("Session {} is busy with another stage", id))
+        }
+    };
+    let result = run_as_job(|job| stage(&mut session, job));
+    if end && result.is_ok() {
+        session::discard(id);
+    } else {
+        session::put_back(id, session);
+    }
+    match result {
+        Ok(result) => to_json_c_char(&result),
+        Err(e) if ops::is_cancelled(&e) => error_json_c_char(ErrorCode::Cancelled, "Cancelled"),
+        Err(e) => error_json_c_char(ErrorCode::Processing, &e.to_string()),
+    }
+}
+
+/// A session's transcript, transcribing it if that hasn't been done
+fn session_transcript(session: &mut session::Session, job: &jobs::Job) -> anyhow::Result<String> {
+    if let Some(transcript) = &session.transcript {
+        return Ok(transcript.clone());
+    }
+    let started = Instant::now();
+    let transcript = transcribe_stage(
+        &session.samples,
+        session.sample_rate,
+        &session.settings,
+        job,
+        &mut session.metrics,
+    );
+    session.metrics.total_ms += started.elapsed().as_millis() as u64;
+    let transcript = transcript?;
+    session.transcript = Some(transcript.clone());
+    Ok(transcript)
+}
+
+/// A session's optimization, running it (and transcription) if that hasn't
+/// been done
+fn session_optimization(
+    session: &mut session::Session,
+    job: &jobs::Job,
+) -> anyhow::Result<llm::prompt_optimizer::OptimizationResult> {
+    if let Some(optimization) = &session.optimization {
+        return Ok(optimization.clone());
+    }
+    let transcript = session_transcript(session, job)?;
+    let started = Instant::now();
+    let optimization = optimize_stage(
+        &transcript,
+        &session.settings,
+        &session.options,
+        job,
+        &mut session.metrics,
+    );
+    session.metrics.total_ms += started.elapsed().as_millis() as u64;
+    let optimization = optimization?;
+    session.optimization = Some(optimization.clone());
+    Ok(optimization)
+}
+
+/// Stop recording and run the rest of phemy_stop_and_process() in the
+/// background. Returns a job id for phemy_poll_job() and phemy_cancel_job().
+#[no_mangle]
+pub extern "C" fn phemy_stop_and_process_async() -> u64 {
+    let recording = api::Handle::unchecked().stop_recording();
+    let job = jobs::create();
+    let id = job.id;
+    match runtime() {
+        Ok(runtime) => {
+            let done = Completion::new(None, std::ptr::null_mut());
+            runtime.spawn_blocking(move || run_job(job, recording, PipelineOptions::default(), done));
+        }
+        Err(e) => job.finish(Err(e)),
+    }
+    id
+}
+
+/// State of a job from phemy_stop_and_process_async(),
+/// phemy_stop_and_process_with_user_data() or
+/// phemy_batch_process_directory() as JSON:
+/// `{state, progress, completed?, total?, result?, error?}`. `state` is
+/// "transcribing", "optimizing", "done", "error" or "cancelled"; `progress`
+/// is a rough fraction from 0 to 1; `completed` and `total` count the files
+/// of a batch; `result` (when done) is the phemy_stop_and_process() result,
+/// or the batch's report, and `error` (on error) what went wrong.
+/// A finished job is forgotten once polled, or 10 minutes after it finished;
+/// unknown ids return an error with code "not_found".
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_poll_job(id: u64) -> *mut c_char {
+    match jobs::poll(id) {
+        Some(status) => to_json_c_char(&status),
+        None => error_json_c_char(ErrorCode::NotFound, "Unknown job"),
+    }
+}
+
+/// Stop a job from phemy_stop_and_process_async() (or _with_user_data) at
+/// its next stage, or
+/// during its local LLM generation; other calls using the LLM carry on. A
+/// transcription in progress runs to the end first. Nothing is saved to
+/// history; a batch from phemy_batch_process_directory() keeps the files it
+/// already imported. Returns false if the job is unknown or already finished.
+#[no_mangle]
+pub extern "C" fn phemy_cancel_job(id: u64) -> bool {
+    jobs::cancel(id)
+}
+
+/// phemy_stop_and_process_async() with the options of
+/// phemy_stop_and_process_ex() (null for none), reporting the outcome to
+/// `done_cb` with `user_data` as well: on success with the
+/// phemy_stop_and_process() result. Returns the job id for phemy_poll_job()
+/// and phemy_cancel_job(), or 0 if the options are invalid; the recording
+/// then keeps running. `done_cb` is called exactly once either way.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_stop_and_process_with_user_data(
+    options_json: *const c_char,
+    done_cb: CompletionCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    let done = Completion::new(done_cb, user_data);
+    let opts = match pipeline_options(unsafe { c_str_to_str(options_json) }) {
+        Ok(opts) => opts,
+        Err(message) => {
+            done.fail(ErrorCode::InvalidArgument, message);
+            return 0;
+        }
+    };
+
+    let recording = api::Handle::unchecked().stop_recording();
+    let job = jobs::create();
+    let id = job.id;
+    match runtime() {
+        Ok(runtime) => {
+            runtime.spawn_blocking(move || run_job(job, recording, opts, done));
+        }
+        Err(e) => {
+            done.fail(ErrorCode::Processing, e.to_string());
+            job.finish(Err(e));
+        }
+    }
+    id
+}
+
+/// Run the pipeline on a stopped recording for `job`, record the outcome
+/// and report it to `done`
+fn run_job(job: jobs::Job, recording: anyhow::Result<(Vec<f32>, u32)>, opts: PipelineOptions, done: Completion) {
+    let result = recording
+        .and_then(|(samples, sample_rate)| process_pipeline(&samples, sample_rate, &opts, &job, None))
+        .and_then(|result| Ok(serde_json::to_value(result)?));
+    match &result {
+        _ if job.is_cancelled() => done.fail(ErrorCode::Cancelled, "Cancelled"),
+        Ok(value) => done.succeed(value.clone()),
+        Err(e) => {
+            log::error!("Processing failed: {}", e);
+            done.fail_with(ErrorCode::Processing, e);
+        }
+    }
+    job.finish(result);
+}
+
+/// Called exactly once when a `_with_user_data` operation ends, including
+/// on error or cancellation, with the `user_data` it was started with. On
+/// success `result_json` is the operation's result, otherwise
+/// { "error": { "code", "message" } }; it is only valid during the call.
+/// It runs on the callback thread. Null means none.
+pub type CompletionCallback =
+    Option<extern "C" fn(success: bool, result_json: *const c_char, user_data: *mut c_void)>;
+
+/// A CompletionCallback waiting for its operation to end. Dropped without
+/// `succeed` or `fail` (the work panicked), it reports an internal error,
+/// so the host hears back exactly once.
+struct Completion {
+    callback: CompletionCallback,
+    user_data: ffi::UserData,
+}
+
+impl Completion {
+    fn new(callback: CompletionCallback, user_data: *mut c_void) -> Self {
+        Self {
+            callback,
+            user_data: ffi::UserData(user_data),
+        }
+    }
+
+    fn succeed(mut self, result: serde_json::Value) {
+        self.report(true, result);
+    }
+
+    fn fail(mut self, code: ErrorCode, message: impl AsRef<str>) {
+        self.report(false, serde_json::json!({ "error": error_value(code, message.as_ref()) }));
+    }
+
+    /// `fail` with `code`, or the busy or cancelled code if `e` is one
+    fn fail_with(self, code: ErrorCode, e: &anyhow::Error) {
+        self.fail(ffi::error_code(e, code), e.to_string());
+    }
+
+    fn report(&mut self, success: bool, json: serde_json::Value) {
+        let Some(callback) = self.callback.take() else {
+            return;
+        };
+        let json = CString::new(json.to_string()).unwrap_or_default();
+        let user_data = self.user_data;
+        ffi::dispatch(move || callback(success, json.as_ptr(), user_data.get()));
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if self.callback.is_some() {
+            self.report(
+                false,
+                serde_json::json!({ "error": error_value(ErrorCode::Internal, "The operation ended unexpectedly") }),
+            );
+        }
+    }
+}
+
+/// Run `work` on the async runtime as operation `id`, reporting its result
+/// to `done`. Returns `id`, or 0 if the runtime isn't available; `done`
+/// is told either way.
+fn spawn_with_completion(
+    id: u64,
+    code: ErrorCode,
+    work: impl std::future::Future<Output = anyhow::Result<serde_json::Value>> + Send + 'static,
+    done: Completion,
+) -> u64 {
+    match runtime() {
+        Ok(runtime) => {
+            runtime.spawn(async move {
+                match work.await {
+                    Ok(result) => done.succeed(result),
+                    Err(e) => {
+                        log::error!("Operation {} failed: {}", id, e);
+                        done.fail_with(code, &e);
+                    }
+                }
+            });
+            id
+        }
+        Err(e) => {
+            done.fail_with(code, &e);
+            0
+        }
+    }
+}
+
+/// Transcribe every WAV file in the directory at `path` into history in
+/// the background. Returns a job id for phemy_poll_job() and
+/// phemy_cancel_job(); while it runs, the job's `completed` and `total`
+/// count files. `options_json` (null for defaults) is `{ "skip_optimization"?,
+/// "mode"?, "language"?, "recursive"? }`, the first three as for
+/// phemy_stop_and_process_ex() and `recursive` to include subdirectories.
+///
+/// Each file goes through the stop-and-process pipeline, one at a time,
+/// and its entry is dated with the file's modification time and records
+/// its path (`source_path`). Files already imported are skipped. A file
+/// that fails doesn't stop the batch; the job's result lists what happened
+/// to each: `{ "total", "imported": [{ "path", "history_id" }], "skipped":
+/// [path], "failed": [{ "path", "error" }] }`. A bad path or options make
+/// the job fail instead.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_batch_process_directory(path: *const c_char, options_json: *const c_char) -> u64 {
+    let path = unsafe { c_str_to_str(path) }.map(PathBuf::from);
+    let options_json = unsafe { c_str_to_str(options_json) }.map(str::to_string);
+    let job = jobs::create();
+    let id = job.id;
+    match runtime() {
+        Ok(runtime) => {
+            runtime.spawn_blocking(move || {
+                let result = run_batch(path, options_json.as_deref(), &job);
+                job.finish(result);
+            });
+        }
+        Err(e) => job.finish(Err(e)),
+    }
+    id
+}
+
+/// Check the arguments of phemy_batch_process_directory() and run the batch
+fn run_batch(path: Option<PathBuf>, options_json: Option<&str>, job: &jobs::Job) -> anyhow::Result<serde_json::Value> {
+    let path = path.ok_or_else(|| anyhow::anyhow!("A directory path is required"))?;
+    let options = match options_json {
+        Some(json) => serde_json::from_str(json).map_err(|e| anyhow::anyhow!("Invalid options: {}", e))?,
+        None => BatchOptions::default(),
+    };
+    let report = process_directory(&path, &options, job)?;
+    Ok(serde_json::to_value(report)?)
+}
+
+/// Options for phemy_batch_process_directory()
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BatchOptions {
+    #[serde(alias = "skip_llm")]
+    skip_optimization: bool,
+    mode: Option<String>,
+    language: Option<String>,
+    recursive: bool,
+}
+
+/// A recording read from a file rather than the microphone
+struct SourceFile {
+    path: PathBuf,
+    modified: chrono::DateTime<chrono::Utc>,
+}
+
+/// What phemy_batch_process_directory() reports
+#[derive(Debug, Default, serde::Serialize)]
+struct BatchReport {
+    total: usize,
+    imported: Vec<BatchImported>,
+    skipped: Vec<String>,
+    failed: Vec<BatchFailure>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct BatchImported {
+    path: String,
+    history_id: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct BatchFailure {
+    path: String,
+    error: String,
+}
+
+/// Run each WAV file under `dir` through the pipeline for `job`
+fn process_directory(dir: &std::path::Path, options: &BatchOptions, job: &jobs::Job) -> anyhow::Result<BatchReport> {
+    let pipeline = PipelineOptions {
+        skip_optimization: options.skip_optimization,
+        mode: options.mode.clone(),
+        language: options.language.clone(),
+        ..Default::default()
+    };
+    pipeline.validate().map_err(anyhow::Error::msg)?;
+    let files = audio_files(dir, options.recursive)?;
+    log::info!("Importing {} audio files from {:?}", files.len(), dir);
+
+    // Entries from an earlier batch may still be on their way to the database
+    flush_history_inserts();
+    let mut report = BatchReport {
+        total: files.len(),
+        ..Default::default()
+    };
+    for (done, path) in files.iter().enumerate() {
+        job.set_items(done, files.len());
+        job.check_cancelled()?;
+        let display = path.to_string_lossy().to_string();
+        if db::history_has_source(&display)? {
+            report.skipped.push(display);
+            continue;
+        }
+        match process_file(path, &pipeline, job) {
+            Ok(result) => report.imported.push(BatchImported {
+                path: display,
+                history_id: result.history_id,
+            }),
+            Err(e) => {
+                job.check_cancelled()?;
+                log::warn!("Failed to import {:?}: {:#}", path, e);
+                report.failed.push(BatchFailure {
+                    path: display,
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+    }
+    job.set_items(files.len(), files.len());
+    flush_history_inserts();
+    log::info!(
+        "Imported {} of {} audio files from {:?} ({} skipped, {} failed)",
+        report.imported.len(),
+        report.total,
+        dir,
+        report.skipped.len(),
+        report.failed.len()
+    );
+    Ok(report)
+}
+
+fn process_file(path: &std::path::Path, opts: &PipelineOptions, job: &jobs::Job) -> anyhow::Result<ProcessResult> {
+    let modified = std::fs::metadata(path)?.modified()?;
+    let (samples, sample_rate) = utils::read_wav(path)?;
+    let source = SourceFile {
+        path: path.to_path_buf(),
+        modified: modified.into(),
+    };
+    process_pipeline(&samples, sample_rate, opts, job, Some(&source))
+}
+
+/// WAV files in `dir` (and its subdirectories if `recursive`), sorted by path
+fn audio_files(dir: &std::path::Path, recursive: bool) -> anyhow::Result<Vec<PathBuf>> {
+    use anyhow::Context;
+
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if recursive {
+                    dirs.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav")) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Which pipeline stages to skip, and settings overridden for one run
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct PipelineOptions {
+    /// Return the raw transcript instead of calling the LLM
+    #[serde(alias = "skip_llm")]
+    pub skip_optimization: bool,
+    /// Don't save a history entry or the recording
+    pub skip_history: bool,
+    /// Prompt mode id (e.g. "code") used instead of `prompt_mode` and
+    /// `prompt_mode_chain`
+    pub mode: Option<String>,
+    /// Spoken language code (or "auto") used instead of `language`
+    pub language: Option<String>,
+    /// Add the run's `metrics` to the result
+    pub include_metrics: bool,
+    /// Application the prompt will be pasted into, for `{{app}}` in custom
+    /// prompts
+    pub target_app: Option<String>,
+}
+
+impl PipelineOptions {
+    /// Check `mode` and `language`, so bad options are reported before the
+    /// recording is stopped
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        self.prompt_mode()?;
+        if let Some(language) = &self.language {
+            if !settings::is_valid_language(language) {
+                return Err(format!("Expected \"auto\" or a language code like \"en\", got {:?}", language));
+            }
+        }
+        Ok(())
+    }
+
+    /// `settings` with this run's overrides applied; nothing is saved
+    fn apply(&self, mut settings: settings::Settings) -> anyhow::Result<settings::Settings> {
+        self.validate().map_err(anyhow::Error::msg)?;
+        if let Some(mode) = self.prompt_mode().map_err(anyhow::Error::msg)? {
+            settings.prompt_mode = mode;
+            settings.prompt_mode_chain.clear();
+        }
+        if let Some(language) = &self.language {
+            settings.language = language.clone();
+        }
+        Ok(settings)
+    }
+
+    fn prompt_mode(&self) -> Result<Option<settings::PromptMode>, String> {
+        self.mode
+            .as_ref()
+            .map(|mode| {
+                serde_json::from_value(serde_json::Value::String(mode.clone()))
+                    .map_err(|_| format!("Unknown prompt mode {:?}", mode))
+            })
+            .transpose()
+    }
+}
+
+/// What stop-and-process returns on success
+#[derive(serde::Serialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct ProcessResult {
+    pub raw_transcript: String,
+    pub optimized_prompt: String,
+    pub mode: String,
+    pub duration_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub elapsed_ms: u64,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm_error: Option<String>,
+    /// Id of the history entry, unless history was skipped. With
+    /// `history_dedupe`, a result duplicating a recent entry is merged into
+    /// that one and this is its id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_id: Option<String>,
+    /// The entry is still being written; failures are recorded as events
+    pub history_pending: bool,
+    /// Stage timings, with the `include_metrics` option
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<PipelineMetrics>,
+}
+
+/// How long each stage of a pipeline run took. Stages the run didn't reach
+/// are 0.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct PipelineMetrics {
+    /// Length of the recording
+    pub recording_secs: f64,
+    pub resample_ms: u64,
+    /// Trimming silence and checking for speech
+    pub vad_ms: u64,
+    /// Whisper, not counting model loading
+    pub transcription_ms: u64,
+    /// Loading the whisper and LLM models
+    pub model_load_ms: u64,
+    /// LLM calls, not counting model loading
+    pub optimization_ms: u64,
+    /// Saving the recording and queueing the history entry
+    pub history_ms: u64,
+    /// The whole run
+    pub total_ms: u64,
+}
+
+/// Timings of the last stop-and-process or phemy_process_samples() run as
+/// JSON (see PipelineMetrics), or JSON null before the first run and while
+/// one is starting. Also recorded for failed runs, up to where they stopped.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_last_pipeline_metrics() -> *mut c_char {
+    optional_json_c_char(LAST_METRICS.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+/// Run the pipeline, recording its metrics in LAST_METRICS and, with
+/// `include_metrics`, in the result. `source` is the file the samples were
+/// read from, if they weren't recorded.
+fn process_pipeline(
+    samples: &[f32],
+    sample_rate: u32,
+    opts: &PipelineOptions,
+    job: &jobs::Job,
+    source: Option<&SourceFile>,
+) -> anyhow::Result<ProcessResult> {
+    with_metrics(opts, |metrics| run_pipeline(samples, sample_rate, opts, job, source, metrics))
+}
+
+/// Optimize and save `text` as if it had been transcribed, with the
+/// metrics of `process_pipeline`. Runs with a `duration_secs` of 0 and no
+/// recording.
+fn process_text_pipeline(text: &str, opts: &PipelineOptions, job: &jobs::Job) -> anyhow::Result<ProcessResult> {
+    with_metrics(opts, |metrics| {
+        if text.trim().is_empty() {
+            anyhow::bail!("No text provided");
+        }
+        let settings = opts.apply(settings::Settings::load())?;
+        finish_pipeline(text.to_string(), 0.0, &settings, opts, job, PipelineAudio::None, metrics)
+    })
+}
+
+/// Time `run`, keeping its metrics in LAST_METRICS and, with
+/// `include_metrics`, in the result
+fn with_metrics(
+    opts: &PipelineOptions,
+    run: impl FnOnce(&mut PipelineMetrics) -> anyhow::Result<ProcessResult>,
+) -> anyhow::Result<ProcessResult> {
+    LAST_METRICS.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let started = Instant::now();
+    let mut metrics = PipelineMetrics::default();
+    let result = run(&mut metrics);
+    metrics.total_ms = started.elapsed().as_millis() as u64;
+    *LAST_METRICS.lock().unwrap_or_else(|e| e.into_inner()) = Some(metrics.clone());
+    result.map(|result| ProcessResult {
+        metrics: opts.include_metrics.then_some(metrics),
+        ..result
+    })
+}
+
+/// Transcribe, optimize and save a recording, reporting each stage on `job`
+/// and stopping between stages if it is cancelled.
+fn run_pipeline(
+    samples: &[f32],
+    sample_rate: u32,
+    opts: &PipelineOptions,
+    job: &jobs::Job,
+    source: Option<&SourceFile>,
+    metrics: &mut PipelineMetrics,
+) -> anyhow::Result<ProcessResult> {
+    if samples.is_empty() {
+        record_event(db::EventKind::NoSpeech, "No audio samples captured", None);
+        anyhow::bail!("No audio samples captured");
+    }
+
+    let duration_secs = samples.len() as f64 / sample_rate as f64;
+    metrics.recording_secs = duration_secs;
+    let settings = opts.apply(settings::Settings::load())?;
+
+    let transcript = transcribe_stage(samples, sample_rate, &settings, job, metrics)?;
+    let audio = match source {
+        Some(source) => PipelineAudio::File(source),
+        None => PipelineAudio::Recording(samples, sample_rate),
+    };
+    finish_pipeline(transcript, duration_secs, &settings, opts, job, audio, metrics)
+}
+
+/// Stage 1 of the pipeline: transcribe the samples, failing if there is no
+/// speech in them
+fn transcribe_stage(
+    samples: &[f32],
+    sample_rate: u32,
+    settings: &settings::Settings,
+    job: &jobs::Job,
+    metrics: &mut PipelineMetrics,
+) -> anyhow::Result<String> {
+    let duration_secs = samples.len() as f64 / sample_rate as f64;
+
+    // 1. Transcribe
+    job.set_state(jobs::JobState::Transcribing, 0.1);
+    let on_step = |step| {
+        use transcription::engine::TranscribeStep;
+        match step {
+            TranscribeStep::Resampling => job.report_phase(jobs::Phase::Resampling, None),
+            TranscribeStep::Vad => job.report_phase(jobs::Phase::Vad, None),
+            TranscribeStep::Transcribing => job.report_phase(jobs::Phase::Transcribing, None),
+            TranscribeStep::Transcribed { segments } => {
+                job.report_phase(jobs::Phase::Transcribing, Some(serde_json::json!({ "segments": segments })))
+            }
+        }
+    };
+    let transcript = match runtime()?.block_on(transcription::engine::transcribe_with_steps(
+        samples,
+        sample_rate,
+        settings,
+        None,
+        &on_step,
+    )) {
+        Ok(result) => {
+            metrics.resample_ms = result.resample_ms;
+            metrics.vad_ms = result.vad_ms;
+            metrics.transcription_ms = result.transcription_ms;
+            metrics.model_load_ms = result.model_load_ms;
+            result.text
+        }
+        Err(e) => {
+            record_event(db::EventKind::TranscriptionError, &e.to_string(), Some(duration_secs));
+            return Err(e);
+        }
+    };
+
+    if transcript.trim().is_empty() {
+        record_event(db::EventKind::NoSpeech, "No speech detected in recording", Some(duration_secs));
+        anyhow::bail!("No speech detected in recording");
+    }
+    Ok(transcript)
+}
+
+/// Where the text a pipeline run optimizes came from
+enum PipelineAudio<'a> {
+    /// Recorded samples, saved with the entry if `save_recordings` is on
+    Recording(&'a [f32], u32),
+    /// An audio file, which the entry points to and is dated by
+    File(&'a SourceFile),
+    /// No audio: the text was given directly
+    None,
+}
+
+/// Optimize, redact and save a transcript, the stages of `run_pipeline`
+/// after transcription
+fn finish_pipeline(
+    transcript: String,
+    duration_secs: f64,
+    settings: &settings::Settings,
+    opts: &PipelineOptions,
+    job: &jobs::Job,
+    audio: PipelineAudio,
+    metrics: &mut PipelineMetrics,
+) -> anyhow::Result<ProcessResult> {
+    let opt_result = optimize_stage(&transcript, settings, opts, job, metrics)?;
+    commit_stage(opt_result, duration_secs, settings, opts, job, audio, metrics)
+}
+
+/// Stages 2 and 3: optimize the transcript (falling back to it unchanged if
+/// the LLM fails) and redact the result
+fn optimize_stage(
+    transcript: &str,
+    settings: &settings::Settings,
+    opts: &PipelineOptions,
+    job: &jobs::Job,
+    metrics: &mut PipelineMetrics,
+) -> anyhow::Result<llm::prompt_optimizer::OptimizationResult> {
+    // 2. Optimize (unless raw mode)
+    job.check_cancelled()?;
+    job.set_state(jobs::JobState::Optimizing, 0.5);
+    let unoptimized = |mode: String| llm::prompt_optimizer::OptimizationResult {
+        raw_transcript: transcript.to_string(),
+        optimized_prompt: transcript.to_string(),
+        mode,
+        provider: None,
+        length: settings.optimization_length.clone(),
+        model: None,
+        elapsed_ms: 0,
+        model_load_ms: 0,
+        attempts: 0,
+        usage: Default::default(),
+        stages: Vec::new(),
+        error: None,
+    };
+    let opt_result = if opts.skip_optimization {
+        unoptimized("raw".to_string())
+    } else {
+        job.report_phase(jobs::Phase::Optimizing, None);
+        let on_token = |mode: &str, tokens: usize| {
+            job.report_phase(jobs::Phase::Optimizing, Some(serde_json::json!({ "mode": mode, "tokens": tokens })))
+        };
+        match runtime()?.block_on(llm::prompt_optimizer::optimize_with_progress(
+            transcript,
+            settings,
+            opts.target_app.as_deref(),
+            job.cancel_flag(),
+            &on_token,
+        )) {
+            Ok(result) => result,
+            Err(e) => {
+                job.check_cancelled()?;
+                log::warn!("Optimization failed, using raw transcript: {}", e);
+                let mode = format!("{:?}", settings.prompt_mode).to_lowercase();
+                llm::prompt_optimizer::OptimizationResult {
+                    error: Some(llm::prompt_optimizer::StageError {
+                        mode: mode.clone(),
+                        message: e.to_string(),
+                    }),
+                    ..unoptimized(mode)
+                }
+            }
+        }
+    };
+    metrics.model_load_ms += opt_result.model_load_ms;
+    metrics.optimization_ms = opt_result.elapsed_ms.saturating_sub(opt_result.model_load_ms);
+    job.check_cancelled()?;
+    job.set_state(jobs::JobState::Optimizing, 0.9);
+
+    // 3. Redact sensitive data before it is stored or returned
+    let mut opt_result = opt_result;
+    if settings.redaction.any_enabled() {
+        opt_result.raw_transcript = postprocess::redact(&opt_result.raw_transcript, &settings.redaction);
+        opt_result.optimized_prompt =
+            postprocess::redact(&opt_result.optimized_prompt, &settings.redaction);
+    }
+
+    Ok(opt_result)
+}
+
+/// Stages 4 and 5: save the optimized transcript to history (with its audio,
+/// as `audio` allows) and build the result
+fn commit_stage(
+    opt_result: llm::prompt_optimizer::OptimizationResult,
+    duration_secs: f64,
+    settings: &settings::Settings,
+    opts: &PipelineOptions,
+    job: &jobs::Job,
+    audio: PipelineAudio,
+    metrics: &mut PipelineMetrics,
+) -> anyhow::Result<ProcessResult> {
+    job.report_phase(jobs::Phase::Saving, None);
+    // 4. Save to history (with the recording, if enabled)
+    let saving = Instant::now();
+    let history_id = if opts.skip_history {
+        None
+    } else {
+        let mut entry = db::new_history_entry(
+            opt_result.raw_transcript.clone(),
+            Some(opt_result.optimized_prompt.clone()),
+            opt_result.mode.clone(),
+            opt_result.provider.clone(),
+            duration_secs,
+            Some(opt_result.elapsed_ms),
+        );
+        match audio {
+            PipelineAudio::File(source) => {
+                // Dated when it was recorded; the file itself is the recording
+                entry.created_at = source.modified.to_rfc3339();
+                entry.created_at_unix = source.modified.timestamp();
+                entry.source_path = Some(source.path.to_string_lossy().to_string());
+            }
+            PipelineAudio::Recording(samples, sample_rate) if settings.save_recordings => {
+                match save_recording(&entry.id, samples, sample_rate) {
+                    Ok(path) => entry.audio_path = Some(path.to_string_lossy().to_string()),
+                    Err(e) => log::error!("Failed to save recording: {}", e),
+                }
+            }
+            _ => {}
+        }
+        // A merged result is reported under the existing entry's id, so find
+        // the duplicate before answering. Inserts still in flight may hold it.
+        let duplicate = if settings.history_dedupe {
+            flush_history_inserts();
+            db::find_history_duplicate(&entry, settings.history_dedupe_window_secs).unwrap_or_else(|e| {
+                log::warn!("Failed to look for a duplicate history entry: {}", e);
+                None
+            })
+        } else {
+            None
+        };
+        match duplicate {
+            Some(id) => {
+                let target = id.clone();
+                spawn_history_write(duration_secs, move || db::merge_history(&target, &entry));
+                Some(id)
+            }
+            None => {
+                let id = entry.id.clone();
+                spawn_history_write(duration_secs, move || db::insert_history(&entry, None).map(|_| ()));
+                Some(id)
+            }
+        }
+    };
+    metrics.history_ms = saving.elapsed().as_millis() as u64;
+
+    // 5. Build the result
+    // A failed stage falls back to the output before it; report why
+    let llm_error = opt_result.error.as_ref().map(|e| e.to_string());
+    if let Some(error) = &llm_error {
+        record_event(db::EventKind::LlmFallback, error, Some(duration_secs));
+    }
+
+    Ok(ProcessResult {
+        raw_transcript: opt_result.raw_transcript,
+        optimized_prompt: opt_result.optimized_prompt,
+        mode: opt_result.mode,
+        duration_secs,
+        model: opt_result.model,
+        elapsed_ms: opt_result.elapsed_ms,
+        attempts: opt_result.attempts,
+        llm_error,
+        history_pending: history_id.is_some(),
+        history_id,
+        metrics: None,
+    })
+}
+
+/// Run a history write on the runtime so the caller doesn't wait for it.
+fn spawn_history_write(duration_secs: f64, write: impl FnOnce() -> anyhow::Result<()> + Send + 'static) {
+    let runtime = match runtime() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("Failed to save history: {}", e);
+            return;
+        }
+    };
+    let handle = runtime.spawn_blocking(move || {
+        if let Err(e) = write() {
+            log::error!("Failed to save history: {}", e);
+            record_event(db::EventKind::HistoryInsertFailed, &e.to_string(), Some(duration_secs));
+        }
+    });
+
+    let mut pending = PENDING_HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    pending.retain(|handle| !handle.is_finished());
+    pending.push(handle);
+}
+
+/// Wait for all spawned history inserts to finish.
+fn flush_history_inserts() {
+    let handles = std::mem::take(&mut *PENDING_HISTORY.lock().unwrap_or_else(|e| e.into_inner()));
+    if handles.is_empty() {
+        return;
+    }
+    // Only fails after shutdown, which already waited for them
+    let Ok(runtime) = runtime() else {
+        return;
+    };
+    runtime.block_on(async {
+        for handle in handles {
+            if let Err(e) = handle.await {
+                log::error!("History insert task failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Log a pipeline event; failures to record it are only logged.
+fn record_event(kind: db::EventKind, message: &str, duration_secs: Option<f64>) {
+    if let Err(e) = db::log_event(kind, message, duration_secs) {
+        log::warn!("Failed to record {:?} event: {}", kind, e);
+    }
+}
+
+/// Write a recording as `<id>.wav` in the recordings directory.
+fn save_recording(id: &str, samples: &[f32], sample_rate: u32) -> anyhow::Result<PathBuf> {
+    let path = utils::recordings_dir()?.join(format!("{}.wav", id));
+    std::fs::write(&path, utils::samples_to_wav(samples, sample_rate)?)?;
+    Ok(path)
+}
+
+/// Check if currently recording.
+#[no_mangle]
+pub extern "C" fn phemy_get_recording_state() -> bool {
+    api::Handle::unchecked().is_recording()
+}
+
+/// Check if the active recording hit its maximum duration or silence
+/// auto-stop (see the `audio` settings). Hosts should poll this while
+/// recording and call phemy_stop_and_process() when it turns true.
+#[no_mangle]
+pub extern "C" fn phemy_recording_should_stop() -> bool {
+    api::Handle::unchecked().recording_should_stop()
+}
+
+// ============================================================
+// Transcription
+// ============================================================
+
+/// Transcribe audio samples. Returns JSON result.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_transcribe(
+    samples: *const f32,
+    len: usize,
+    rate: u32,
+) -> *mut c_char {
+    if samples.is_null() || len == 0 {
+        return legacy_error_c_char(None, ErrorCode::InvalidArgument, "No audio samples provided");
+    }
+
+    let samples = unsafe { std::slice::from_raw_parts(samples, len) };
+    match api::Handle::unchecked().transcribe(samples, rate) {
+        Ok(result) => to_json_c_char(&result),
+        Err(e) => {
+            log::error!("Transcription failed: {}", e);
+            legacy_error_c_char(None, ErrorCode::Transcription, &e.to_string())
+        }
+    }
+}
+
+/// List available whisper models as JSON array.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_list_whisper_models() -> *mut c_char {
+    match api::Handle::unchecked().whisper_models() {
+        Ok(models) => to_json_c_char(&models),
+        Err(e) => {
+            log::error!("Failed to list whisper models: {}", e);
+            legacy_error_c_char(Some("[]"), ErrorCode::Io, &e.to_string())
+        }
+    }
+}
+
+/// Download a whisper model by name. Blocking.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_download_whisper_model(name: *const c_char) -> bool {
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("name is required"),
+    };
+
+    match api::Handle::unchecked().download_whisper_model(name, |_, _, _| {}) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Download, "Failed to download model", &e),
+    }
+}
+
+/// Download a whisper model by name in the background, reporting to
+/// `progress_cb` and, once it ends, `done_cb` (see DownloadDoneCallback).
+/// Returns false, without calling either, if the name is null or not a
+/// known model or the download thread can't be started.
+#[no_mangle]
+pub extern "C" fn phemy_download_whisper_model_cb(
+    name: *const c_char,
+    progress_cb: DownloadProgressCallback,
+    done_cb: DownloadDoneCallback,
+) -> bool {
+    phemy_download_whisper_model_async(name, progress_cb, done_cb) != 0
+}
+
+/// Like phemy_download_whisper_model_cb(), returning the download's
+/// operation id (see phemy_cancel()), or 0 where that returns false.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_download_whisper_model_async(
+    name: *const c_char,
+    progress_cb: DownloadProgressCallback,
+    done_cb: DownloadDoneCallback,
+) -> u64 {
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(s) if transcription::model_manager::is_known_model(s) => s.to_string(),
+        _ => return 0,
+    };
+
+    spawn_download(
+        ops::start(ops::OpKind::Download, Some(&name)),
+        move |op, mut progress| async move {
+            transcription::model_manager::download_model_as(&name, op, |p| {
+                progress.send(p.downloaded_bytes, p.total_bytes, p.progress)
+            })
+            .await
+        },
+        progress_cb,
+        done_cb,
+    )
+}
+
+/// Download a whisper model by name on the async runtime, reporting the
+/// outcome to `done_cb` with `user_data` (see CompletionCallback); on
+/// success `result_json` is { "model": "<name>" }. Returns the download's
+/// operation id (see phemy_cancel()), or 0 if the name is null or not a
+/// known model; `done_cb` is still called once then. Progress is available
+/// from phemy_get_download_progress().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_download_whisper_model_with_user_data(
+    name: *const c_char,
+    done_cb: CompletionCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    let done = Completion::new(done_cb, user_data);
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(s) if transcription::model_manager::is_known_model(s) => s.to_string(),
+        name => {
+            done.fail(ErrorCode::InvalidArgument, format!("Unknown whisper model {:?}", name.unwrap_or_default()));
+            return 0;
+        }
+    };
+
+    let op = ops::start(ops::OpKind::Download, Some(&name));
+    let id = op.id();
+    let download = async move {
+        transcription::model_manager::download_model_as(&name, op, |_| {}).await?;
+        Ok(serde_json::json!({ "model": name }))
+    };
+    spawn_with_completion(id, ErrorCode::Download, download, done)
+}
+
+/// Get download progress as JSON, or JSON null if not downloading.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_download_progress() -> *mut c_char {
+    optional_json_c_char(transcription::model_manager::get_download_progress())
+}
+
+// ============================================================
+// LLM
+// ============================================================
+
+/// Optimize a transcript into a polished prompt. Returns JSON.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_optimize_prompt(transcript: *const c_char) -> *mut c_char {
+    let transcript = match unsafe { c_str_to_str(transcript) } {
+        Some(s) => s,
+        None => return legacy_error_c_char(None, ErrorCode::InvalidArgument, "transcript is required"),
+    };
+
+    match api::Handle::unchecked().optimize(transcript, None, None) {
+        Ok(result) => to_json_c_char(&result),
+        Err(e) => {
+            log::error!("Optimization failed: {}", e);
+            legacy_error_c_char(None, ffi::error_code(&e, ErrorCode::Llm), &e.to_string())
+        }
+    }
+}
+
+/// Optimize a transcript with a per-call length override. Returns JSON.
+/// `length` is "concise", "balanced" or "detailed", or null to use the saved setting.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_optimize_prompt_ex(
+    transcript: *const c_char,
+    length: *const c_char,
+) -> *mut c_char {
+    phemy_optimize_prompt_for_app(transcript, length, std::ptr::null())
+}
+
+/// Like phemy_optimize_prompt_ex(), for a prompt that will be pasted into
+/// `target_app` (see phemy_paste_text_ex()), which custom prompts can use
+/// as `{{app}}`. Null leaves `{{app}}` empty.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_optimize_prompt_for_app(
+    transcript: *const c_char,
+    length: *const c_char,
+    target_app: *const c_char,
+) -> *mut c_char {
+    let transcript = match unsafe { c_str_to_str(transcript) } {
+        Some(s) => s,
+        None => return legacy_error_c_char(None, ErrorCode::InvalidArgument, "transcript is required"),
+    };
+
+    let length = match unsafe { c_str_to_str(length) } {
+        Some(length) => match serde_json::from_value(serde_json::Value::String(length.to_string())) {
+            Ok(l) => Some(l),
+            Err(e) => {
+                log::error!("Invalid optimization length '{}': {}", length, e);
+                return legacy_error_c_char(
+                    None,
+                    ErrorCode::InvalidArgument,
+                    &format!("Invalid optimization length '{}': {}", length, e),
+                );
+            }
+        },
+        None => None,
+    };
+
+    let target_app = unsafe { c_str_to_str(target_app) };
+    match api::Handle::unchecked().optimize(transcript, length, target_app) {
+        Ok(result) => to_json_c_char(&result),
+        Err(e) => {
+            log::error!("Optimization failed: {}", e);
+            legacy_error_c_char(None, ffi::error_code(&e, ErrorCode::Llm), &e.to_string())
+        }
+    }
+}
+
+/// List prompt modes and the template variables usable in custom prompts as JSON.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_list_prompt_modes() -> *mut c_char {
+    to_json_c_char(&llm::prompt_templates::mode_catalog())
+}
+
+/// List available local LLM models as JSON array.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_list_llm_models() -> *mut c_char {
+    match api::Handle::unchecked().llm_models() {
+        Ok(models) => to_json_c_char(&models),
+        Err(e) => {
+            log::error!("Failed to list LLM models: {}", e);
+            legacy_error_c_char(Some("[]"), ErrorCode::Io, &e.to_string())
+        }
+    }
+}
+
+/// Download a local LLM model by name. Blocking.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_download_llm_model(name: *const c_char) -> bool {
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("name is required"),
+    };
+
+    match api::Handle::unchecked().download_llm_model(name, |_, _, _| {}) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Download, "Failed to download LLM model", &e),
+    }
+}
+
+/// Download a local LLM model by name in the background; see
+/// phemy_download_whisper_model_cb().
+#[no_mangle]
+pub extern "C" fn phemy_download_llm_model_cb(
+    name: *const c_char,
+    progress_cb: DownloadProgressCallback,
+    done_cb: DownloadDoneCallback,
+) -> bool {
+    phemy_download_llm_model_async(name, progress_cb, done_cb) != 0
+}
+
+/// Download a local LLM model by name in the background; see
+/// phemy_download_whisper_model_async().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_download_llm_model_async(
+    name: *const c_char,
+    progress_cb: DownloadProgressCallback,
+    done_cb: DownloadDoneCallback,
+) -> u64 {
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(s) if llm::llm_model_manager::is_known_model(s) => s.to_string(),
+        _ => return 0,
+    };
+
+    spawn_download(
+        ops::start(ops::OpKind::Download, Some(&name)),
+        move |op, mut progress| async move {
+            llm::llm_model_manager::download_model_as(&name, op, |p| {
+                progress.send(p.downloaded_bytes, p.total_bytes, p.progress)
+            })
+            .await
+        },
+        progress_cb,
+        done_cb,
+    )
+}
+
+/// Download a local LLM model by name on the async runtime; see
+/// phemy_download_whisper_model_with_user_data(). Progress is available
+/// from phemy_get_llm_download_progress().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_download_llm_model_with_user_data(
+    name: *const c_char,
+    done_cb: CompletionCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    let done = Completion::new(done_cb, user_data);
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(s) if llm::llm_model_manager::is_known_model(s) => s.to_string(),
+        name => {
+            done.fail(ErrorCode::InvalidArgument, format!("Unknown LLM model {:?}", name.unwrap_or_default()));
+            return 0;
+        }
+    };
+
+    let op = ops::start(ops::OpKind::Download, Some(&name));
+    let id = op.id();
+    let download = async move {
+        llm::llm_model_manager::download_model_as(&name, op, |_| {}).await?;
+        Ok(serde_json::json!({ "model": name }))
+    };
+    spawn_with_completion(id, ErrorCode::Download, download, done)
+}
+
+/// Get LLM model download progress as JSON, or JSON null if not downloading.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_llm_download_progress() -> *mut c_char {
+    optional_json_c_char(llm::llm_model_manager::get_download_progress())
+}
+
+/// Stop any whisper or LLM model download before its next chunk. The
+/// download call then fails (blocking ones return false; `_cb` ones report
+/// the "cancelled" code) and the partial file is removed. To stop just one
+/// download, pass its id to phemy_cancel().
+#[no_mangle]
+pub extern "C" fn phemy_cancel_downloads() {
+    api::Handle::unchecked().cancel_downloads();
+}
+
+/// Stop any download of one kind of model, 0 for whisper or 1 for LLM,
+/// before its next chunk as phemy_cancel_downloads() does: the download
+/// fails with the "cancelled" code, its partial file is removed and its
+/// progress goes back to null. Returns false if none was running or the
+/// kind is unknown.
+#[no_mangle]
+pub extern "C" fn phemy_cancel_download(kind: i32) -> bool {
+    let found = match kind {
+        0 => transcription::model_manager::cancel_download(),
+        1 => llm::llm_model_manager::cancel_download(),
+        _ => return ffi::invalid_argument(&format!("Unknown download kind {}", kind)),
+    };
+    if !found {
+        ffi::set_last_error(ErrorCode::NotFound, "No download of that kind is running");
+    }
+    found
+}
+
+/// Called with (downloaded bytes, total bytes, fraction done) while a `_cb`
+/// download runs, at most every DOWNLOAD_CALLBACK_INTERVAL, on the callback
+/// thread. The total is 0 when the server doesn't send a length. Null means
+/// none.
+pub type DownloadProgressCallback =
+    Option<extern "C" fn(downloaded_bytes: u64, total_bytes: u64, progress: f64)>;
+
+/// Called exactly once when a `_cb` download ends, including on error or
+/// cancellation. `error_json` is null on success, otherwise
+/// { "error": { "code", "message" } } (code "cancelled" after
+/// phemy_cancel_downloads()); it is only valid during the call. It runs on
+/// the callback thread after the last progress call. Null means none.
+pub type DownloadDoneCallback = Option<extern "C" fn(success: bool, error_json: *const c_char)>;
+
+/// Most often a `_cb` download reports progress
+const DOWNLOAD_CALLBACK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Passes download progress on to a host callback (C or UniFFI), dropping
+/// updates that come sooner than DOWNLOAD_CALLBACK_INTERVAL after the last
+/// one sent
+pub(crate) struct ProgressForwarder {
+    callback: Box<dyn FnMut(u64, u64, f64) + Send>,
+    last_sent: Option<Instant>,
+}
+
+impl ProgressForwarder {
+    pub(crate) fn new(callback: impl FnMut(u64, u64, f64) + Send + 'static) -> Self {
+        Self {
+            callback: Box::new(callback),
+            last_sent: None,
+        }
+    }
+
+    pub(crate) fn send(&mut self, downloaded_bytes: u64, total_bytes: u64, progress: f64) {
+        if self
+            .last_sent
+            .is_some_and(|at| at.elapsed() < DOWNLOAD_CALLBACK_INTERVAL)
+        {
+            return;
+        }
+        self.last_sent = Some(Instant::now());
+        (self.callback)(downloaded_bytes, total_bytes, progress);
+    }
+}
+
+/// Run `download` as `op` on a new thread, passing its progress and outcome
+/// to the callback thread. Returns the op's id, or 0 if the thread couldn't
+/// be started.
+fn spawn_download<F, Fut>(
+    op: ops::Op,
+    download: F,
+    progress_cb: DownloadProgressCallback,
+    done_cb: DownloadDoneCallback,
+) -> u64
+where
+    F: FnOnce(ops::Op, ProgressForwarder) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = anyhow::Result<()>>,
+{
+    let progress = ProgressForwarder::new(move |downloaded, total, fraction| {
+        if let Some(callback) = progress_cb {
+            ffi::dispatch_lossy("download_progress", move || callback(downloaded, total, fraction));
+        }
+    });
+    let id = op.id();
+    let spawned = std::thread::Builder::new()
+        .name("phemy-download".into())
+        .spawn(move || {
+            // block_on polls the download on this thread, so the progress
+            // hook runs here too
+            let error = match runtime().and_then(|runtime| runtime.block_on(download(op, progress))) {
+                Ok(()) => None,
+                Err(e) => {
+                    log::error!("Failed to download model: {}", e);
+                    let code = ffi::error_code(&e, ErrorCode::Download);
+                    let json = serde_json::json!({ "error": error_value(code, &e.to_string()) });
+                    Some(CString::new(json.to_string()).unwrap_or_default())
+                }
+            };
+            if let Some(done) = done_cb {
+                ffi::dispatch(move || done(error.is_none(), error.as_ref().map_or(std::ptr::null(), |e| e.as_ptr())));
+            }
+        });
+    match spawned {
+        Ok(_) => id,
+        Err(e) => {
+            log::error!("Failed to start download thread: {}", e);
+            0
+        }
+    }
+}
+
+/// `value` as JSON, with None as JSON null (a null pointer with the
+/// `legacy-errors` feature).
+#[cfg_attr(feature = "track-strings", track_caller)]
+fn optional_json_c_char<T: serde::Serialize>(value: Option<T>) -> *mut c_char {
+    match value {
+        None if cfg!(feature = "legacy-errors") => std::ptr::null_mut(),
+        value => to_json_c_char(&value),
+    }
+}
+
+/// Delete a downloaded whisper model by name. Returns true on success.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_delete_whisper_model(name: *const c_char) -> bool {
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("name is required"),
+    };
+
+    match api::Handle::unchecked().delete_whisper_model(name) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Io, "Failed to delete whisper model", &e),
+    }
+}
+
+/// Delete a downloaded LLM model by name. Returns true on success.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_delete_llm_model(name: *const c_char) -> bool {
+    let name = match unsafe { c_str_to_str(name) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("name is required"),
+    };
+
+    match api::Handle::unchecked().delete_llm_model(name) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Llm, "Failed to delete LLM model", &e),
+    }
+}
+
+// ============================================================
+// History
+// ============================================================
+
+/// Block until history entries from earlier phemy_stop_and_process() calls
+/// have been written. Call before shutting down.
+#[no_mangle]
+pub extern "C" fn phemy_flush_history() {
+    api::Handle::unchecked().flush_history();
+}
+
+/// Get history entries as JSON array.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_history(limit: i32, offset: i32) -> *mut c_char {
+    match api::Handle::unchecked().history(limit as usize, offset as usize) {
+        Ok(entries) => to_json_c_char(&entries),
+        Err(e) => {
+            log::error!("Failed to get history: {}", e);
+            legacy_error_c_char(Some("[]"), ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Query history entries matching a JSON filter, returned as a JSON array.
+/// The filter may contain `from`/`to` (RFC 3339), `mode`, `provider`,
+/// `min_duration`, `favorites_only` and `favorites_first`; null or "{}"
+/// matches everything.
+/// On an invalid filter returns an error with code "invalid_argument".
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_query_history(filter_json: *const c_char, limit: i32, offset: i32) -> *mut c_char {
+    let filter = match unsafe { c_str_to_str(filter_json) } {
+        Some(json) => match serde_json::from_str::<db::HistoryFilter>(json) {
+            Ok(f) => f,
+            Err(e) => {
+                log::error!("Invalid history filter: {}", e);
+                return error_json_c_char(ErrorCode::InvalidArgument, &format!("Invalid history filter: {}", e));
+            }
+        },
+        None => db::HistoryFilter::default(),
+    };
+
+    match api::Handle::unchecked().query_history(&filter, limit as usize, offset as usize) {
+        Ok(entries) => to_json_c_char(&entries),
+        Err(e) => {
+            log::error!("Failed to query history: {}", e);
+            error_json_c_char(ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Full-text search history. Returns a JSON array of
+/// { "entry": {...}, "snippet": { "text", "highlights": [{ "start", "end" }] }, "score" },
+/// best match first. Highlight offsets count characters, not bytes.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_search_history(query: *const c_char, limit: i32, offset: i32) -> *mut c_char {
+    let query = match unsafe { c_str_to_str(query) } {
+        Some(q) => q,
+        None => return legacy_error_c_char(Some("[]"), ErrorCode::InvalidArgument, "query is required"),
+    };
+
+    match api::Handle::unchecked().search_history(query, limit as usize, offset as usize) {
+        Ok(results) => to_json_c_char(&results),
+        Err(e) => {
+            log::error!("Failed to search history: {}", e);
+            legacy_error_c_char(Some("[]"), ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Get the saved recording path for a history entry as `{ "path": "..." }`
+/// (a bare string with the `legacy-errors` feature). Returns an error object
+/// with code "not_found" if the entry doesn't exist or has no recording.
+/// The path is returned even if the file has since been removed; check the
+/// entry's `audio_missing` flag.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_history_audio_path(id: *const c_char) -> *mut c_char {
+    let id = match unsafe { c_str_to_str(id) } {
+        Some(s) => s,
+        None => return legacy_error_c_char(None, ErrorCode::InvalidArgument, "id is required"),
+    };
+
+    match db::get_history_entry(id) {
+        Ok(Some(entry)) => match entry.audio_path {
+            Some(path) if cfg!(feature = "legacy-errors") => str_to_c_char(&path),
+            Some(path) => to_json_c_char(&serde_json::json!({ "path": path })),
+            None => legacy_error_c_char(None, ErrorCode::NotFound, "History entry has no recording"),
+        },
+        Ok(None) => legacy_error_c_char(None, ErrorCode::NotFound, "History entry not found"),
+        Err(e) => {
+            log::error!("Failed to get history entry: {}", e);
+            legacy_error_c_char(None, ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Update fields of a history entry from a JSON patch, e.g.
+/// { "optimized_prompt": "...", "prompt_mode": "code" }.
+/// Returns false if the patch contains unknown fields or the entry doesn't exist.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_update_history_entry(id: *const c_char, json_patch: *const c_char) -> bool {
+    let (id, json_patch) = match unsafe { (c_str_to_str(id), c_str_to_str(json_patch)) } {
+        (Some(id), Some(patch)) => (id, patch),
+        _ => return ffi::invalid_argument("id and json_patch are required"),
+    };
+
+    let update = match db::HistoryUpdate::from_json(json_patch) {
+        Ok(u) => u,
+        Err(e) => {
+            log::error!("Invalid history update: {}", e);
+            return ffi::invalid_argument(&format!("Invalid history update: {}", e));
+        }
+    };
+
+    match db::update_history_entry(id, &update) {
+        Ok(true) => ffi::succeeded(),
+        Ok(false) => {
+            log::warn!("History entry '{}' not found", id);
+            history_entry_not_found(id)
+        }
+        Err(e) => ffi::failed(ErrorCode::Database, "Failed to update history entry", &e),
+    }
+}
+
+/// Delete a history entry by ID. Returns true on success.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_delete_history_entry(id: *const c_char) -> bool {
+    let id = match unsafe { c_str_to_str(id) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("id is required"),
+    };
+
+    match api::Handle::unchecked().delete_history_entry(id) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Database, "Failed to delete history entry", &e),
+    }
+}
+
+/// Pin or unpin a history entry. Returns false if the entry doesn't exist.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_set_history_favorite(id: *const c_char, favorite: bool) -> bool {
+    let id = match unsafe { c_str_to_str(id) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("id is required"),
+    };
+
+    match db::set_favorite(id, favorite) {
+        Ok(true) => ffi::succeeded(),
+        Ok(false) => history_entry_not_found(id),
+        Err(e) => ffi::failed(ErrorCode::Database, "Failed to set history favorite", &e),
+    }
+}
+
+/// Clear all history. Returns true on success.
+#[no_mangle]
+pub extern "C" fn phemy_clear_history() -> bool {
+    match api::Handle::unchecked().clear_history(false) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Database, "Failed to clear history", &e),
+    }
+}
+
+/// Clear all history except favorites. Returns true on success.
+#[no_mangle]
+pub extern "C" fn phemy_clear_history_keep_favorites() -> bool {
+    match api::Handle::unchecked().clear_history(true) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Database, "Failed to clear history", &e),
+    }
+}
+
+/// Clear history (all of it, or everything but favorites with `keep_favorites`)
+/// and the entries' recordings. Returns { "deleted": n, "files_removed": m }
+/// or { "error": {...} }.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_clear_history_ex(keep_favorites: bool) -> *mut c_char {
+    match api::Handle::unchecked().clear_history(keep_favorites) {
+        Ok(report) => to_json_c_char(&report),
+        Err(e) => {
+            log::error!("Failed to clear history: {}", e);
+            error_json_c_char(ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Get usage statistics as JSON, with per-day counts for the last `days` days.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_stats(days: i32) -> *mut c_char {
+    match db::get_stats(days.max(0) as usize) {
+        Ok(stats) => to_json_c_char(&stats),
+        Err(e) => {
+            log::error!("Failed to get stats: {}", e);
+            error_json_c_char(ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Merge duplicate history entries (same transcript within the configured
+/// dedupe window). Returns a report as JSON, or { "error": {...} }.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_dedupe_history() -> *mut c_char {
+    let settings = settings::Settings::load();
+    match db::dedupe_history(settings.history_dedupe_window_secs) {
+        Ok(report) => to_json_c_char(&report),
+        Err(e) => {
+            log::error!("Failed to dedupe history: {}", e);
+            error_json_c_char(ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Run database maintenance (integrity check, VACUUM, optimize) and return
+/// the report as JSON, or { "error": {...} } if the database is busy.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_db_maintenance() -> *mut c_char {
+    match db::maintenance() {
+        Ok(report) => to_json_c_char(&report),
+        Err(e) => {
+            log::error!("Database maintenance failed: {}", e);
+            error_json_c_char(ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Back up the database to `path` while it stays in use.
+/// Returns { "success": true, "schema_version", "history_rows", "vocabulary_rows" }
+/// or { "success": false, "error": {...} }.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_backup_database(path: *const c_char) -> *mut c_char {
+    let path = match unsafe { c_str_to_str(path) } {
+        Some(p) => p,
+        None => return backup_error_json(ErrorCode::InvalidArgument, "path is required"),
+    };
+
+    let result = db::backup_to(std::path::Path::new(path));
+    if let Err(e) = &result {
+        log::error!("Database backup failed: {}", e);
+    }
+    backup_result_json(result)
+}
+
+/// Replace the database with a backup made by phemy_backup_database().
+/// The current database is left untouched if the backup is invalid.
+/// Returns the same JSON shape as phemy_backup_database(), with the
+/// restored row counts.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_restore_database(path: *const c_char) -> *mut c_char {
+    let path = match unsafe { c_str_to_str(path) } {
+        Some(p) => p,
+        None => return backup_error_json(ErrorCode::InvalidArgument, "path is required"),
+    };
+
+    let result = db::restore_from(std::path::Path::new(path));
+    if let Err(e) = &result {
+        log::error!("Database restore failed: {}", e);
+    }
+    backup_result_json(result)
+}
+
+fn backup_result_json(result: anyhow::Result<db::BackupReport>) -> *mut c_char {
+    match result {
+        Ok(report) => to_json_c_char(&serde_json::json!({
+            "success": true,
+            "schema_version": report.schema_version,
+            "history_rows": report.history_rows,
+            "vocabulary_rows": report.vocabulary_rows,
+        })),
+        Err(e) => backup_error_json(ErrorCode::Database, &e.to_string()),
+    }
+}
+
+fn backup_error_json(code: ErrorCode, message: &str) -> *mut c_char {
+    let json = to_json_c_char(&serde_json::json!({ "success": false, "error": error_value(code, message) }));
+    ffi::set_last_error(code, message);
+    json
+}
+
+/// Import history from a JSON export file. `strategy` is "merge" (keep
+/// existing entries with the same id) or "replace" (overwrite them).
+/// Returns the import report as JSON, or { "error": {...} }.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_import_history(path: *const c_char, strategy: *const c_char) -> *mut c_char {
+    let (path, strategy) = match unsafe { (c_str_to_str(path), c_str_to_str(strategy)) } {
+        (Some(p), Some(s)) => (p, s),
+        _ => return error_json_c_char(ErrorCode::InvalidArgument, "path and strategy are required"),
+    };
+
+    let strategy: db::ImportStrategy =
+        match serde_json::from_value(serde_json::Value::String(strategy.to_string())) {
+            Ok(s) => s,
+            Err(_) => {
+                return error_json_c_char(
+                    ErrorCode::InvalidArgument,
+                    &format!("Unknown import strategy: {}", strategy),
+                )
+            }
+        };
+
+    let result = std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| db::import_history(&json, strategy));
+
+    match result {
+        Ok(report) => to_json_c_char(&report),
+        Err(e) => {
+            log::error!("Failed to import history: {}", e);
+            error_json_c_char(ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Get recorded pipeline failures (newest first) as a JSON array.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_events(limit: u32, offset: u32) -> *mut c_char {
+    match db::get_events(limit as usize, offset as usize) {
+        Ok(events) => to_json_c_char(&events),
+        Err(e) => {
+            log::error!("Failed to get events: {}", e);
+            legacy_error_c_char(Some("[]"), ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+// ============================================================
+// Vocabulary
+// ============================================================
+
+/// Get vocabulary words as JSON array.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_vocabulary() -> *mut c_char {
+    match db::list_vocabulary() {
+        Ok(words) => to_json_c_char(&words),
+        Err(e) => {
+            log::error!("Failed to get vocabulary: {}", e);
+            legacy_error_c_char(Some("[]"), ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Suggest vocabulary words from history as a JSON array of `{ word, count }`:
+/// uncommon words seen at least `min_occurrences` times that aren't in the
+/// vocabulary yet, most frequent first, at most `limit` entries.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_suggest_vocabulary(min_occurrences: u32, limit: u32) -> *mut c_char {
+    match db::suggest_vocabulary(min_occurrences.max(1) as u64, limit as usize) {
+        Ok(suggestions) => to_json_c_char(&suggestions),
+        Err(e) => {
+            log::error!("Failed to suggest vocabulary: {}", e);
+            legacy_error_c_char(Some("[]"), ErrorCode::Database, &e.to_string())
+        }
+    }
+}
+
+/// Add a vocabulary word. Returns true if it was added, false if it was
+/// already present (case-insensitive) or on error.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_add_vocabulary_word(word: *const c_char) -> bool {
+    let word = match unsafe { c_str_to_str(word) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("word is required"),
+    };
+
+    match db::add_vocabulary_word(word) {
+        Ok(added) => ffi::succeeded() && added,
+        Err(e) => ffi::failed(ErrorCode::Database, "Failed to add vocabulary word", &e),
+    }
+}
+
+/// Remove a vocabulary word. Returns true if it was removed.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_remove_vocabulary_word(word: *const c_char) -> bool {
+    let word = match unsafe { c_str_to_str(word) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("word is required"),
+    };
+
+    match db::remove_vocabulary_word(word) {
+        Ok(removed) => ffi::succeeded() && removed,
+        Err(e) => ffi::failed(ErrorCode::Database, "Failed to remove vocabulary word", &e),
+    }
+}
+
+// ============================================================
+// Clipboard
+// ============================================================
+
+/// Paste text into the focused application, pressing the submit key
+/// afterwards if auto_submit is on. Returns false if the paste failed, was
+/// cut short (see phemy_cancel_paste), or was blocked by macOS secure input
+/// (the text is then left on the clipboard; use phemy_paste_text_ex to tell
+/// these apart).
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_paste_text(text: *const c_char) -> bool {
+    let text = match unsafe { c_str_to_str(text) } {
+        Some(s) => s,
+        None => return ffi::invalid_argument("text is required"),
+    };
+
+    let settings = settings::Settings::load();
+    match clipboard::paste::paste_and_maybe_submit(text, &settings, None, None) {
+        Ok(outcome) => paste_succeeded(&outcome),
+        Err(e) => ffi::failed(ErrorCode::Clipboard, "Failed to paste text", &e),
+    }
+}
+
+/// Paste text on a background thread and return immediately. Pastes run
+/// one at a time in the order they were requested. `done_cb`, if given,
+/// is called on the callback thread with the same success value as
+/// phemy_paste_text() and the phemy_paste_text_ex() JSON, which is only
+/// valid during the call. `progress_cb`, if given, is called there too
+/// after each TypeOut chunk with (characters typed, total characters).
+/// `target_app` is as in phemy_paste_text_ex().
+/// Returns false (without calling `done_cb`) if `text` is null or the
+/// paste couldn't be queued.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_paste_text_async(
+    text: *const c_char,
+    target_app: *const c_char,
+    progress_cb: clipboard::worker::PasteProgressCallback,
+    done_cb: clipboard::worker::PasteDoneCallback,
+) -> bool {
+    let text = match unsafe { c_str_to_str(text) } {
+        Some(s) => s.to_string(),
+        None => return ffi::invalid_argument("text is required"),
+    };
+    let target_app = unsafe { c_str_to_str(target_app) }.map(str::to_string);
+
+    match clipboard::worker::enqueue(text, target_app, progress_cb, done_cb) {
+        Ok(()) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Clipboard, "Failed to queue paste", &e),
+    }
+}
+
+/// Stop a paste that is typing text out (TypeOut method) before its next
+/// chunk. Characters already typed stay; the paste reports
+/// `aborted: "cancelled"` and the submit key is not pressed.
+#[no_mangle]
+pub extern "C" fn phemy_cancel_paste() {
+    clipboard::paste::cancel_paste();
+}
+
+/// Like `phemy_paste_text`, but returns what happened as JSON:
+/// `{attempted, method_used, retried, clipboard_restored, clipboard_only,
+/// clipboard_retries, aborted}`, or `{error}`. `aborted` is null, or
+/// "cancelled" / "focus-changed" if a TypeOut stopped part way through.
+/// `target_app` (nullable) is the frontmost app's name or bundle id, used
+/// to pick a method from `app_paste_overrides`.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_paste_text_ex(
+    text: *const c_char,
+    target_app: *const c_char,
+) -> *mut c_char {
+    let text = match unsafe { c_str_to_str(text) } {
+        Some(s) => s,
+        None => return error_json_c_char(ErrorCode::InvalidArgument, "text is required"),
+    };
+    let target_app = unsafe { c_str_to_str(target_app) };
+
+    let settings = settings::Settings::load();
+    match clipboard::paste::paste_and_maybe_submit(text, &settings, target_app, None) {
+        Ok(outcome) => to_json_c_char(&outcome),
+        Err(e) => {
+            log::error!("Failed to paste text: {}", e);
+            error_json_c_char(ErrorCode::Clipboard, &e.to_string())
+        }
+    }
+}
+
+/// Show what phemy_paste_text_ex() would deliver, without touching the
+/// clipboard or pressing any keys. Returns JSON
+/// `{text, method, auto_submit, submit_key, rules_applied}`, or `{error}`:
+/// the final text after paste post-processing, the paste method after
+/// per-app overrides, whether the submit key would be pressed and which
+/// one, and the names of the `paste_postprocess` rules that changed the
+/// text. `target_app` is as in phemy_paste_text_ex().
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_preview_paste(
+    text: *const c_char,
+    target_app: *const c_char,
+) -> *mut c_char {
+    let text = match unsafe { c_str_to_str(text) } {
+        Some(s) => s,
+        None => return error_json_c_char(ErrorCode::InvalidArgument, "text is required"),
+    };
+    let target_app = unsafe { c_str_to_str(target_app) };
+
+    let settings = settings::Settings::load();
+    to_json_c_char(&clipboard::paste::preview_paste(text, &settings, target_app))
+}
+
+/// Paste a history entry's optimized prompt (or raw transcript with
+/// `use_raw`) into the focused application. Entries without an optimized
+/// prompt fall back to the raw transcript. Returns false if the entry
+/// doesn't exist or has no text, or the paste failed or was blocked as in
+/// phemy_paste_text().
+#[no_mangle]
+pub extern "C" fn phemy_paste_history_entry(id: *const c_char, use_raw: bool) -> bool {
+    let text = match history_entry_text(id, use_raw) {
+        Some(text) => text,
+        None => return false,
+    };
+
+    let settings = settings::Settings::load();
+    match clipboard::paste::paste_and_maybe_submit(&text, &settings, None, None) {
+        Ok(outcome) => paste_succeeded(&outcome),
+        Err(e) => ffi::failed(ErrorCode::Clipboard, "Failed to paste history entry", &e),
+    }
+}
+
+/// Put text on the clipboard and nothing else: no delays, no keystrokes and
+/// no clipboard restore. Returns false for null or empty text.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_copy_text(text: *const c_char) -> bool {
+    let text = match unsafe { c_str_to_str(text) } {
+        Some(s) if !s.is_empty() => s,
+        _ => return ffi::invalid_argument("text is required"),
+    };
+
+    match clipboard::paste::copy_to_clipboard(text) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Clipboard, "Failed to copy text", &e),
+    }
+}
+
+/// Paste, copy or ignore text according to the `post_process_action`
+/// setting. `target_app` is as in phemy_paste_text_ex(). Returns false for
+/// null or empty text or if delivery failed.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_deliver_text(text: *const c_char, target_app: *const c_char) -> bool {
+    let text = match unsafe { c_str_to_str(text) } {
+        Some(s) if !s.is_empty() => s,
+        _ => return ffi::invalid_argument("text is required"),
+    };
+    let target_app = unsafe { c_str_to_str(target_app) };
+
+    let settings = settings::Settings::load();
+    match clipboard::paste::deliver_text(text, &settings, target_app) {
+        Ok(()) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Clipboard, "Failed to deliver text", &e),
+    }
+}
+
+/// Copy a history entry's text to the clipboard without pasting.
+/// Same text selection and return value as phemy_paste_history_entry().
+#[no_mangle]
+pub extern "C" fn phemy_copy_history_entry(id: *const c_char, use_raw: bool) -> bool {
+    let text = match history_entry_text(id, use_raw) {
+        Some(text) => text,
+        None => return false,
+    };
+
+    match clipboard::paste::copy_to_clipboard(&text) {
+        Ok(_) => ffi::succeeded(),
+        Err(e) => ffi::failed(ErrorCode::Clipboard, "Failed to copy history entry", &e),
+    }
+}
+
+/// Text to paste or copy for a history entry, or None (logged) if there is nothing to use.
+fn history_entry_text(id: *const c_char, use_raw: bool) -> Option<String> {
+    let Some(id) = (unsafe { c_str_to_str(id) }) else {
+        ffi::invalid_argument("id is required");
+        return None;
+    };
+
+    let entry = match db::get_history_entry(id) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => {
+            log::warn!("History entry not found: {}", id);
+            history_entry_not_found(id);
+            return None;
+        }
+        Err(e) => {
+            ffi::failed(ErrorCode::Database, &format!("Failed to load history entry {}", id), &e);
+            return None;
+        }
+    };
+
+    let text = match entry.optimized_prompt {
+        Some(optimized) if !use_raw => optimized,
+        _ => entry.raw_transcript,
+    };
+    if text.trim().is_empty() {
+        log::info!("History entry {} has no text to use", id);
+        ffi::invalid_argument(&format!("History entry {} has no text to use", id));
+        return None;
+    }
+    Some(text)
+}
+
+/// Record that history entry `id` doesn't exist as the last error; false
+fn history_entry_not_found(id: &str) -> bool {
+    ffi::set_last_error(ErrorCode::NotFound, &format!("History entry '{}' not found", id));
+    false
+}
+
+/// Whether a paste put the text into the target app, recording why not as
+/// the last error
+fn paste_succeeded(outcome: &clipboard::paste::PasteOutcome) -> bool {
+    use clipboard::paste::PasteAbort;
+
+    match outcome.aborted {
+        _ if outcome.clipboard_only => ffi::set_last_error(
+            ErrorCode::Clipboard,
+            "Keystrokes are blocked by secure input; the text was only put on the clipboard",
+        ),
+        Some(PasteAbort::Cancelled) => ffi::set_last_error(ErrorCode::Cancelled, "The paste was cancelled"),
+        Some(PasteAbort::FocusChanged) => ffi::set_last_error(
+            ErrorCode::Clipboard,
+            "Another window took focus, so typing stopped part way through",
+        ),
+        None => return ffi::succeeded(),
+    }
+    false
+}
+
+// ============================================================
+// Operations
+// ============================================================
+
+/// Ask a running operation (see phemy_list_operations()) to stop at its
+/// next check. It then ends the way its own cancel export makes it end:
+/// a download reports "cancelled", a job polls as cancelled, a generation
+/// fails so the pipeline falls back to the raw transcript, and so on.
+/// Returns false if no operation with that id is running; cancelling one
+/// twice is harmless.
+#[no_mangle]
+pub extern "C" fn phemy_cancel(op_id: u64) -> bool {
+    ops::cancel(op_id)
+}
+
+/// Running operations as a JSON array, oldest first:
+/// `[{id, kind, name?, progress, cancelled, elapsed_ms}]`. `kind` is
+/// "download", "transcription", "generation", "paste" or "job"; `name` is
+/// the model, where there is one; `progress` is a rough fraction from 0 to
+/// 1; `cancelled` means phemy_cancel() was called but it hasn't stopped yet.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_list_operations() -> *mut c_char {
+    to_json_c_char(&ops::list())
+}
+
+// ============================================================
+// Diagnostics
+// ============================================================
+
+/// Check the parts phemy depends on, for a diagnostics panel. Returns JSON
+/// with an `{ "ok", "detail" }` item for each of "initialized", "data_dir"
+/// (writable), "database" (open, passes a quick integrity check),
+/// "whisper_model" and "llm_model" (the configured model's file is present
+/// and support for it compiled in; `detail` has the path), "audio_input",
+/// "clipboard", "disk_space" (enough free for the configured models not yet
+/// downloaded) and "features" (compiled in), plus "ok" when all passed and
+/// "duration_ms". Loads no models and doesn't touch the network, so it
+/// returns quickly, unless `check_network` is set: then "network" says
+/// whether the model download host answers (up to 5 seconds).
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_health_check(check_network: bool) -> *mut c_char {
+    to_json_c_char(&api::Handle::unchecked().health_check(check_network))
+}
+
+/// Why the last failing phemy_* call on this thread failed, as
+/// { "code": "...", "message": "..." } with the same codes as `error`
+/// fields, or null if the last call that reports errors succeeded. Like
+/// errno, each thread has its own; exports returning false or an error
+/// JSON set it, and their successful calls clear it. Reading it changes
+/// nothing.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_last_error() -> *mut c_char {
+    match ffi::last_error() {
+        Some((code, message)) => str_to_c_char(&serde_json::json!({ "code": code, "message": message }).to_string()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Describe this build as JSON: { "version", "git_hash"?, "features": [...],
+/// "target", "profile", "whisper_rs"?, "llama_cpp_2"? }. `features` lists
+/// the optional features compiled in; `whisper_rs` and `llama_cpp_2` are
+/// the versions of the crates bundling whisper.cpp and llama.cpp. Can be
+/// called before phemy_init.
+/// Caller must free the returned string with phemy_free_string().
+#[no_mangle]
+pub extern "C" fn phemy_get_build_info() -> *mut c_char {
+    to_json_c_char(&api::build_info())
+}
+
+// ============================================================
+// Memory management
+// ============================================================
+
+/// Free a string returned by any phemy_* function. Null is ignored.
+/// Built with the `track-strings` feature, a pointer phemy didn't return or
+/// already freed is logged and ignored instead of corrupting the heap, and
+/// phemy_shutdown logs the strings never freed with the export that
+/// returned each.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_free_string(ptr: *mut c_char) {
+    unsafe { ffi::free_c_char(ptr) }
+}
+
+/// Free samples from phemy_stop_recording_with_samples(), passing the
+/// length it returned with them. Null is ignored.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_free_samples(ptr: *mut f32, len: usize) {
+    unsafe { ffi::free_samples(ptr, len) }
+}
+
+// ============================================================
+// Caller-allocated buffers
+// ============================================================
+//
+// `_buf` variants of the read-only string exports, for hosts that can't
+// safely free memory allocated here. Each writes the same string as the
+// original into `buf` as NUL-terminated UTF-8 if it fits in `buf_len` bytes
+// and returns the size needed including the NUL, so a call with a null `buf`
+// queries the size. -1 means the original returned null. Exports that change
+// state have no variant, since retrying with a bigger buffer would repeat them.
+
+/// Like phemy_get_settings(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_settings_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_settings(), buf, buf_len) }
+}
+
+/// Like phemy_get_setting(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_setting_buf(
+    key: *const c_char,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_setting(key), buf, buf_len) }
+}
+
+/// Like phemy_get_settings_schema(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_settings_schema_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_settings_schema(), buf, buf_len) }
+}
+
+/// Like phemy_validate_hotkey(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_validate_hotkey_buf(
+    hotkey: *const c_char,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_validate_hotkey(hotkey), buf, buf_len) }
+}
+
+/// Like phemy_list_profiles(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_list_profiles_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_list_profiles(), buf, buf_len) }
+}
+
+/// Like phemy_list_audio_devices(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_list_audio_devices_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_list_audio_devices(), buf, buf_len) }
+}
+
+/// Like phemy_list_whisper_models(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_list_whisper_models_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_list_whisper_models(), buf, buf_len) }
+}
+
+/// Like phemy_get_download_progress(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_download_progress_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_download_progress(), buf, buf_len) }
+}
+
+/// Like phemy_list_prompt_modes(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_list_prompt_modes_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_list_prompt_modes(), buf, buf_len) }
+}
+
+/// Like phemy_list_llm_models(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_list_llm_models_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_list_llm_models(), buf, buf_len) }
+}
+
+/// Like phemy_get_llm_download_progress(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_llm_download_progress_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_llm_download_progress(), buf, buf_len) }
+}
+
+/// Like phemy_get_history(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_history_buf(
+    limit: i32,
+    offset: i32,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_history(limit, offset), buf, buf_len) }
+}
+
+/// Like phemy_query_history(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_query_history_buf(
+    filter_json: *const c_char,
+    limit: i32,
+    offset: i32,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_query_history(filter_json, limit, offset), buf, buf_len) }
+}
+
+/// Like phemy_search_history(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_search_history_buf(
+    query: *const c_char,
+    limit: i32,
+    offset: i32,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_search_history(query, limit, offset), buf, buf_len) }
+}
+
+/// Like phemy_get_history_audio_path(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_history_audio_path_buf(
+    id: *const c_char,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_history_audio_path(id), buf, buf_len) }
+}
+
+/// Like phemy_get_stats(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_stats_buf(days: i32, buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_stats(days), buf, buf_len) }
+}
+
+/// Like phemy_get_events(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_events_buf(
+    limit: u32,
+    offset: u32,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_events(limit, offset), buf, buf_len) }
+}
+
+/// Like phemy_get_vocabulary(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_get_vocabulary_buf(buf: *mut c_char, buf_len: usize) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_get_vocabulary(), buf, buf_len) }
+}
+
+/// Like phemy_suggest_vocabulary(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_suggest_vocabulary_buf(
+    min_occurrences: u32,
+    limit: u32,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_suggest_vocabulary(min_occurrences, limit), buf, buf_len) }
+}
+
+/// Like phemy_preview_paste(), writing into a caller-allocated buffer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn phemy_preview_paste_buf(
+    text: *const c_char,
+    target_app: *const c_char,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> i32 {
+    unsafe { ffi::copy_to_buf(phemy_preview_paste(text, target_app), buf, buf_len) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::ffi::CStr;
+
+    #[test]
+    fn failed_settings_save_leaves_vocabulary_alone() {
+        let env = test_support::env();
+        db::replace_vocabulary(&["kept".to_string()]).unwrap();
+
+        // A data directory under a plain file can't be created
+        let blocker = env.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        settings::set_data_dir(blocker.join("data"));
+
+        let new = settings::Settings {
+            vocabulary: vec!["replaced".to_string()],
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&new).unwrap();
+        let result = save_settings_json(Some(&json));
+        assert!(matches!(result, Err((ErrorCode::Io, _))), "{:?}", result.map(|_| ()));
+        assert_eq!(db::list_vocabulary().unwrap(), vec!["kept".to_string()]);
+    }
+
+    #[test]
+    fn saved_settings_replace_vocabulary() {
+        let _env = test_support::env();
+        db::replace_vocabulary(&["old".to_string()]).unwrap();
+        let new = settings::Settings {
+            vocabulary: vec!["new".to_string()],
+            ..Default::default()
+        };
+        assert!(save_settings_json(Some(&serde_json::to_string(&new).unwrap())).is_ok());
+        assert_eq!(db::list_vocabulary().unwrap(), vec!["new".to_string()]);
+        assert!(matches!(save_settings_json(None), Err((ErrorCode::InvalidArgument, _))));
+    }
+
+    /// Parse and free a string returned by an export
+    fn take_json(ptr: *mut c_char) -> serde_json::Value {
+        assert!(!ptr.is_null());
+        let json = unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_string();
+        phemy_free_string(ptr);
+        serde_json::from_str(&json).unwrap_or_else(|e| panic!("{}: {:?}", e, json))
+    }
+
+    #[test]
+    fn buffer_exports_report_the_size_and_write_only_when_it_fits() {
+        let schema = take_json(phemy_get_settings_schema());
+        let expected = serde_json::to_string(&schema).unwrap();
+        let needed = phemy_get_settings_schema_buf(std::ptr::null_mut(), 0);
+        assert_eq!(needed as usize, expected.len() + 1);
+
+        let mut short = vec![0x7f as c_char; expected.len()];
+        assert_eq!(phemy_get_settings_schema_buf(short.as_mut_ptr(), short.len()), needed);
+        assert!(short.iter().all(|&b| b == 0x7f as c_char), "a short buffer is left alone");
+
+        let mut exact = vec![0x7f as c_char; expected.len() + 1];
+        assert_eq!(phemy_get_settings_schema_buf(exact.as_mut_ptr(), exact.len()), needed);
+        let written = unsafe { CStr::from_ptr(exact.as_ptr()) }.to_str().unwrap();
+        assert_eq!(written, expected);
+    }
+
+    /// Code of the `{ "error": { "code", "message" } }` in `ptr`
+    #[cfg(not(feature = "legacy-errors"))]
+    fn error_code_of(ptr: *mut c_char) -> String {
+        let value = take_json(ptr);
+        assert!(value["error"]["message"].is_string(), "{}", value);
+        value["error"]["code"].as_str().unwrap_or_else(|| panic!("{}", value)).to_string()
+    }
+
+    #[test]
+    #[cfg(not(feature = "legacy-errors"))]
+    fn string_exports_report_failures_as_json_errors() {
+        let _env = test_support::env();
+        let null = std::ptr::null();
+        let missing = CString::new("no-such-id").unwrap();
+        let bogus = CString::new("no_such_setting").unwrap();
+        let samples = [0.0f32; 4];
+
+        let invalid_argument = [
+            phemy_get_setting(null),
+            phemy_validate_hotkey(null),
+            phemy_save_settings_ex(null),
+            phemy_update_settings(null),
+            phemy_import_settings(null),
+            phemy_reset_settings_section(null),
+            phemy_switch_profile(null),
+            phemy_process_samples(std::ptr::null(), 0, 16_000, null),
+            phemy_process_samples(samples.as_ptr(), samples.len(), 0, null),
+            phemy_transcribe(std::ptr::null(), 0, 16_000),
+            phemy_optimize_prompt(null),
+            phemy_optimize_prompt_ex(null, null),
+            phemy_optimize_prompt_for_app(null, null, null),
+            phemy_search_history(null, 10, 0),
+            phemy_get_history_audio_path(null),
+            phemy_backup_database(null),
+            phemy_restore_database(null),
+            phemy_import_history(null, null),
+            phemy_paste_text_ex(null, null),
+            phemy_preview_paste(null, null),
+        ];
+        for (i, ptr) in invalid_argument.into_iter().enumerate() {
+            assert_eq!(error_code_of(ptr), "invalid_argument", "call {}", i);
+        }
+
+        assert_eq!(error_code_of(phemy_get_setting(bogus.as_ptr())), "not_found");
+        assert_eq!(error_code_of(phemy_get_history_audio_path(missing.as_ptr())), "not_found");
+        assert_eq!(error_code_of(phemy_poll_job(u64::MAX)), "not_found");
+        assert!(take_json(phemy_stop_and_process())["error"]["code"].is_string());
+
+        // Every database-backed export fails once the database is closed
+        db::close();
+        let database = [
+            phemy_get_history(10, 0),
+            phemy_query_history(null, 10, 0),
+            phemy_clear_history_ex(false),
+            phemy_get_stats(7),
+            phemy_dedupe_history(),
+            phemy_db_maintenance(),
+            phemy_get_events(10, 0),
+            phemy_get_vocabulary(),
+            phemy_suggest_vocabulary(2, 10),
+            phemy_get_history_audio_path(missing.as_ptr()),
+        ];
+        for (i, ptr) in database.into_iter().enumerate() {
+            assert_eq!(error_code_of(ptr), "database", "call {}", i);
+        }
+    }
+
+    #[test]
+    fn audio_path_is_returned_as_json() {
+        let _env = test_support::env();
+        let mut entry =
+            db::new_history_entry("raw".into(), None, "raw".into(), None, 1.0, None);
+        entry.audio_path = Some("/recordings/a.wav".into());
+        db::insert_history(&entry, None).unwrap();
+        let id = CString::new(entry.id).unwrap();
+        let ptr = phemy_get_history_audio_path(id.as_ptr());
+        if cfg!(feature = "legacy-errors") {
+            assert_eq!(unsafe { CStr::from_ptr(ptr) }.to_str().unwrap(), "/recordings/a.wav");
+            phemy_free_string(ptr);
+        } else {
+            assert_eq!(take_json(ptr), serde_json::json!({ "path": "/recordings/a.wav" }));
+        }
+    }
+
+    fn init_dir(dir: &std::path::Path) -> bool {
+        init(&api::Config::from_data_dir(Some(dir.to_str().unwrap()), None)).is_ok()
+    }
+
+    #[test]
+    fn reinit_switches_directories_and_back() {
+        let _lock = test_support::lock();
+        let a = tempfile::tempdir().unwrap();
+        let b = tempfile::tempdir().unwrap();
+
+        assert!(init_dir(a.path()));
+        db::replace_vocabulary(&["in a".to_string()]).unwrap();
+        assert!(init_dir(a.path()), "same directory is a no-op");
+        assert_eq!(db::list_vocabulary().unwrap(), ["in a"]);
+
+        assert!(init_dir(b.path()));
+        assert!(b.path().join("phemy.db").exists());
+        assert!(db::list_vocabulary().unwrap().is_empty());
+
+        assert!(init_dir(a.path()));
+        assert_eq!(db::list_vocabulary().unwrap(), ["in a"]);
+        shutdown();
+    }
+
+    #[test]
+    fn init_status_explains_success_and_failure() {
+        let _lock = test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(init_dir(dir.path()));
+        assert!(phemy_is_initialized());
+        let status = take_json(phemy_get_init_status());
+        assert_eq!(status["initialized"], true, "{}", status);
+        assert_eq!(status["db_open"], true);
+        assert_eq!(status["data_dir"], dir.path().to_str().unwrap());
+        assert!(status["db_path"].as_str().unwrap().ends_with("phemy.db"));
+        assert!(status.get("error").is_none());
+        shutdown();
+
+        // A data directory under a plain file can't be created
+        let blocker = dir.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        assert!(!init_dir(&blocker.join("data")));
+        assert!(!phemy_is_initialized());
+        let status = take_json(phemy_get_init_status());
+        assert_eq!(status["initialized"], false, "{}", status);
+        assert_eq!(status["db_open"], false);
+        assert!(status["error"].as_str().unwrap().contains("database"), "{}", status);
+
+        assert!(init_dir(dir.path()));
+        shutdown();
+        let status = take_json(phemy_get_init_status());
+        assert_eq!(status["initialized"], false, "{}", status);
+        assert!(status["data_dir"].is_null());
+    }
+
+    #[test]
+    fn health_check_follows_initialization() {
+        let _lock = test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(init_dir(dir.path()));
+        let health = take_json(phemy_health_check(false));
+        assert_eq!(health["initialized"]["ok"], true, "{}", health);
+        assert!(health["initialized"]["detail"].as_str().unwrap().contains(dir.path().to_str().unwrap()));
+        assert_eq!(health["data_dir"]["ok"], true, "{}", health);
+        assert_eq!(health["database"]["ok"], true, "{}", health);
+        assert_eq!(health["whisper_model"]["ok"], false);
+        assert!(health.get("network").is_none());
+        shutdown();
+
+        // Shutdown forgets the directory; keep the checks out of the real one
+        settings::set_data_dir(dir.path().to_path_buf());
+        let health = take_json(phemy_health_check(false));
+        assert_eq!(health["initialized"]["ok"], false);
+        assert_eq!(health["database"]["ok"], false);
+        assert_eq!(health["ok"], false);
+    }
+
+    #[test]
+    fn last_error_reports_the_last_failure_on_this_thread() {
+        let _env = test_support::env();
+        let missing = CString::new("no-such-id").unwrap();
+        let profile = CString::new("no-such-profile").unwrap();
+
+        phemy_clear_history();
+        assert!(phemy_get_last_error().is_null());
+
+        assert!(!phemy_delete_history_entry(std::ptr::null()));
+        let error = take_json(phemy_get_last_error());
+        assert_eq!(error["code"], "invalid_argument");
+        assert_eq!(error["message"], "id is required");
+        // Reading it leaves it in place
+        assert_eq!(take_json(phemy_get_last_error()), error);
+        // Other threads have their own
+        assert!(std::thread::spawn(|| phemy_get_last_error().is_null()).join().unwrap());
+
+        assert!(!phemy_set_history_favorite(missing.as_ptr(), true));
+        let error = take_json(phemy_get_last_error());
+        assert_eq!(error["code"], "not_found");
+        assert!(error["message"].as_str().unwrap().contains("no-such-id"));
+
+        assert!(phemy_clear_history());
+        assert!(phemy_get_last_error().is_null());
+
+        // Exports returning JSON errors set it too
+        phemy_free_string(phemy_switch_profile(profile.as_ptr()));
+        assert_eq!(take_json(phemy_get_last_error())["code"], "not_found");
+        phemy_free_string(phemy_get_settings());
+        assert!(phemy_get_last_error().is_null());
+    }
+
+    #[test]
+    fn failed_init_leaves_nothing_behind_for_a_retry() {
+        let _lock = test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+        let blocker = dir.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let unusable = blocker.join("data");
+        let good = dir.path().join("good");
+
+        // A first init that fails
+        assert!(init_dir(dir.path()));
+        shutdown();
+        assert!(!init_dir(&unusable));
+        assert!(!phemy_is_initialized());
+        assert!(settings::get_data_dir().is_none());
+        assert!(runtime().is_err(), "no runtime was started");
+
+        assert!(init_dir(&good));
+        assert_eq!(settings::get_data_dir().as_deref(), Some(good.as_path()));
+        assert!(runtime().is_ok());
+        assert!(utils::models_dir().unwrap().starts_with(&good));
+        assert!(db::list_vocabulary().is_ok());
+
+        // Switching to a directory that fails doesn't keep the old one
+        assert!(!init_dir(&unusable));
+        assert!(!phemy_is_initialized());
+        assert!(settings::get_data_dir().is_none());
+        assert!(db::list_vocabulary().is_err());
+
+        assert!(init_dir(&good));
+        assert!(phemy_is_initialized());
+        assert_eq!(take_json(phemy_get_init_status())["initialized"], true);
+        shutdown();
+    }
+
+    #[test]
+    fn cancel_download_stops_only_downloads_of_that_kind() {
+        let _lock = test_support::lock();
+        let whisper = ops::start(ops::OpKind::Download, Some("base"));
+        let llm = ops::start(ops::OpKind::Download, Some("qwen3-4b-instruct-q4km"));
+
+        assert!(phemy_cancel_download(0));
+        assert!(whisper.is_cancelled());
+        assert!(!llm.is_cancelled());
+        assert!(phemy_cancel_download(1));
+        assert!(llm.is_cancelled());
+
+        assert!(!phemy_cancel_download(7));
+        assert_eq!(take_json(phemy_get_last_error())["code"], "invalid_argument");
+        drop((whisper, llm));
+    }
+
+    #[test]
+    fn shutdown_cancels_downloads_and_forgets_the_data_directory() {
+        let _lock = test_support::lock();
+        let a = tempfile::tempdir().unwrap();
+        let b = tempfile::tempdir().unwrap();
+
+        assert!(init_dir(a.path()));
+        let download = ops::start(ops::OpKind::Download, Some("base.en"));
+        shutdown();
+        assert!(download.is_cancelled());
+        assert!(settings::get_data_dir().is_none());
+        assert!(!phemy_is_initialized());
+
+        shutdown();
+        phemy_shutdown();
+
+        assert!(init_dir(b.path()));
+        assert_eq!(settings::get_data_dir().as_deref(), Some(b.path()));
+        assert!(b.path().join("phemy.db").exists());
+        shutdown();
+    }
+
+    #[test]
+    fn runtime_fails_after_shutdown_until_init() {
+        let _lock = test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+        assert!(init_dir(dir.path()));
+        assert!(runtime().is_ok());
+
+        shutdown();
+        assert!(runtime().is_err());
+        assert!(init_dir(dir.path()));
+        assert!(runtime().is_ok());
+        shutdown();
+    }
+
+    #[test]
+    fn init_ex_builds_the_configured_runtime() {
+        let _lock = test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+        let init_ex = |runtime: serde_json::Value| {
+            let config = serde_json::json!({ "data_dir": dir.path(), "runtime": runtime }).to_string();
+            phemy_init_ex(CString::new(config).unwrap().as_ptr())
+        };
+        // The name of the thread a spawned task runs on
+        let task_thread = || {
+            let (tx, rx) = std::sync::mpsc::channel();
+            runtime().unwrap().spawn(async move {
+                let _ = tx.send(std::thread::current().name().map(str::to_string));
+            });
+            rx.recv_timeout(Duration::from_secs(5)).expect("spawned task ran")
+        };
+
+        assert!(init_ex(serde_json::json!({ "current_thread": true, "thread_name": "phemy-single" })));
+        assert_eq!(task_thread().as_deref(), Some("phemy-single"));
+        shutdown();
+
+        assert!(init_ex(serde_json::json!({ "worker_threads": 2, "thread_name": "phemy-pool" })));
+        assert!(task_thread().unwrap().starts_with("phemy-pool-"));
+
+        assert!(!phemy_init_ex(c"{\"runtime\": 3}".as_ptr()));
+        assert!(phemy_is_initialized());
+        let status = take_json(phemy_get_init_status());
+        assert!(status["error"].as_str().unwrap().starts_with("Invalid configuration"), "{}", status);
+        shutdown();
+    }
+
+    #[test]
+    fn runtime_in_use_at_shutdown_stops_once_released() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct SetOnDrop(Arc<AtomicBool>);
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let _lock = test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+        assert!(init_dir(dir.path()));
+        let held = runtime().unwrap();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let guard = SetOnDrop(stopped.clone());
+        held.spawn(async move {
+            let _guard = guard;
+            std::future::pending::<()>().await
+        });
+
+        shutdown();
+        assert!(!stopped.load(Ordering::SeqCst));
+        drop(held);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !stopped.load(Ordering::SeqCst) {
+            assert!(Instant::now() < deadline, "runtime was never stopped");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn mocked_pipeline_saves_its_result_to_history() {
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "hello world".to_string(),
+            completion: "Hello, world.".to_string(),
+            delay: Duration::ZERO,
+        });
+        let samples = test_support::speech(16_000);
+        let result = take_json(phemy_process_samples(
+            samples.as_ptr(),
+            samples.len(),
+            16_000,
+            std::ptr::null(),
+        ));
+        assert_eq!(result["raw_transcript"], "hello world", "{}", result);
+        assert_eq!(result["optimized_prompt"], "Hello, world.");
+
+        // The entry is written in the background (`history_pending`)
+        flush_history_inserts();
+        let id = result["history_id"].as_str().unwrap();
+        let entry = db::get_history_entry(id).unwrap().unwrap();
+        assert_eq!(entry.optimized_prompt.as_deref(), Some("Hello, world."));
+    }
+
+    #[test]
+    fn results_keep_nul_bytes_from_the_transcript() {
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "hello\0 w\u{f6}rld \u{1f642}".to_string(),
+            completion: "Hello,\0 world.".to_string(),
+            delay: Duration::ZERO,
+        });
+        let samples = test_support::speech(16_000);
+        let result = phemy_process_samples(samples.as_ptr(), samples.len(), 16_000, std::ptr::null());
+        assert!(!result.is_null());
+        let result = take_json(result);
+        assert_eq!(result["raw_transcript"], "hello\0 w\u{f6}rld \u{1f642}", "{}", result);
+        assert_eq!(result["optimized_prompt"], "Hello,\0 world.");
+        flush_history_inserts();
+    }
+
+    #[test]
+    fn process_text_optimizes_and_saves_text_from_elsewhere() {
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "unused".to_string(),
+            completion: "Turn on the lights.".to_string(),
+            delay: Duration::ZERO,
+        });
+        let text = CString::new("turn on the lights").unwrap();
+        let result = take_json(phemy_process_text(text.as_ptr(), false));
+        assert_eq!(result["raw_transcript"], "turn on the lights", "{}", result);
+        assert_eq!(result["optimized_prompt"], "Turn on the lights.");
+        assert_eq!(result["duration_secs"], 0.0);
+        assert!(result.get("paste").is_none());
+
+        flush_history_inserts();
+        let id = result["history_id"].as_str().unwrap();
+        let entry = db::get_history_entry(id).unwrap().unwrap();
+        assert_eq!(entry.duration_secs, 0.0);
+        assert!(entry.audio_path.is_none());
+
+        for blank in ["", "  \n\t"] {
+            let blank = CString::new(blank).unwrap();
+            let result = take_json(phemy_process_text(blank.as_ptr(), false));
+            assert_eq!(result["error"]["code"], "invalid_argument", "{}", result);
+        }
+        let result = take_json(phemy_process_text(std::ptr::null(), true));
+        assert_eq!(result["error"]["code"], "invalid_argument", "{}", result);
+    }
+
+    #[test]
+    fn failed_chain_stage_reports_llm_error_and_keeps_the_earlier_output() {
+        let _env = test_support::env();
+        llm::mock::install(llm::mock::MockLlm {
+            completion: "Cleaned up.".to_string(),
+            delay: Duration::ZERO,
+            fail_mode: Some(settings::PromptMode::Code),
+        });
+        settings::Settings {
+            prompt_mode_chain: vec![settings::PromptMode::Clean, settings::PromptMode::Code],
+            ..Default::default()
+        }
+        .save()
+        .unwrap();
+
+        let text = CString::new("um cleaned up").unwrap();
+        let result = take_json(phemy_process_text(text.as_ptr(), false));
+        assert_eq!(result["optimized_prompt"], "Cleaned up.", "{}", result);
+        assert_eq!(result["llm_error"], "code stage failed: Mock completion failed", "{}", result);
+        let events = db::get_events(10, 0).unwrap();
+        assert!(events.iter().any(|e| e.kind == db::EventKind::LlmFallback), "{:?}", events);
+    }
+
+    #[test]
+    fn history_keeps_the_text_from_before_paste_postprocess() {
+        let _env = test_support::env();
+        let completion = "First line.\nSecond line.";
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "unused".to_string(),
+            completion: completion.to_string(),
+            delay: Duration::ZERO,
+        });
+        settings::Settings {
+            paste_postprocess: settings::PastePostprocess {
+                append: settings::PasteAppend::Newline,
+                collapse_internal_newlines: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .save()
+        .unwrap();
+
+        // The paste itself may fail without a display; history is saved first
+        let text = CString::new("first line second line").unwrap();
+        let result = take_json(phemy_process_text(text.as_ptr(), true));
+        assert!(result.get("paste").is_some(), "{}", result);
+        assert_eq!(result["optimized_prompt"], completion);
+
+        flush_history_inserts();
+        let id = result["history_id"].as_str().unwrap();
+        let entry = db::get_history_entry(id).unwrap().unwrap();
+        assert_eq!(entry.optimized_prompt.as_deref(), Some(completion));
+
+        let plan = clipboard::paste::preview_paste(completion, &settings::Settings::load(), None);
+        assert_eq!(plan.text, "First line. Second line.\n");
+    }
+
+    #[test]
+    fn pipeline_options_override_settings_for_one_run() {
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "hello world".to_string(),
+            completion: "Hello, world.".to_string(),
+            delay: Duration::ZERO,
+        });
+        let samples = test_support::speech(16_000);
+        let process = |options: &str| {
+            let options = CString::new(options).unwrap();
+            take_json(phemy_process_samples(samples.as_ptr(), samples.len(), 16_000, options.as_ptr()))
+        };
+
+        let quick = process(r#"{ "skip_llm": true, "skip_history": true }"#);
+        assert_eq!(quick["mode"], "raw", "{}", quick);
+        assert_eq!(quick["optimized_prompt"], "hello world");
+        assert!(quick.get("history_id").is_none());
+
+        let code = process(r#"{ "mode": "code", "language": "de", "target_app": "iTerm2" }"#);
+        assert_eq!(code["mode"], "code", "{}", code);
+        let saved = settings::Settings::load();
+        assert_eq!(saved.prompt_mode, settings::PromptMode::Clean);
+        assert_eq!(saved.language, "en");
+
+        flush_history_inserts();
+        assert_eq!(db::get_history(10, 0).unwrap().len(), 1);
+
+        for invalid in [r#"{ "skip_lm": true }"#, r#"{ "mode": "poetic" }"#, r#"{ "language": "German" }"#] {
+            let result = process(invalid);
+            assert_eq!(result["error"]["code"], "invalid_argument", "{}: {}", invalid, result);
+        }
+    }
+
+    #[test]
+    fn last_pipeline_metrics_describe_the_latest_run() {
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "hello world".to_string(),
+            completion: "Hello, world.".to_string(),
+            delay: Duration::from_millis(20),
+        });
+        let samples = test_support::speech(16_000);
+        let options = CString::new(r#"{ "include_metrics": true }"#).unwrap();
+        let result = take_json(phemy_process_samples(samples.as_ptr(), samples.len(), 16_000, options.as_ptr()));
+        let metrics = &result["metrics"];
+        assert_eq!(metrics["recording_secs"], 1.0, "{}", result);
+        assert!(metrics["total_ms"].as_u64().unwrap() >= 40, "{}", metrics);
+        assert_eq!(take_json(phemy_get_last_pipeline_metrics()), *metrics);
+
+        // Without the option the result has none, but the slot is replaced
+        let result = take_json(phemy_process_samples(samples.as_ptr(), 8_000, 16_000, std::ptr::null()));
+        assert!(result.get("metrics").is_none(), "{}", result);
+        assert_eq!(take_json(phemy_get_last_pipeline_metrics())["recording_secs"], 0.5);
+    }
+
+    /// Wait until the mock mic's recording holds `frames`, failing the test
+    /// after a few seconds
+    #[cfg(feature = "mock-audio")]
+    fn wait_for_mock_frames(frames: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let delivered = audio::mock::delivered_frames();
+            if delivered >= frames {
+                return;
+            }
+            assert!(Instant::now() < deadline, "the mock mic delivered only {} frames", delivered);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "mock-audio")]
+    fn stop_and_process_runs_the_pipeline_on_a_mock_recording() {
+        use audio::mock::{self, MockDevice};
+
+        let env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "turn on the lights".to_string(),
+            completion: "Turn on the lights.".to_string(),
+            delay: Duration::ZERO,
+        });
+        // A canned utterance: half a second of tone
+        let utterance = test_support::speech(8_000);
+        let wav = env.path().join("utterance.wav");
+        std::fs::write(&wav, utils::samples_to_wav(&utterance, 16_000).unwrap()).unwrap();
+        mock::install(vec![MockDevice::wav("Mock Mic", &wav).unwrap()]);
+
+        assert!(phemy_start_recording(std::ptr::null(), None));
+        wait_for_mock_frames(utterance.len());
+        let result = take_json(phemy_stop_and_process());
+        mock::uninstall();
+
+        assert_eq!(result["raw_transcript"], "turn on the lights", "{}", result);
+        assert_eq!(result["optimized_prompt"], "Turn on the lights.");
+        assert!(result["duration_secs"].as_f64().unwrap() >= 0.5, "{}", result);
+        flush_history_inserts();
+        assert_eq!(db::get_history(10, 0).unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "mock-audio")]
+    type Phases = Mutex<Vec<(i32, Option<serde_json::Value>)>>;
+
+    #[cfg(feature = "mock-audio")]
+    extern "C" fn collect_phase(phase: i32, detail_json: *const c_char, user_data: *mut c_void) {
+        let phases = unsafe { &*(user_data as *const Phases) };
+        let detail = unsafe { c_str_to_str(detail_json) }.map(|detail| serde_json::from_str(detail).unwrap());
+        phases.lock().unwrap().push((phase, detail));
+    }
+
+    #[test]
+    #[cfg(feature = "mock-audio")]
+    fn stop_and_process_reports_its_phases() {
+        use audio::mock::{self, MockDevice, Source};
+        use jobs::Phase;
+
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "turn on the lights".to_string(),
+            completion: "Turn on the lights.".to_string(),
+            delay: Duration::ZERO,
+        });
+        let utterance = test_support::speech(8_000);
+        mock::install(vec![MockDevice::new("Mock Mic", Source::Samples(utterance))]);
+
+        let phases: Phases = Mutex::new(Vec::new());
+        assert!(phemy_start_recording(std::ptr::null(), None));
+        wait_for_mock_frames(8_000);
+        let user_data = &phases as *const _ as *mut c_void;
+        let result = take_json(phemy_stop_and_process_with_progress(Some(collect_phase), user_data));
+        assert_eq!(result["optimized_prompt"], "Turn on the lights.", "{}", result);
+
+        let phases = phases.into_inner().unwrap();
+        let mut seen: Vec<i32> = phases.iter().map(|(phase, _)| *phase).collect();
+        seen.dedup();
+        let all = [
+            Phase::Stopping,
+            Phase::Resampling,
+            Phase::Vad,
+            Phase::Transcribing,
+            Phase::Optimizing,
+            Phase::Saving,
+        ];
+        assert_eq!(seen, all.map(|phase| phase as i32), "every phase, once and in order");
+
+        let tokens = |n: usize| Some(serde_json::json!({ "mode": "clean", "tokens": n }));
+        let expected = vec![
+            (Phase::Stopping as i32, None),
+            (Phase::Resampling as i32, None),
+            (Phase::Vad as i32, None),
+            (Phase::Transcribing as i32, None),
+            (Phase::Transcribing as i32, Some(serde_json::json!({ "segments": 1 }))),
+            (Phase::Optimizing as i32, None),
+            (Phase::Optimizing as i32, tokens(1)),
+            (Phase::Optimizing as i32, tokens(2)),
+            (Phase::Optimizing as i32, tokens(3)),
+            (Phase::Optimizing as i32, tokens(4)),
+            (Phase::Saving as i32, None),
+        ];
+        assert_eq!(phases, expected);
+
+        // Without a callback it is plain phemy_stop_and_process()
+        assert!(phemy_start_recording(std::ptr::null(), None));
+        wait_for_mock_frames(8_000);
+        let result = take_json(phemy_stop_and_process_with_progress(None, std::ptr::null_mut()));
+        assert_eq!(result["optimized_prompt"], "Turn on the lights.", "{}", result);
+        mock::uninstall();
+        flush_history_inserts();
+    }
+
+    #[test]
+    #[cfg(feature = "mock-audio")]
+    fn staged_pipeline_shows_the_transcript_before_committing() {
+        use audio::mock::{self, MockDevice, Source};
+
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "turn on the lights".to_string(),
+            completion: "Turn on the lights.".to_string(),
+            delay: Duration::ZERO,
+        });
+        let utterance = test_support::speech(8_000);
+        mock::install(vec![MockDevice::new("Mock Mic", Source::Samples(utterance))]);
+        let record = || {
+            assert!(phemy_start_recording(std::ptr::null(), None));
+            wait_for_mock_frames(8_000);
+            let finished = take_json(phemy_finish_recording());
+            assert_eq!(finished["sample_rate"], 16_000, "{}", finished);
+            assert!(finished["duration_secs"].as_f64().unwrap() >= 0.5, "{}", finished);
+            finished["session_id"].as_u64().unwrap()
+        };
+
+        let id = record();
+        let transcript = take_json(phemy_session_transcribe(id));
+        assert_eq!(transcript["raw_transcript"], "turn on the lights", "{}", transcript);
+        let optimized = take_json(phemy_session_optimize(id));
+        assert_eq!(optimized["optimized_prompt"], "Turn on the lights.", "{}", optimized);
+        flush_history_inserts();
+        assert!(db::get_history(10, 0).unwrap().is_empty(), "nothing is saved before commit");
+
+        let result = take_json(phemy_session_commit(id));
+        assert_eq!(result["raw_transcript"], "turn on the lights", "{}", result);
+        assert_eq!(result["optimized_prompt"], "Turn on the lights.");
+        assert!(result["duration_secs"].as_f64().unwrap() >= 0.5, "{}", result);
+        flush_history_inserts();
+        assert_eq!(db::get_history(10, 0).unwrap().len(), 1);
+        assert_eq!(take_json(phemy_session_commit(id))["error"]["code"], "not_found");
+
+        // Committing runs the stages not yet run; discarding saves nothing
+        let id = record();
+        assert_eq!(take_json(phemy_session_commit(id))["optimized_prompt"], "Turn on the lights.");
+        let id = record();
+        assert!(phemy_session_discard(id));
+        assert!(!phemy_session_discard(id));
+        assert_eq!(take_json(phemy_session_transcribe(id))["error"]["code"], "not_found");
+        mock::uninstall();
+        flush_history_inserts();
+        assert_eq!(db::get_history(10, 0).unwrap().len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "mock-audio")]
+    fn stop_recording_with_samples_hands_over_the_recording() {
+        use audio::mock::{self, MockDevice, Source};
+
+        let _env = test_support::env();
+        let utterance = test_support::speech(8_000);
+        mock::install(vec![MockDevice::new("Mock Mic", Source::Samples(utterance.clone()))]);
+
+        let (mut ptr, mut len, mut rate) = (std::ptr::null_mut(), 0usize, 0u32);
+        assert!(phemy_start_recording(std::ptr::null(), None));
+        assert!(!phemy_stop_recording_with_samples(&mut ptr, std::ptr::null_mut(), &mut rate));
+        assert_eq!(take_json(phemy_get_last_error())["code"], "invalid_argument");
+        assert!(audio::capture::is_recording(), "bad arguments leave the recording running");
+
+        wait_for_mock_frames(utterance.len());
+        assert!(phemy_stop_recording_with_samples(&mut ptr, &mut len, &mut rate));
+        mock::uninstall();
+
+        assert_eq!(rate, 16_000);
+        assert!(len >= utterance.len(), "{} samples", len);
+        let samples = unsafe { std::slice::from_raw_parts(ptr, len) };
+        assert_eq!(&samples[..utterance.len()], &utterance[..]);
+        phemy_free_samples(ptr, len);
+        phemy_free_samples(std::ptr::null_mut(), 0);
+    }
+
+    /// Id of a running operation of `kind`, as phemy_list_operations()
+    /// reports it, waiting a few seconds for one to start
+    fn wait_for_operation(kind: &str) -> u64 {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let ops = take_json(phemy_list_operations());
+            let found = ops.as_array().unwrap().iter().find(|op| op["kind"] == kind && op["cancelled"] == false);
+            if let Some(op) = found {
+                return op["id"].as_u64().unwrap();
+            }
+            assert!(Instant::now() < deadline, "no {} operation started: {}", kind, ops);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    type Completions = Mutex<Vec<(bool, serde_json::Value)>>;
+
+    extern "C" fn collect_completion(success: bool, result_json: *const c_char, user_data: *mut c_void) {
+        let completions = unsafe { &*(user_data as *const Completions) };
+        let json = unsafe { std::ffi::CStr::from_ptr(result_json) }.to_str().unwrap();
+        completions.lock().unwrap().push((success, serde_json::from_str(json).unwrap()));
+    }
+
+    #[test]
+    fn user_data_completions_fire_exactly_once() {
+        let _env = test_support::env();
+        let completions: Completions = Mutex::new(Vec::new());
+        let user_data = &completions as *const Completions as *mut c_void;
+        let bad_options = CString::new(r#"{"no_such_option": true}"#).unwrap();
+        let unknown = CString::new("no-such-model").unwrap();
+
+        assert_eq!(phemy_stop_and_process_with_user_data(bad_options.as_ptr(), Some(collect_completion), user_data), 0);
+        assert_eq!(phemy_download_whisper_model_with_user_data(unknown.as_ptr(), Some(collect_completion), user_data), 0);
+        assert_eq!(phemy_download_llm_model_with_user_data(std::ptr::null(), Some(collect_completion), user_data), 0);
+        // Nothing recorded, so the pipeline fails
+        let id = phemy_stop_and_process_with_user_data(std::ptr::null(), Some(collect_completion), user_data);
+        assert_ne!(id, 0);
+        assert_eq!(jobs::wait(id).unwrap().state, jobs::JobState::Error);
+        // Work that ends without reporting still answers
+        drop(Completion::new(Some(collect_completion), user_data));
+        ffi::stop_dispatcher();
+
+        let completions = completions.into_inner().unwrap();
+        let codes: Vec<_> = completions.iter().map(|(success, json)| (*success, json["error"]["code"].clone())).collect();
+        assert_eq!(
+            codes,
+            [
+                (false, "invalid_argument".into()),
+                (false, "invalid_argument".into()),
+                (false, "invalid_argument".into()),
+                (false, "processing".into()),
+                (false, "internal".into()),
+            ],
+            "{:?}",
+            completions
+        );
+    }
+
+    #[test]
+    fn batch_imports_each_file_once_and_reports_failures() {
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "voice memo".to_string(),
+            completion: "Voice memo.".to_string(),
+            delay: Duration::ZERO,
+        });
+        let memos = tempfile::tempdir().unwrap();
+        let wav = utils::samples_to_wav(&vec![0.1; 16_000], 16_000).unwrap();
+        let recorded = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for name in ["a.wav", "b.WAV", "nested/c.wav"] {
+            let path = memos.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, &wav).unwrap();
+            std::fs::File::options().write(true).open(&path).unwrap().set_modified(recorded).unwrap();
+        }
+        std::fs::write(memos.path().join("broken.wav"), "not audio").unwrap();
+        std::fs::write(memos.path().join("notes.txt"), "not audio either").unwrap();
+
+        let dir = CString::new(memos.path().to_str().unwrap()).unwrap();
+        let run = |options: &std::ffi::CStr| {
+            let id = phemy_batch_process_directory(dir.as_ptr(), options.as_ptr());
+            let status = jobs::wait(id).unwrap();
+            assert_eq!(status.state, jobs::JobState::Done, "{:?}", status);
+            assert_eq!(status.completed, status.total);
+            status.result.unwrap()
+        };
+
+        let report = run(c"{\"skip_optimization\": true}");
+        assert_eq!(report["total"], 3, "{}", report);
+        assert_eq!(report["imported"].as_array().unwrap().len(), 2);
+        assert_eq!(report["failed"][0]["path"], memos.path().join("broken.wav").to_str().unwrap());
+        let entries = db::get_history(10, 0).unwrap();
+        assert_eq!(entries.len(), 2);
+        for entry in &entries {
+            assert_eq!(entry.created_at_unix, 1_700_000_000);
+            assert_eq!(entry.prompt_mode, "raw");
+            assert!(entry.source_path.as_ref().unwrap().starts_with(memos.path().to_str().unwrap()));
+            assert!(entry.audio_path.is_none());
+        }
+
+        // Already imported files are skipped; the nested one is new
+        let report = run(c"{\"recursive\": true, \"skip_optimization\": true}");
+        assert_eq!(report["total"], 4, "{}", report);
+        assert_eq!(report["skipped"].as_array().unwrap().len(), 2);
+        assert_eq!(report["imported"][0]["path"], memos.path().join("nested/c.wav").to_str().unwrap());
+        assert_eq!(db::get_history(10, 0).unwrap().len(), 3);
+
+        let missing = CString::new(memos.path().join("missing").to_str().unwrap()).unwrap();
+        let id = phemy_batch_process_directory(missing.as_ptr(), std::ptr::null());
+        let status = jobs::wait(id).unwrap();
+        assert_eq!(status.state, jobs::JobState::Error);
+        assert!(status.error.unwrap().starts_with("Failed to read"));
+    }
+
+    #[test]
+    fn phemy_cancel_stops_jobs_transcriptions_and_generations() {
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "hello world".to_string(),
+            completion: "Hello, world.".to_string(),
+            delay: Duration::from_millis(300),
+        });
+
+        for kind in ["job", "transcription", "generation"] {
+            let run = std::thread::spawn(|| {
+                let samples = test_support::speech(16_000);
+                take_json(phemy_process_samples(samples.as_ptr(), samples.len(), 16_000, std::ptr::null()))
+            });
+            let id = wait_for_operation(kind);
+            assert!(phemy_cancel(id));
+            assert!(phemy_cancel(id), "cancelling twice is harmless");
+
+            let result = run.join().unwrap();
+            if kind == "generation" {
+                // Like any LLM failure, this falls back to the transcript
+                assert_eq!(result["optimized_prompt"], "hello world", "{}", result);
+                assert!(result["llm_error"].as_str().unwrap().contains("Cancelled"), "{}", result);
+            } else {
+                assert_eq!(result["error"]["code"], "cancelled", "{}: {}", kind, result);
+            }
+            assert!(!phemy_cancel(id), "the {} has ended", kind);
+        }
+
+        flush_history_inserts();
+        assert_eq!(db::get_history(10, 0).unwrap().len(), 1);
+        assert_eq!(take_json(phemy_list_operations()), serde_json::json!([]));
+    }
+
+    #[test]
+    fn markdown_output_reaches_history_json_verbatim() {
+        let _env = test_support::env();
+        let markdown = "# Title\n\nIntro.\n\n- first\n  - nested   spaced\n- second";
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "a title and two points".to_string(),
+            completion: markdown.to_string(),
+            delay: Duration::ZERO,
+        });
+        let samples = test_support::speech(16_000);
+        let result = take_json(phemy_process_samples(
+            samples.as_ptr(),
+            samples.len(),
+            16_000,
+            std::ptr::null(),
+        ));
+        assert_eq!(result["optimized_prompt"], markdown, "{}", result);
+
+        flush_history_inserts();
+        let history = take_json(phemy_get_history(10, 0));
+        assert_eq!(history[0]["optimized_prompt"], markdown, "{}", history);
+    }
+
+    #[test]
+    fn redaction_applies_to_the_result_and_history() {
+        let _env = test_support::env();
+        let settings = settings::Settings {
+            redaction: settings::RedactionSettings {
+                emails: true,
+                phone_numbers: true,
+                digit_sequences: true,
+            },
+            ..Default::default()
+        };
+        settings.save().unwrap();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "mail jo@example.com or call 555-123-4567".to_string(),
+            completion: "Email jo@example.com.".to_string(),
+            delay: Duration::ZERO,
+        });
+        let samples = test_support::speech(16_000);
+        let result = take_json(phemy_process_samples(
+            samples.as_ptr(),
+            samples.len(),
+            16_000,
+            std::ptr::null(),
+        ));
+        assert_eq!(result["raw_transcript"], "mail [EMAIL] or call [PHONE]", "{}", result);
+        assert_eq!(result["optimized_prompt"], "Email [EMAIL].");
+
+        flush_history_inserts();
+        let entry = &db::get_history(1, 0).unwrap()[0];
+        assert_eq!(entry.raw_transcript, "mail [EMAIL] or call [PHONE]");
+        assert_eq!(entry.optimized_prompt.as_deref(), Some("Email [EMAIL]."));
+    }
+
+    #[test]
+    fn invalid_history_filter_is_an_error_not_an_empty_list() {
+        let _env = test_support::env();
+        let bad_date = CString::new(r#"{ "from": "yesterday" }"#).unwrap();
+        let result = take_json(phemy_query_history(bad_date.as_ptr(), 10, 0));
+        assert!(!result["error"].is_null(), "{}", result);
+
+        let all = take_json(phemy_query_history(std::ptr::null(), 10, 0));
+        assert_eq!(all, serde_json::json!([]));
+    }
+
+    #[test]
+    fn save_settings_ex_lists_every_invalid_field() {
+        let _env = test_support::env();
+        let mut invalid = serde_json::to_value(settings::Settings::default()).unwrap();
+        invalid["paste_delay_ms"] = 600_000.into();
+        invalid["whisper_model"] = "huge".into();
+        invalid["hotkey"] = "".into();
+        let json = CString::new(invalid.to_string()).unwrap();
+
+        let result = take_json(phemy_save_settings_ex(json.as_ptr()));
+        assert!(result.get("ok").is_none(), "{}", result);
+        let fields: Vec<_> = result["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, ["paste_delay_ms", "whisper_model", "hotkey"]);
+        assert!(!phemy_save_settings(json.as_ptr()));
+
+        let valid = serde_json::to_string(&settings::Settings::default()).unwrap();
+        let valid = CString::new(valid).unwrap();
+        assert_eq!(take_json(phemy_save_settings_ex(valid.as_ptr()))["ok"], true);
+        assert!(phemy_save_settings(valid.as_ptr()));
+    }
+
+    #[test]
+    fn update_settings_saves_the_patch_and_returns_everything() {
+        let _env = test_support::env();
+        let patch = CString::new(r#"{ "prompt_mode": "formal", "paste_delay_ms": 300 }"#).unwrap();
+        let updated = take_json(phemy_update_settings(patch.as_ptr()));
+        assert_eq!(updated["prompt_mode"], "formal");
+        assert_eq!(updated["hotkey"], settings::Settings::default().hotkey.as_str());
+
+        let saved = settings::Settings::load();
+        assert_eq!(saved.prompt_mode, settings::PromptMode::Formal);
+        assert_eq!(saved.paste_delay_ms, 300);
+
+        // Values that fail validation are rejected and nothing is saved
+        let patch = r#"{ "prompt_mode": "casual", "paste_delay_ms": 600000 }"#;
+        let patch = CString::new(patch).unwrap();
+        let result = take_json(phemy_update_settings(patch.as_ptr()));
+        assert_eq!(result["errors"][0]["field"], "paste_delay_ms", "{}", result);
+        assert_eq!(settings::Settings::load().prompt_mode, settings::PromptMode::Formal);
+    }
+
+    #[test]
+    fn exported_settings_import_on_another_machine() {
+        let env = test_support::env();
+        let mut configured = settings::Settings {
+            prompt_mode: settings::PromptMode::Formal,
+            hotkey: "Alt+F9".to_string(),
+            vocabulary: vec!["Kubernetes".to_string()],
+            ..Default::default()
+        };
+        configured.redaction.emails = true;
+        let json = CString::new(serde_json::to_string(&configured).unwrap()).unwrap();
+        assert!(phemy_save_settings(json.as_ptr()));
+        secrets::set(secrets::Secret::LlmApiKey, "sk-not-exported").unwrap();
+
+        let file = env.path().join("exported.json");
+        let path = CString::new(file.to_str().unwrap()).unwrap();
+        assert!(phemy_export_settings(path.as_ptr()));
+        let exported = std::fs::read_to_string(&file).unwrap();
+        assert!(exported.contains("\n  \"prompt_mode\": \"formal\""), "{}", exported);
+        assert!(!exported.contains("sk-not-exported"));
+        let exported: serde_json::Value = serde_json::from_str(&exported).unwrap();
+        assert_eq!(exported["schema_version"], settings::SETTINGS_VERSION);
+
+        // A fresh machine
+        let defaults = CString::new(serde_json::to_string(&settings::Settings::default()).unwrap());
+        assert!(phemy_save_settings(defaults.unwrap().as_ptr()));
+        assert!(db::list_vocabulary().unwrap().is_empty());
+
+        let imported = take_json(phemy_import_settings(path.as_ptr()));
+        assert_eq!(imported["prompt_mode"], "formal", "{}", imported);
+        let loaded = settings::Settings::load();
+        assert_eq!(loaded.hotkey, "Alt+F9");
+        assert!(loaded.redaction.emails);
+        assert_eq!(db::list_vocabulary().unwrap(), ["Kubernetes".to_string()]);
+    }
+
+    #[test]
+    fn imported_files_are_migrated() {
+        let env = test_support::env();
+        let file = env.path().join("old.json");
+        std::fs::write(&file, include_str!("../fixtures/settings/v0.json")).unwrap();
+        let path = CString::new(file.to_str().unwrap()).unwrap();
+
+        let imported = take_json(phemy_import_settings(path.as_ptr()));
+        assert_eq!(imported["paste_delay_ms"], settings::MAX_PASTE_DELAY_MS, "{}", imported);
+        assert_eq!(imported["llm"]["model"], "qwen3-4b-instruct-q4km");
+        assert_eq!(settings::Settings::load().hotkey, "Ctrl+Space");
+    }
+
+    #[test]
+    fn rejected_imports_change_nothing() {
+        let env = test_support::env();
+        let file = env.path().join("bad.json");
+        let path = CString::new(file.to_str().unwrap()).unwrap();
+
+        // Current-version files aren't clamped, so this fails validation
+        let bad = serde_json::json!({
+            "schema_version": settings::SETTINGS_VERSION,
+            "prompt_mode": "casual",
+            "paste_delay_ms": 600_000,
+            "whisper_model": "../../etc",
+        });
+        std::fs::write(&file, bad.to_string()).unwrap();
+        let result = take_json(phemy_import_settings(path.as_ptr()));
+        let fields: Vec<_> = result["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, ["paste_delay_ms", "whisper_model"]);
+
+        std::fs::write(&file, "{ not json").unwrap();
+        let result = take_json(phemy_import_settings(path.as_ptr()));
+        assert_eq!(result["errors"][0]["field"], "settings", "{}", result);
+
+        let missing = CString::new(env.path().join("missing.json").to_str().unwrap()).unwrap();
+        let result = take_json(phemy_import_settings(missing.as_ptr()));
+        assert_eq!(result["errors"][0]["field"], "settings", "{}", result);
+
+        assert_eq!(settings::Settings::load().prompt_mode, settings::PromptMode::default());
+    }
+
+    #[test]
+    fn get_setting_returns_one_value() {
+        let _env = test_support::env();
+        let patch = CString::new(r#"{ "llm": { "max_tokens": 300 }, "prompt_mode": "code" }"#);
+        take_json(phemy_update_settings(patch.unwrap().as_ptr()));
+
+        let get = |key: &str| phemy_get_setting(CString::new(key).unwrap().as_ptr());
+        assert_eq!(take_json(get("llm.max_tokens")), 300);
+        assert_eq!(take_json(get("prompt-mode")), "code");
+
+        let unknown = get("llm.volume");
+        #[cfg(not(feature = "legacy-errors"))]
+        assert_eq!(error_code_of(unknown), "not_found");
+        #[cfg(feature = "legacy-errors")]
+        assert!(unknown.is_null());
+    }
+
+    /// Threads calling read-only exports in a loop while others run the
+    /// pipeline (with mocked whisper and LLM). Every thread has to finish
+    /// once told to stop; one that doesn't is stuck on a lock.
+    #[test]
+    #[cfg(feature = "stress-test")]
+    fn read_only_exports_keep_answering_during_pipelines() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::mpsc;
+
+        const PIPELINES: usize = 2;
+        const READERS: usize = 8;
+        const RUN_FOR: Duration = Duration::from_secs(3);
+        const DEADLOCK_AFTER: Duration = Duration::from_secs(20);
+
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "hello world".to_string(),
+            completion: "Hello, world.".to_string(),
+            delay: Duration::from_millis(20),
+        });
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let processed = Arc::new(AtomicUsize::new(0));
+        let (done_tx, done_rx) = mpsc::channel::<String>();
+
+        for i in 0..PIPELINES {
+            let (stop, processed, done_tx) = (stop.clone(), processed.clone(), done_tx.clone());
+            std::thread::spawn(move || {
+                let samples = test_support::speech(16_000);
+                while !stop.load(Ordering::Relaxed) {
+                    let result = take_json(phemy_process_samples(
+                        samples.as_ptr(),
+                        samples.len(),
+                        16_000,
+                        std::ptr::null(),
+                    ));
+                    assert_eq!(result["optimized_prompt"], "Hello, world.", "{}", result);
+                    processed.fetch_add(1, Ordering::Relaxed);
+                }
+                done_tx.send(format!("pipeline {}", i)).unwrap();
+            });
+        }
+
+        let query = CString::new("hello").unwrap();
+        for i in 0..READERS {
+            let (stop, done_tx, query) = (stop.clone(), done_tx.clone(), query.clone());
+            std::thread::spawn(move || {
+                let mut n = 0u64;
+                while !stop.load(Ordering::Relaxed) {
+                    let ptr = match (i as u64 + n) % 9 {
+                        0 => phemy_get_settings(),
+                        1 => phemy_get_download_progress(),
+                        2 => phemy_get_llm_download_progress(),
+                        3 => phemy_get_history(20, 0),
+                        4 => phemy_search_history(query.as_ptr(), 20, 0),
+                        5 => phemy_get_stats(7),
+                        6 => phemy_get_events(20, 0),
+                        7 => phemy_poll_job(n),
+                        _ => {
+                            phemy_get_recording_state();
+                            std::ptr::null_mut()
+                        }
+                    };
+                    if !ptr.is_null() {
+                        phemy_free_string(ptr);
+                    }
+                    n += 1;
+                }
+                done_tx.send(format!("reader {}", i)).unwrap();
+            });
+        }
+        drop(done_tx);
+
+        std::thread::sleep(RUN_FOR);
+        stop.store(true, Ordering::Relaxed);
+        let deadline = Instant::now() + DEADLOCK_AFTER;
+        let mut finished = Vec::new();
+        while finished.len() < PIPELINES + READERS {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match done_rx.recv_timeout(left) {
+                Ok(name) => finished.push(name),
+                Err(e) => {
+                    panic!("only {:?} finished ({:?}); the rest look deadlocked", finished, e)
+                }
+            }
         }
+        assert!(processed.load(Ordering::Relaxed) > 0, "no pipeline finished");
     }
 }
@@ -1,7 +1,8 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::AtomicBool;
 
-use crate::settings::Settings;
+use crate::settings::{LlmProvider, PromptMode, Settings, DEFAULT_LLM_MODEL};
 use super::{local, llm_model_manager};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,35 +11,128 @@ pub struct ChatMessage {
     pub content: String,
 }
 
+/// Output of a chat completion along with the model that produced it.
+#[derive(Debug, Clone)]
+pub struct ChatCompletion {
+    pub content: String,
+    pub model: String,
+    /// Time spent loading the model for this request
+    pub load_ms: u64,
+    pub usage: TokenUsage,
+}
+
+/// Tokens a completion read and generated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TokenUsage {
+    /// Tokens in the prompt, including the chat template
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
 /// Send a chat completion request using the local LLM.
+/// `mode` selects a per-mode model override, if one is configured.
+/// Setting `cancel` stops the request. `on_token` gets the number of tokens
+/// generated so far as they come.
 pub async fn chat_completion(
     system_prompt: &str,
     user_message: &str,
     settings: &Settings,
-) -> Result<String> {
-    local_completion(system_prompt, user_message, settings)
+    mode: &PromptMode,
+    cancel: &AtomicBool,
+    on_token: &(dyn Fn(usize) + Sync),
+) -> Result<ChatCompletion> {
+    #[cfg(any(test, feature = "mock-audio"))]
+    if let Some(llm) = super::mock::installed() {
+        return llm
+            .chat_completion(system_prompt, user_message, settings, mode, cancel, on_token)
+            .await;
+    }
+
+    match settings.llm.provider {
+        LlmProvider::Local => local_completion(system_prompt, user_message, settings, mode, cancel, on_token),
+    }
+}
+
+/// Name of the model configured for `mode`: the entry in
+/// `mode_model_overrides` if present, otherwise `llm.model`.
+pub fn model_name<'a>(settings: &'a Settings, mode: &PromptMode) -> &'a str {
+    mode_override(settings, mode)
+        .or(settings.llm.model.as_deref())
+        .unwrap_or(DEFAULT_LLM_MODEL)
+}
+
+fn mode_override<'a>(settings: &'a Settings, mode: &PromptMode) -> Option<&'a str> {
+    let mode_id = format!("{:?}", mode).to_lowercase();
+    settings.mode_model_overrides.get(&mode_id).map(|s| s.as_str())
+}
+
+/// Pick the model to run and make sure it is loaded.
+///
+/// Swapping local models costs seconds, so a non-strict per-mode override
+/// does not evict a different model that is already loaded — the loaded
+/// model is used for that call instead. With `strict_mode_model_overrides`
+/// the override model is always loaded, even if that means swapping.
+/// `llm.model` itself always wins over whatever is loaded.
+fn ensure_model_loaded(settings: &Settings, mode: &PromptMode) -> Result<String> {
+    let wanted = model_name(settings, mode);
+    let wanted_path = llm_model_manager::get_model_path(wanted)?;
+
+    if let Some(loaded_path) = local::loaded_path() {
+        if loaded_path == wanted_path {
+            return Ok(wanted.to_string());
+        }
+        if mode_override(settings, mode).is_some() && !settings.strict_mode_model_overrides {
+            if let Some(loaded) = llm_model_manager::name_for_path(&loaded_path) {
+                log::debug!("Using already-loaded model '{}' instead of '{}'", loaded, wanted);
+                return Ok(loaded.to_string());
+            }
+        }
+        local::try_unload(local::MODEL_WAIT)?;
+    }
+
+    if !wanted_path.exists() {
+        anyhow::bail!(
+            "Local LLM model '{}' not downloaded. Download it from Settings > LLM.",
+            wanted
+        );
+    }
+    local::load_model(&wanted_path, &settings.llm)?;
+    Ok(wanted.to_string())
+}
+
+/// Load the model that `mode` would use, so the first dictation doesn't
+/// pay the load time.
+pub fn warm_up(settings: &Settings, mode: &PromptMode) -> Result<()> {
+    match settings.llm.provider {
+        LlmProvider::Local => {
+            let model = ensure_model_loaded(settings, mode)?;
+            local::set_idle_unload(settings.llm.idle_unload_secs);
+            log::info!("Warmed up local LLM '{}'", model);
+            Ok(())
+        }
+    }
 }
 
 fn local_completion(
     system_prompt: &str,
     user_message: &str,
     settings: &Settings,
-) -> Result<String> {
-    // Load model on first call if not already loaded
-    if !local::is_loaded() {
-        let model_name = settings
-            .local_llm_model
-            .as_deref()
-            .unwrap_or("qwen3-4b-instruct-q4km");
-        let model_path = llm_model_manager::get_model_path(model_name)?;
-        if !model_path.exists() {
-            anyhow::bail!(
-                "Local LLM model '{}' not downloaded. Download it from Settings > LLM.",
-                model_name
-            );
-        }
-        local::load_model(&model_path)?;
-    }
-
-    local::optimize(user_message, system_prompt)
+    mode: &PromptMode,
+    cancel: &AtomicBool,
+    on_token: &(dyn Fn(usize) + Sync),
+) -> Result<ChatCompletion> {
+    let loading = std::time::Instant::now();
+    let model = ensure_model_loaded(settings, mode)?;
+    let load_ms = loading.elapsed().as_millis() as u64;
+    local::set_idle_unload(settings.llm.idle_unload_secs);
+    let (content, usage) =
+        local::optimize(user_message, system_prompt, &settings.llm, cancel, on_token)?;
+    Ok(ChatCompletion { content, model, load_ms, usage })
 }
@@ -0,0 +1,196 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Opening phrases that indicate the model added commentary before the prompt
+const PREAMBLE_PREFIXES: &[&str] = &[
+    "here is",
+    "here's",
+    "sure",
+    "certainly",
+    "okay",
+    "ok,",
+    "the optimized prompt",
+    "optimized prompt:",
+];
+
+/// One test transcript and the properties its optimized output must have.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvalCase {
+    pub id: String,
+    pub transcript: String,
+    #[serde(default)]
+    pub must_contain: Vec<String>,
+    #[serde(default)]
+    pub must_not_contain: Vec<String>,
+    #[serde(default)]
+    pub must_match: Vec<String>,
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    #[serde(default)]
+    pub no_preamble: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub check: String,
+    pub passed: bool,
+}
+
+/// Parse a JSONL file of eval cases. Blank lines are ignored.
+pub fn parse_cases(jsonl: &str) -> anyhow::Result<Vec<EvalCase>> {
+    jsonl
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow::anyhow!("Invalid eval case on line {}: {}", i + 1, e))
+        })
+        .collect()
+}
+
+/// Run every check a case declares against an optimized output.
+/// Text comparisons are case-insensitive.
+pub fn run_checks(case: &EvalCase, output: &str) -> Vec<CheckResult> {
+    let lower = output.to_lowercase();
+    let mut results = Vec::new();
+
+    for term in &case.must_contain {
+        results.push(CheckResult {
+            check: format!("contains {:?}", term),
+            passed: lower.contains(&term.to_lowercase()),
+        });
+    }
+
+    for term in &case.must_not_contain {
+        results.push(CheckResult {
+            check: format!("does not contain {:?}", term),
+            passed: !lower.contains(&term.to_lowercase()),
+        });
+    }
+
+    for pattern in &case.must_match {
+        let passed = match Regex::new(pattern) {
+            Ok(re) => re.is_match(output),
+            Err(e) => {
+                log::warn!("Invalid regex {:?} in eval case '{}': {}", pattern, case.id, e);
+                false
+            }
+        };
+        results.push(CheckResult {
+            check: format!("matches /{}/", pattern),
+            passed,
+        });
+    }
+
+    if let Some(max) = case.max_length {
+        results.push(CheckResult {
+            check: format!("at most {} chars", max),
+            passed: output.chars().count() <= max,
+        });
+    }
+
+    if case.no_preamble {
+        results.push(CheckResult {
+            check: "no preamble".to_string(),
+            passed: !has_preamble(output),
+        });
+    }
+
+    results
+}
+
+/// Whether the output opens with chatty commentary instead of the prompt itself.
+pub fn has_preamble(output: &str) -> bool {
+    let start = output.trim_start().to_lowercase();
+    PREAMBLE_PREFIXES.iter().any(|p| start.starts_with(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn case(json: &str) -> EvalCase {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn failed(case: &EvalCase, output: &str) -> Vec<String> {
+        run_checks(case, output)
+            .into_iter()
+            .filter(|result| !result.passed)
+            .map(|result| result.check)
+            .collect()
+    }
+
+    #[test]
+    fn parses_cases_skipping_blank_lines() {
+        let jsonl = r#"{"id": "a", "transcript": "x"}
+
+{"id": "b", "transcript": "y", "max_length": 5}
+"#;
+        let cases = parse_cases(jsonl).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[1].max_length, Some(5));
+        assert!(cases[0].must_contain.is_empty() && !cases[0].no_preamble);
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let jsonl = "{\"id\": \"a\", \"transcript\": \"x\"}\n\n{\"id\": \"b\"}";
+        let err = parse_cases(jsonl).unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+    }
+
+    #[test]
+    fn shipped_fixture_parses() {
+        let cases = parse_cases(include_str!("../../fixtures/eval.jsonl")).unwrap();
+        assert!(!cases.is_empty());
+        for case in &cases {
+            for pattern in &case.must_match {
+                assert!(Regex::new(pattern).is_ok(), "{}: {}", case.id, pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn contains_checks_ignore_case() {
+        let case = case(
+            r#"{"id": "c", "transcript": "", "must_contain": ["Rust"], "must_not_contain": ["UM"]}"#,
+        );
+        assert!(failed(&case, "write it in rust").is_empty());
+        assert_eq!(failed(&case, "um, in Go"), ["contains \"Rust\"", "does not contain \"UM\""]);
+    }
+
+    #[test]
+    fn regex_checks_match_the_output() {
+        let case =
+            case(r#"{"id": "r", "transcript": "", "must_match": ["(?i)column 2|second column"]}"#);
+        assert!(failed(&case, "Average the Second Column").is_empty());
+        assert_eq!(failed(&case, "average it"), ["matches /(?i)column 2|second column/"]);
+
+        let invalid = EvalCase {
+            must_match: vec!["(".to_string()],
+            ..case
+        };
+        assert_eq!(failed(&invalid, "("), ["matches /(/"]);
+    }
+
+    #[test]
+    fn max_length_counts_characters() {
+        let case = case(r#"{"id": "l", "transcript": "", "max_length": 3}"#);
+        assert!(failed(&case, "äöü").is_empty());
+        assert_eq!(failed(&case, "abcd"), ["at most 3 chars"]);
+    }
+
+    #[test]
+    fn preamble_is_detected_at_the_start_only() {
+        assert!(has_preamble("Here is the prompt: do X"));
+        assert!(has_preamble("  Sure! Write a function"));
+        assert!(has_preamble("OK, write a function"));
+        assert!(!has_preamble("Write a function. Here is why."));
+        assert!(!has_preamble(""));
+
+        let case = case(r#"{"id": "p", "transcript": "", "no_preamble": true}"#);
+        assert_eq!(failed(&case, "Certainly. Do X"), ["no preamble"]);
+    }
+}
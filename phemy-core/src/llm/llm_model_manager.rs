@@ -1,10 +1,10 @@
 use anyhow::Result;
 use serde::Serialize;
-use sha2::{Digest, Sha256};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
 #[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct LlmModelInfo {
     pub name: String,
     pub size_mb: u64,
@@ -84,6 +84,20 @@ pub fn get_model_path(name: &str) -> Result<PathBuf> {
     Ok(models_dir.join(filename))
 }
 
+/// Look up the catalog name of a model from its file path.
+pub fn name_for_path(path: &Path) -> Option<&'static str> {
+    let filename = path.file_name()?.to_str()?;
+    MODELS
+        .iter()
+        .find(|(_, f, _, _, _, _)| *f == filename)
+        .map(|(n, _, _, _, _, _)| *n)
+}
+
+/// Whether `name` is a model in the catalog (downloaded or not)
+pub fn is_known_model(name: &str) -> bool {
+    MODELS.iter().any(|(n, ..)| *n == name)
+}
+
 pub fn list_models() -> Result<Vec<LlmModelInfo>> {
     let models_dir = llm_models_dir()?;
 
@@ -102,6 +116,26 @@ pub fn list_models() -> Result<Vec<LlmModelInfo>> {
 }
 
 pub async fn download_model(name: &str) -> Result<()> {
+    download_model_with_progress(name, |_| {}).await
+}
+
+/// Like `download_model`, also passing each progress update (the one
+/// `get_download_progress` returns) to `on_progress`
+pub async fn download_model_with_progress(
+    name: &str,
+    on_progress: impl FnMut(&LlmDownloadProgress),
+) -> Result<()> {
+    let op = crate::ops::start(crate::ops::OpKind::Download, Some(name));
+    download_model_as(name, op, on_progress).await
+}
+
+/// `download_model_with_progress` run as the already registered `op`, for
+/// callers that hand out its id before the download starts
+pub async fn download_model_as(
+    name: &str,
+    op: crate::ops::Op,
+    mut on_progress: impl FnMut(&LlmDownloadProgress),
+) -> Result<()> {
     let (_, filename, _, _, url, expected_sha256) = MODELS
         .iter()
         .find(|(n, _, _, _, _, _)| *n == name)
@@ -111,79 +145,56 @@ pub async fn download_model(name: &str) -> Result<()> {
 
     log::info!("Downloading LLM model '{}' from {}", name, url);
 
-    let client = reqwest::Client::new();
-    let response = client.get(*url).send().await?;
-
-    if !response.status().is_success() {
-        anyhow::bail!("Failed to download LLM model: HTTP {}", response.status());
-    }
-
-    let total_bytes = response.content_length().unwrap_or(0);
-    let mut downloaded_bytes: u64 = 0;
-    let mut hasher = Sha256::new();
-
-    let mut file = tokio::fs::File::create(&dest).await?;
-    let mut stream = response.bytes_stream();
-
-    use futures_util::StreamExt;
-    use tokio::io::AsyncWriteExt;
-
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        file.write_all(&chunk).await?;
-        hasher.update(&chunk);
-        downloaded_bytes += chunk.len() as u64;
-
-        let progress = if total_bytes > 0 {
-            downloaded_bytes as f64 / total_bytes as f64
-        } else {
-            0.0
-        };
-
-        if let Ok(mut p) = DOWNLOAD_PROGRESS.lock() {
-            *p = Some(LlmDownloadProgress {
+    let result = crate::download::download_verified(
+        name,
+        url,
+        &dest,
+        expected_sha256,
+        op.cancel_flag(),
+        |downloaded_bytes, total_bytes, progress| {
+            let p = LlmDownloadProgress {
                 model: name.to_string(),
                 downloaded_bytes,
                 total_bytes,
                 progress,
-            });
-        }
-    }
-
-    file.flush().await?;
+            };
+            if let Ok(mut current) = DOWNLOAD_PROGRESS.lock() {
+                *current = Some(p.clone());
+            }
+            op.set_progress(progress);
+            on_progress(&p);
+        },
+    )
+    .await;
 
-    // Clear progress
+    // Clear progress, however the download ended
     if let Ok(mut p) = DOWNLOAD_PROGRESS.lock() {
         *p = None;
     }
-
-    // Verify SHA256 checksum
-    let actual_sha256 = format!("{:x}", hasher.finalize());
-    if actual_sha256 != *expected_sha256 {
-        // Remove the corrupted file
-        let _ = tokio::fs::remove_file(&dest).await;
-        anyhow::bail!(
-            "SHA256 mismatch for model '{}': expected {}, got {}",
-            name,
-            expected_sha256,
-            actual_sha256
-        );
-    }
+    result?;
 
     log::info!("LLM model '{}' downloaded and verified (SHA256 OK) at {:?}", name, dest);
     Ok(())
 }
 
+/// Stop any LLM model download before its next chunk; it then fails with a
+/// cancelled error and its partial file is removed. Returns false if none
+/// was running.
+pub fn cancel_download() -> bool {
+    crate::ops::cancel_named(crate::ops::OpKind::Download, is_known_model)
+}
+
 pub fn get_download_progress() -> Option<LlmDownloadProgress> {
     DOWNLOAD_PROGRESS.lock().ok()?.clone()
 }
 
-/// Delete a downloaded LLM model by name. Unloads first if currently loaded.
+/// Delete a downloaded LLM model by name. Unloads first if currently loaded,
+/// failing with `Busy` if it stays in use (see `local::try_unload`).
 pub fn delete_model(name: &str) -> Result<()> {
     let path = get_model_path(name)?;
     // Unload the model if it's currently loaded
     if super::local::is_loaded() {
-        super::local::unload();
+        super::local::try_unload(super::local::MODEL_WAIT)?;
     }
     match std::fs::remove_file(&path) {
         Ok(_) => {
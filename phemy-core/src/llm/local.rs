@@ -8,40 +8,160 @@ use llama_cpp_2::{
 };
 
 use anyhow::Result;
+#[cfg(feature = "llm-local")]
 use std::num::NonZeroU32;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "llm-local")]
 use std::sync::Mutex;
+#[cfg(feature = "llm-local")]
+use std::time::{Duration, Instant};
+
+use super::client::TokenUsage;
+use crate::settings::LlmSettings;
 
 #[cfg(feature = "llm-local")]
 struct LoadedModel {
     backend: LlamaBackend,
     model: LlamaModel,
+    last_used: Instant,
 }
 
 #[cfg(feature = "llm-local")]
 // SAFETY: LlamaBackend and LlamaModel are internally synchronized by llama.cpp.
 // We only access them through the LOADED_MODEL mutex which ensures single-threaded access.
 unsafe impl Send for LoadedModel {}
+#[cfg(feature = "llm-local")]
 unsafe impl Sync for LoadedModel {}
 
+/// Held for the whole of a generation, so only take it through `lock_model`
 #[cfg(feature = "llm-local")]
 static LOADED_MODEL: std::sync::LazyLock<Mutex<Option<LoadedModel>>> =
     std::sync::LazyLock::new(|| Mutex::new(None));
 
-/// Load a GGUF model from disk with Metal GPU acceleration.
+/// Path of the model in LOADED_MODEL, kept apart so `is_loaded` and
+/// `loaded_path` don't wait for a generation. Only changed while holding
+/// LOADED_MODEL.
 #[cfg(feature = "llm-local")]
-pub fn load_model(path: &Path) -> Result<()> {
-    log::info!("Loading local LLM from {:?}", path);
+static LOADED_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Seconds without use before the loaded model is dropped. 0 = never.
+static IDLE_UNLOAD_SECS: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the idle watcher thread is running
+#[cfg(feature = "llm-local")]
+static WATCHER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// How often the idle watcher checks the loaded model
+#[cfg(feature = "llm-local")]
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long a call waits for another call's generation before failing busy
+pub const MODEL_WAIT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How often a waiting call checks whether the model is free
+#[cfg(feature = "llm-local")]
+const MODEL_WAIT_POLL: Duration = Duration::from_millis(50);
+
+/// Set the idle-unload timeout, starting the watcher thread if needed.
+/// With 0 the watcher exits at its next check.
+pub fn set_idle_unload(secs: u64) {
+    IDLE_UNLOAD_SECS.store(secs, Ordering::Relaxed);
+    #[cfg(feature = "llm-local")]
+    if secs > 0 && !WATCHER_RUNNING.swap(true, Ordering::SeqCst) {
+        std::thread::spawn(idle_watcher);
+    }
+}
+
+#[cfg(feature = "llm-local")]
+fn idle_watcher() {
+    loop {
+        std::thread::sleep(IDLE_CHECK_INTERVAL);
+        let secs = IDLE_UNLOAD_SECS.load(Ordering::Relaxed);
+        if secs == 0 {
+            WATCHER_RUNNING.store(false, Ordering::SeqCst);
+            // Turned back on after we decided to stop: keep watching,
+            // unless set_idle_unload already started a new watcher
+            if IDLE_UNLOAD_SECS.load(Ordering::Relaxed) == 0
+                || WATCHER_RUNNING.swap(true, Ordering::SeqCst)
+            {
+                return;
+            }
+            continue;
+        }
+        // Holding the lock means no generation is running; one that is
+        // isn't idle
+        if let Ok(mut loaded) = lock_model(Duration::ZERO, None) {
+            if loaded
+                .as_ref()
+                .is_some_and(|l| l.last_used.elapsed() >= Duration::from_secs(secs))
+            {
+                set_loaded(&mut loaded, None);
+                log::info!("Local LLM model unloaded after {}s idle", secs);
+            }
+        }
+    }
+}
+
+/// Take the model lock, waiting up to `wait` for a generation in progress.
+/// Fails with `Busy` after that, or with `Cancelled` once `cancel` is set.
+#[cfg(feature = "llm-local")]
+fn lock_model(
+    wait: Duration,
+    cancel: Option<&AtomicBool>,
+) -> Result<std::sync::MutexGuard<'static, Option<LoadedModel>>> {
+    let deadline = Instant::now() + wait;
+    loop {
+        match LOADED_MODEL.try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(std::sync::TryLockError::Poisoned(e)) => return Ok(e.into_inner()),
+            Err(std::sync::TryLockError::WouldBlock) => {}
+        }
+        if cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+            return Err(anyhow::Error::new(crate::ops::Cancelled).context("Local LLM generation cancelled"));
+        }
+        if Instant::now() >= deadline {
+            return Err(crate::utils::Busy("the local LLM is generating for another call".into()).into());
+        }
+        std::thread::sleep(MODEL_WAIT_POLL);
+    }
+}
+
+/// Replace the loaded model, keeping LOADED_PATH in step
+#[cfg(feature = "llm-local")]
+fn set_loaded(loaded: &mut Option<LoadedModel>, model: Option<(LoadedModel, PathBuf)>) {
+    let path = model.as_ref().map(|(_, path)| path.clone());
+    *loaded = model.map(|(model, _)| model);
+    *LOADED_PATH.lock().unwrap_or_else(|e| e.into_inner()) = path;
+}
 
+/// Load a GGUF model from disk, offloading `llm.gpu_layers` layers to the GPU.
+/// Loads run one at a time under the model lock (waiting as `lock_model`
+/// does), so two calls never read the weights at once; a call that finds
+/// `path` already loaded by the other returns straight away.
+#[cfg(feature = "llm-local")]
+pub fn load_model(path: &Path, llm: &LlmSettings) -> Result<()> {
     if !path.exists() {
         anyhow::bail!("Model file not found: {:?}", path);
     }
 
+    let mut loaded = lock_model(MODEL_WAIT, None)?;
+    if loaded.is_some() {
+        if loaded_path().as_deref() == Some(path) {
+            return Ok(());
+        }
+        // Another call loaded a different model while we waited. Drop it
+        // first: llama.cpp allows one backend at a time.
+        set_loaded(&mut loaded, None);
+    }
+
+    log::info!("Loading local LLM from {:?}", path);
+
     let backend = LlamaBackend::init()
         .map_err(|e| anyhow::anyhow!("Failed to init llama backend: {}", e))?;
 
     let model_params = LlamaModelParams::default()
-        .with_n_gpu_layers(1000); // Offload all layers to Metal GPU
+        .with_n_gpu_layers(llm.gpu_layers);
 
     let model = LlamaModel::load_from_file(&backend, path, &model_params)
         .map_err(|e| anyhow::anyhow!("Failed to load model: {}", e))?;
@@ -52,23 +172,39 @@ pub fn load_model(path: &Path) -> Result<()> {
         model.size() / (1024 * 1024)
     );
 
-    if let Ok(mut loaded) = LOADED_MODEL.lock() {
-        *loaded = Some(LoadedModel { backend, model });
-    }
+    let model = LoadedModel {
+        backend,
+        model,
+        last_used: Instant::now(),
+    };
+    set_loaded(&mut loaded, Some((model, path.to_path_buf())));
 
     Ok(())
 }
 
 /// Run prompt optimization using the loaded local model.
+/// Generations run one at a time: this waits up to `MODEL_WAIT` for one in
+/// progress, then fails with `Busy`. Setting `cancel` stops this call, while
+/// waiting or before its next token. Once it has the model the generation
+/// is also a `crate::ops` operation, which cancelling stops the same way.
+/// `on_token` gets the number of tokens generated so far after each one.
+/// Returns the output and the tokens it took.
 #[cfg(feature = "llm-local")]
-pub fn optimize(transcript: &str, system_prompt: &str) -> Result<String> {
-    let guard = LOADED_MODEL
-        .lock()
-        .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+pub fn optimize(
+    transcript: &str,
+    system_prompt: &str,
+    llm: &LlmSettings,
+    cancel: &AtomicBool,
+    on_token: &(dyn Fn(usize) + Sync),
+) -> Result<(String, TokenUsage)> {
+    let mut guard = lock_model(MODEL_WAIT, Some(cancel))?;
+    let op = crate::ops::start(crate::ops::OpKind::Generation, None);
 
     let loaded = guard
-        .as_ref()
+        .as_mut()
         .ok_or_else(|| anyhow::anyhow!("No local LLM model loaded"))?;
+    loaded.last_used = Instant::now();
+    let started = Instant::now();
 
     // Build chat messages
     let messages = vec![
@@ -94,9 +230,14 @@ pub fn optimize(transcript: &str, system_prompt: &str) -> Result<String> {
         .map_err(|e| anyhow::anyhow!("Failed to apply chat template: {}", e))?;
 
     // Create context
-    let ctx_params = LlamaContextParams::default()
-        .with_n_ctx(Some(NonZeroU32::new(2048).unwrap()))
-        .with_n_batch(512);
+    let mut ctx_params = LlamaContextParams::default()
+        .with_n_ctx(NonZeroU32::new(llm.n_ctx))
+        .with_n_batch(llm.n_batch);
+    if llm.threads > 0 {
+        ctx_params = ctx_params
+            .with_n_threads(llm.threads as i32)
+            .with_n_threads_batch(llm.threads as i32);
+    }
 
     let mut ctx = loaded
         .model
@@ -109,8 +250,17 @@ pub fn optimize(transcript: &str, system_prompt: &str) -> Result<String> {
         .str_to_token(&prompt, AddBos::Always)
         .map_err(|e| anyhow::anyhow!("Failed to tokenize: {}", e))?;
 
+    let n_ctx = llm.n_ctx as usize;
+    if tokens.len() >= n_ctx {
+        anyhow::bail!(
+            "Prompt is {} tokens, which doesn't fit the {}-token context (llm.n_ctx)",
+            tokens.len(),
+            n_ctx
+        );
+    }
+
     // Create batch and add prompt tokens
-    let mut batch = LlamaBatch::new(2048, 1);
+    let mut batch = LlamaBatch::new(n_ctx, 1);
     for (i, token) in tokens.iter().enumerate() {
         let is_last = i == tokens.len() - 1;
         batch
@@ -122,22 +272,36 @@ pub fn optimize(transcript: &str, system_prompt: &str) -> Result<String> {
     ctx.decode(&mut batch)
         .map_err(|e| anyhow::anyhow!("Failed to decode prompt: {}", e))?;
 
-    // Sample with temp=0.3 for focused but not fully deterministic output
     let mut sampler = LlamaSampler::chain_simple([
-        LlamaSampler::top_k(40),
-        LlamaSampler::top_p(0.95, 1),
-        LlamaSampler::temp(0.3),
-        LlamaSampler::dist(42),
+        LlamaSampler::top_k(llm.top_k),
+        LlamaSampler::top_p(llm.top_p, 1),
+        LlamaSampler::temp(llm.temperature),
+        LlamaSampler::dist(llm.seed),
     ]);
 
     let mut output = String::new();
-    let max_tokens = 1024;
+    // Never generate past the end of the context window
+    let max_tokens = (llm.max_tokens as usize).min(n_ctx - tokens.len());
+    let timeout = (llm.timeout_secs > 0).then(|| Duration::from_secs(llm.timeout_secs));
     let mut decoder = encoding_rs::UTF_8.new_decoder();
     let mut n_cur = tokens.len() as i32;
+    let mut usage = TokenUsage {
+        prompt_tokens: tokens.len() as u64,
+        completion_tokens: 0,
+    };
+
+    for generated in 0..max_tokens {
+        if timeout.is_some_and(|t| started.elapsed() >= t) {
+            anyhow::bail!("Local LLM timed out after {}s", llm.timeout_secs);
+        }
+        if cancel.load(Ordering::Relaxed) || op.is_cancelled() {
+            return Err(anyhow::Error::new(crate::ops::Cancelled).context("Local LLM generation cancelled"));
+        }
+        op.set_progress(generated as f64 / max_tokens as f64);
 
-    for _ in 0..max_tokens {
         let new_token = sampler.sample(&ctx, batch.n_tokens() - 1);
         sampler.accept(new_token);
+        usage.completion_tokens += 1;
 
         if loaded.model.is_eog_token(new_token) {
             break;
@@ -149,6 +313,7 @@ pub fn optimize(transcript: &str, system_prompt: &str) -> Result<String> {
             .map_err(|e| anyhow::anyhow!("Failed to convert token: {}", e))?;
 
         output.push_str(&token_str);
+        on_token(generated + 1);
 
         batch.clear();
         batch
@@ -171,43 +336,77 @@ pub fn optimize(transcript: &str, system_prompt: &str) -> Result<String> {
         result
     };
 
-    Ok(result.to_string())
+    Ok((result.to_string(), usage))
 }
 
-/// Unload the model to free memory.
+/// Unload the model to free memory, waiting for a generation in progress
+/// however long it takes. For shutdown, after cancelling operations; use
+/// `try_unload` elsewhere.
 #[cfg(feature = "llm-local")]
 pub fn unload() {
-    if let Ok(mut loaded) = LOADED_MODEL.lock() {
-        *loaded = None;
+    let mut loaded = LOADED_MODEL.lock().unwrap_or_else(|e| e.into_inner());
+    if loaded.is_some() {
+        set_loaded(&mut loaded, None);
         log::info!("Local LLM model unloaded");
     }
 }
 
-/// Check if a model is currently loaded.
+/// Unload the model, waiting up to `wait` for a generation in progress
+/// (see `lock_model`).
+#[cfg(feature = "llm-local")]
+pub fn try_unload(wait: Duration) -> Result<()> {
+    let mut loaded = lock_model(wait, None)?;
+    if loaded.is_some() {
+        set_loaded(&mut loaded, None);
+        log::info!("Local LLM model unloaded");
+    }
+    Ok(())
+}
+
+/// Check if a model is currently loaded. Doesn't wait for a generation.
 #[cfg(feature = "llm-local")]
 pub fn is_loaded() -> bool {
-    LOADED_MODEL
-        .lock()
-        .map(|l| l.is_some())
-        .unwrap_or(false)
+    loaded_path().is_some()
+}
+
+/// Path of the currently loaded model, if any. Doesn't wait for a generation.
+#[cfg(feature = "llm-local")]
+pub fn loaded_path() -> Option<PathBuf> {
+    LOADED_PATH.lock().unwrap_or_else(|e| e.into_inner()).clone()
 }
 
 // Stub implementations when llm-local feature is disabled
 
 #[cfg(not(feature = "llm-local"))]
-pub fn load_model(_path: &Path) -> Result<()> {
+pub fn load_model(_path: &Path, _llm: &LlmSettings) -> Result<()> {
     anyhow::bail!("Local LLM support not compiled (enable 'llm-local' feature)")
 }
 
 #[cfg(not(feature = "llm-local"))]
-pub fn optimize(_transcript: &str, _system_prompt: &str) -> Result<String> {
+pub fn optimize(
+    _transcript: &str,
+    _system_prompt: &str,
+    _llm: &LlmSettings,
+    _cancel: &AtomicBool,
+    _on_token: &(dyn Fn(usize) + Sync),
+) -> Result<(String, TokenUsage)> {
     anyhow::bail!("Local LLM support not compiled (enable 'llm-local' feature)")
 }
 
 #[cfg(not(feature = "llm-local"))]
 pub fn unload() {}
 
+#[cfg(not(feature = "llm-local"))]
+pub fn try_unload(_wait: std::time::Duration) -> Result<()> {
+    Ok(())
+}
+
 #[cfg(not(feature = "llm-local"))]
 pub fn is_loaded() -> bool {
     false
 }
+
+#[cfg(not(feature = "llm-local"))]
+pub fn loaded_path() -> Option<PathBuf> {
+    None
+}
@@ -0,0 +1,85 @@
+//! A stand-in for the local LLM, for running the pipeline without a model
+//! (the `mock-audio` feature, and unit tests). While `install`ed, every
+//! completion returns its text after its delay, a token per word.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use super::client::{model_name, ChatCompletion, TokenUsage};
+use crate::settings::{PromptMode, Settings};
+
+#[derive(Debug, Clone)]
+pub struct MockLlm {
+    pub completion: String,
+    /// How long each completion takes
+    pub delay: Duration,
+    /// Mode whose completions fail, to exercise the fallback to earlier output
+    pub fail_mode: Option<PromptMode>,
+}
+
+static INSTALLED: Mutex<Option<MockLlm>> = Mutex::new(None);
+
+fn slot() -> std::sync::MutexGuard<'static, Option<MockLlm>> {
+    INSTALLED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Use `llm` instead of the local model until `uninstall`
+pub fn install(llm: MockLlm) {
+    *slot() = Some(llm);
+}
+
+/// Go back to the local model
+pub fn uninstall() {
+    *slot() = None;
+}
+
+pub fn is_installed() -> bool {
+    slot().is_some()
+}
+
+/// The installed mock, if any
+pub(super) fn installed() -> Option<MockLlm> {
+    slot().clone()
+}
+
+impl MockLlm {
+    /// A completion, running as a generation operation that stops with
+    /// `Cancelled` like a real one
+    pub(super) async fn chat_completion(
+        self,
+        system_prompt: &str,
+        user_message: &str,
+        settings: &Settings,
+        mode: &PromptMode,
+        cancel: &AtomicBool,
+        on_token: &(dyn Fn(usize) + Sync),
+    ) -> Result<ChatCompletion> {
+        let model = model_name(settings, mode);
+        let op = crate::ops::start(crate::ops::OpKind::Generation, Some(model));
+        tokio::time::sleep(self.delay).await;
+        if cancel.load(Ordering::Relaxed) {
+            return Err(crate::ops::Cancelled.into());
+        }
+        op.check_cancelled()?;
+        if self.fail_mode.as_ref() == Some(mode) {
+            anyhow::bail!("Mock completion failed");
+        }
+        for (count, _) in self.completion.split_whitespace().enumerate() {
+            on_token(count + 1);
+        }
+        let usage = TokenUsage {
+            prompt_tokens: (system_prompt.split_whitespace().count()
+                + user_message.split_whitespace().count()) as u64,
+            completion_tokens: self.completion.split_whitespace().count() as u64,
+        };
+        Ok(ChatCompletion {
+            content: self.completion,
+            model: model.to_string(),
+            load_ms: 0,
+            usage,
+        })
+    }
+}
@@ -1,5 +1,8 @@
 pub mod client;
+pub mod eval;
 pub mod llm_model_manager;
 pub mod local;
+#[cfg(any(test, feature = "mock-audio"))]
+pub mod mock;
 pub mod prompt_optimizer;
 pub mod prompt_templates;
@@ -1,7 +1,9 @@
 use anyhow::Result;
 use serde::Serialize;
+use std::sync::atomic::AtomicBool;
 
-use crate::settings::{PromptMode, Settings};
+use crate::settings::{OptimizationLength, PromptMode, Settings};
+use super::client::TokenUsage;
 use super::{client, prompt_templates};
 
 
@@ -11,59 +13,245 @@ pub struct OptimizationResult {
     pub optimized_prompt: String,
     pub mode: String,
     pub provider: Option<String>,
+    pub length: OptimizationLength,
+    /// Model that produced the output, if the LLM was called
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Total time spent in LLM calls
+    pub elapsed_ms: u64,
+    /// Part of `elapsed_ms` spent loading models
+    pub model_load_ms: u64,
+    /// Number of LLM calls made
+    pub attempts: u32,
+    /// Tokens used by the LLM calls that completed
+    pub usage: TokenUsage,
+    /// Per-stage outputs when a mode chain is configured
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stages: Vec<OptimizationStage>,
+    /// The stage that failed, if one did; `optimized_prompt` is then the
+    /// output of the stage before it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<StageError>,
 }
 
-/// Optimize a raw transcript into a polished prompt
-pub async fn optimize(transcript: &str, settings: &Settings) -> Result<OptimizationResult> {
+#[derive(Debug, Clone, Serialize)]
+pub struct OptimizationStage {
+    pub mode: String,
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageError {
+    pub mode: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for StageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} stage failed: {}", self.mode, self.message)
+    }
+}
+
+/// Optimize a raw transcript into a polished prompt.
+///
+/// If `settings.prompt_mode_chain` is non-empty its modes run in order, each
+/// stage receiving the previous stage's output. Otherwise `settings.prompt_mode`
+/// runs as a single stage. `app` is the application the prompt is for, if
+/// the host knows it; custom prompts see it as `{{app}}`.
+pub async fn optimize(transcript: &str, settings: &Settings, app: Option<&str>) -> Result<OptimizationResult> {
+    optimize_cancellable(transcript, settings, app, &AtomicBool::new(false)).await
+}
+
+/// Like `optimize`, stopping the LLM call in progress once `cancel` is set.
+/// The result then holds the output of the stages that finished.
+pub async fn optimize_cancellable(
+    transcript: &str,
+    settings: &Settings,
+    app: Option<&str>,
+    cancel: &AtomicBool,
+) -> Result<OptimizationResult> {
+    optimize_with_progress(transcript, settings, app, cancel, &|_, _| {}).await
+}
+
+/// Like `optimize_cancellable`, calling `on_token` with the stage's mode
+/// and the number of tokens it has generated so far as the LLM runs
+pub async fn optimize_with_progress(
+    transcript: &str,
+    settings: &Settings,
+    app: Option<&str>,
+    cancel: &AtomicBool,
+    on_token: &(dyn Fn(&str, usize) + Sync),
+) -> Result<OptimizationResult> {
     let transcript = transcript.trim();
 
+    let chain = if settings.prompt_mode_chain.is_empty() {
+        vec![settings.prompt_mode.clone()]
+    } else {
+        settings.prompt_mode_chain.clone()
+    };
+    let mode = chain.iter().map(mode_name).collect::<Vec<_>>().join("+");
+
     if transcript.is_empty() {
         return Ok(OptimizationResult {
             raw_transcript: String::new(),
             optimized_prompt: String::new(),
-            mode: format!("{:?}", settings.prompt_mode),
+            mode,
             provider: None,
+            length: settings.optimization_length.clone(),
+            model: None,
+            elapsed_ms: 0,
+            model_load_ms: 0,
+            attempts: 0,
+            usage: TokenUsage::default(),
+            stages: Vec::new(),
+            error: None,
         });
     }
 
-    // Raw mode bypasses LLM entirely
-    if settings.prompt_mode == PromptMode::Raw {
-        return Ok(OptimizationResult {
-            raw_transcript: transcript.to_string(),
-            optimized_prompt: transcript.to_string(),
-            mode: "raw".to_string(),
-            provider: None,
-        });
+    let record_stages = !settings.prompt_mode_chain.is_empty();
+    let mut current = transcript.to_string();
+    let mut provider = None;
+    let mut stages = Vec::new();
+    let mut model = None;
+    let mut elapsed_ms = 0u64;
+    let mut model_load_ms = 0u64;
+    let mut attempts = 0u32;
+    let mut usage = TokenUsage::default();
+    let mut error = None;
+
+    for stage_mode in &chain {
+        // Raw mode bypasses LLM entirely
+        if *stage_mode == PromptMode::Raw {
+            continue;
+        }
+
+        let system_prompt = system_prompt_for(stage_mode, settings, app);
+
+        // Call LLM
+        let started = std::time::Instant::now();
+        let stage_name = mode_name(stage_mode);
+        let completion = client::chat_completion(&system_prompt, &current, settings, stage_mode, cancel, &|tokens| {
+            on_token(&stage_name, tokens)
+        })
+        .await;
+        elapsed_ms += started.elapsed().as_millis() as u64;
+        attempts += 1;
+
+        match completion {
+            Ok(result) => {
+                model_load_ms += result.load_ms;
+                usage += result.usage;
+                current = result.content.trim().to_string();
+                model = Some(result.model);
+                provider = Some("local".to_string());
+                if record_stages {
+                    stages.push(OptimizationStage {
+                        mode: mode_name(stage_mode),
+                        output: current.clone(),
+                    });
+                }
+            }
+            Err(e) => {
+                model = Some(client::model_name(settings, stage_mode).to_string());
+                // Keep the best output so far (the raw transcript if this was the first stage)
+                log::warn!(
+                    "LLM optimization failed at stage '{}', using previous output: {}",
+                    mode_name(stage_mode),
+                    e
+                );
+                provider = Some(format!("local (failed: {})", e));
+                error = Some(StageError {
+                    mode: mode_name(stage_mode),
+                    message: e.to_string(),
+                });
+                break;
+            }
+        }
     }
 
-    // Get system prompt (built-in or custom)
-    let system_prompt = if settings.prompt_mode == PromptMode::Custom {
-        settings
+    Ok(OptimizationResult {
+        raw_transcript: transcript.to_string(),
+        optimized_prompt: current,
+        mode,
+        provider,
+        length: settings.optimization_length.clone(),
+        model,
+        elapsed_ms,
+        model_load_ms,
+        attempts,
+        usage,
+        stages,
+        error,
+    })
+}
+
+/// Lowercase identifier for a mode, as used in results and history.
+fn mode_name(mode: &PromptMode) -> String {
+    format!("{:?}", mode).to_lowercase()
+}
+
+/// Build the full system prompt for one stage.
+fn system_prompt_for(mode: &PromptMode, settings: &Settings, app: Option<&str>) -> String {
+    // Get system prompt (built-in or custom, with template variables filled in)
+    let system_prompt = if *mode == PromptMode::Custom {
+        let custom = settings
             .custom_system_prompt
             .as_deref()
-            .unwrap_or("Clean up this voice transcript into a clear prompt. Output only the result.")
+            .unwrap_or("Clean up this voice transcript into a clear prompt. Output only the result.");
+        let vars = prompt_templates::TemplateVariables::from_settings(settings, app);
+        prompt_templates::substitute_variables(custom, &vars)
     } else {
-        prompt_templates::get_system_prompt(&settings.prompt_mode)
+        prompt_templates::get_system_prompt(mode).to_string()
     };
 
-    // Call LLM
-    let optimized = match client::chat_completion(system_prompt, transcript, settings).await {
-        Ok(result) => result.trim().to_string(),
-        Err(e) => {
-            log::warn!("LLM optimization failed, using raw transcript: {}", e);
-            return Ok(OptimizationResult {
-                raw_transcript: transcript.to_string(),
-                optimized_prompt: transcript.to_string(),
-                mode: format!("{:?}", settings.prompt_mode),
-                provider: Some(format!("local (failed: {})", e)),
-            });
-        }
-    };
+    // Append length instruction (Balanced leaves the prompt untouched)
+    match prompt_templates::get_length_instruction(&settings.optimization_length) {
+        Some(instruction) => format!("{}\n\n{}", system_prompt, instruction),
+        None => system_prompt,
+    }
+}
 
-    Ok(OptimizationResult {
-        raw_transcript: transcript.to_string(),
-        optimized_prompt: optimized,
-        mode: format!("{:?}", settings.prompt_mode).to_lowercase(),
-        provider: Some("local".to_string()),
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_prompt_sees_target_app() {
+        let settings = Settings {
+            custom_system_prompt: Some("Write for {{app}}.".to_string()),
+            ..Settings::default()
+        };
+        let prompt = system_prompt_for(&PromptMode::Custom, &settings, Some("Slack"));
+        assert!(prompt.starts_with("Write for Slack."), "{}", prompt);
+
+        let prompt = system_prompt_for(&PromptMode::Custom, &settings, None);
+        assert!(prompt.starts_with("Write for ."), "{}", prompt);
+    }
+
+    #[test]
+    fn usage_adds_up_the_chain_stages() {
+        let _env = crate::test_support::env();
+        crate::test_support::set_mocks(crate::test_support::Mocks {
+            transcript: String::new(),
+            completion: "two words".to_string(),
+            delay: std::time::Duration::ZERO,
+        });
+        let settings = Settings {
+            prompt_mode_chain: vec![PromptMode::Clean, PromptMode::Code],
+            ..Settings::default()
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(optimize("um hello there", &settings, None)).unwrap();
+        // The mock counts a token per word; each stage reads the previous output
+        let words = |mode| system_prompt_for(&mode, &settings, None).split_whitespace().count();
+        let prompt_tokens = words(PromptMode::Clean) + 3 + words(PromptMode::Code) + 2;
+        assert_eq!(
+            result.usage,
+            TokenUsage {
+                prompt_tokens: prompt_tokens as u64,
+                completion_tokens: 4,
+            }
+        );
+    }
 }
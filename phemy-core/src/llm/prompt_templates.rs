@@ -1,4 +1,6 @@
-use crate::settings::PromptMode;
+use serde::Serialize;
+
+use crate::settings::{OptimizationLength, PromptMode, Settings};
 
 /// Get the system prompt for a given prompt mode
 pub fn get_system_prompt(mode: &PromptMode) -> &'static str {
@@ -53,6 +55,17 @@ pub fn get_system_prompt(mode: &PromptMode) -> &'static str {
              - List specific requirements as bullet points if multiple are mentioned\n\
              - Output ONLY the optimized prompt, nothing else"
         }
+        PromptMode::Structured => {
+            "You are a prompt structuring assistant. Transform the voice transcript into a \
+             well-organized markdown document. \
+             Rules:\n\
+             - Remove all filler words\n\
+             - Start with a single `#` heading that names the task\n\
+             - Follow with a one or two sentence introduction\n\
+             - List requirements, constraints, and details as `-` bullet points\n\
+             - Use `##` subheadings only if the transcript covers clearly separate topics\n\
+             - Output ONLY the markdown, nothing else"
+        }
         PromptMode::Verbatim => {
             "You are a transcript cleaner. Minimally clean the voice transcript. \
              Rules:\n\
@@ -69,3 +82,247 @@ pub fn get_system_prompt(mode: &PromptMode) -> &'static str {
         }
     }
 }
+
+/// Get the extra instruction appended to the system prompt for a length preference.
+/// Balanced keeps the mode's prompt unchanged.
+pub fn get_length_instruction(length: &OptimizationLength) -> Option<&'static str> {
+    match length {
+        OptimizationLength::Concise => Some(
+            "Length:\n\
+             - Be as concise as possible; compress the request into one or two tight sentences\n\
+             - Drop repetition and tangents, but keep every concrete requirement",
+        ),
+        OptimizationLength::Balanced => None,
+        OptimizationLength::Detailed => Some(
+            "Length:\n\
+             - Expand terse notes into a detailed, complete prompt\n\
+             - Spell out implied context, requirements, and expected output\n\
+             - Do not invent facts the speaker did not mention",
+        ),
+    }
+}
+
+/// Variables available in custom system prompts as `{{name}}`.
+/// (name, description)
+pub const TEMPLATE_VARIABLES: &[(&str, &str)] = &[
+    ("date", "Today's date (YYYY-MM-DD)"),
+    ("time", "Current local time (HH:MM)"),
+    ("vocabulary", "Custom vocabulary words, comma-separated"),
+    ("app", "Name of the application the prompt will be pasted into, if known"),
+    ("language", "Transcription language code"),
+];
+
+/// Values substituted into `{{name}}` placeholders.
+#[derive(Debug, Clone)]
+pub struct TemplateVariables {
+    pub date: String,
+    pub time: String,
+    pub vocabulary: String,
+    pub app: Option<String>,
+    pub language: String,
+}
+
+impl TemplateVariables {
+    /// Build variables from the current settings and local clock.
+    pub fn from_settings(settings: &Settings, app: Option<&str>) -> Self {
+        let now = chrono::Local::now();
+        Self {
+            date: now.format("%Y-%m-%d").to_string(),
+            time: now.format("%H:%M").to_string(),
+            vocabulary: crate::db::list_vocabulary()
+                .unwrap_or_else(|_| settings.vocabulary.clone())
+                .join(", "),
+            app: app.map(|a| a.to_string()),
+            language: settings.language.clone(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&str> {
+        match name {
+            "date" => Some(&self.date),
+            "time" => Some(&self.time),
+            "vocabulary" => Some(&self.vocabulary),
+            "app" => Some(self.app.as_deref().unwrap_or("")),
+            "language" => Some(&self.language),
+            _ => None,
+        }
+    }
+}
+
+/// Replace `{{name}}` placeholders with their values.
+/// Unknown variables are left intact, and `\{{` produces a literal `{{`.
+pub fn substitute_variables(template: &str, vars: &TemplateVariables) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(pos) = rest.find("{{") {
+        // Escaped opening braces: drop the backslash, keep the braces literal
+        if rest[..pos].ends_with('\\') {
+            out.push_str(&rest[..pos - 1]);
+            out.push_str("{{");
+            rest = &rest[pos + 2..];
+            continue;
+        }
+
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 2..];
+        let value = after
+            .find("}}")
+            .and_then(|end| vars.lookup(after[..end].trim()).map(|v| (v, end)));
+
+        match value {
+            Some((v, end)) => {
+                out.push_str(v);
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptModeInfo {
+    pub mode: PromptMode,
+    pub system_prompt: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateVariableInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptModeCatalog {
+    pub modes: Vec<PromptModeInfo>,
+    pub template_variables: Vec<TemplateVariableInfo>,
+}
+
+/// List all prompt modes with their built-in system prompts and the
+/// variables available to custom prompts.
+pub fn mode_catalog() -> PromptModeCatalog {
+    let modes = [
+        PromptMode::Clean,
+        PromptMode::Technical,
+        PromptMode::Formal,
+        PromptMode::Casual,
+        PromptMode::Code,
+        PromptMode::Structured,
+        PromptMode::Verbatim,
+        PromptMode::Raw,
+        PromptMode::Custom,
+    ];
+
+    PromptModeCatalog {
+        modes: modes
+            .into_iter()
+            .map(|mode| PromptModeInfo {
+                system_prompt: get_system_prompt(&mode),
+                mode,
+            })
+            .collect(),
+        template_variables: TEMPLATE_VARIABLES
+            .iter()
+            .map(|(name, description)| TemplateVariableInfo { name, description })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> TemplateVariables {
+        TemplateVariables {
+            date: "2024-05-01".to_string(),
+            time: "09:30".to_string(),
+            vocabulary: "Phemy, Qwen".to_string(),
+            app: Some("Slack".to_string()),
+            language: "en".to_string(),
+        }
+    }
+
+    #[test]
+    fn substitutes_every_variable() {
+        let template = "{{date}} {{time}} [{{vocabulary}}] {{app}} {{language}}";
+        assert_eq!(
+            substitute_variables(template, &vars()),
+            "2024-05-01 09:30 [Phemy, Qwen] Slack en"
+        );
+        assert_eq!(substitute_variables("{{ app }}/{{app}}", &vars()), "Slack/Slack");
+    }
+
+    #[test]
+    fn unknown_and_unclosed_variables_are_left_intact() {
+        let vars = vars();
+        assert_eq!(substitute_variables("Hi {{name}}, {{date}}", &vars), "Hi {{name}}, 2024-05-01");
+        assert_eq!(substitute_variables("{{date", &vars), "{{date");
+        assert_eq!(substitute_variables("{{}} {{{{date}}", &vars), "{{}} {{2024-05-01");
+        assert_eq!(substitute_variables("no variables", &vars), "no variables");
+        assert_eq!(substitute_variables("", &vars), "");
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() {
+        let vars = vars();
+        assert_eq!(substitute_variables(r"\{{date}} is {{date}}", &vars), "{{date}} is 2024-05-01");
+        assert_eq!(substitute_variables(r"a\b \{{app}}", &vars), r"a\b {{app}}");
+    }
+
+    #[test]
+    fn values_are_not_substituted_again() {
+        let vars = TemplateVariables {
+            app: Some("{{date}}".to_string()),
+            ..vars()
+        };
+        assert_eq!(substitute_variables("{{app}}", &vars), "{{date}}");
+    }
+
+    #[test]
+    fn unknown_app_is_empty() {
+        let vars = TemplateVariables { app: None, ..vars() };
+        assert_eq!(substitute_variables("for {{app}}.", &vars), "for .");
+    }
+
+    #[test]
+    fn vocabulary_comes_from_the_database() {
+        let _env = crate::test_support::env();
+        crate::db::replace_vocabulary(&["Phemy".to_string(), "Qwen".to_string()]).unwrap();
+        let settings = Settings {
+            language: "de".to_string(),
+            ..Settings::default()
+        };
+        let vars = TemplateVariables::from_settings(&settings, Some("Mail"));
+        let filled = substitute_variables("{{vocabulary}}|{{app}}|{{language}}", &vars);
+        assert_eq!(filled, "Phemy, Qwen|Mail|de");
+    }
+
+    #[test]
+    fn catalog_documents_every_variable() {
+        let catalog = mode_catalog();
+        let names: Vec<_> = catalog.template_variables.iter().map(|v| v.name).collect();
+        assert_eq!(names, ["date", "time", "vocabulary", "app", "language"]);
+        for name in names {
+            assert!(vars().lookup(name).is_some(), "{} isn't substituted", name);
+        }
+    }
+
+    #[test]
+    fn structured_mode_asks_for_markdown() {
+        let catalog = mode_catalog();
+        let structured = catalog
+            .modes
+            .iter()
+            .find(|info| info.mode == PromptMode::Structured)
+            .expect("structured mode in the catalog");
+        assert!(structured.system_prompt.contains("markdown"));
+        assert!(structured.system_prompt.contains("`#` heading"));
+        assert!(structured.system_prompt.contains("`-` bullet"));
+    }
+}
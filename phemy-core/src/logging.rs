@@ -0,0 +1,155 @@
+//! Global logger: forwards records to a host callback when one is set
+//! (phemy_set_log_callback), otherwise writes them to stderr via env_logger.
+
+use std::ffi::{c_char, CString};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Once, RwLock};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Called with the level (1 = error … 5 = trace), the target (usually the
+/// module path) and the message. Both strings are only valid during the
+/// call. May be called from any thread, including while phemy holds
+/// internal locks, so it must not call phemy functions. Null means none.
+pub type LogCallback = Option<extern "C" fn(level: i32, target: *const c_char, message: *const c_char)>;
+
+/// Held for reading while the callback runs, so clearing it waits for
+/// calls in progress
+static CALLBACK: RwLock<LogCallback> = RwLock::new(None);
+
+/// Most verbose level passed on, as a `LevelFilter` index
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Error as usize);
+
+struct HostLogger {
+    fallback: env_logger::Logger,
+}
+
+impl Log for HostLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.level() > max_level() {
+            return false;
+        }
+        match *CALLBACK.read().unwrap_or_else(|e| e.into_inner()) {
+            Some(_) => true,
+            None => self.fallback.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() > max_level() {
+            return;
+        }
+        let callback = CALLBACK.read().unwrap_or_else(|e| e.into_inner());
+        let Some(callback) = *callback else {
+            self.fallback.log(record);
+            return;
+        };
+        let target = to_c_string(record.target());
+        let message = to_c_string(&record.args().to_string());
+        callback(record.level() as i32, target.as_ptr(), message.as_ptr());
+    }
+
+    fn flush(&self) {
+        self.fallback.flush();
+    }
+}
+
+/// CString of `s` with any NUL bytes dropped
+fn to_c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+fn max_level() -> LevelFilter {
+    LevelFilter::iter()
+        .nth(MAX_LEVEL.load(Ordering::Relaxed))
+        .unwrap_or(LevelFilter::Trace)
+}
+
+/// Level filter for a level number from the host: 0 = off, 1 = error …
+/// 5 = trace. Out-of-range values are clamped.
+pub fn level_filter(level: i32) -> LevelFilter {
+    LevelFilter::iter()
+        .nth(level.clamp(0, LevelFilter::Trace as i32) as usize)
+        .unwrap_or(LevelFilter::Trace)
+}
+
+/// Make this the global logger. Safe to call more than once. The level
+/// starts at RUST_LOG's if set (whose per-module filters then also apply to
+/// stderr), otherwise at error.
+pub fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let from_env = std::env::var_os(env_logger::DEFAULT_FILTER_ENV).is_some();
+        let mut builder = env_logger::Builder::from_default_env();
+        if !from_env {
+            // Leave filtering to `set_level`
+            builder.filter_level(LevelFilter::Trace);
+        }
+        let fallback = builder.build();
+        let level = if from_env { fallback.filter() } else { LevelFilter::Error };
+        match log::set_boxed_logger(Box::new(HostLogger { fallback })) {
+            Ok(()) => set_level(level),
+            // The embedding app installed its own logger
+            Err(e) => eprintln!("phemy-core: not installing logger: {}", e),
+        }
+    });
+}
+
+/// Most verbose level logged, for the callback and stderr alike
+pub fn set_level(level: LevelFilter) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+    log::set_max_level(level);
+}
+
+/// Send log records to `callback` up to `level`, or back to stderr with
+/// None. Returns once no call to the previous callback is in progress.
+pub fn set_callback(callback: LogCallback, level: LevelFilter) {
+    install();
+    *CALLBACK.write().unwrap_or_else(|e| e.into_inner()) = callback;
+    set_level(level);
+}
+
+/// Drop the callback (see `set_callback`), keeping the current level
+pub fn clear_callback() {
+    *CALLBACK.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::ffi::CStr;
+    use std::sync::Mutex;
+
+    const TARGET: &str = "phemy-logging-test";
+
+    static RECEIVED: Mutex<Vec<(i32, String)>> = Mutex::new(Vec::new());
+
+    extern "C" fn collect(level: i32, target: *const c_char, message: *const c_char) {
+        // Copied here: neither string outlives the call
+        let target = unsafe { CStr::from_ptr(target) }.to_string_lossy();
+        if target == TARGET {
+            let message = unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned();
+            RECEIVED.lock().unwrap().push((level, message));
+        }
+    }
+
+    fn received() -> Vec<(i32, String)> {
+        std::mem::take(&mut *RECEIVED.lock().unwrap())
+    }
+
+    #[test]
+    fn callback_gets_records_up_to_its_level_until_cleared() {
+        let _lock = test_support::lock();
+
+        set_callback(Some(collect), level_filter(2));
+        log::error!(target: TARGET, "failed {}", 1);
+        log::warn!(target: TARGET, "with\0nul");
+        log::info!(target: TARGET, "too verbose");
+        assert_eq!(received(), [(1, "failed 1".to_string()), (2, "withnul".to_string())]);
+
+        set_callback(None, level_filter(1));
+        log::error!(target: TARGET, "to stderr");
+        assert!(received().is_empty());
+    }
+}
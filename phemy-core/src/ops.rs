@@ -0,0 +1,221 @@
+//! Registry of long-running operations: model downloads, transcriptions,
+//! local LLM generations, TypeOut pastes and pipeline jobs. Each registers
+//! while it runs with a cancel flag it checks as it goes, so hosts can list
+//! them (phemy_list_operations) and cancel any of them by id (phemy_cancel).
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpKind {
+    Download,
+    Transcription,
+    Generation,
+    Paste,
+    Job,
+}
+
+/// What `list` reports about an operation
+#[derive(Debug, Clone, Serialize)]
+pub struct OpInfo {
+    pub id: u64,
+    pub kind: OpKind,
+    /// Model being downloaded or used, where there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Rough fraction done, from 0 to 1
+    pub progress: f64,
+    /// Cancel was requested; the operation stops at its next check
+    pub cancelled: bool,
+    pub elapsed_ms: u64,
+}
+
+struct Entry {
+    kind: OpKind,
+    name: Option<String>,
+    progress: f64,
+    cancel: Arc<AtomicBool>,
+    started: Instant,
+}
+
+static OPS: std::sync::LazyLock<Mutex<HashMap<u64, Entry>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Ids start at 1 so hosts can use 0 for "no operation"
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Error of an operation stopped by `cancel`
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Whether `e`, or anything it wraps, is a `Cancelled` error
+pub fn is_cancelled(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Cancelled>())
+}
+
+/// A registered operation, held by the code running it. Dropping it
+/// unregisters the operation.
+pub struct Op {
+    id: u64,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Op {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Flag set when the operation is cancelled, for work that checks it
+    /// as it goes
+    pub fn cancel_flag(&self) -> &AtomicBool {
+        &self.cancel
+    }
+
+    pub(crate) fn shared_cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    /// Fail with `Cancelled` if `cancel` was called
+    pub fn check_cancelled(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+
+    pub fn set_progress(&self, progress: f64) {
+        if let Some(entry) = OPS.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&self.id) {
+            entry.progress = progress.clamp(0.0, 1.0);
+        }
+    }
+}
+
+impl Drop for Op {
+    fn drop(&mut self) {
+        OPS.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+    }
+}
+
+/// Register an operation of `kind`, on `name` (a model) if given
+pub fn start(kind: OpKind, name: Option<&str>) -> Op {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let cancel = Arc::new(AtomicBool::new(false));
+    OPS.lock().unwrap_or_else(|e| e.into_inner()).insert(
+        id,
+        Entry {
+            kind,
+            name: name.map(str::to_string),
+            progress: 0.0,
+            cancel: cancel.clone(),
+            started: Instant::now(),
+        },
+    );
+    Op { id, cancel }
+}
+
+/// Ask an operation to stop at its next check. Returns false if it isn't
+/// running (unknown or finished); cancelling twice is harmless.
+pub fn cancel(id: u64) -> bool {
+    match OPS.lock().unwrap_or_else(|e| e.into_inner()).get(&id) {
+        Some(entry) => {
+            entry.cancel.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Cancel every running operation of `kind`
+pub fn cancel_kind(kind: OpKind) {
+    for entry in OPS.lock().unwrap_or_else(|e| e.into_inner()).values() {
+        if entry.kind == kind {
+            entry.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Cancel every running operation of `kind` whose name `matches`. Returns
+/// false if there was none.
+pub fn cancel_named(kind: OpKind, matches: impl Fn(&str) -> bool) -> bool {
+    let mut found = false;
+    for entry in OPS.lock().unwrap_or_else(|e| e.into_inner()).values() {
+        if entry.kind == kind && entry.name.as_deref().is_some_and(&matches) {
+            entry.cancel.store(true, Ordering::Relaxed);
+            found = true;
+        }
+    }
+    found
+}
+
+/// Cancel every running operation
+pub fn cancel_all() {
+    for entry in OPS.lock().unwrap_or_else(|e| e.into_inner()).values() {
+        entry.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Kind of a running operation, or None if it isn't running
+pub fn kind(id: u64) -> Option<OpKind> {
+    OPS.lock().unwrap_or_else(|e| e.into_inner()).get(&id).map(|entry| entry.kind)
+}
+
+/// Running operations, oldest first
+pub fn list() -> Vec<OpInfo> {
+    let ops = OPS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut list: Vec<OpInfo> = ops
+        .iter()
+        .map(|(&id, entry)| OpInfo {
+            id,
+            kind: entry.kind,
+            name: entry.name.clone(),
+            progress: entry.progress,
+            cancelled: entry.cancel.load(Ordering::Relaxed),
+            elapsed_ms: entry.started.elapsed().as_millis() as u64,
+        })
+        .collect();
+    list.sort_by_key(|op| op.id);
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_are_listed_until_dropped_and_cancel_once_or_twice() {
+        let op = start(OpKind::Download, Some("base.en"));
+        op.set_progress(0.25);
+        let listed = list().into_iter().find(|info| info.id == op.id()).unwrap();
+        assert_eq!(listed.kind, OpKind::Download);
+        assert_eq!(listed.name.as_deref(), Some("base.en"));
+        assert_eq!(listed.progress, 0.25);
+        assert!(!listed.cancelled);
+
+        assert!(cancel(op.id()));
+        assert!(cancel(op.id()), "cancelling twice is harmless");
+        assert!(op.is_cancelled());
+        assert!(is_cancelled(&op.check_cancelled().unwrap_err()));
+
+        let id = op.id();
+        drop(op);
+        assert!(kind(id).is_none());
+        assert!(!cancel(id));
+    }
+}
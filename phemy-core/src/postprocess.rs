@@ -0,0 +1,283 @@
+use regex::Regex;
+
+use crate::settings::{PasteAppend, PastePostprocess, RedactionSettings};
+
+static EMAIL_RE: std::sync::LazyLock<Regex> = std::sync::LazyLock::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}")
+        .expect("valid email regex")
+});
+
+/// 13–19 digits, optionally grouped with spaces or dashes (card numbers, account numbers)
+static DIGITS_RE: std::sync::LazyLock<Regex> = std::sync::LazyLock::new(|| {
+    Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").expect("valid digit sequence regex")
+});
+
+/// North American style numbers with an optional country code:
+/// 555-123-4567, (555) 123-4567, +1 555.123.4567
+static PHONE_RE: std::sync::LazyLock<Regex> = std::sync::LazyLock::new(|| {
+    Regex::new(r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{3}\)|\b\d{3})[ .-]?\d{3}[ .-]?\d{4}\b")
+        .expect("valid phone regex")
+});
+
+/// A line break inside text together with the spaces around it
+static LINE_BREAK_RE: std::sync::LazyLock<Regex> = std::sync::LazyLock::new(|| {
+    Regex::new(r"(?:[ \t]*(?:\r\n|\r|\n))+[ \t]*").expect("valid line break regex")
+});
+
+/// Replace sensitive spans in `text` with typed placeholders like `[EMAIL]`.
+/// Each detector runs only if enabled in `rules`.
+pub fn redact(text: &str, rules: &RedactionSettings) -> String {
+    let mut out = text.to_string();
+
+    if rules.emails {
+        out = EMAIL_RE.replace_all(&out, "[EMAIL]").into_owned();
+    }
+    // Long digit runs first so card numbers aren't partially matched as phone numbers
+    if rules.digit_sequences {
+        out = DIGITS_RE.replace_all(&out, "[NUMBER]").into_owned();
+    }
+    if rules.phone_numbers {
+        out = PHONE_RE.replace_all(&out, "[PHONE]").into_owned();
+    }
+
+    out
+}
+
+fn is_line_break(c: char) -> bool {
+    c == '\n' || c == '\r'
+}
+
+/// Adjust text for the paste target as configured in `rules`. `previous` is
+/// the text of the last paste, used to tell whether this one continues it.
+/// Also returns the names of the rules that changed the text, in the order
+/// they ran.
+pub fn prepare_for_paste(
+    text: &str,
+    rules: &PastePostprocess,
+    previous: Option<&str>,
+) -> (String, Vec<&'static str>) {
+    let mut out = text.to_string();
+    let mut applied = Vec::new();
+    if out.is_empty() {
+        return (out, applied);
+    }
+
+    if rules.collapse_internal_newlines {
+        let body = out.trim_matches(is_line_break);
+        let start = out.len() - out.trim_start_matches(is_line_break).len();
+        let collapsed = LINE_BREAK_RE.replace_all(body, " ");
+        if collapsed != body {
+            out = format!("{}{}{}", &out[..start], collapsed, &out[start + body.len()..]);
+            applied.push("collapse_internal_newlines");
+        }
+    }
+    if rules.strip_trailing_newline {
+        let len = out.trim_end_matches(is_line_break).len();
+        if len < out.len() {
+            out.truncate(len);
+            applied.push("strip_trailing_newline");
+        }
+    }
+
+    match rules.append {
+        PasteAppend::Space if !out.ends_with(char::is_whitespace) => {
+            out.push(' ');
+            applied.push("append");
+        }
+        PasteAppend::Newline if !out.ends_with('\n') => {
+            out.push('\n');
+            applied.push("append");
+        }
+        _ => {}
+    }
+
+    if rules.prepend_space_if_midword {
+        let before = previous.and_then(|p| p.chars().next_back());
+        let after = out.chars().next();
+        if let (Some(before), Some(after)) = (before, after) {
+            // No space before closing punctuation or between CJK characters,
+            // which are written without spaces
+            let no_space = before.is_whitespace()
+                || after.is_whitespace()
+                || matches!(after, '.' | ',' | ';' | ':' | '!' | '?' | ')' | ']' | '}' | '\u{2026}')
+                || crate::text::is_cjk(before)
+                || crate::text::is_cjk(after);
+            if !no_space {
+                out.insert(0, ' ');
+                applied.push("prepend_space_if_midword");
+            }
+        }
+    }
+
+    (out, applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: RedactionSettings = RedactionSettings {
+        emails: true,
+        phone_numbers: true,
+        digit_sequences: true,
+    };
+
+    #[test]
+    fn redacts_emails() {
+        assert_eq!(redact("mail jane.doe+work@mail.example.co.uk now", &ALL), "mail [EMAIL] now");
+        assert_eq!(redact("a@b.io, c_d@e-f.org", &ALL), "[EMAIL], [EMAIL]");
+    }
+
+    #[test]
+    fn redacts_phone_numbers() {
+        for phone in ["555-123-4567", "(555) 123-4567", "+1 555.123.4567", "5551234567"] {
+            let text = format!("call {} today", phone);
+            assert_eq!(redact(&text, &ALL), "call [PHONE] today", "{}", phone);
+        }
+    }
+
+    #[test]
+    fn redacts_card_numbers_whole() {
+        for card in ["4111 1111 1111 1111", "4111-1111-1111-1111", "4111111111111111"] {
+            assert_eq!(redact(&format!("card {}.", card), &ALL), "card [NUMBER].", "{}", card);
+        }
+    }
+
+    #[test]
+    fn each_detector_can_be_turned_off() {
+        let text = "x@y.com 555-123-4567 4111111111111111";
+        let only = |emails, phone_numbers, digit_sequences| {
+            redact(text, &RedactionSettings { emails, phone_numbers, digit_sequences })
+        };
+        assert_eq!(only(true, false, false), "[EMAIL] 555-123-4567 4111111111111111");
+        assert_eq!(only(false, true, false), "x@y.com [PHONE] 4111111111111111");
+        assert_eq!(only(false, false, true), "x@y.com 555-123-4567 [NUMBER]");
+        assert_eq!(only(false, false, false), text);
+    }
+
+    #[test]
+    fn leaves_versions_dates_and_short_numbers_alone() {
+        for text in [
+            "upgrade to 1.2.3 or v10.15.7",
+            "due 2024-05-01, or 12/31/2024 at 10:30",
+            "order 12345 of 678",
+            "host 192.168.1.1 port 8080",
+            "the year 2024 and 1999",
+            "at @here in #general",
+        ] {
+            assert_eq!(redact(text, &ALL), text);
+        }
+    }
+
+    fn rules(append: PasteAppend) -> PastePostprocess {
+        PastePostprocess {
+            append,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn appends_a_space_or_newline_unless_already_there() {
+        let cases = [
+            (PasteAppend::None, "hi", "hi"),
+            (PasteAppend::Space, "hi", "hi "),
+            (PasteAppend::Space, "hi ", "hi "),
+            (PasteAppend::Space, "hi\n", "hi\n"),
+            (PasteAppend::Newline, "hi", "hi\n"),
+            (PasteAppend::Newline, "hi ", "hi \n"),
+            (PasteAppend::Newline, "hi\n", "hi\n"),
+        ];
+        for (append, text, expected) in cases {
+            let (out, applied) = prepare_for_paste(text, &rules(append.clone()), None);
+            assert_eq!(out, expected, "{:?} {:?}", append, text);
+            assert_eq!(applied.contains(&"append"), text != expected, "{:?} {:?}", append, text);
+        }
+    }
+
+    #[test]
+    fn prepends_a_space_only_when_continuing_a_word() {
+        let midword = PastePostprocess {
+            prepend_space_if_midword: true,
+            ..Default::default()
+        };
+        let cases = [
+            (Some("hello"), "world", " world"),
+            (Some("Hello,"), "world", " world"),
+            (Some("hello "), "world", "world"),
+            (Some("hello\n"), "world", "world"),
+            (Some("hello"), " world", " world"),
+            (Some("hello"), ", world", ", world"),
+            (Some("hello"), "...", "..."),
+            (Some("caf\u{e9}"), "ol\u{e9}", " ol\u{e9}"),
+            (Some("\u{1f642}"), "ok", " ok"),
+            (Some("\u{65e5}\u{672c}"), "\u{8a9e}", "\u{8a9e}"),
+            (Some("word"), "\u{65e5}\u{672c}", "\u{65e5}\u{672c}"),
+            (Some(""), "world", "world"),
+            (None, "world", "world"),
+        ];
+        for (previous, text, expected) in cases {
+            let (out, applied) = prepare_for_paste(text, &midword, previous);
+            assert_eq!(out, expected, "{:?} then {:?}", previous, text);
+            assert_eq!(applied.is_empty(), text == expected, "{:?} then {:?}", previous, text);
+        }
+    }
+
+    #[test]
+    fn strips_trailing_line_breaks_of_any_style() {
+        let strip = PastePostprocess {
+            strip_trailing_newline: true,
+            ..Default::default()
+        };
+        assert_eq!(prepare_for_paste("hi\r\n", &strip, None).0, "hi");
+        assert_eq!(prepare_for_paste("hi\r\n\r\n\n", &strip, None).0, "hi");
+        assert_eq!(prepare_for_paste("hi\r", &strip, None).0, "hi");
+        let (out, applied) = prepare_for_paste("one\r\ntwo", &strip, None);
+        assert_eq!(out, "one\r\ntwo");
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn collapses_internal_line_breaks_into_spaces() {
+        let collapse = PastePostprocess {
+            collapse_internal_newlines: true,
+            ..Default::default()
+        };
+        let cases = [
+            ("one\ntwo", "one two"),
+            ("one  \r\n\r\n  two\rthree", "one two three"),
+            ("\none\ntwo\n", "\none two\n"),
+            ("one two", "one two"),
+        ];
+        for (text, expected) in cases {
+            let (out, applied) = prepare_for_paste(text, &collapse, None);
+            assert_eq!(out, expected, "{:?}", text);
+            assert_eq!(applied.is_empty(), text == expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn reports_the_rules_that_changed_the_text_in_order() {
+        let all = PastePostprocess {
+            append: PasteAppend::Space,
+            prepend_space_if_midword: true,
+            strip_trailing_newline: true,
+            collapse_internal_newlines: true,
+        };
+        let (out, applied) = prepare_for_paste("one\ntwo\n", &all, Some("zero"));
+        assert_eq!(out, " one two ");
+        assert_eq!(
+            applied,
+            [
+                "collapse_internal_newlines",
+                "strip_trailing_newline",
+                "append",
+                "prepend_space_if_midword"
+            ]
+        );
+
+        let (out, applied) = prepare_for_paste("done ", &all, Some("zero "));
+        assert_eq!(out, "done ");
+        assert!(applied.is_empty(), "{:?}", applied);
+        assert_eq!(prepare_for_paste("", &all, Some("zero")), (String::new(), Vec::new()));
+    }
+}
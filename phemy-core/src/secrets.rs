@@ -0,0 +1,145 @@
+//! API keys and tokens, kept in `secrets.json` next to (not inside) the
+//! settings file. The file is owner-only and shared by all profiles.
+//! Settings only ever see whether a secret is set.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Secret {
+    /// Key for a remote LLM endpoint
+    LlmApiKey,
+    /// Hugging Face token for gated model downloads
+    HfToken,
+}
+
+impl Secret {
+    pub const ALL: [Secret; 2] = [Secret::LlmApiKey, Secret::HfToken];
+
+    /// Name used over FFI and as the key in `secrets.json` and legacy settings files
+    pub fn name(self) -> &'static str {
+        match self {
+            Secret::LlmApiKey => "llm_api_key",
+            Secret::HfToken => "hf_token",
+        }
+    }
+
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|s| s.name() == name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown secret: {}", name))
+    }
+}
+
+/// Serializes read-modify-write of the secrets file
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn secrets_path() -> PathBuf {
+    crate::settings::get_data_dir()
+        .unwrap_or_else(|| {
+            dirs::data_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("phemy")
+        })
+        .join("secrets.json")
+}
+
+fn read_all() -> BTreeMap<Secret, String> {
+    let path = secrets_path();
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable secrets file {:?}: {}", path, e);
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+fn write_all(secrets: &BTreeMap<Secret, String>) -> anyhow::Result<()> {
+    let path = secrets_path();
+    if secrets.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Create owner-only before writing so the secret is never world-readable
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(&path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    serde_json::to_writer_pretty(file, secrets)?;
+    Ok(())
+}
+
+/// Value of a secret, for code that sends it. Never log or serialize it.
+pub fn get(secret: Secret) -> Option<String> {
+    let _guard = LOCK.lock().ok()?;
+    read_all().remove(&secret)
+}
+
+/// Whether a secret is set
+pub fn has(secret: Secret) -> bool {
+    get(secret).is_some()
+}
+
+/// Store a secret, replacing any previous value. Empty values clear it.
+pub fn set(secret: Secret, value: &str) -> anyhow::Result<()> {
+    let value = value.trim();
+    if value.is_empty() {
+        return clear(secret);
+    }
+    let _guard = LOCK.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+    let mut secrets = read_all();
+    secrets.insert(secret, value.to_string());
+    write_all(&secrets)?;
+    log::info!("Stored secret {}", secret.name());
+    Ok(())
+}
+
+/// Remove a secret
+pub fn clear(secret: Secret) -> anyhow::Result<()> {
+    let _guard = LOCK.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+    let mut secrets = read_all();
+    if secrets.remove(&secret).is_some() {
+        write_all(&secrets)?;
+        log::info!("Cleared secret {}", secret.name());
+    }
+    Ok(())
+}
+
+/// Remove secret keys from raw settings JSON (older files and frontends put
+/// them there) and move their values into the secret store.
+pub fn take_from_settings(map: &mut serde_json::Map<String, serde_json::Value>) {
+    for secret in Secret::ALL {
+        let Some(value) = map.remove(secret.name()) else {
+            continue;
+        };
+        match value.as_str().map(str::trim) {
+            Some(value) if !value.is_empty() => {
+                if let Err(e) = set(secret, value) {
+                    log::error!("Failed to move {} out of settings: {}", secret.name(), e);
+                }
+            }
+            _ => {}
+        }
+    }
+}
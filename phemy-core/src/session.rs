@@ -0,0 +1,136 @@
+//! Sessions of the staged pipeline (phemy_finish_recording and the
+//! phemy_session_* exports). A session holds a stopped recording and what
+//! each stage made of it, so the host can show the transcript before the
+//! LLM is done. Sessions left unused for `SESSION_TTL` are dropped.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::llm::prompt_optimizer::OptimizationResult;
+use crate::settings::Settings;
+use crate::{PipelineMetrics, PipelineOptions};
+
+/// How long a session is kept after its last stage
+const SESSION_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A recording on its way through the pipeline
+pub struct Session {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    /// Settings as they were when the recording finished, so every stage
+    /// uses the same ones
+    pub settings: Settings,
+    pub options: PipelineOptions,
+    pub transcript: Option<String>,
+    pub optimization: Option<OptimizationResult>,
+    pub metrics: PipelineMetrics,
+}
+
+/// Why `take` couldn't hand out a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unavailable {
+    /// Unknown, committed, discarded or expired
+    NotFound,
+    /// Another stage is running on it
+    InUse,
+}
+
+struct Entry {
+    /// None while a stage has it
+    session: Option<Session>,
+    touched: Instant,
+}
+
+static SESSIONS: std::sync::LazyLock<Mutex<HashMap<u64, Entry>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Ids start at 1 so hosts can use 0 for "no session"
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Keep `session` and return its id
+pub fn create(session: Session) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    expire(&mut sessions, SESSION_TTL);
+    sessions.insert(
+        id,
+        Entry {
+            session: Some(session),
+            touched: Instant::now(),
+        },
+    );
+    id
+}
+
+/// Take session `id` to run a stage on; hand it back with `put_back`
+pub fn take(id: u64) -> Result<Session, Unavailable> {
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    expire(&mut sessions, SESSION_TTL);
+    let entry = sessions.get_mut(&id).ok_or(Unavailable::NotFound)?;
+    entry.session.take().ok_or(Unavailable::InUse)
+}
+
+/// Return a session from `take`. It is dropped instead if it was discarded
+/// in the meantime.
+pub fn put_back(id: u64, session: Session) {
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(entry) = sessions.get_mut(&id) {
+        entry.session = Some(session);
+        entry.touched = Instant::now();
+    }
+}
+
+/// Drop session `id`, even if a stage has it (that stage's result is then
+/// thrown away). Returns false if it doesn't exist.
+pub fn discard(id: u64) -> bool {
+    SESSIONS.lock().unwrap_or_else(|e| e.into_inner()).remove(&id).is_some()
+}
+
+/// Drop every session
+pub fn clear() {
+    SESSIONS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Drop sessions untouched for `ttl`, except those a stage has
+fn expire(sessions: &mut HashMap<u64, Entry>, ttl: Duration) {
+    sessions.retain(|_, entry| entry.session.is_none() || entry.touched.elapsed() < ttl);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session {
+            samples: vec![0.0; 16],
+            sample_rate: 16_000,
+            settings: Settings::default(),
+            options: PipelineOptions::default(),
+            transcript: None,
+            optimization: None,
+            metrics: PipelineMetrics::default(),
+        }
+    }
+
+    #[test]
+    fn sessions_are_taken_one_stage_at_a_time_until_discarded_or_expired() {
+        let id = create(session());
+        let mut taken = take(id).unwrap();
+        assert_eq!(take(id).err(), Some(Unavailable::InUse));
+        taken.transcript = Some("hello".to_string());
+        put_back(id, taken);
+        assert_eq!(take(id).unwrap().transcript.as_deref(), Some("hello"));
+        assert!(discard(id), "taken sessions can be discarded");
+        assert_eq!(take(id).err(), Some(Unavailable::NotFound));
+        assert!(!discard(id));
+
+        // On a map of its own, so sessions of tests running alongside stay
+        let entry = |session| Entry { session, touched: Instant::now() };
+        let mut sessions = HashMap::from([(1, entry(Some(session()))), (2, entry(None))]);
+        expire(&mut sessions, Duration::ZERO);
+        assert!(!sessions.contains_key(&1));
+        assert!(sessions.contains_key(&2), "a session in use doesn't expire");
+    }
+}
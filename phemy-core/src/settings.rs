@@ -1,89 +1,373 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Mutex;
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum PromptMode {
+    #[default]
     Clean,
     Technical,
     Formal,
     Casual,
     Code,
+    Structured,
     Verbatim,
     Raw,
     Custom,
 }
 
-impl Default for PromptMode {
-    fn default() -> Self {
-        Self::Clean
-    }
+impl PromptMode {
+    pub const ALL: [Self; 9] = [Self::Clean, Self::Technical, Self::Formal, Self::Casual, Self::Code, Self::Structured, Self::Verbatim, Self::Raw, Self::Custom];
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OptimizationLength {
+    Concise,
+    #[default]
+    Balanced,
+    Detailed,
+}
+
+impl OptimizationLength {
+    pub const ALL: [Self; 3] = [Self::Concise, Self::Balanced, Self::Detailed];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum PasteMethod {
+    #[default]
     CtrlV,
     CtrlShiftV,
     ShiftInsert,
     TypeOut,
+    /// Linux only: put the text on the primary selection and middle-click
+    /// at the mouse pointer
+    MiddleClick,
+    /// Press a key combination in hotkey syntax, such as
+    /// "Cmd+Option+Shift+V" (paste and match style in Pages). Stored as
+    /// `{"custom-combo": "..."}`.
+    CustomCombo(String),
 }
 
-impl Default for PasteMethod {
-    fn default() -> Self {
-        Self::CtrlV
+impl PasteMethod {
+    pub const ALL: [Self; 5] = [
+        Self::CtrlV,
+        Self::CtrlShiftV,
+        Self::ShiftInsert,
+        Self::TypeOut,
+        Self::MiddleClick,
+    ];
+
+    /// Whether this method works on the current platform. Unsupported
+    /// methods fall back to Ctrl+V.
+    pub fn is_supported(&self) -> bool {
+        *self != Self::MiddleClick || cfg!(target_os = "linux")
+    }
+
+    /// The keys this method presses, or None for methods that don't paste
+    /// with a shortcut. Ctrl is Cmd on macOS. Fails if a custom combo
+    /// doesn't parse.
+    pub fn key_combo(&self) -> Result<Option<Hotkey>, String> {
+        use HotkeyModifier::{Ctrl, Shift, Super};
+
+        let primary = if cfg!(target_os = "macos") { Super } else { Ctrl };
+        Ok(Some(match self {
+            Self::CtrlV => Hotkey::new(&[primary], "V"),
+            Self::CtrlShiftV => Hotkey::new(&[primary, Shift], "V"),
+            Self::ShiftInsert => Hotkey::new(&[Shift], "Insert"),
+            Self::CustomCombo(combo) => parse_hotkey(combo)?,
+            Self::TypeOut | Self::MiddleClick => return Ok(None),
+        }))
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// Key pressed after pasting when `auto_submit` is on
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubmitKey {
+    #[default]
+    Enter,
+    /// For apps where Enter inserts a newline and Ctrl+Enter sends
+    CtrlEnter,
+    /// Cmd+Enter on macOS, Super+Enter elsewhere
+    CmdEnter,
+}
+
+impl SubmitKey {
+    pub const ALL: [Self; 3] = [Self::Enter, Self::CtrlEnter, Self::CmdEnter];
+
+    /// The keys pressed to submit
+    pub fn key_combo(&self) -> Hotkey {
+        let modifiers: &[HotkeyModifier] = match self {
+            Self::Enter => &[],
+            Self::CtrlEnter => &[HotkeyModifier::Ctrl],
+            Self::CmdEnter => &[HotkeyModifier::Super],
+        };
+        Hotkey::new(modifiers, "Enter")
+    }
+}
+
+/// What to do with the finished text
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostProcessAction {
+    /// Paste into the focused app (see `paste_method`)
+    #[default]
+    Paste,
+    /// Only put it on the clipboard
+    CopyOnly,
+    /// Leave it in the app and history
+    None,
+}
+
+impl PostProcessAction {
+    pub const ALL: [Self; 3] = [Self::Paste, Self::CopyOnly, Self::None];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum HotkeyMode {
+    #[default]
     Toggle,
     PushToTalk,
 }
 
-impl Default for HotkeyMode {
-    fn default() -> Self {
-        Self::Toggle
-    }
+impl HotkeyMode {
+    pub const ALL: [Self; 2] = [Self::Toggle, Self::PushToTalk];
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum Theme {
     Light,
+    #[default]
     Dark,
 }
 
-impl Default for Theme {
+impl Theme {
+    pub const ALL: [Self; 2] = [Self::Light, Self::Dark];
+}
+
+/// Which kinds of sensitive data to scrub from transcripts and prompts
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RedactionSettings {
+    pub emails: bool,
+    pub phone_numbers: bool,
+    /// Long digit runs such as card or account numbers
+    pub digit_sequences: bool,
+}
+
+impl RedactionSettings {
+    pub fn any_enabled(&self) -> bool {
+        self.emails || self.phone_numbers || self.digit_sequences
+    }
+}
+
+/// Text added after each paste
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PasteAppend {
+    #[default]
+    None,
+    Space,
+    Newline,
+}
+
+impl PasteAppend {
+    pub const ALL: [Self; 3] = [Self::None, Self::Space, Self::Newline];
+}
+
+/// Adjustments made to text just before it is pasted. History keeps the
+/// text as produced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PastePostprocess {
+    pub append: PasteAppend,
+    /// Add a space before the text when continuing the previous paste mid-sentence
+    pub prepend_space_if_midword: bool,
+    pub strip_trailing_newline: bool,
+    /// Join lines with single spaces, keeping leading and trailing line breaks
+    pub collapse_internal_newlines: bool,
+}
+
+/// Recording and voice-detection options. Defaults match the behavior from
+/// before these were configurable.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AudioSettings {
+    /// RMS level above which a 30ms frame counts as speech
+    pub vad_threshold: f32,
+    /// Stop capturing after this many seconds. 0 = unlimited.
+    pub max_duration_secs: u32,
+    /// Multiplier applied to input samples (clipped to [-1, 1])
+    pub input_gain: f32,
+    /// Ask the host to stop after this much silence following speech. 0 = off.
+    pub silence_auto_stop_ms: u32,
+    /// Audio kept before the first detected speech when trimming silence
+    pub preroll_ms: u32,
+    /// Scale the trimmed recording so its peak is at full level before transcribing
+    pub normalize: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            vad_threshold: crate::audio::vad::DEFAULT_ENERGY_THRESHOLD,
+            max_duration_secs: 0,
+            input_gain: 1.0,
+            silence_auto_stop_ms: 0,
+            preroll_ms: crate::audio::vad::DEFAULT_PREROLL_MS,
+            normalize: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LlmProvider {
+    /// llama.cpp running a downloaded GGUF model
+    #[default]
+    Local,
+}
+
+impl LlmProvider {
+    pub const ALL: [Self; 1] = [Self::Local];
+}
+
+/// Default model for prompt optimization
+pub const DEFAULT_LLM_MODEL: &str = "qwen3-4b-instruct-q4km";
+
+/// LLM runtime and sampling options. Defaults match the values that were
+/// hardcoded before these were configurable.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LlmSettings {
+    pub provider: LlmProvider,
+    /// Model used unless a per-mode override applies (was `local_llm_model`)
+    pub model: Option<String>,
+    pub temperature: f32,
+    pub top_k: i32,
+    pub top_p: f32,
+    pub seed: u32,
+    /// Context window in tokens; prompt and output must fit in it
+    pub n_ctx: u32,
+    pub n_batch: u32,
+    /// Layers offloaded to the GPU (values above the layer count offload everything)
+    pub gpu_layers: u32,
+    /// CPU threads for generation. 0 = llama.cpp default.
+    pub threads: u32,
+    pub max_tokens: u32,
+    /// Give up on a generation after this many seconds. 0 = no limit.
+    pub timeout_secs: u64,
+    /// Unload the model after this many seconds without use. 0 = keep loaded.
+    pub idle_unload_secs: u64,
+    /// Load the model in the background at startup
+    pub warmup: bool,
+}
+
+impl Default for LlmSettings {
     fn default() -> Self {
-        Self::Dark
+        Self {
+            provider: LlmProvider::default(),
+            model: Some(DEFAULT_LLM_MODEL.to_string()),
+            temperature: 0.3,
+            top_k: 40,
+            top_p: 0.95,
+            seed: 42,
+            n_ctx: 2048,
+            n_batch: 512,
+            gpu_layers: 1000,
+            threads: 0,
+            max_tokens: 1024,
+            timeout_secs: 0,
+            idle_unload_secs: 0,
+            warmup: false,
+        }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Settings {
+    /// Layout version of the settings file; older files are upgraded by `migrate`
+    pub schema_version: u32,
+
     // Audio
     pub input_device: Option<String>,
+    /// Keep a WAV of each recording alongside its history entry
+    pub save_recordings: bool,
+    pub audio: AudioSettings,
 
     // Transcription
     pub whisper_model: String,
     pub language: String,
+    /// Language code (e.g. "ja") → whisper model used instead of
+    /// `whisper_model` for that language, when downloaded
+    pub language_model_map: HashMap<String, String>,
 
     // LLM
     pub prompt_mode: PromptMode,
+    /// Modes run in sequence, each on the previous output. Empty = `prompt_mode` only.
+    /// Raw may only be used on its own: in a longer chain it would be a
+    /// no-op stage wherever it appears, so validation rejects it anywhere.
+    pub prompt_mode_chain: Vec<PromptMode>,
     pub custom_system_prompt: Option<String>,
-    pub local_llm_model: Option<String>,
+    pub optimization_length: OptimizationLength,
+    pub llm: LlmSettings,
+    /// Whether an LLM API key is in the secret store. Set with phemy_set_secret.
+    #[serde(skip_deserializing)]
+    pub has_llm_api_key: bool,
+    /// Whether a Hugging Face token is in the secret store
+    #[serde(skip_deserializing)]
+    pub has_hf_token: bool,
+    /// Mode id (e.g. "verbatim") → model name used instead of `llm.model`
+    pub mode_model_overrides: HashMap<String, String>,
+    /// Always swap to the override model, even if another model is already loaded
+    pub strict_mode_model_overrides: bool,
 
     // Paste
+    pub post_process_action: PostProcessAction,
     pub paste_method: PasteMethod,
+    /// App name or bundle id → paste method used instead of `paste_method`
+    /// when pasting into that app. See `paste_method_for`.
+    pub app_paste_overrides: HashMap<String, PasteMethod>,
     pub paste_delay_ms: u64,
+    /// Put the previous clipboard contents back after pasting. When off the
+    /// pasted text stays on the clipboard.
+    pub restore_clipboard: bool,
+    /// Mark the text we put on the clipboard for pasting so clipboard
+    /// history managers don't record it (not with the Wayland helpers)
+    pub clipboard_transient: bool,
+    /// Wait after the paste keystroke before putting the previous clipboard back
+    pub clipboard_restore_delay_ms: u64,
+    /// Retry the paste keystroke once if it failed or our text didn't make it
+    /// onto the clipboard
+    pub paste_verify: bool,
+    /// With `paste_verify`, wait at least this long after the paste before
+    /// retrying or restoring the clipboard
+    pub paste_verify_window_ms: u64,
+    /// TypeOut sends text in pieces of this many characters; 0 sends it all at once
+    pub typeout_chars_per_chunk: usize,
+    /// Pause between TypeOut pieces
+    pub typeout_delay_ms: u64,
+    /// Press `submit_key` after pasting
     pub auto_submit: bool,
+    pub submit_key: SubmitKey,
+    /// Wait between the paste and the submit key
+    pub submit_delay_ms: u64,
+    /// Don't submit after the TypeOut method
+    pub auto_submit_skip_type_out: bool,
+    pub paste_postprocess: PastePostprocess,
+
+    // Privacy
+    pub redaction: RedactionSettings,
 
     // Hotkey
     pub hotkey: String,
@@ -93,27 +377,68 @@ pub struct Settings {
     pub theme: Theme,
     pub launch_at_startup: bool,
 
+    // History
+    /// Merge a new entry into an existing one with the same transcript
+    /// recorded within `history_dedupe_window_secs`
+    pub history_dedupe: bool,
+    pub history_dedupe_window_secs: u64,
+
     // Vocabulary
+    /// Mirror of the database `vocabulary` table, which is the canonical store.
+    /// Filled from the database by phemy_get_settings; saving settings writes
+    /// this list back to the database.
     pub vocabulary: Vec<String>,
+
+    /// Top-level fields this build doesn't know, written by a newer frontend
+    /// or core. Kept so that saving doesn't erase them.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
+            schema_version: SETTINGS_VERSION,
             input_device: None,
+            save_recordings: false,
+            audio: AudioSettings::default(),
             whisper_model: "base".to_string(),
             language: "en".to_string(),
+            language_model_map: HashMap::new(),
             prompt_mode: PromptMode::default(),
+            prompt_mode_chain: Vec::new(),
             custom_system_prompt: None,
-            local_llm_model: Some("qwen3-4b-instruct-q4km".to_string()),
+            optimization_length: OptimizationLength::default(),
+            llm: LlmSettings::default(),
+            has_llm_api_key: false,
+            has_hf_token: false,
+            mode_model_overrides: HashMap::new(),
+            strict_mode_model_overrides: false,
+            post_process_action: PostProcessAction::default(),
             paste_method: PasteMethod::default(),
+            app_paste_overrides: HashMap::new(),
             paste_delay_ms: 100,
+            restore_clipboard: true,
+            clipboard_transient: true,
+            clipboard_restore_delay_ms: 100,
+            paste_verify: false,
+            paste_verify_window_ms: 300,
+            typeout_chars_per_chunk: 0,
+            typeout_delay_ms: 10,
             auto_submit: false,
+            submit_key: SubmitKey::default(),
+            submit_delay_ms: 150,
+            auto_submit_skip_type_out: false,
+            paste_postprocess: PastePostprocess::default(),
+            redaction: RedactionSettings::default(),
             hotkey: "Ctrl+Space".to_string(),
             hotkey_mode: HotkeyMode::default(),
             theme: Theme::default(),
             launch_at_startup: false,
+            history_dedupe: false,
+            history_dedupe_window_secs: 120,
             vocabulary: Vec::new(),
+            extra: serde_json::Map::new(),
         }
     }
 }
@@ -129,14 +454,21 @@ pub fn set_data_dir(path: PathBuf) {
     }
 }
 
+/// Forget the data directory (called from phemy_shutdown)
+pub fn clear_data_dir() {
+    if let Ok(mut dir) = DATA_DIR.lock() {
+        *dir = None;
+    }
+}
+
 /// Get the data directory set by phemy_init, if any.
 pub fn get_data_dir() -> Option<PathBuf> {
     DATA_DIR.lock().ok()?.clone()
 }
 
-/// Get the settings file path
-fn settings_path() -> anyhow::Result<PathBuf> {
-    let dir = DATA_DIR
+/// Data directory root, falling back to the platform default
+fn data_root() -> anyhow::Result<PathBuf> {
+    Ok(DATA_DIR
         .lock()
         .map_err(|e| anyhow::anyhow!("{}", e))?
         .clone()
@@ -144,45 +476,2172 @@ fn settings_path() -> anyhow::Result<PathBuf> {
             dirs::data_dir()
                 .unwrap_or_else(|| PathBuf::from("."))
                 .join("phemy")
-        });
+        }))
+}
 
+/// Get the settings file path of the active profile
+fn settings_path() -> anyhow::Result<PathBuf> {
+    let root = data_root()?;
+    let dir = profile_dir(&root, &active_profile_in(&root));
     std::fs::create_dir_all(&dir)?;
     Ok(dir.join("settings.json"))
 }
 
-impl Settings {
-    /// Load settings from JSON file on disk
-    pub fn load() -> Self {
-        let path = match settings_path() {
-            Ok(p) => p,
-            Err(_) => return Self::default(),
-        };
+/// Profile that uses `settings.json` in the data directory itself, so
+/// single-profile installs need no migration.
+pub const DEFAULT_PROFILE: &str = "default";
 
-        if !path.exists() {
-            return Self::default();
+/// Marker file in the data directory naming the active profile. Absent = default.
+const ACTIVE_PROFILE_FILE: &str = "active_profile";
+
+const MAX_PROFILE_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Profiles {
+    pub active: String,
+    /// All profiles, default first, the rest sorted by name
+    pub profiles: Vec<String>,
+}
+
+fn profile_dir(root: &std::path::Path, name: &str) -> PathBuf {
+    if name == DEFAULT_PROFILE {
+        root.to_path_buf()
+    } else {
+        root.join("profiles").join(name)
+    }
+}
+
+fn active_profile_in(root: &std::path::Path) -> String {
+    let name = std::fs::read_to_string(root.join(ACTIVE_PROFILE_FILE)).unwrap_or_default();
+    let name = name.trim();
+    if name.is_empty() || name == DEFAULT_PROFILE {
+        return DEFAULT_PROFILE.to_string();
+    }
+    if check_profile_name(name).is_err() || !profile_dir(root, name).join("settings.json").exists() {
+        log::warn!("Active profile {:?} is missing, using the default profile", name);
+        return DEFAULT_PROFILE.to_string();
+    }
+    name.to_string()
+}
+
+/// Profile names become directory names: letters, digits, '-' and '_' only
+fn check_profile_name(name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !name.is_empty() && name.len() <= MAX_PROFILE_NAME_LEN,
+        "Profile name must be 1 to {} characters",
+        MAX_PROFILE_NAME_LEN
+    );
+    anyhow::ensure!(
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "Profile name {:?} may only contain letters, digits, '-' and '_'",
+        name
+    );
+    Ok(())
+}
+
+/// Name of the active settings profile
+pub fn active_profile() -> String {
+    data_root()
+        .map(|root| active_profile_in(&root))
+        .unwrap_or_else(|_| DEFAULT_PROFILE.to_string())
+}
+
+/// List all settings profiles and the active one
+pub fn list_profiles() -> anyhow::Result<Profiles> {
+    let root = data_root()?;
+    let mut profiles = Vec::new();
+    match std::fs::read_dir(root.join("profiles")) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if check_profile_name(&name).is_ok()
+                    && name != DEFAULT_PROFILE
+                    && entry.path().join("settings.json").exists()
+                {
+                    profiles.push(name);
+                }
+            }
         }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    profiles.sort();
+    profiles.insert(0, DEFAULT_PROFILE.to_string());
 
-        match std::fs::read_to_string(&path) {
-            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
-            Err(_) => Self::default(),
+    Ok(Profiles {
+        active: active_profile_in(&root),
+        profiles,
+    })
+}
+
+/// Create a profile, starting from the active profile's settings if
+/// `copy_current` is set and from defaults otherwise. Does not switch to it.
+pub fn create_profile(name: &str, copy_current: bool) -> anyhow::Result<()> {
+    check_profile_name(name)?;
+    anyhow::ensure!(name != DEFAULT_PROFILE, "The default profile always exists");
+
+    let dir = profile_dir(&data_root()?, name);
+    let path = dir.join("settings.json");
+    anyhow::ensure!(!path.exists(), "Profile {:?} already exists", name);
+
+    let settings = if copy_current {
+        Settings::load()
+    } else {
+        Settings::default()
+    };
+    std::fs::create_dir_all(&dir)?;
+    write_settings(&path, &settings)?;
+    log::info!("Created settings profile {:?}", name);
+    Ok(())
+}
+
+/// Delete a profile. The default and the active profile can't be deleted.
+pub fn delete_profile(name: &str) -> anyhow::Result<()> {
+    check_profile_name(name)?;
+    anyhow::ensure!(name != DEFAULT_PROFILE, "The default profile can't be deleted");
+
+    let root = data_root()?;
+    anyhow::ensure!(
+        active_profile_in(&root) != name,
+        "Profile {:?} is active; switch to another profile first",
+        name
+    );
+    let dir = profile_dir(&root, name);
+    anyhow::ensure!(dir.join("settings.json").exists(), "Profile {:?} does not exist", name);
+
+    std::fs::remove_dir_all(&dir)?;
+    log::info!("Deleted settings profile {:?}", name);
+    Ok(())
+}
+
+/// Make `name` the active profile and notify settings listeners.
+pub fn switch_profile(name: &str) -> anyhow::Result<Settings> {
+    let root = data_root()?;
+    let marker = root.join(ACTIVE_PROFILE_FILE);
+    if name == DEFAULT_PROFILE {
+        match std::fs::remove_file(&marker) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
         }
+    } else {
+        check_profile_name(name)?;
+        anyhow::ensure!(
+            profile_dir(&root, name).join("settings.json").exists(),
+            "Profile {:?} does not exist",
+            name
+        );
+        std::fs::create_dir_all(&root)?;
+        std::fs::write(&marker, name)?;
     }
+    log::info!("Switched to settings profile {:?}", name);
 
-    /// Save settings to JSON file on disk
-    pub fn save(&self) -> anyhow::Result<()> {
-        let path = settings_path()?;
-        let json = serde_json::to_string_pretty(self)?;
-        std::fs::write(&path, &json)?;
+    // Settings are read from disk on every load, so there is nothing cached
+    // to drop; listeners get the new profile's settings.
+    let settings = Settings::load();
+    notify_listeners(&settings);
+    Ok(settings)
+}
 
-        // Set restrictive permissions (owner-only read/write)
-        #[cfg(unix)]
-        {
-            let perms = std::fs::Permissions::from_mode(0o600);
-            if let Err(e) = std::fs::set_permissions(&path, perms) {
-                log::warn!("Failed to set settings file permissions: {}", e);
+type Listener = std::sync::Arc<dyn Fn(&Settings) + Send + Sync>;
+
+static LISTENERS: std::sync::LazyLock<Mutex<Vec<Listener>>> =
+    std::sync::LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Register a function called with the new settings after every save and
+/// profile switch.
+pub fn add_listener(listener: impl Fn(&Settings) + Send + Sync + 'static) {
+    if let Ok(mut listeners) = LISTENERS.lock() {
+        listeners.push(std::sync::Arc::new(listener));
+    }
+}
+
+fn notify_listeners(settings: &Settings) {
+    // Call outside the lock so a listener may register another or save
+    let listeners = match LISTENERS.lock() {
+        Ok(listeners) => listeners.clone(),
+        Err(_) => return,
+    };
+    for listener in listeners {
+        listener(settings);
+    }
+}
+
+/// Current settings file layout version
+pub const SETTINGS_VERSION: u32 = 3;
+
+/// Upgrades the raw settings JSON from the previous version to `version`.
+///
+/// Keys that `Settings` doesn't know are kept as unknown fields, so a
+/// migration that retires a field must list it in `removes`.
+struct SettingsMigration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&mut serde_json::Map<String, serde_json::Value>),
+    /// Top-level keys deleted after `apply`
+    removes: &'static [&'static str],
+}
+
+/// Ordered list of settings migrations. Append new ones; never edit or reorder existing ones.
+const SETTINGS_MIGRATIONS: &[SettingsMigration] = &[
+    SettingsMigration {
+        version: 1,
+        description: "clamp values that validation now rejects",
+        apply: migrate_v1,
+        removes: &[],
+    },
+    SettingsMigration {
+        version: 2,
+        description: "move local_llm_model into the llm block",
+        apply: migrate_v2,
+        removes: &["local_llm_model"],
+    },
+    SettingsMigration {
+        version: 3,
+        description: "store the hotkey in canonical form",
+        apply: migrate_v3,
+        removes: &[],
+    },
+];
+
+/// Files written before versioning could hold values that `validate` now
+/// rejects, which would make every later save fail. Bring them into range.
+fn migrate_v1(map: &mut serde_json::Map<String, serde_json::Value>) {
+    if let Some(delay) = map.get("paste_delay_ms").and_then(|v| v.as_u64()) {
+        if delay > MAX_PASTE_DELAY_MS {
+            map.insert("paste_delay_ms".into(), MAX_PASTE_DELAY_MS.into());
+        }
+    }
+    if let Some(window) = map.get("history_dedupe_window_secs").and_then(|v| v.as_u64()) {
+        map.insert(
+            "history_dedupe_window_secs".into(),
+            window.clamp(1, MAX_DEDUPE_WINDOW_SECS).into(),
+        );
+    }
+    if map.get("hotkey").and_then(|v| v.as_str()).is_some_and(|h| check_hotkey(h).is_err()) {
+        map.remove("hotkey");
+    }
+    if let Some(chain) = map.get_mut("prompt_mode_chain").and_then(|v| v.as_array_mut()) {
+        chain.truncate(MAX_MODE_CHAIN_LEN);
+    }
+}
+
+/// Modifier names accepted in `hotkey` at version 1
+const V1_HOTKEY_MODIFIERS: &[&str] = &[
+    "Ctrl", "Control", "Shift", "Alt", "Option", "Super", "Cmd", "Command", "Meta",
+];
+
+/// Hotkey check as of version 1, kept so `migrate_v1` behaves as it shipped.
+/// Hotkeys are "Modifier+...+Key", e.g. "Ctrl+Space" or "Super+Shift+K"
+fn check_hotkey(hotkey: &str) -> Result<(), String> {
+    let parts: Vec<&str> = hotkey.split('+').map(str::trim).collect();
+    if parts.iter().any(|p| p.is_empty()) {
+        return Err(format!("Invalid hotkey {:?}", hotkey));
+    }
+
+    let (key, modifiers) = parts.split_last().expect("split yields at least one part");
+    if let Some(unknown) = modifiers
+        .iter()
+        .find(|m| !V1_HOTKEY_MODIFIERS.iter().any(|known| known.eq_ignore_ascii_case(m)))
+    {
+        return Err(format!("Unknown modifier {:?} in hotkey", unknown));
+    }
+    if V1_HOTKEY_MODIFIERS.iter().any(|known| known.eq_ignore_ascii_case(key)) {
+        return Err(format!("Hotkey {:?} has no key besides modifiers", hotkey));
+    }
+    Ok(())
+}
+
+fn migrate_v2(map: &mut serde_json::Map<String, serde_json::Value>) {
+    move_legacy_llm_model(map);
+}
+
+/// Hotkeys are now parsed strictly and stored in canonical form. Rewrite
+/// the saved one, and drop it (back to the default) if it no longer parses,
+/// since it would fail validation on every save.
+fn migrate_v3(map: &mut serde_json::Map<String, serde_json::Value>) {
+    let Some(hotkey) = map.get("hotkey").and_then(|v| v.as_str()) else {
+        return;
+    };
+    match normalize_hotkey(hotkey) {
+        Ok(canonical) => {
+            map.insert("hotkey".into(), canonical.into());
+        }
+        Err(_) => {
+            map.remove("hotkey");
+        }
+    }
+}
+
+/// Move a top-level `local_llm_model` into `llm.model`. Also applied to
+/// incoming JSON that still uses the old key; an explicit `llm.model` wins.
+fn move_legacy_llm_model(map: &mut serde_json::Map<String, serde_json::Value>) {
+    let Some(model) = map.remove("local_llm_model") else {
+        return;
+    };
+    let llm = map
+        .entry("llm")
+        .or_insert_with(|| serde_json::Value::Object(Default::default()));
+    if let Some(llm) = llm.as_object_mut() {
+        llm.entry("model").or_insert(model);
+    }
+}
+
+/// Parse settings JSON sent by a frontend. Old key names are still accepted,
+/// but values are not migrated, so out-of-range values fail validation.
+/// Secrets in the payload are moved to the secret store.
+pub fn from_json(json: &str) -> anyhow::Result<Settings> {
+    let mut raw: serde_json::Value = serde_json::from_str(json)?;
+    if let Some(map) = raw.as_object_mut() {
+        move_legacy_llm_model(map);
+        crate::secrets::take_from_settings(map);
+    }
+    Ok(serde_json::from_value::<Settings>(raw)?.normalized())
+}
+
+/// Version recorded in raw settings JSON; files from before versioning are version 0
+fn raw_version(map: &serde_json::Map<String, serde_json::Value>) -> u32 {
+    map.get("schema_version")
+        .and_then(|v| v.as_u64())
+        .map_or(0, |v| v as u32)
+}
+
+/// Upgrade raw settings JSON to the current layout and deserialize it.
+/// Invalid JSON shapes fall back to defaults, as `load` always has.
+pub fn migrate(raw: serde_json::Value) -> Settings {
+    try_migrate(raw).unwrap_or_else(|e| {
+        log::warn!("Failed to parse settings, using defaults: {}", e);
+        Settings::default()
+    })
+}
+
+/// Like `migrate`, but reports settings that can't be read instead of
+/// falling back to defaults. Secrets found in `raw` are moved to the secret store.
+pub fn try_migrate(raw: serde_json::Value) -> anyhow::Result<Settings> {
+    let serde_json::Value::Object(mut map) = raw else {
+        anyhow::bail!("Settings must be a JSON object");
+    };
+    migrate_map(&mut map);
+    Ok(serde_json::from_value::<Settings>(serde_json::Value::Object(map))?.normalized())
+}
+
+/// Bring raw settings JSON up to the current layout in place
+fn migrate_map(map: &mut serde_json::Map<String, serde_json::Value>) {
+    crate::secrets::take_from_settings(map);
+
+    let version = raw_version(map);
+    if version > SETTINGS_VERSION {
+        log::warn!(
+            "Settings schema version {} is newer than this build supports ({})",
+            version,
+            SETTINGS_VERSION
+        );
+    }
+    for migration in SETTINGS_MIGRATIONS.iter().filter(|m| m.version > version) {
+        log::info!(
+            "Applying settings migration {} ({})",
+            migration.version,
+            migration.description
+        );
+        (migration.apply)(map);
+        for key in migration.removes {
+            map.remove(*key);
+        }
+    }
+    if version < SETTINGS_VERSION {
+        map.insert("schema_version".into(), SETTINGS_VERSION.into());
+    }
+}
+
+/// Maximum number of modes in `prompt_mode_chain`
+pub const MAX_MODE_CHAIN_LEN: usize = 3;
+
+/// Longest accepted `paste_delay_ms`
+pub const MAX_PASTE_DELAY_MS: u64 = 5000;
+
+/// Longest accepted `history_dedupe_window_secs` (one day)
+pub const MAX_DEDUPE_WINDOW_SECS: u64 = 86_400;
+
+/// JSON type of a setting
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    Bool,
+    Integer,
+    Number,
+    String,
+    /// One of `options`
+    Enum,
+    /// Array; items are `options` if set
+    List,
+    /// Object with arbitrary string keys
+    Map,
+    /// Nested settings block, described by its own dotted entries
+    Object,
+}
+
+/// One row of the settings table. Validation checks `range`; the schema
+/// returned by phemy_get_settings_schema is generated from the same rows.
+pub struct FieldSpec {
+    /// Dotted path as it appears in the settings JSON
+    pub key: &'static str,
+    pub field_type: FieldType,
+    pub description: &'static str,
+    options: Option<fn() -> Vec<String>>,
+    /// Inclusive bounds for numeric fields
+    range: Option<(f64, f64)>,
+    unit: Option<&'static str>,
+    /// 0 is allowed outside `range` and means "off"
+    zero_is_off: bool,
+    nullable: bool,
+    /// Takes effect only after the app restarts
+    restart: bool,
+    /// Reported by the core; ignored when saving
+    read_only: bool,
+}
+
+impl FieldSpec {
+    const fn new(key: &'static str, field_type: FieldType, description: &'static str) -> Self {
+        Self {
+            key,
+            field_type,
+            description,
+            options: None,
+            range: None,
+            unit: None,
+            zero_is_off: false,
+            nullable: false,
+            restart: false,
+            read_only: false,
+        }
+    }
+
+    const fn options(mut self, options: fn() -> Vec<String>) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    const fn range(mut self, min: f64, max: f64) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    const fn unit(mut self, unit: &'static str) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+
+    const fn zero_is_off(mut self) -> Self {
+        self.zero_is_off = true;
+        self
+    }
+
+    const fn nullable(mut self) -> Self {
+        self.nullable = true;
+        self
+    }
+
+    const fn restart(mut self) -> Self {
+        self.restart = true;
+        self
+    }
+
+    const fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Error message if `value` is outside `range`
+    fn check_range(&self, value: &serde_json::Value) -> Option<String> {
+        let (min, max) = self.range?;
+        let value = value.as_f64()?;
+        let in_range = match self.field_type {
+            // Compare at the precision the field is stored with
+            FieldType::Number => (min as f32..=max as f32).contains(&(value as f32)),
+            _ => (min..=max).contains(&value),
+        };
+        if in_range || (self.zero_is_off && value == 0.0) {
+            return None;
+        }
+
+        let unit = self.unit.map(|u| format!(" {}", u)).unwrap_or_default();
+        let got = match self.field_type {
+            FieldType::Number => (value as f32).to_string(),
+            _ => value.to_string(),
+        };
+        Some(if self.zero_is_off {
+            format!("Must be 0 (off) or between {} and {}{}, got {}", min, max, unit, got)
+        } else if min == 0.0 {
+            format!("Must be at most {}{}, got {}", max, unit, got)
+        } else {
+            format!("Must be between {} and {}{}, got {}", min, max, unit, got)
+        })
+    }
+}
+
+fn names_of<T: Serialize>(values: &[T]) -> Vec<String> {
+    values
+        .iter()
+        .filter_map(|v| serde_json::to_value(v).ok()?.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Every setting, in display order
+pub const SETTINGS_FIELDS: &[FieldSpec] = {
+    use FieldType::*;
+    &[
+        FieldSpec::new("schema_version", Integer, "Layout version of the settings file").read_only(),
+        FieldSpec::new("input_device", String, "Microphone to record from; null uses the system default").nullable(),
+        FieldSpec::new("save_recordings", Bool, "Keep a WAV of each recording alongside its history entry"),
+        FieldSpec::new("audio", Object, "Recording and voice detection"),
+        FieldSpec::new("audio.vad-threshold", Number, "Loudness above which audio counts as speech")
+            .range(0.0001, 0.5),
+        FieldSpec::new("audio.max-duration-secs", Integer, "Stop recording after this long")
+            .range(0.0, 3600.0)
+            .unit("seconds"),
+        FieldSpec::new("audio.input-gain", Number, "Multiplier applied to the microphone signal").range(0.1, 10.0),
+        FieldSpec::new("audio.silence-auto-stop-ms", Integer, "Stop after this much silence following speech")
+            .range(500.0, 30_000.0)
+            .unit("ms")
+            .zero_is_off(),
+        FieldSpec::new("audio.preroll-ms", Integer, "Audio kept before the first detected speech")
+            .range(0.0, 2000.0)
+            .unit("ms"),
+        FieldSpec::new("audio.normalize", Bool, "Raise quiet recordings to full level before transcribing"),
+        FieldSpec::new("whisper_model", String, "Whisper model used for transcription"),
+        FieldSpec::new("language", String, "Spoken language code, or \"auto\" to detect it"),
+        FieldSpec::new("language_model_map", Map, "Whisper model to use for specific languages"),
+        FieldSpec::new("prompt_mode", Enum, "How transcripts are rewritten").options(|| names_of(&PromptMode::ALL)),
+        FieldSpec::new("prompt_mode_chain", List, "Modes applied in sequence; empty uses prompt_mode")
+            .options(|| names_of(&PromptMode::ALL)),
+        FieldSpec::new("custom_system_prompt", String, "System prompt for the custom mode").nullable(),
+        FieldSpec::new("optimization_length", Enum, "How long rewritten prompts should be")
+            .options(|| names_of(&OptimizationLength::ALL)),
+        FieldSpec::new("llm", Object, "Language model used for rewriting"),
+        FieldSpec::new("llm.provider", Enum, "Where the language model runs").options(|| names_of(&LlmProvider::ALL)),
+        FieldSpec::new("llm.model", String, "Model used unless a mode override applies").nullable(),
+        FieldSpec::new("llm.temperature", Number, "Sampling temperature; lower is more predictable").range(0.0, 2.0),
+        FieldSpec::new("llm.top_k", Integer, "Sample from this many most likely tokens").range(1.0, 1000.0),
+        FieldSpec::new("llm.top_p", Number, "Sample from tokens covering this probability mass").range(0.01, 1.0),
+        FieldSpec::new("llm.seed", Integer, "Random seed for sampling").range(0.0, u32::MAX as f64),
+        FieldSpec::new("llm.n_ctx", Integer, "Context window; prompt and output must fit in it")
+            .range(512.0, 32_768.0)
+            .unit("tokens"),
+        FieldSpec::new("llm.n_batch", Integer, "Tokens processed per batch; at most n_ctx").range(32.0, 32_768.0),
+        FieldSpec::new("llm.gpu_layers", Integer, "Model layers offloaded to the GPU").range(0.0, 1000.0),
+        FieldSpec::new("llm.threads", Integer, "CPU threads for generation; 0 picks automatically").range(0.0, 256.0),
+        FieldSpec::new("llm.max_tokens", Integer, "Longest output to generate; at most n_ctx")
+            .range(16.0, 32_768.0)
+            .unit("tokens"),
+        FieldSpec::new("llm.timeout_secs", Integer, "Give up on a generation after this long; 0 = no limit")
+            .range(0.0, 600.0)
+            .unit("seconds"),
+        FieldSpec::new("llm.idle_unload_secs", Integer, "Free the model after this long unused; 0 = keep loaded")
+            .range(0.0, 86_400.0)
+            .unit("seconds"),
+        FieldSpec::new("llm.warmup", Bool, "Load the model in the background at startup").restart(),
+        FieldSpec::new("has_llm_api_key", Bool, "Whether an LLM API key is stored").read_only(),
+        FieldSpec::new("has_hf_token", Bool, "Whether a Hugging Face token is stored").read_only(),
+        FieldSpec::new("mode_model_overrides", Map, "LLM model to use for specific prompt modes"),
+        FieldSpec::new("strict_mode_model_overrides", Bool, "Always load the override model, even if another is loaded"),
+        FieldSpec::new("post_process_action", Enum, "Paste the result, only copy it, or do nothing")
+            .options(|| names_of(&PostProcessAction::ALL)),
+        FieldSpec::new(
+            "paste_method",
+            Enum,
+            "How text is inserted into the focused app, or {\"custom-combo\": \"Cmd+Option+Shift+V\"}",
+        )
+            .options(|| {
+                let supported: Vec<_> = PasteMethod::ALL.into_iter().filter(PasteMethod::is_supported).collect();
+                names_of(&supported)
+            }),
+        FieldSpec::new("app_paste_overrides", Map, "Paste method to use for specific apps"),
+        FieldSpec::new("paste_delay_ms", Integer, "Wait before pasting")
+            .range(0.0, MAX_PASTE_DELAY_MS as f64)
+            .unit("ms"),
+        FieldSpec::new("restore_clipboard", Bool, "Put the previous clipboard contents back after pasting"),
+        FieldSpec::new("clipboard_transient", Bool, "Keep pasted text out of clipboard history managers"),
+        FieldSpec::new("clipboard_restore_delay_ms", Integer, "Wait after pasting before restoring the clipboard")
+            .range(0.0, MAX_PASTE_DELAY_MS as f64)
+            .unit("ms"),
+        FieldSpec::new("paste_verify", Bool, "Retry the paste once if it didn't go through"),
+        FieldSpec::new("paste_verify_window_ms", Integer, "Wait after pasting before retrying or restoring the clipboard")
+            .range(0.0, MAX_PASTE_DELAY_MS as f64)
+            .unit("ms"),
+        FieldSpec::new("typeout_chars_per_chunk", Integer, "Characters typed at a time by the TypeOut method")
+            .range(1.0, 10_000.0)
+            .zero_is_off(),
+        FieldSpec::new("typeout_delay_ms", Integer, "Pause between typed pieces")
+            .range(0.0, MAX_PASTE_DELAY_MS as f64)
+            .unit("ms"),
+        FieldSpec::new("auto_submit", Bool, "Press the submit key after pasting"),
+        FieldSpec::new("submit_key", Enum, "Key that sends the pasted text").options(|| names_of(&SubmitKey::ALL)),
+        FieldSpec::new("submit_delay_ms", Integer, "Wait between pasting and submitting")
+            .range(0.0, MAX_PASTE_DELAY_MS as f64)
+            .unit("ms"),
+        FieldSpec::new("auto_submit_skip_type_out", Bool, "Don't submit when text is typed out"),
+        FieldSpec::new("paste_postprocess", Object, "Adjustments made to text just before pasting"),
+        FieldSpec::new("paste_postprocess.append", Enum, "Add a space or newline after the text")
+            .options(|| names_of(&PasteAppend::ALL)),
+        FieldSpec::new(
+            "paste_postprocess.prepend_space_if_midword",
+            Bool,
+            "Add a space before the text when continuing the previous paste",
+        ),
+        FieldSpec::new("paste_postprocess.strip_trailing_newline", Bool, "Remove line breaks at the end"),
+        FieldSpec::new("paste_postprocess.collapse_internal_newlines", Bool, "Join lines with spaces"),
+        FieldSpec::new("redaction", Object, "Sensitive data scrubbed from transcripts"),
+        FieldSpec::new("redaction.emails", Bool, "Redact email addresses"),
+        FieldSpec::new("redaction.phone_numbers", Bool, "Redact phone numbers"),
+        FieldSpec::new("redaction.digit_sequences", Bool, "Redact long digit runs such as card numbers"),
+        FieldSpec::new("hotkey", String, "Shortcut that starts dictation, e.g. \"Ctrl+Space\""),
+        FieldSpec::new("hotkey_mode", Enum, "Toggle recording, or record while held")
+            .options(|| names_of(&HotkeyMode::ALL)),
+        FieldSpec::new("theme", Enum, "App appearance").options(|| names_of(&Theme::ALL)),
+        FieldSpec::new("launch_at_startup", Bool, "Start the app at login"),
+        FieldSpec::new("history_dedupe", Bool, "Merge repeated transcripts into one history entry"),
+        FieldSpec::new("history_dedupe_window_secs", Integer, "How close repeats must be to merge")
+            .range(1.0, MAX_DEDUPE_WINDOW_SECS as f64)
+            .unit("seconds"),
+        FieldSpec::new("vocabulary", List, "Words and names the transcriber should recognize"),
+    ]
+};
+
+/// Whether `key` is a top-level field of `Settings`
+fn is_known_field(key: &str) -> bool {
+    SETTINGS_FIELDS.iter().any(|spec| spec.key == key)
+}
+
+/// Top-level fields in the settings file at `path` that this build doesn't
+/// know, after migrations have removed retired ones
+fn unknown_fields_on_disk(path: &std::path::Path) -> serde_json::Map<String, serde_json::Value> {
+    let Some(serde_json::Value::Object(mut map)) = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+    else {
+        return serde_json::Map::new();
+    };
+    migrate_map(&mut map);
+    map.retain(|key, _| !is_known_field(key));
+    map
+}
+
+/// Value at an exact dotted path in settings JSON
+fn value_at<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a serde_json::Value> {
+    key.split('.').try_fold(value, |value, segment| value.get(segment))
+}
+
+/// Machine-readable description of every setting, for generating forms
+pub fn schema() -> serde_json::Value {
+    let defaults = serde_json::to_value(Settings::default()).unwrap_or_default();
+    let fields: Vec<serde_json::Value> = SETTINGS_FIELDS
+        .iter()
+        .map(|spec| {
+            let mut field = serde_json::json!({
+                "key": spec.key,
+                "type": spec.field_type,
+                "description": spec.description,
+                "default": value_at(&defaults, spec.key).cloned().unwrap_or_default(),
+                "nullable": spec.nullable,
+                "restart_required": spec.restart,
+                "read_only": spec.read_only,
+            });
+            if let Some(options) = spec.options {
+                field["options"] = options().into();
+            }
+            if let Some((min, max)) = spec.range {
+                field["min"] = min.into();
+                field["max"] = max.into();
+                field["zero_is_off"] = spec.zero_is_off.into();
+            }
+            if let Some(unit) = spec.unit {
+                field["unit"] = unit.into();
+            }
+            field
+        })
+        .collect();
+
+    serde_json::json!({
+        "schema_version": SETTINGS_VERSION,
+        "fields": fields,
+        "sections": SETTINGS_SECTIONS
+            .iter()
+            .map(|(name, keys)| serde_json::json!({ "name": name, "keys": keys }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Groups of top-level settings that can be reset together. The vocabulary
+/// lives in the database and is never reset here.
+pub const SETTINGS_SECTIONS: &[(&str, &[&str])] = &[
+    ("audio", &["input_device", "save_recordings", "audio"]),
+    ("transcription", &["whisper_model", "language", "language_model_map"]),
+    ("prompt", &["prompt_mode", "prompt_mode_chain", "custom_system_prompt", "optimization_length"]),
+    ("llm", &["llm", "mode_model_overrides", "strict_mode_model_overrides"]),
+    (
+        "paste",
+        &[
+            "post_process_action",
+            "paste_method",
+            "app_paste_overrides",
+            "paste_delay_ms",
+            "restore_clipboard",
+            "clipboard_transient",
+            "clipboard_restore_delay_ms",
+            "paste_verify",
+            "paste_verify_window_ms",
+            "typeout_chars_per_chunk",
+            "typeout_delay_ms",
+            "auto_submit",
+            "submit_key",
+            "submit_delay_ms",
+            "auto_submit_skip_type_out",
+            "paste_postprocess",
+        ],
+    ),
+    ("privacy", &["redaction"]),
+    ("hotkey", &["hotkey", "hotkey_mode"]),
+    ("general", &["theme", "launch_at_startup"]),
+    ("history", &["history_dedupe", "history_dedupe_window_secs"]),
+];
+
+/// Section name that resets every section
+pub const ALL_SECTIONS: &str = "all";
+
+/// Names accepted by `Settings::reset_section`
+pub fn section_names() -> Vec<&'static str> {
+    SETTINGS_SECTIONS
+        .iter()
+        .map(|(name, _)| *name)
+        .chain(std::iter::once(ALL_SECTIONS))
+        .collect()
+}
+
+/// A settings field that failed validation
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// An error about the settings as a whole (unparseable JSON, I/O failures)
+    pub fn general(message: impl Into<String>) -> Self {
+        Self::new("settings", message)
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Join field errors into a single message
+pub fn describe_errors(errors: &[FieldError]) -> String {
+    errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+impl Settings {
+    /// Check settings for values that can't be used as-is.
+    /// Returns every failing field, not just the first.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        // Numeric ranges come from SETTINGS_FIELDS
+        if let Ok(json) = serde_json::to_value(self) {
+            for spec in SETTINGS_FIELDS {
+                if let Some(message) = value_at(&json, spec.key).and_then(|v| spec.check_range(v)) {
+                    errors.push(FieldError::new(spec.key, message));
+                }
             }
         }
 
-        Ok(())
+        if !crate::transcription::model_manager::is_known_model(&self.whisper_model) {
+            errors.push(FieldError::new(
+                "whisper_model",
+                format!("Unknown whisper model: {}", self.whisper_model),
+            ));
+        }
+
+        if !is_valid_language(&self.language) {
+            errors.push(FieldError::new(
+                "language",
+                format!("Expected \"auto\" or a language code like \"en\", got {:?}", self.language),
+            ));
+        }
+
+        for (language, model) in &self.language_model_map {
+            if language == "auto" || !is_valid_language(language) {
+                errors.push(FieldError::new(
+                    "language_model_map",
+                    format!("Expected a language code like \"ja\", got {:?}", language),
+                ));
+            }
+            if !crate::transcription::model_manager::is_known_model(model) {
+                errors.push(FieldError::new(
+                    "language_model_map",
+                    format!("Unknown whisper model for {}: {}", language, model),
+                ));
+            }
+        }
+
+        errors.extend(self.llm.validate());
+
+        for (mode, model) in &self.mode_model_overrides {
+            if serde_json::from_value::<PromptMode>(serde_json::Value::String(mode.clone())).is_err() {
+                errors.push(FieldError::new("mode_model_overrides", format!("Unknown prompt mode: {}", mode)));
+            }
+            if !crate::llm::llm_model_manager::is_known_model(model) {
+                errors.push(FieldError::new(
+                    "mode_model_overrides",
+                    format!("Unknown LLM model for {}: {}", mode, model),
+                ));
+            }
+        }
+
+        if self.prompt_mode_chain.len() > MAX_MODE_CHAIN_LEN {
+            errors.push(FieldError::new(
+                "prompt_mode_chain",
+                format!(
+                    "Has {} modes (maximum {})",
+                    self.prompt_mode_chain.len(),
+                    MAX_MODE_CHAIN_LEN
+                ),
+            ));
+        }
+        // Not only in the middle: a raw stage is skipped at either end as well
+        if self.prompt_mode_chain.len() > 1 && self.prompt_mode_chain.contains(&PromptMode::Raw) {
+            errors.push(FieldError::new(
+                "prompt_mode_chain",
+                "Cannot include raw mode alongside other modes",
+            ));
+        }
+
+        if let Err(message) = normalize_hotkey(&self.hotkey) {
+            errors.push(FieldError::new("hotkey", message));
+        }
+        if let Err(message) = self.paste_method.key_combo() {
+            errors.push(FieldError::new("paste_method", message));
+        }
+        for (app, method) in &self.app_paste_overrides {
+            if let Err(message) = method.key_combo() {
+                errors.push(FieldError::new("app_paste_overrides", format!("{}: {}", app, message)));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Paste method for the given frontmost app (name or bundle id): the
+    /// `app_paste_overrides` entry matching it case-insensitively, either
+    /// exactly or as a prefix ("iTerm" matches "iTerm2"), otherwise
+    /// `paste_method`. An exact match beats a prefix, and a longer prefix
+    /// beats a shorter one.
+    pub fn paste_method_for(&self, target_app: Option<&str>) -> &PasteMethod {
+        let Some(app) = target_app.map(|app| app.trim().to_lowercase()).filter(|app| !app.is_empty()) else {
+            return &self.paste_method;
+        };
+        self.app_paste_overrides
+            .iter()
+            .filter_map(|(key, method)| {
+                let key = key.trim().to_lowercase();
+                if key.is_empty() || !app.starts_with(&key) {
+                    return None;
+                }
+                Some(((key == app, key.len()), method))
+            })
+            .max_by_key(|(rank, _)| *rank)
+            .map_or(&self.paste_method, |(_, method)| method)
+    }
+
+    /// Problems that don't stop the settings from being saved, such as a
+    /// language mapped to a whisper model that isn't downloaded yet.
+    pub fn warnings(&self) -> Vec<FieldError> {
+        let mut warnings: Vec<FieldError> = self
+            .language_model_map
+            .iter()
+            .filter(|(_, model)| {
+                crate::transcription::model_manager::is_known_model(model)
+                    && !crate::transcription::model_manager::is_downloaded(model)
+            })
+            .map(|(language, model)| {
+                FieldError::new(
+                    "language_model_map",
+                    format!(
+                        "Whisper model {} for {} is not downloaded; {} is used until it is",
+                        model, language, self.whisper_model
+                    ),
+                )
+            })
+            .collect();
+        if !self.paste_method.is_supported() {
+            warnings.push(FieldError::new(
+                "paste_method",
+                "Middle-click paste only works on Linux; Ctrl+V is used instead",
+            ));
+        }
+        warnings.sort_by(|a, b| a.message.cmp(&b.message));
+        warnings
+    }
+
+    /// Value at a dotted path such as "llm.max_tokens", as it appears in
+    /// the settings JSON. Dashes and underscores are interchangeable
+    /// ("prompt-mode", "audio.vad_threshold"). Secrets are never returned.
+    /// None if the path doesn't name a setting.
+    pub fn get_path(&self, key: &str) -> Option<serde_json::Value> {
+        let mut value = serde_json::to_value(self.for_export()).ok()?;
+        for segment in key.split('.') {
+            let mut map = match value {
+                serde_json::Value::Object(map) => map,
+                _ => return None,
+            };
+            value = map
+                .remove(segment)
+                .or_else(|| map.remove(&segment.replace('-', "_")))
+                .or_else(|| map.remove(&segment.replace('_', "-")))?;
+        }
+        Some(value)
+    }
+
+    /// Copy of these settings for sharing with another machine. Secrets
+    /// live in the secret store and don't travel with the file.
+    pub fn for_export(&self) -> Settings {
+        Settings {
+            schema_version: SETTINGS_VERSION,
+            ..self.clone()
+        }
+    }
+
+    /// Apply a JSON patch of top-level fields, returning the updated settings.
+    ///
+    /// Only fields present in `patch` change; `null` clears optional fields.
+    /// Nested blocks (e.g. `redaction`) are replaced as a whole. Unknown keys
+    /// and values of the wrong type are reported per field. The result is not
+    /// validated.
+    pub fn merged(&self, patch: &serde_json::Value) -> Result<Settings, Vec<FieldError>> {
+        let patch = patch
+            .as_object()
+            .ok_or_else(|| vec![FieldError::general("Patch must be a JSON object")])?;
+
+        let mut current = match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => return Err(vec![FieldError::general("Failed to serialize settings")]),
+        };
+
+        // Accept the pre-`llm` key, applied on top of the current llm block
+        let mut patch = patch.clone();
+        if let Some(model) = patch.remove("local_llm_model") {
+            let llm = patch
+                .entry("llm")
+                .or_insert_with(|| current.get("llm").cloned().unwrap_or_default());
+            if let Some(llm) = llm.as_object_mut() {
+                llm.entry("model").or_insert(model);
+            }
+        }
+
+        let unknown: Vec<FieldError> = patch
+            .keys()
+            .filter(|key| !current.contains_key(*key))
+            .map(|key| FieldError::new(key, "Unknown setting"))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(unknown);
+        }
+
+        // Check each field on its own so every bad value is reported
+        let mut errors = Vec::new();
+        for (key, value) in &patch {
+            let mut single = serde_json::Map::new();
+            single.insert(key.clone(), value.clone());
+            if let Err(e) = serde_json::from_value::<Settings>(serde_json::Value::Object(single)) {
+                errors.push(FieldError::new(key, e.to_string()));
+            }
+            current.insert(key.clone(), value.clone());
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        serde_json::from_value::<Settings>(serde_json::Value::Object(current))
+            .map(Settings::normalized)
+            .map_err(|e| vec![FieldError::general(e.to_string())])
+    }
+
+    /// Copy of these settings with one section (see `SETTINGS_SECTIONS`), or
+    /// every section for "all", back at its defaults.
+    pub fn reset_section(&self, section: &str) -> anyhow::Result<Settings> {
+        let keys: Vec<&str> = if section == ALL_SECTIONS {
+            SETTINGS_SECTIONS.iter().flat_map(|(_, keys)| keys.iter().copied()).collect()
+        } else {
+            SETTINGS_SECTIONS
+                .iter()
+                .find(|(name, _)| *name == section)
+                .map(|(_, keys)| keys.to_vec())
+                .ok_or_else(|| anyhow::anyhow!("Unknown settings section {:?}", section))?
+        };
+
+        let serde_json::Value::Object(defaults) = serde_json::to_value(Settings::default())? else {
+            anyhow::bail!("Failed to serialize default settings");
+        };
+        let serde_json::Value::Object(mut current) = serde_json::to_value(self)? else {
+            anyhow::bail!("Failed to serialize settings");
+        };
+        for key in keys {
+            if let Some(value) = defaults.get(key) {
+                current.insert(key.to_string(), value.clone());
+            }
+        }
+
+        let mut reset: Settings = serde_json::from_value(serde_json::Value::Object(current))?;
+        // Presence flags are skipped when deserializing
+        reset.has_llm_api_key = self.has_llm_api_key;
+        reset.has_hf_token = self.has_hf_token;
+        Ok(reset)
+    }
+
+    /// Rewrite values that have several spellings (the hotkey and custom
+    /// paste combos) in their canonical form. Values that don't parse are
+    /// left for `validate`.
+    pub fn normalized(mut self) -> Self {
+        if let Ok(hotkey) = normalize_hotkey(&self.hotkey) {
+            self.hotkey = hotkey;
+        }
+        for method in std::iter::once(&mut self.paste_method).chain(self.app_paste_overrides.values_mut()) {
+            if let PasteMethod::CustomCombo(combo) = method {
+                if let Ok(canonical) = normalize_hotkey(combo) {
+                    *combo = canonical;
+                }
+            }
+        }
+        // Fields skipped when deserializing land in `extra`; they aren't unknown
+        self.extra.retain(|key, _| !is_known_field(key));
+        self
+    }
+
+    /// Load settings from JSON file on disk
+    pub fn load() -> Self {
+        let path = match settings_path() {
+            Ok(p) => p,
+            Err(_) => return Self::default().with_secret_flags(),
+        };
+
+        if !path.exists() {
+            return Self::default().with_secret_flags();
+        }
+
+        let settings = match std::fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str::<serde_json::Value>(&contents) {
+                Ok(raw) => {
+                    let had_secrets = crate::secrets::Secret::ALL
+                        .iter()
+                        .any(|secret| raw.get(secret.name()).is_some());
+                    let settings = migrate(raw);
+                    if had_secrets {
+                        // Don't leave the moved secrets on disk until the next save
+                        backup_before_upgrade(&path);
+                        if let Err(e) = write_settings(&path, &settings) {
+                            log::error!("Failed to strip secrets from settings file: {}", e);
+                        }
+                    }
+                    settings
+                }
+                Err(_) => Self::default(),
+            },
+            Err(_) => Self::default(),
+        };
+        settings.with_secret_flags()
+    }
+
+    /// Fill in which secrets are set
+    fn with_secret_flags(mut self) -> Self {
+        self.has_llm_api_key = crate::secrets::has(crate::secrets::Secret::LlmApiKey);
+        self.has_hf_token = crate::secrets::has(crate::secrets::Secret::HfToken);
+        self
+    }
+
+    /// Validate and save settings to JSON file on disk
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.validate()
+            .map_err(|errors| anyhow::anyhow!("Invalid settings: {}", describe_errors(&errors)))?;
+
+        for warning in self.warnings() {
+            log::warn!("Settings warning: {}", warning);
+        }
+
+        let path = settings_path()?;
+        backup_before_upgrade(&path);
+        let mut settings = self.clone().normalized();
+        // Frontends that predate a field drop it from what they send; keep
+        // the copy on disk unless the incoming settings have their own
+        for (key, value) in unknown_fields_on_disk(&path) {
+            settings.extra.entry(key).or_insert(value);
+        }
+        write_settings(&path, &settings)?;
+        notify_listeners(self);
+        Ok(())
+    }
+}
+
+fn write_settings(path: &std::path::Path, settings: &Settings) -> anyhow::Result<()> {
+    write_json(path, settings)
+}
+
+/// Write `value` as pretty JSON that only the owner can read
+fn write_json(path: &std::path::Path, value: &impl serde::Serialize) -> anyhow::Result<()> {
+    let json = serde_json::to_string_pretty(value)?;
+    std::fs::write(path, &json)?;
+
+    // Set restrictive permissions (owner-only read/write)
+    #[cfg(unix)]
+    {
+        let perms = std::fs::Permissions::from_mode(0o600);
+        if let Err(e) = std::fs::set_permissions(path, perms) {
+            log::warn!("Failed to set settings file permissions: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+impl LlmSettings {
+    /// Checks beyond the ranges in SETTINGS_FIELDS
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if let Some(model) = &self.model {
+            if !crate::llm::llm_model_manager::is_known_model(model) {
+                errors.push(FieldError::new("llm.model", format!("Unknown LLM model: {}", model)));
+            }
+        }
+        if self.n_batch > self.n_ctx {
+            errors.push(FieldError::new(
+                "llm.n_batch",
+                format!("Must be at most n_ctx ({}), got {}", self.n_ctx, self.n_batch),
+            ));
+        }
+        if self.max_tokens > self.n_ctx {
+            errors.push(FieldError::new(
+                "llm.max_tokens",
+                format!("Must be at most n_ctx ({}), got {}", self.n_ctx, self.max_tokens),
+            ));
+        }
+        errors
+    }
+}
+
+/// Keep a copy of a settings file from an older version as `settings.json.bak`
+/// before it is first overwritten in the current layout. Secrets are left
+/// out of the copy; they belong in the secret store, not in a second file.
+fn backup_before_upgrade(path: &std::path::Path) {
+    let Some(mut map) = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+        .and_then(|raw| match raw {
+            serde_json::Value::Object(map) => Some(map),
+            _ => None,
+        })
+    else {
+        return;
+    };
+    let version = raw_version(&map);
+    if version >= SETTINGS_VERSION {
+        return;
+    }
+
+    let backup = path.with_extension("json.bak");
+    if backup.exists() {
+        return;
+    }
+    for secret in crate::secrets::Secret::ALL {
+        map.remove(secret.name());
+    }
+    match write_json(&backup, &serde_json::Value::Object(map)) {
+        Ok(()) => log::info!("Saved version {} settings to {:?}", version, backup),
+        Err(e) => log::warn!("Failed to back up settings before upgrade: {}", e),
+    }
+}
+
+/// "auto" or a 2–3 letter lowercase language code, as whisper expects
+pub(crate) fn is_valid_language(language: &str) -> bool {
+    language == "auto"
+        || ((2..=3).contains(&language.len()) && language.bytes().all(|b| b.is_ascii_lowercase()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyModifier {
+    Ctrl,
+    /// Option on macOS
+    Alt,
+    Shift,
+    /// Cmd on macOS, the Windows key on Windows
+    Super,
+}
+
+/// Hotkey modifiers in canonical order: (modifier, aliases, macOS name, name elsewhere)
+const HOTKEY_MODIFIERS: &[(HotkeyModifier, &[&str], &str, &str)] = &[
+    (HotkeyModifier::Ctrl, &["ctrl", "control", "ctl", "\u{2303}"], "Ctrl", "Ctrl"),
+    (HotkeyModifier::Alt, &["alt", "option", "opt", "\u{2325}"], "Option", "Alt"),
+    (HotkeyModifier::Shift, &["shift", "\u{21e7}"], "Shift", "Shift"),
+    (
+        HotkeyModifier::Super,
+        &["super", "cmd", "command", "meta", "win", "windows", "\u{2318}"],
+        "Cmd",
+        "Super",
+    ),
+];
+
+/// Named keys: (aliases, canonical name). Letters, digits and F1–F24 are handled separately.
+const HOTKEY_KEYS: &[(&[&str], &str)] = &[
+    (&["space", " "], "Space"),
+    (&["enter", "return"], "Enter"),
+    (&["tab"], "Tab"),
+    (&["escape", "esc"], "Escape"),
+    (&["backspace"], "Backspace"),
+    (&["delete", "del"], "Delete"),
+    (&["insert", "ins"], "Insert"),
+    (&["home"], "Home"),
+    (&["end"], "End"),
+    (&["pageup", "pgup"], "PageUp"),
+    (&["pagedown", "pgdn"], "PageDown"),
+    (&["up", "arrowup"], "Up"),
+    (&["down", "arrowdown"], "Down"),
+    (&["left", "arrowleft"], "Left"),
+    (&["right", "arrowright"], "Right"),
+    (&["minus", "-"], "Minus"),
+    (&["plus", "+"], "Plus"),
+    (&["equal", "equals", "="], "Equal"),
+    (&["comma", ","], "Comma"),
+    (&["period", "dot", "."], "Period"),
+    (&["slash", "/"], "Slash"),
+    (&["backslash", "\\"], "Backslash"),
+    (&["semicolon", ";"], "Semicolon"),
+    (&["quote", "apostrophe", "'"], "Quote"),
+    (&["backquote", "backtick", "grave", "`"], "Backquote"),
+    (&["bracketleft", "["], "BracketLeft"),
+    (&["bracketright", "]"], "BracketRight"),
+    (&["capslock"], "CapsLock"),
+];
+
+/// Split a hotkey into parts on '+', or on '-' if it has no '+'
+/// ("Cmd-Shift-D"). A doubled separator at the end is the key itself ("Ctrl++").
+fn hotkey_parts(hotkey: &str) -> Vec<&str> {
+    let hotkey = hotkey.trim();
+    let separator = if hotkey.contains('+') { '+' } else { '-' };
+    if hotkey.chars().count() <= 1 {
+        return vec![hotkey];
+    }
+
+    let doubled = format!("{0}{0}", separator);
+    let (rest, last) = match hotkey.strip_suffix(doubled.as_str()) {
+        Some(rest) => (rest, Some(&hotkey[hotkey.len() - 1..])),
+        None => (hotkey, None),
+    };
+    let mut parts: Vec<&str> = rest.split(separator).map(str::trim).collect();
+    parts.extend(last);
+    parts
+}
+
+fn hotkey_key(part: &str) -> Option<String> {
+    let lower = part.to_lowercase();
+    if let Some((_, name)) = HOTKEY_KEYS.iter().find(|(aliases, _)| aliases.contains(&lower.as_str())) {
+        return Some(name.to_string());
+    }
+
+    let mut chars = part.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return c.is_ascii_alphanumeric().then(|| c.to_ascii_uppercase().to_string());
+    }
+
+    let number = lower.strip_prefix('f')?.parse::<u8>().ok()?;
+    (1..=24).contains(&number).then(|| format!("F{}", number))
+}
+
+/// A parsed hotkey: modifiers in canonical order, then the key's canonical
+/// name ("D", "Space", "F5"). Displays in the form `normalize_hotkey` returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hotkey {
+    pub modifiers: Vec<HotkeyModifier>,
+    pub key: String,
+}
+
+impl Hotkey {
+    pub fn new(modifiers: &[HotkeyModifier], key: &str) -> Self {
+        let modifiers = HOTKEY_MODIFIERS
+            .iter()
+            .map(|(modifier, ..)| *modifier)
+            .filter(|modifier| modifiers.contains(modifier))
+            .collect();
+        Self {
+            modifiers,
+            key: key.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (modifier, _, mac, other) in HOTKEY_MODIFIERS {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}+", if cfg!(target_os = "macos") { mac } else { other })?;
+            }
+        }
+        f.write_str(&self.key)
+    }
+}
+
+/// Parse a hotkey such as "ctrl + shift + d", "Cmd-Shift-D" or "opt+space"
+/// into its canonical form: modifiers in a fixed order using this
+/// platform's names, then the key ("Ctrl+Shift+D", "Alt+Space").
+pub fn normalize_hotkey(hotkey: &str) -> Result<String, String> {
+    parse_hotkey(hotkey).map(|hotkey| hotkey.to_string())
+}
+
+/// Parse a hotkey as described in `normalize_hotkey`
+pub fn parse_hotkey(hotkey: &str) -> Result<Hotkey, String> {
+    let parts = hotkey_parts(hotkey);
+    if parts.iter().any(|p| p.is_empty()) {
+        return Err(format!("Invalid hotkey {:?}", hotkey));
+    }
+
+    let mut modifiers = [false; HOTKEY_MODIFIERS.len()];
+    let mut key = None;
+    for (i, part) in parts.iter().enumerate() {
+        let lower = part.to_lowercase();
+        if let Some(index) = HOTKEY_MODIFIERS
+            .iter()
+            .position(|(_, aliases, _, _)| aliases.contains(&lower.as_str()))
+        {
+            if key.is_some() {
+                return Err(format!("Modifier {:?} must come before the key", part));
+            }
+            if std::mem::replace(&mut modifiers[index], true) {
+                return Err(format!("Modifier {:?} appears twice in hotkey", part));
+            }
+        } else if let Some(name) = hotkey_key(part) {
+            if let Some(previous) = key.replace(name) {
+                return Err(format!("Hotkey has more than one key ({} and {})", previous, part));
+            }
+        } else if i + 1 < parts.len() {
+            return Err(format!("Unknown modifier {:?} in hotkey", part));
+        } else {
+            return Err(format!("Unknown key {:?} in hotkey", part));
+        }
+    }
+    let key = key.ok_or_else(|| format!("Hotkey {:?} has no key besides modifiers", hotkey))?;
+
+    let modifiers = HOTKEY_MODIFIERS
+        .iter()
+        .zip(modifiers)
+        .filter(|(_, held)| *held)
+        .map(|((modifier, ..), _)| *modifier)
+        .collect();
+    Ok(Hotkey { modifiers, key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_json(path: &std::path::Path) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn load_backs_up_without_secrets_before_stripping() {
+        let env = crate::test_support::env();
+        let path = settings_path().unwrap();
+        let old = serde_json::json!({
+            "hotkey": "Ctrl+Shift+Space",
+            "llm_api_key": "sk-test",
+            "hf_token": "hf_test",
+        });
+        std::fs::write(&path, old.to_string()).unwrap();
+
+        let settings = Settings::load();
+        assert!(settings.has_llm_api_key && settings.has_hf_token);
+
+        let backup = read_json(&path.with_extension("json.bak"));
+        assert_eq!(backup["hotkey"], "Ctrl+Shift+Space");
+        assert!(backup.get("llm_api_key").is_none());
+        assert!(backup.get("hf_token").is_none());
+
+        let rewritten = read_json(&path);
+        assert!(rewritten.get("llm_api_key").is_none());
+        assert_eq!(rewritten["schema_version"], SETTINGS_VERSION);
+        assert!(env.path().join("secrets.json").exists());
+    }
+
+    #[test]
+    fn v1_migration_keeps_its_shipped_hotkey_check() {
+        // Valid under the version 1 rules, so v1 keeps it; v3 then canonicalizes
+        let mut map = serde_json::json!({ "hotkey": "control + shift + k" });
+        let map = map.as_object_mut().unwrap();
+        migrate_v1(map);
+        assert_eq!(map["hotkey"], "control + shift + k");
+        migrate_v3(map);
+        assert_eq!(map["hotkey"], "Ctrl+Shift+K");
+    }
+
+    #[test]
+    fn hotkeys_normalize_to_one_spelling() {
+        // Expected values use the non-macOS names
+        let cases: &[(&str, Result<&str, &str>)] = &[
+            ("Cmd+D", Ok("Super+D")),
+            ("command+d", Ok("Super+D")),
+            ("Meta+K", Ok("Super+K")),
+            ("super+k", Ok("Super+K")),
+            ("Win+V", Ok("Super+V")),
+            ("Option+Space", Ok("Alt+Space")),
+            ("alt+space", Ok("Alt+Space")),
+            ("Control+Shift+K", Ok("Ctrl+Shift+K")),
+            ("ctrl+a", Ok("Ctrl+A")),
+            ("Shift+Ctrl+Alt+F5", Ok("Ctrl+Alt+Shift+F5")),
+            ("Super+Alt+Ctrl+Shift+z", Ok("Ctrl+Alt+Shift+Super+Z")),
+            ("Alt +Space", Ok("Alt+Space")),
+            ("  CTRL + shift + d  ", Ok("Ctrl+Shift+D")),
+            ("Cmd-Shift-D", Ok("Shift+Super+D")),
+            ("Ctrl++", Ok("Ctrl+Plus")),
+            ("Ctrl+Control+K", Err("appears twice")),
+            ("Cmd+Meta+K", Err("appears twice")),
+            ("", Err("Invalid hotkey")),
+            ("Ctrl+", Err("Invalid hotkey")),
+            ("Shift", Err("no key besides modifiers")),
+            ("Ctrl+Alt", Err("no key besides modifiers")),
+            ("A+B", Err("more than one key")),
+            ("Ctrl+Space+K", Err("more than one key")),
+            ("K+Ctrl", Err("must come before the key")),
+            ("Hyper+K", Err("Unknown modifier")),
+            ("Ctrl+Nonsense", Err("Unknown key")),
+        ];
+        for (input, expected) in cases {
+            match (normalize_hotkey(input), expected) {
+                (Ok(got), Ok(want)) => {
+                    let want = if cfg!(target_os = "macos") {
+                        want.replace("Alt", "Option").replace("Super", "Cmd")
+                    } else {
+                        want.to_string()
+                    };
+                    assert_eq!(got, want, "{:?}", input);
+                    // Canonical spellings are fixed points
+                    assert_eq!(normalize_hotkey(&got).as_ref(), Ok(&got), "{:?}", input);
+                }
+                (Err(got), Err(want)) => assert!(got.contains(want), "{:?}: {}", input, got),
+                (got, want) => panic!("{:?}: got {:?}, want {:?}", input, got, want),
+            }
+        }
+    }
+
+    #[test]
+    fn parsed_hotkeys_keep_modifiers_in_canonical_order() {
+        let hotkey = parse_hotkey("Cmd+Option+Shift+Control+V").unwrap();
+        assert_eq!(
+            hotkey.modifiers,
+            [
+                HotkeyModifier::Ctrl,
+                HotkeyModifier::Alt,
+                HotkeyModifier::Shift,
+                HotkeyModifier::Super
+            ]
+        );
+        assert_eq!(hotkey.key, "V");
+        let shuffled = [
+            HotkeyModifier::Super,
+            HotkeyModifier::Ctrl,
+            HotkeyModifier::Shift,
+            HotkeyModifier::Alt,
+        ];
+        assert_eq!(hotkey, Hotkey::new(&shuffled, "V"));
+    }
+
+    #[test]
+    fn v3_migration_drops_hotkeys_that_no_longer_parse() {
+        let _env = crate::test_support::env();
+        let settings =
+            migrate(serde_json::json!({ "schema_version": 2, "hotkey": "Ctrl+Nonsense" }));
+        assert_eq!(settings.hotkey, Settings::default().hotkey);
+        assert_eq!(settings.schema_version, SETTINGS_VERSION);
+    }
+
+    #[test]
+    fn current_files_are_not_backed_up() {
+        let _env = crate::test_support::env();
+        let path = settings_path().unwrap();
+        Settings::default().save().unwrap();
+        Settings::load().save().unwrap();
+        assert!(!path.with_extension("json.bak").exists());
+    }
+
+    #[test]
+    fn structured_mode_round_trips_through_json() {
+        let settings = Settings {
+            prompt_mode: PromptMode::Structured,
+            prompt_mode_chain: vec![PromptMode::Clean, PromptMode::Structured],
+            ..Settings::default()
+        };
+        let json = serde_json::to_value(&settings).unwrap();
+        assert_eq!(json["prompt_mode"], "structured");
+        assert_eq!(json["prompt_mode_chain"], serde_json::json!(["clean", "structured"]));
+
+        let parsed = from_json(&json.to_string()).unwrap();
+        assert_eq!(parsed.prompt_mode, PromptMode::Structured);
+        assert!(parsed.validate().is_ok());
+    }
+
+    /// The (field, message) pairs `validate` reports for `settings`
+    fn errors(settings: &Settings) -> Vec<(String, String)> {
+        match settings.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|e| (e.field, e.message)).collect(),
+        }
+    }
+
+    fn fields(settings: &Settings) -> Vec<String> {
+        errors(settings).into_iter().map(|(field, _)| field).collect()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(errors(&Settings::default()), []);
+    }
+
+    #[test]
+    fn schema_lists_every_serialized_field() {
+        let skipped = |key: &str| ["extra", "has_llm_api_key", "has_hf_token"].contains(&key);
+        let json = serde_json::to_value(Settings::default()).unwrap();
+        let mut serialized = std::collections::BTreeSet::new();
+        for (key, value) in json.as_object().unwrap() {
+            serialized.insert(key.clone());
+            if let Some(block) = value.as_object() {
+                serialized.extend(block.keys().map(|sub| format!("{}.{}", key, sub)));
+            }
+        }
+        serialized.retain(|key| !skipped(key));
+        let listed: std::collections::BTreeSet<String> = SETTINGS_FIELDS
+            .iter()
+            .map(|spec| spec.key.to_string())
+            .filter(|key| !skipped(key))
+            .collect();
+
+        let unlisted: Vec<_> = serialized.difference(&listed).collect();
+        assert!(unlisted.is_empty(), "missing from SETTINGS_FIELDS: {:?}", unlisted);
+        let stale: Vec<_> = listed.difference(&serialized).collect();
+        assert!(stale.is_empty(), "not in Settings: {:?}", stale);
+    }
+
+    #[test]
+    fn numeric_fields_must_be_in_range() {
+        let mut settings = Settings {
+            paste_delay_ms: 600_000,
+            ..Default::default()
+        };
+        settings.audio.input_gain = 0.0;
+        settings.llm.temperature = 2.5;
+        assert_eq!(
+            errors(&settings),
+            [
+                ("audio.input-gain".to_string(), "Must be between 0.1 and 10, got 0".to_string()),
+                ("llm.temperature".to_string(), "Must be at most 2, got 2.5".to_string()),
+                ("paste_delay_ms".to_string(), "Must be at most 5000 ms, got 600000".to_string()),
+            ]
+        );
+
+        // Bounds are inclusive, compared at the stored precision
+        let mut settings = Settings::default();
+        settings.audio.input_gain = 10.0;
+        settings.llm.top_p = 0.01;
+        assert_eq!(errors(&settings), []);
+    }
+
+    #[test]
+    fn zero_turns_off_fields_that_allow_it() {
+        let mut settings = Settings::default();
+        settings.audio.silence_auto_stop_ms = 0;
+        assert_eq!(errors(&settings), []);
+
+        settings.audio.silence_auto_stop_ms = 100;
+        assert_eq!(
+            errors(&settings),
+            [(
+                "audio.silence-auto-stop-ms".to_string(),
+                "Must be 0 (off) or between 500 and 30000 ms, got 100".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn token_limits_must_fit_the_context() {
+        let mut settings = Settings::default();
+        settings.llm.n_ctx = 1024;
+        settings.llm.n_batch = 2048;
+        settings.llm.max_tokens = 1024;
+        assert_eq!(fields(&settings), ["llm.n_batch"]);
+
+        settings.llm.n_batch = 512;
+        settings.llm.max_tokens = 1025;
+        assert_eq!(fields(&settings), ["llm.max_tokens"]);
+    }
+
+    #[test]
+    fn models_must_be_in_the_catalogs() {
+        let mut settings = Settings {
+            whisper_model: "huge".to_string(),
+            ..Default::default()
+        };
+        settings.llm.model = Some("gpt-9".to_string());
+        assert_eq!(fields(&settings), ["whisper_model", "llm.model"]);
+
+        settings.whisper_model = "base".to_string();
+        settings.llm.model = None;
+        assert_eq!(errors(&settings), []);
+    }
+
+    #[test]
+    fn language_must_be_auto_or_a_code() {
+        for valid in ["auto", "en", "yue"] {
+            let settings = Settings {
+                language: valid.to_string(),
+                ..Default::default()
+            };
+            assert_eq!(errors(&settings), [], "{}", valid);
+        }
+        for invalid in ["", "EN", "english", "e1"] {
+            let settings = Settings {
+                language: invalid.to_string(),
+                ..Default::default()
+            };
+            assert_eq!(fields(&settings), ["language"], "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn language_model_map_needs_codes_and_known_models() {
+        let mut settings = Settings::default();
+        settings.language_model_map.insert("ja".to_string(), "small".to_string());
+        assert_eq!(errors(&settings), []);
+
+        settings.language_model_map.insert("auto".to_string(), "small".to_string());
+        assert_eq!(fields(&settings), ["language_model_map"]);
+
+        settings.language_model_map.remove("auto");
+        settings.language_model_map.insert("de".to_string(), "huge".to_string());
+        assert_eq!(
+            errors(&settings),
+            [("language_model_map".to_string(), "Unknown whisper model for de: huge".to_string())]
+        );
+    }
+
+    #[test]
+    fn mode_model_overrides_need_known_modes_and_models() {
+        let model = "qwen3-4b-instruct-q4km".to_string();
+        let mut settings = Settings::default();
+        settings.mode_model_overrides.insert("code".to_string(), model.clone());
+        assert_eq!(errors(&settings), []);
+
+        settings.mode_model_overrides.insert("poetry".to_string(), model);
+        settings.mode_model_overrides.insert("formal".to_string(), "gpt-9".to_string());
+        let mut found = errors(&settings);
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                (
+                    "mode_model_overrides".to_string(),
+                    "Unknown LLM model for formal: gpt-9".to_string()
+                ),
+                ("mode_model_overrides".to_string(), "Unknown prompt mode: poetry".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn mode_chain_is_limited_and_keeps_raw_alone() {
+        let mut settings = Settings {
+            prompt_mode_chain: vec![PromptMode::Raw],
+            ..Default::default()
+        };
+        assert_eq!(errors(&settings), []);
+
+        for chain in [
+            vec![PromptMode::Raw, PromptMode::Clean],
+            vec![PromptMode::Clean, PromptMode::Raw, PromptMode::Code],
+            vec![PromptMode::Clean, PromptMode::Raw],
+        ] {
+            settings.prompt_mode_chain = chain;
+            assert_eq!(
+                errors(&settings),
+                [(
+                    "prompt_mode_chain".to_string(),
+                    "Cannot include raw mode alongside other modes".to_string()
+                )]
+            );
+        }
+
+        settings.prompt_mode_chain = vec![PromptMode::Clean; MAX_MODE_CHAIN_LEN + 1];
+        assert_eq!(
+            errors(&settings),
+            [("prompt_mode_chain".to_string(), "Has 4 modes (maximum 3)".to_string())]
+        );
+    }
+
+    #[test]
+    fn hotkeys_and_paste_combos_must_parse() {
+        for invalid in ["", "Ctrl+", "Space+Ctrl", "Ctrl+Ctrl+A", "Hyper+A", "Ctrl+Shift"] {
+            let settings = Settings {
+                hotkey: invalid.to_string(),
+                ..Default::default()
+            };
+            assert_eq!(fields(&settings), ["hotkey"], "{:?}", invalid);
+        }
+
+        let mut settings = Settings {
+            paste_method: PasteMethod::CustomCombo("Ctrl+Bogus".to_string()),
+            ..Default::default()
+        };
+        settings
+            .app_paste_overrides
+            .insert("Terminal".to_string(), PasteMethod::CustomCombo("Shift".to_string()));
+        assert_eq!(fields(&settings), ["paste_method", "app_paste_overrides"]);
+
+        settings.paste_method = PasteMethod::CustomCombo("Ctrl+Shift+V".to_string());
+        settings.app_paste_overrides.clear();
+        assert_eq!(errors(&settings), []);
+    }
+
+    #[test]
+    fn save_refuses_invalid_settings_without_writing() {
+        let _env = crate::test_support::env();
+        let settings = Settings {
+            hotkey: String::new(),
+            ..Default::default()
+        };
+        let err = settings.save().unwrap_err();
+        assert!(err.to_string().contains("hotkey"), "{}", err);
+        assert!(!settings_path().unwrap().exists());
+    }
+
+    fn merge(settings: &Settings, patch: serde_json::Value) -> Result<Settings, Vec<FieldError>> {
+        settings.merged(&patch)
+    }
+
+    #[test]
+    fn patch_changes_only_the_fields_it_names() {
+        let current = Settings {
+            custom_system_prompt: Some("be brief".to_string()),
+            paste_delay_ms: 200,
+            ..Default::default()
+        };
+        let updated = merge(&current, serde_json::json!({ "prompt_mode": "formal" })).unwrap();
+        assert_eq!(updated.prompt_mode, PromptMode::Formal);
+        let expected = Settings {
+            prompt_mode: PromptMode::Formal,
+            ..current.clone()
+        };
+        assert_eq!(
+            serde_json::to_value(&updated).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+
+        let patch = serde_json::json!({ "paste_method": { "custom-combo": "Ctrl+B" } });
+        let updated = merge(&current, patch).unwrap();
+        assert_eq!(updated.paste_method, PasteMethod::CustomCombo("Ctrl+B".to_string()));
+    }
+
+    #[test]
+    fn null_clears_optional_fields() {
+        let mut current = Settings {
+            custom_system_prompt: Some("be brief".to_string()),
+            ..Default::default()
+        };
+        current.llm.model = Some("qwen3-4b-instruct-q4km".to_string());
+
+        let updated = merge(&current, serde_json::json!({ "custom_system_prompt": null })).unwrap();
+        assert_eq!(updated.custom_system_prompt, None);
+        assert_eq!(updated.llm.model, current.llm.model);
+
+        // A required field can't be cleared
+        let errors = merge(&current, serde_json::json!({ "prompt_mode": null })).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "prompt_mode");
+    }
+
+    #[test]
+    fn nested_blocks_are_replaced_whole() {
+        let current = Settings {
+            redaction: RedactionSettings {
+                emails: true,
+                phone_numbers: true,
+                digit_sequences: false,
+            },
+            ..Default::default()
+        };
+        let patch = serde_json::json!({ "redaction": { "digit_sequences": true } });
+        assert_eq!(
+            merge(&current, patch).unwrap().redaction,
+            RedactionSettings {
+                emails: false,
+                phone_numbers: false,
+                digit_sequences: true,
+            }
+        );
+
+        // The old top-level model key patches into the current llm block
+        let mut current = Settings::default();
+        current.llm.temperature = 0.2;
+        let patch = serde_json::json!({ "local_llm_model": "qwen3-4b-instruct-q4km" });
+        let updated = merge(&current, patch).unwrap();
+        assert_eq!(updated.llm.model.as_deref(), Some("qwen3-4b-instruct-q4km"));
+        assert_eq!(updated.llm.temperature, 0.2);
+    }
+
+    #[test]
+    fn patch_errors_list_every_bad_field() {
+        let current = Settings::default();
+        let patch = serde_json::json!({ "colour": "red", "prompt_mode": "formal", "volume": 3 });
+        let errors = merge(&current, patch).unwrap_err();
+        let mut fields: Vec<_> =
+            errors.iter().map(|e| (e.field.as_str(), e.message.as_str())).collect();
+        fields.sort();
+        assert_eq!(fields, [("colour", "Unknown setting"), ("volume", "Unknown setting")]);
+
+        let patch = serde_json::json!({ "prompt_mode": "poetry", "paste_delay_ms": "soon" });
+        let errors = merge(&current, patch).unwrap_err();
+        let mut fields: Vec<_> = errors.iter().map(|e| e.field.as_str()).collect();
+        fields.sort();
+        assert_eq!(fields, ["paste_delay_ms", "prompt_mode"]);
+
+        let errors = merge(&current, serde_json::json!(["prompt_mode"])).unwrap_err();
+        assert_eq!(errors, [FieldError::general("Patch must be a JSON object")]);
+    }
+
+    /// A settings file as written by an earlier version
+    fn fixture(version: u32) -> serde_json::Value {
+        let json = match version {
+            0 => include_str!("../fixtures/settings/v0.json"),
+            1 => include_str!("../fixtures/settings/v1.json"),
+            2 => include_str!("../fixtures/settings/v2.json"),
+            3 => include_str!("../fixtures/settings/v3.json"),
+            _ => unreachable!(),
+        };
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn v0_files_are_clamped_and_upgraded() {
+        let _env = crate::test_support::env();
+        let settings = migrate(fixture(0));
+        assert_eq!(settings.schema_version, SETTINGS_VERSION);
+        assert_eq!(settings.hotkey, "Ctrl+Space");
+        assert_eq!(settings.paste_delay_ms, MAX_PASTE_DELAY_MS);
+        assert_eq!(settings.history_dedupe_window_secs, 1);
+        assert_eq!(
+            settings.prompt_mode_chain,
+            [PromptMode::Clean, PromptMode::Technical, PromptMode::Formal]
+        );
+        assert_eq!(settings.prompt_mode, PromptMode::Technical);
+        assert_eq!(settings.llm.model.as_deref(), Some("qwen3-4b-instruct-q4km"));
+        assert!(settings.extra.is_empty(), "{:?}", settings.extra);
+        assert_eq!(settings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn v1_files_move_the_llm_model_into_the_llm_block() {
+        let _env = crate::test_support::env();
+        let settings = migrate(fixture(1));
+        assert_eq!(settings.llm.model.as_deref(), Some("qwen3-4b-instruct-q4km"));
+        assert_eq!(settings.llm.temperature, 0.3);
+        assert_eq!(settings.llm.n_ctx, LlmSettings::default().n_ctx);
+        assert_eq!(settings.hotkey, normalize_hotkey("Super+Shift+K").unwrap());
+        assert_eq!(settings.whisper_model, "small");
+        assert_eq!(settings.paste_delay_ms, 250);
+        assert!(settings.extra.is_empty(), "{:?}", settings.extra);
+        assert_eq!(settings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn v2_files_get_a_canonical_hotkey() {
+        let _env = crate::test_support::env();
+        let settings = migrate(fixture(2));
+        assert_eq!(settings.hotkey, "Alt+F9");
+        assert_eq!(settings.llm.max_tokens, 256);
+        assert_eq!(
+            settings.mode_model_overrides.get("code").map(String::as_str),
+            Some("qwen3-4b-instruct-q4km")
+        );
+        assert_eq!(settings.schema_version, SETTINGS_VERSION);
+        assert_eq!(settings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn current_files_load_unchanged() {
+        let _env = crate::test_support::env();
+        let settings = migrate(fixture(3));
+        assert_eq!(settings.hotkey, "Ctrl+Shift+Space");
+        assert_eq!(settings.prompt_mode, PromptMode::Formal);
+        assert!(settings.redaction.emails && !settings.redaction.phone_numbers);
+        // Fields from a newer build survive a round trip
+        assert_eq!(
+            serde_json::to_value(&settings).unwrap()["added_by_a_newer_build"],
+            serde_json::json!({ "kept": true })
+        );
+    }
+
+    #[test]
+    fn unknown_fields_survive_load_and_save() {
+        let _env = crate::test_support::env();
+        let path = settings_path().unwrap();
+        let file = serde_json::json!({
+            "schema_version": SETTINGS_VERSION,
+            "hotkey": "Ctrl+Shift+Space",
+            "added_by_a_newer_build": { "kept": true },
+            "another_new_flag": 7,
+        });
+        std::fs::write(&path, file.to_string()).unwrap();
+
+        let settings = Settings::load();
+        assert_eq!(settings.extra.len(), 2, "{:?}", settings.extra);
+        settings.save().unwrap();
+        let saved = read_json(&path);
+        assert_eq!(saved["added_by_a_newer_build"], serde_json::json!({ "kept": true }));
+        assert_eq!(saved["another_new_flag"], 7);
+        assert_eq!(saved["hotkey"], "Ctrl+Shift+Space");
+
+        // A frontend that predates the fields doesn't send them at all
+        Settings::default().save().unwrap();
+        let saved = read_json(&path);
+        assert_eq!(saved["added_by_a_newer_build"], serde_json::json!({ "kept": true }));
+        assert_eq!(saved["another_new_flag"], 7);
+    }
+
+    #[test]
+    fn frontend_values_for_unknown_fields_win_over_disk() {
+        let _env = crate::test_support::env();
+        let path = settings_path().unwrap();
+        let file = serde_json::json!({
+            "schema_version": SETTINGS_VERSION,
+            "added_by_a_newer_build": "on disk",
+            "another_new_flag": 7,
+        });
+        std::fs::write(&path, file.to_string()).unwrap();
+
+        from_json(r#"{ "added_by_a_newer_build": "from the frontend" }"#)
+            .unwrap()
+            .save()
+            .unwrap();
+        let saved = read_json(&path);
+        assert_eq!(saved["added_by_a_newer_build"], "from the frontend");
+        assert_eq!(saved["another_new_flag"], 7, "fields it didn't send are kept");
+    }
+
+    #[test]
+    fn fields_removed_by_a_migration_stay_removed() {
+        let _env = crate::test_support::env();
+        let path = settings_path().unwrap();
+        let file = serde_json::json!({
+            "schema_version": 1,
+            "local_llm_model": "qwen3-4b-instruct-q4km",
+            "added_by_a_newer_build": true,
+        });
+        std::fs::write(&path, file.to_string()).unwrap();
+
+        let settings = Settings::load();
+        assert!(!settings.extra.contains_key("local_llm_model"));
+        assert_eq!(settings.llm.model.as_deref(), Some("qwen3-4b-instruct-q4km"));
+        // The first save reads the unmigrated file for unknown fields
+        settings.save().unwrap();
+        assert!(read_json(&path).get("local_llm_model").is_none());
+        assert_eq!(read_json(&path)["added_by_a_newer_build"], true);
+
+        Settings::load().save().unwrap();
+        Settings::default().save().unwrap();
+        let saved = read_json(&path);
+        assert!(saved.get("local_llm_model").is_none(), "{}", saved);
+        assert_eq!(saved["added_by_a_newer_build"], true);
+    }
+
+    #[test]
+    fn first_migrated_save_keeps_the_original_file() {
+        let _env = crate::test_support::env();
+        let path = settings_path().unwrap();
+        let original = fixture(1);
+        std::fs::write(&path, original.to_string()).unwrap();
+
+        let settings = Settings::load();
+        assert_eq!(read_json(&path), original, "loading alone doesn't rewrite the file");
+        settings.save().unwrap();
+        assert_eq!(read_json(&path.with_extension("json.bak")), original);
+        assert_eq!(read_json(&path)["schema_version"], SETTINGS_VERSION);
+
+        // Later saves leave the backup alone
+        Settings {
+            paste_delay_ms: 10,
+            ..Settings::load()
+        }
+        .save()
+        .unwrap();
+        assert_eq!(read_json(&path.with_extension("json.bak")), original);
+    }
+
+    #[test]
+    fn audio_defaults_match_the_old_hardcoded_behavior() {
+        let audio = AudioSettings::default();
+        assert_eq!(audio.vad_threshold, 0.005);
+        assert_eq!(audio.max_duration_secs, 0, "unlimited");
+        assert_eq!(audio.input_gain, 1.0);
+        assert_eq!(audio.silence_auto_stop_ms, 0, "off");
+        assert_eq!(audio.preroll_ms, 60, "two 30ms frames");
+        assert!(!audio.normalize);
+
+        // Files from before the block existed get the same values
+        let settings = from_json(r#"{ "input_device": "USB Mic" }"#).unwrap();
+        assert_eq!(settings.audio, audio);
+        assert_eq!(settings.input_device.as_deref(), Some("USB Mic"));
+    }
+
+    #[test]
+    fn audio_block_round_trips_with_kebab_case_keys() {
+        let audio = AudioSettings {
+            vad_threshold: 0.02,
+            max_duration_secs: 120,
+            input_gain: 2.5,
+            silence_auto_stop_ms: 1500,
+            preroll_ms: 300,
+            normalize: true,
+        };
+        let json = serde_json::to_value(&audio).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "vad-threshold": 0.02f32,
+                "max-duration-secs": 120,
+                "input-gain": 2.5,
+                "silence-auto-stop-ms": 1500,
+                "preroll-ms": 300,
+                "normalize": true,
+            })
+        );
+        let settings = Settings {
+            audio: audio.clone(),
+            ..Default::default()
+        };
+        let parsed = from_json(&serde_json::to_string(&settings).unwrap()).unwrap();
+        assert_eq!(parsed.audio, audio);
+        assert_eq!(parsed.validate(), Ok(()));
+
+        // Missing keys keep their defaults
+        let partial = from_json(r#"{ "audio": { "input-gain": 3.0 } }"#).unwrap();
+        assert_eq!(partial.audio.input_gain, 3.0);
+        assert_eq!(partial.audio.preroll_ms, AudioSettings::default().preroll_ms);
+    }
+
+    #[test]
+    fn mapping_to_a_missing_model_warns_without_failing() {
+        let _env = crate::test_support::env();
+        let mut settings = Settings::default();
+        settings.language_model_map.insert("ja".to_string(), "small".to_string());
+        assert_eq!(settings.validate(), Ok(()));
+        let warnings = settings.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].field, "language_model_map");
+        assert!(warnings[0].message.contains("small"), "{}", warnings[0].message);
+        settings.save().unwrap();
+
+        let path = crate::transcription::model_manager::get_model_path("small").unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"").unwrap();
+        assert!(settings.warnings().is_empty());
+    }
+
+    #[test]
+    fn paste_method_for_picks_the_closest_app_override() {
+        let overrides = [
+            ("iTerm", PasteMethod::ShiftInsert),
+            ("iterm2", PasteMethod::TypeOut),
+            ("Code", PasteMethod::CtrlShiftV),
+            ("Code - Insiders", PasteMethod::CustomCombo("Ctrl+Alt+V".to_string())),
+            ("  ", PasteMethod::MiddleClick),
+        ];
+        let settings = Settings {
+            paste_method: PasteMethod::CtrlV,
+            app_paste_overrides: overrides
+                .into_iter()
+                .map(|(app, method)| (app.to_string(), method))
+                .collect(),
+            ..Default::default()
+        };
+        let cases = [
+            (None, PasteMethod::CtrlV),
+            (Some(""), PasteMethod::CtrlV),
+            (Some("   "), PasteMethod::CtrlV),
+            // The blank key would be a prefix of every app; it is ignored
+            (Some("Safari"), PasteMethod::CtrlV),
+            // Exact matches ignore case and surrounding spaces
+            (Some("ITERM"), PasteMethod::ShiftInsert),
+            (Some(" code "), PasteMethod::CtrlShiftV),
+            // Prefixes
+            (Some("iTerm3"), PasteMethod::ShiftInsert),
+            (Some("Code Helper"), PasteMethod::CtrlShiftV),
+            // An exact match beats the shorter prefix "iTerm"
+            (Some("iTerm2"), PasteMethod::TypeOut),
+            // The longer prefix wins
+            (Some("Code - Insiders (Beta)"), PasteMethod::CustomCombo("Ctrl+Alt+V".to_string())),
+            // Keys are matched from the start of the app name
+            (Some("Visual Studio Code"), PasteMethod::CtrlV),
+        ];
+        for (app, expected) in cases {
+            assert_eq!(settings.paste_method_for(app), &expected, "{:?}", app);
+        }
+    }
+
+    #[test]
+    fn get_path_walks_nested_blocks() {
+        let mut settings = Settings::default();
+        settings.llm.max_tokens = 300;
+        settings.audio.vad_threshold = 0.25;
+        assert_eq!(settings.get_path("llm.max_tokens"), Some(300.into()));
+        assert_eq!(settings.get_path("audio.vad-threshold"), Some(serde_json::json!(0.25f32)));
+        // Dashes and underscores are interchangeable
+        assert_eq!(settings.get_path("audio.vad_threshold"), Some(serde_json::json!(0.25f32)));
+        assert_eq!(settings.get_path("llm"), serde_json::to_value(&settings.llm).ok());
+    }
+
+    #[test]
+    fn get_path_returns_enums_and_options_as_json() {
+        let settings = Settings {
+            prompt_mode: PromptMode::Structured,
+            paste_method: PasteMethod::CustomCombo("Ctrl+B".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(settings.get_path("prompt-mode"), Some("structured".into()));
+        assert_eq!(settings.get_path("prompt_mode"), Some("structured".into()));
+        assert_eq!(
+            settings.get_path("paste_method"),
+            Some(serde_json::json!({ "custom-combo": "Ctrl+B" }))
+        );
+        assert_eq!(settings.get_path("custom_system_prompt"), Some(serde_json::Value::Null));
+        assert_eq!(settings.get_path("schema_version"), Some(SETTINGS_VERSION.into()));
+    }
+
+    #[test]
+    fn get_path_is_none_for_unknown_keys_and_secrets() {
+        let _env = crate::test_support::env();
+        crate::secrets::set(crate::secrets::Secret::LlmApiKey, "sk-secret").unwrap();
+        let settings = Settings::load();
+        let keys = ["volume", "llm.volume", "prompt_mode.name", "", "llm.", "llm_api_key", "hf_token"];
+        for key in keys {
+            assert_eq!(settings.get_path(key), None, "{:?}", key);
+        }
+        assert_eq!(settings.get_path("has_llm_api_key"), Some(true.into()));
     }
 }
@@ -0,0 +1,93 @@
+//! Setup shared by unit tests that touch global state: the database, the
+//! data directory and the settings stored there.
+
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Held by every test using global state, so they don't run concurrently
+static GLOBAL_STATE: Mutex<()> = Mutex::new(());
+
+/// What transcription and LLM completion return while mocked, so tests can
+/// run the pipeline without models
+#[derive(Debug, Clone)]
+pub struct Mocks {
+    pub transcript: String,
+    pub completion: String,
+    /// How long each mocked call takes
+    pub delay: Duration,
+}
+
+/// Mock transcription and LLM completion until the test's `TestEnv` drops
+pub fn set_mocks(mocks: Mocks) {
+    crate::transcription::mock::install(crate::transcription::mock::MockWhisper {
+        transcript: mocks.transcript,
+        delay: mocks.delay,
+    });
+    crate::llm::mock::install(crate::llm::mock::MockLlm {
+        completion: mocks.completion,
+        delay: mocks.delay,
+        fail_mode: None,
+    });
+}
+
+/// Go back to whisper and the LLM
+fn clear_mocks() {
+    crate::transcription::mock::uninstall();
+    crate::llm::mock::uninstall();
+}
+
+/// `len` samples at 16 kHz of a tone loud enough to pass voice activity
+/// detection, for running the pipeline with mocked whisper
+pub fn speech(len: usize) -> Vec<f32> {
+    (0..len).map(|i| 0.3 * (i as f32 * 0.2).sin()).collect()
+}
+
+/// A test's hold on global state: an empty data directory and a fresh
+/// in-memory database, closed again on drop
+pub struct TestEnv {
+    _dir: tempfile::TempDir,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestEnv {
+    /// The test's data directory
+    pub fn path(&self) -> &std::path::Path {
+        self._dir.path()
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        clear_mocks();
+        crate::db::close();
+    }
+}
+
+/// Take the global-state lock without setting anything up, for tests that
+/// initialize on their own
+pub fn lock() -> MutexGuard<'static, ()> {
+    let guard = GLOBAL_STATE.lock().unwrap_or_else(|e| e.into_inner());
+    // An earlier test may have shut phemy down
+    reopen_runtime();
+    guard
+}
+
+/// Let `crate::runtime` start a runtime again after phemy_shutdown, for
+/// tests that don't go through phemy_init
+fn reopen_runtime() {
+    let mut state = crate::RUNTIME.lock().unwrap_or_else(|e| e.into_inner());
+    if matches!(*state, crate::RuntimeState::ShutDown) {
+        *state = crate::RuntimeState::NotStarted;
+    }
+}
+
+/// Take the global-state lock, point the data directory at a new temp dir
+/// and open a fresh in-memory database
+pub fn env() -> TestEnv {
+    let guard = lock();
+    let dir = tempfile::tempdir().expect("temp dir");
+    crate::settings::set_data_dir(dir.path().to_path_buf());
+    crate::db::close();
+    crate::db::init_in_memory().expect("in-memory database");
+    TestEnv { _dir: dir, _guard: guard }
+}
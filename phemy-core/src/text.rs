@@ -0,0 +1,167 @@
+//! Small Unicode-aware text measurements for transcripts.
+
+use std::collections::HashSet;
+
+/// Common English words that are never worth adding to the vocabulary
+static COMMON_WORDS: std::sync::LazyLock<HashSet<&'static str>> = std::sync::LazyLock::new(|| {
+    include_str!("common_words.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+});
+
+/// Scripts written without spaces between words. Each character counts as a word.
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF     // Hiragana, Katakana
+        | 0x3400..=0x4DBF   // CJK Extension A
+        | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+        | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+        | 0xFF66..=0xFF9F   // Halfwidth Katakana
+        | 0x20000..=0x2FA1F // CJK Extensions B–F, Compatibility Supplement
+    )
+}
+
+/// Characters that join two word characters into one word ("don't", "well-known")
+fn is_joiner(c: char) -> bool {
+    matches!(c, '\'' | '\u{2019}' | '-' | '\u{2010}')
+}
+
+/// Split text into words.
+///
+/// A word is a run of letters, digits and combining marks, optionally joined
+/// by apostrophes or hyphens. Punctuation on its own is not a word. CJK
+/// ideographs and kana are returned one character at a time.
+pub fn words(text: &str) -> impl Iterator<Item = &str> {
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || {
+        // Skip to the start of the next word
+        let (start, first) = loop {
+            let (i, c) = chars.next()?;
+            if c.is_alphanumeric() {
+                break (i, c);
+            }
+        };
+        if is_cjk(first) {
+            return Some(&text[start..start + first.len_utf8()]);
+        }
+
+        let mut end = start + first.len_utf8();
+        while let Some(&(i, c)) = chars.peek() {
+            if is_cjk(c) {
+                break;
+            }
+            if c.is_alphanumeric() || is_mark(c) {
+                end = i + c.len_utf8();
+                chars.next();
+            } else if is_joiner(c) {
+                // Only a joiner if a word character follows
+                chars.next();
+                match chars.peek() {
+                    Some(&(_, next)) if next.is_alphanumeric() && !is_cjk(next) => {}
+                    _ => break,
+                }
+            } else {
+                break;
+            }
+        }
+        Some(&text[start..end])
+    })
+}
+
+/// Combining marks (e.g. Devanagari vowel signs) that `is_alphanumeric` rejects
+fn is_mark(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F     // Combining Diacritical Marks
+        | 0x0900..=0x0DFF   // Indic scripts (vowel signs, viramas)
+        | 0x0E31..=0x0E4E   // Thai vowel and tone marks
+        | 0x200C..=0x200D   // Zero-width non-joiner / joiner
+    )
+}
+
+/// Number of words in `text`, as split by [`words`].
+pub fn count_words(text: &str) -> usize {
+    words(text).count()
+}
+
+/// Number of Unicode scalar values in `text`.
+pub fn count_chars(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Whether a word (as returned by [`words`]) could be a custom vocabulary
+/// entry: at least three characters, contains a letter, not a single CJK
+/// character and not one of the bundled common words.
+pub fn is_vocabulary_candidate(word: &str) -> bool {
+    if word.chars().count() < 3 || !word.chars().any(char::is_alphabetic) {
+        return false;
+    }
+    if word.chars().next().is_some_and(is_cjk) {
+        return false;
+    }
+    let normalized = word.to_lowercase().replace('\u{2019}', "'");
+    !COMMON_WORDS.contains(normalized.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str) -> Vec<&str> {
+        words(text).collect()
+    }
+
+    #[test]
+    fn punctuation_is_not_a_word() {
+        assert_eq!(split("Hello, world! -- (yes) ..."), ["Hello", "world", "yes"]);
+        assert_eq!(split("   "), Vec::<&str>::new());
+        assert_eq!(count_words("... !!! ?"), 0);
+        assert_eq!(split("v1.2 costs $30"), ["v1", "2", "costs", "30"]);
+    }
+
+    #[test]
+    fn apostrophes_and_hyphens_join_words() {
+        assert_eq!(
+            split("don't re-run the well\u{2010}known"),
+            ["don't", "re-run", "the", "well\u{2010}known"]
+        );
+        assert_eq!(split("it\u{2019}s state-of-the-art"), ["it\u{2019}s", "state-of-the-art"]);
+        // Not between two word characters: not part of the word
+        let split_off = split("'quoted' - dash trailing- -leading");
+        assert_eq!(split_off, ["quoted", "dash", "trailing", "leading"]);
+    }
+
+    #[test]
+    fn cjk_counts_each_character() {
+        assert_eq!(split("我爱你"), ["我", "爱", "你"]);
+        assert_eq!(split("ひらがなカタカナ"), ["ひ", "ら", "が", "な", "カ", "タ", "カ", "ナ"]);
+        assert_eq!(split("Rust语言 ok"), ["Rust", "语", "言", "ok"]);
+    }
+
+    #[test]
+    fn other_scripts_split_on_spaces() {
+        assert_eq!(split("Привет, мир"), ["Привет", "мир"]);
+        assert_eq!(split("नमस्ते दुनिया"), ["नमस्ते", "दुनिया"]);
+        assert_eq!(split("café naïve"), ["café", "naïve"]);
+        // Combining accent after a plain letter stays in the word
+        assert_eq!(split("cafe\u{301} x"), ["cafe\u{301}", "x"]);
+    }
+
+    #[test]
+    fn chars_count_scalar_values() {
+        assert_eq!(count_chars("héllo"), 5);
+        assert_eq!(count_chars("我爱你"), 3);
+        assert_eq!(count_chars(""), 0);
+    }
+
+    #[test]
+    fn vocabulary_candidates_skip_short_common_and_numeric_words() {
+        for word in ["Kubernetes", "phemy", "SQLite", "naïve"] {
+            assert!(is_vocabulary_candidate(word), "{}", word);
+        }
+        for word in ["the", "The", "would", "don't", "it\u{2019}s", "ab", "2024", "语"] {
+            assert!(!is_vocabulary_candidate(word), "{}", word);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use serde::Serialize;
+use std::time::Instant;
 
 use crate::settings::Settings;
 
@@ -8,6 +9,58 @@ pub struct TranscriptionResult {
     pub text: String,
     pub language: Option<String>,
     pub duration_secs: f64,
+    /// Whisper model that produced `text`
+    pub model_used: String,
+    /// Time spent resampling to 16 kHz
+    pub resample_ms: u64,
+    /// Time spent trimming silence and checking for speech
+    pub vad_ms: u64,
+    /// Time spent loading whisper models
+    pub model_load_ms: u64,
+    /// Time spent in whisper itself, not counting `model_load_ms`
+    pub transcription_ms: u64,
+}
+
+/// Output of one whisper run
+pub struct Transcript {
+    pub text: String,
+    /// Language whisper detected, when it was asked to ("auto")
+    pub detected_language: Option<String>,
+    /// Time spent loading the model
+    pub model_load_ms: u64,
+    /// Number of segments whisper split the audio into
+    pub segments: usize,
+}
+
+/// A step of a transcription, as reported to `transcribe_with_steps`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscribeStep {
+    Resampling,
+    /// Trimming silence and checking for speech
+    Vad,
+    /// Whisper is running
+    Transcribing,
+    /// A whisper run finished with this many segments
+    Transcribed { segments: usize },
+}
+
+/// Pick the whisper model for a transcription: an explicit `requested`
+/// model wins, then the `language_model_map` entry for `language` if that
+/// model is downloaded, then `whisper_model`.
+pub fn resolve_model<'a>(
+    settings: &'a Settings,
+    language: Option<&str>,
+    requested: Option<&'a str>,
+    is_downloaded: impl Fn(&str) -> bool,
+) -> &'a str {
+    if let Some(model) = requested {
+        return model;
+    }
+    language
+        .and_then(|language| settings.language_model_map.get(language))
+        .filter(|model| is_downloaded(model))
+        .map(String::as_str)
+        .unwrap_or(&settings.whisper_model)
 }
 
 /// Transcribe audio using local Whisper
@@ -16,38 +69,221 @@ pub async fn transcribe(
     sample_rate: u32,
     settings: &Settings,
 ) -> Result<TranscriptionResult> {
+    transcribe_with_model(samples, sample_rate, settings, None).await
+}
+
+/// Transcribe audio with `model` if given, otherwise the model chosen by
+/// [`resolve_model`].
+///
+/// With language "auto" the language isn't known until whisper detects it,
+/// so the audio is transcribed with the default model first and again with
+/// the mapped model if the detected language has a different one.
+///
+/// Runs as a transcription operation (see `crate::ops`); cancelling it stops
+/// the work before its next step with a `Cancelled` error.
+pub async fn transcribe_with_model(
+    samples: &[f32],
+    sample_rate: u32,
+    settings: &Settings,
+    model: Option<&str>,
+) -> Result<TranscriptionResult> {
+    transcribe_with_steps(samples, sample_rate, settings, model, &|_| {}).await
+}
+
+/// Like `transcribe_with_model`, calling `on_step` as each step starts and
+/// after each whisper run
+pub async fn transcribe_with_steps(
+    samples: &[f32],
+    sample_rate: u32,
+    settings: &Settings,
+    model: Option<&str>,
+    on_step: &(dyn Fn(TranscribeStep) + Sync),
+) -> Result<TranscriptionResult> {
+    let op = crate::ops::start(
+        crate::ops::OpKind::Transcription,
+        Some(model.unwrap_or(&settings.whisper_model)),
+    );
+
+    let configured_language = (settings.language != "auto").then_some(settings.language.as_str());
+    let model_used = resolve_model(
+        settings,
+        configured_language,
+        model,
+        crate::transcription::model_manager::is_downloaded,
+    )
+    .to_string();
+
     // Resample to 16kHz if needed
+    on_step(TranscribeStep::Resampling);
+    let started = Instant::now();
     let resampled = crate::audio::resampler::resample_to_16khz(samples, sample_rate)?;
+    let resample_ms = elapsed_ms(started);
+    op.check_cancelled()?;
+    op.set_progress(0.1);
 
     // Trim silence
-    let trimmed = crate::audio::vad::trim_silence(&resampled);
+    on_step(TranscribeStep::Vad);
+    let started = Instant::now();
+    let trimmed = crate::audio::vad::trim_silence(&resampled, &settings.audio);
+    let has_speech = crate::audio::vad::has_speech(trimmed, &settings.audio);
+    let vad_ms = elapsed_ms(started);
+    op.check_cancelled()?;
+    op.set_progress(0.2);
 
-    if !crate::audio::vad::has_speech(trimmed) {
+    if !has_speech {
         return Ok(TranscriptionResult {
             text: String::new(),
             language: Some(settings.language.clone()),
             duration_secs: trimmed.len() as f64 / 16000.0,
+            model_used,
+            resample_ms,
+            vad_ms,
+            model_load_ms: 0,
+            transcription_ms: 0,
         });
     }
 
     let duration_secs = trimmed.len() as f64 / 16000.0;
 
-    #[cfg(feature = "whisper-local")]
-    let text = super::whisper_local::transcribe(trimmed, &settings.whisper_model, &settings.language)
-        .await?;
+    let (text, language, model_used, model_load_ms, transcription_ms) = {
+        let started = Instant::now();
+        let normalized;
+        let samples = if settings.audio.normalize {
+            normalized = crate::audio::vad::normalize(trimmed);
+            &normalized[..]
+        } else {
+            trimmed
+        };
+        on_step(TranscribeStep::Transcribing);
+        let transcript = run_whisper(samples, &model_used, &settings.language).await?;
+        on_step(TranscribeStep::Transcribed { segments: transcript.segments });
+        let mut model_load_ms = transcript.model_load_ms;
+        op.check_cancelled()?;
+        op.set_progress(0.6);
 
-    #[cfg(not(feature = "whisper-local"))]
-    let text = {
-        anyhow::bail!(
-            "Local whisper not available. Build with --features whisper-local."
-        );
-        #[allow(unreachable_code)]
-        String::new()
+        let (text, language, model_used) = match transcript.detected_language {
+            Some(detected) => {
+                let mapped = resolve_model(
+                    settings,
+                    Some(&detected),
+                    model,
+                    crate::transcription::model_manager::is_downloaded,
+                );
+                if mapped != model_used {
+                    log::info!("Detected {}, transcribing again with '{}'", detected, mapped);
+                    on_step(TranscribeStep::Transcribing);
+                    let again = run_whisper(samples, mapped, &detected).await?;
+                    on_step(TranscribeStep::Transcribed { segments: again.segments });
+                    model_load_ms += again.model_load_ms;
+                    (again.text, detected, mapped.to_string())
+                } else {
+                    (transcript.text, detected, model_used)
+                }
+            }
+            None => (transcript.text, settings.language.clone(), model_used),
+        };
+        let transcription_ms = elapsed_ms(started).saturating_sub(model_load_ms);
+        (text, language, model_used, model_load_ms, transcription_ms)
     };
 
     Ok(TranscriptionResult {
         text,
-        language: Some(settings.language.clone()),
+        language: Some(language),
         duration_secs,
+        model_used,
+        resample_ms,
+        vad_ms,
+        model_load_ms,
+        transcription_ms,
     })
 }
+
+/// One whisper run: the mock's while one is installed (see `super::mock`),
+/// otherwise whisper.cpp
+async fn run_whisper(samples: &[f32], model: &str, language: &str) -> Result<Transcript> {
+    #[cfg(any(test, feature = "mock-audio"))]
+    if let Some(whisper) = super::mock::installed() {
+        return Ok(whisper.transcribe().await);
+    }
+
+    #[cfg(feature = "whisper-local")]
+    {
+        super::whisper_local::transcribe(samples, model, language).await
+    }
+
+    #[cfg(not(feature = "whisper-local"))]
+    {
+        let _ = (samples, model, language);
+        anyhow::bail!("Local whisper not available. Build with --features whisper-local.")
+    }
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    fn settings_with_map() -> Settings {
+        let mut settings = Settings {
+            whisper_model: "base".to_string(),
+            ..Default::default()
+        };
+        settings.language_model_map.insert("ja".to_string(), "small".to_string());
+        settings
+    }
+
+    #[test]
+    fn requested_model_beats_the_language_map() {
+        let settings = settings_with_map();
+        let model = resolve_model(&settings, Some("ja"), Some("tiny"), |_| true);
+        assert_eq!(model, "tiny");
+    }
+
+    #[test]
+    fn language_map_beats_the_default_when_downloaded() {
+        let settings = settings_with_map();
+        assert_eq!(resolve_model(&settings, Some("ja"), None, |_| true), "small");
+        // Not downloaded yet: fall back to the default
+        assert_eq!(resolve_model(&settings, Some("ja"), None, |_| false), "base");
+    }
+
+    #[test]
+    fn unmapped_or_unknown_languages_use_the_default() {
+        let settings = settings_with_map();
+        assert_eq!(resolve_model(&settings, Some("en"), None, |_| true), "base");
+        assert_eq!(resolve_model(&settings, None, None, |_| true), "base");
+    }
+
+    #[test]
+    fn steps_come_in_order_and_stop_after_vad_without_speech() {
+        let _env = test_support::env();
+        test_support::set_mocks(test_support::Mocks {
+            transcript: "hello".to_string(),
+            completion: String::new(),
+            delay: Duration::ZERO,
+        });
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let settings = Settings::default();
+        let run = |samples: Vec<f32>| {
+            let steps = Mutex::new(Vec::new());
+            let on_step = |step| steps.lock().unwrap().push(step);
+            let result = runtime.block_on(transcribe_with_steps(&samples, 16_000, &settings, None, &on_step)).unwrap();
+            (result.text, steps.into_inner().unwrap())
+        };
+
+        let (text, steps) = run(test_support::speech(8_000));
+        assert_eq!(text, "hello");
+        use TranscribeStep::*;
+        assert_eq!(steps, [Resampling, Vad, Transcribing, Transcribed { segments: 1 }]);
+
+        let (text, steps) = run(vec![0.0; 8_000]);
+        assert_eq!(text, "");
+        assert_eq!(steps, [Resampling, Vad]);
+    }
+}
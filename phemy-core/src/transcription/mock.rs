@@ -0,0 +1,54 @@
+//! A stand-in for whisper, for running the pipeline without a model (the
+//! `mock-audio` feature, and unit tests). While `install`ed, every whisper
+//! run returns its transcript after its delay; resampling and VAD still
+//! run as usual.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::engine::Transcript;
+
+#[derive(Debug, Clone)]
+pub struct MockWhisper {
+    pub transcript: String,
+    /// How long each run takes
+    pub delay: Duration,
+}
+
+static INSTALLED: Mutex<Option<MockWhisper>> = Mutex::new(None);
+
+fn slot() -> std::sync::MutexGuard<'static, Option<MockWhisper>> {
+    INSTALLED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Use `whisper` instead of whisper.cpp until `uninstall`
+pub fn install(whisper: MockWhisper) {
+    *slot() = Some(whisper);
+}
+
+/// Go back to whisper.cpp
+pub fn uninstall() {
+    *slot() = None;
+}
+
+pub fn is_installed() -> bool {
+    slot().is_some()
+}
+
+/// The installed mock, if any
+pub(super) fn installed() -> Option<MockWhisper> {
+    slot().clone()
+}
+
+impl MockWhisper {
+    /// A whisper run: one segment, no detected language
+    pub(super) async fn transcribe(self) -> Transcript {
+        tokio::time::sleep(self.delay).await;
+        Transcript {
+            text: self.transcript,
+            detected_language: None,
+            model_load_ms: 0,
+            segments: 1,
+        }
+    }
+}
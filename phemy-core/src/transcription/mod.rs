@@ -1,4 +1,6 @@
 pub mod engine;
+#[cfg(any(test, feature = "mock-audio"))]
+pub mod mock;
 pub mod model_manager;
 #[cfg(feature = "whisper-local")]
 pub mod whisper_local;
@@ -1,10 +1,10 @@
 use anyhow::Result;
 use serde::Serialize;
-use sha2::{Digest, Sha256};
 use std::path::PathBuf;
 use std::sync::Mutex;
 
 #[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct WhisperModel {
     pub name: String,
     pub size_mb: u64,
@@ -51,6 +51,16 @@ pub fn get_model_path(name: &str) -> Result<PathBuf> {
     Ok(models_dir.join(filename))
 }
 
+/// Whether `name` is a model in the catalog (downloaded or not)
+pub fn is_known_model(name: &str) -> bool {
+    MODELS.iter().any(|(n, _, _, _)| *n == name)
+}
+
+/// Whether `name` is a catalog model whose file is present
+pub fn is_downloaded(name: &str) -> bool {
+    get_model_path(name).map(|path| path.exists()).unwrap_or(false)
+}
+
 pub fn list_models() -> Result<Vec<WhisperModel>> {
     let models_dir = crate::utils::models_dir()?;
 
@@ -68,6 +78,26 @@ pub fn list_models() -> Result<Vec<WhisperModel>> {
 }
 
 pub async fn download_model(name: &str) -> Result<()> {
+    download_model_with_progress(name, |_| {}).await
+}
+
+/// Like `download_model`, also passing each progress update (the one
+/// `get_download_progress` returns) to `on_progress`
+pub async fn download_model_with_progress(
+    name: &str,
+    on_progress: impl FnMut(&DownloadProgress),
+) -> Result<()> {
+    let op = crate::ops::start(crate::ops::OpKind::Download, Some(name));
+    download_model_as(name, op, on_progress).await
+}
+
+/// `download_model_with_progress` run as the already registered `op`, for
+/// callers that hand out its id before the download starts
+pub async fn download_model_as(
+    name: &str,
+    op: crate::ops::Op,
+    mut on_progress: impl FnMut(&DownloadProgress),
+) -> Result<()> {
     let (_, filename, _, expected_sha256) = MODELS
         .iter()
         .find(|(n, _, _, _)| *n == name)
@@ -78,69 +108,45 @@ pub async fn download_model(name: &str) -> Result<()> {
 
     log::info!("Downloading whisper model '{}' from {}", name, url);
 
-    let client = reqwest::Client::new();
-    let response = client.get(&url).send().await?;
-
-    if !response.status().is_success() {
-        anyhow::bail!("Failed to download model: HTTP {}", response.status());
-    }
-
-    let total_bytes = response.content_length().unwrap_or(0);
-    let mut downloaded_bytes: u64 = 0;
-    let mut hasher = Sha256::new();
-
-    let mut file = tokio::fs::File::create(&dest).await?;
-    let mut stream = response.bytes_stream();
-
-    use tokio::io::AsyncWriteExt;
-    use futures_util::StreamExt;
-
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        file.write_all(&chunk).await?;
-        hasher.update(&chunk);
-        downloaded_bytes += chunk.len() as u64;
-
-        let progress = if total_bytes > 0 {
-            downloaded_bytes as f64 / total_bytes as f64
-        } else {
-            0.0
-        };
-
-        if let Ok(mut p) = DOWNLOAD_PROGRESS.lock() {
-            *p = Some(DownloadProgress {
+    let result = crate::download::download_verified(
+        name,
+        &url,
+        &dest,
+        expected_sha256,
+        op.cancel_flag(),
+        |downloaded_bytes, total_bytes, progress| {
+            let p = DownloadProgress {
                 model: name.to_string(),
                 downloaded_bytes,
                 total_bytes,
                 progress,
-            });
-        }
-    }
-
-    file.flush().await?;
+            };
+            if let Ok(mut current) = DOWNLOAD_PROGRESS.lock() {
+                *current = Some(p.clone());
+            }
+            op.set_progress(progress);
+            on_progress(&p);
+        },
+    )
+    .await;
 
-    // Clear progress
+    // Clear progress, however the download ended
     if let Ok(mut p) = DOWNLOAD_PROGRESS.lock() {
         *p = None;
     }
-
-    // Verify SHA256 checksum
-    let actual_sha256 = format!("{:x}", hasher.finalize());
-    if actual_sha256 != *expected_sha256 {
-        // Remove the corrupted file
-        let _ = tokio::fs::remove_file(&dest).await;
-        anyhow::bail!(
-            "SHA256 mismatch for model '{}': expected {}, got {}",
-            name,
-            expected_sha256,
-            actual_sha256
-        );
-    }
+    result?;
 
     log::info!("Model '{}' downloaded and verified (SHA256 OK) at {:?}", name, dest);
     Ok(())
 }
 
+/// Stop any whisper model download before its next chunk; it then fails
+/// with a cancelled error and its partial file is removed. Returns false if
+/// none was running.
+pub fn cancel_download() -> bool {
+    crate::ops::cancel_named(crate::ops::OpKind::Download, is_known_model)
+}
+
 pub fn get_download_progress() -> Option<DownloadProgress> {
     DOWNLOAD_PROGRESS.lock().ok()?.clone()
 }
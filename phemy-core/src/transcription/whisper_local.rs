@@ -1,10 +1,16 @@
 use anyhow::Result;
 use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
 
+use super::engine::Transcript;
 use super::model_manager;
 
+/// Held for each whisper run. Every run loads its own copy of the model, so
+/// running several at once (a batch import during a dictation, say) would
+/// multiply memory use; they take turns instead.
+static RUN: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 /// Transcribe audio using local whisper.cpp
-pub async fn transcribe(samples: &[f32], model_name: &str, language: &str) -> Result<String> {
+pub async fn transcribe(samples: &[f32], model_name: &str, language: &str) -> Result<Transcript> {
     let model_path = model_manager::get_model_path(model_name)?;
 
     if !model_path.exists() {
@@ -20,8 +26,11 @@ pub async fn transcribe(samples: &[f32], model_name: &str, language: &str) -> Re
 
     // Run whisper in a blocking thread to avoid blocking the async runtime
     tokio::task::spawn_blocking(move || {
+        let _running = RUN.lock().unwrap_or_else(|e| e.into_inner());
+        let loading = std::time::Instant::now();
         let ctx = WhisperContext::new_with_params(&model_path_str, WhisperContextParameters::default())
             .map_err(|e| anyhow::anyhow!("Failed to load whisper model: {}", e))?;
+        let model_load_ms = loading.elapsed().as_millis() as u64;
 
         let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
         params.set_language(Some(&language));
@@ -50,7 +59,22 @@ pub async fn transcribe(samples: &[f32], model_name: &str, language: &str) -> Re
             }
         }
 
-        Ok(text.trim().to_string())
+        let detected_language = if language == "auto" {
+            state
+                .full_lang_id_from_state()
+                .ok()
+                .and_then(whisper_rs::get_lang_str)
+                .map(str::to_string)
+        } else {
+            None
+        };
+
+        Ok(Transcript {
+            text: text.trim().to_string(),
+            detected_language,
+            model_load_ms,
+            segments: num_segments.max(0) as usize,
+        })
     })
     .await?
 }
@@ -0,0 +1,350 @@
+//! UniFFI interface (the `uniffi` feature) for Swift and Kotlin hosts, as an
+//! alternative to the C API: results are `Result`s and records instead of
+//! JSON strings the caller has to free. It calls the same internals as the
+//! `phemy_*` exports. Bindings are generated with the `generate-bindings` bin.
+//!
+//! Settings cross as JSON, since they carry free-form extra fields.
+
+use std::future::Future;
+
+use crate::audio::device::AudioDevice;
+use crate::db::HistoryEntry;
+use crate::llm::llm_model_manager::LlmModelInfo;
+use crate::transcription::model_manager::WhisperModel;
+use crate::{PipelineOptions, ProcessResult, ProgressForwarder};
+
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum PhemyError {
+    /// A required argument was empty or malformed
+    InvalidArgument(String),
+    /// Settings failed validation or couldn't be saved
+    Settings(String),
+    /// No such history entry or model
+    NotFound(String),
+    /// The history database failed
+    Database(String),
+    /// Recording or listing audio devices failed
+    Audio(String),
+    /// Listing, downloading or deleting a model failed
+    Models(String),
+    /// Stop-and-process failed; the message says at which stage
+    Processing(String),
+    /// Initialization failed
+    Init(String),
+}
+
+impl std::fmt::Display for PhemyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidArgument(message)
+            | Self::Settings(message)
+            | Self::NotFound(message)
+            | Self::Database(message)
+            | Self::Audio(message)
+            | Self::Models(message)
+            | Self::Processing(message)
+            | Self::Init(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for PhemyError {}
+
+/// Map an internal error to the `kind` of `PhemyError`, e.g.
+/// `.map_err(err(PhemyError::Database))`
+fn err(kind: fn(String) -> PhemyError) -> impl Fn(anyhow::Error) -> PhemyError {
+    move |e| kind(format!("{:#}", e))
+}
+
+/// Receives model download progress as the download runs, on the thread
+/// that called the download, at most every 100 ms (as the C `_cb` downloads).
+#[uniffi::export(callback_interface)]
+pub trait DownloadListener: Send + Sync {
+    fn on_progress(&self, downloaded_bytes: u64, total_bytes: u64);
+}
+
+/// See phemy_init(); `data_dir` None uses the platform default.
+#[uniffi::export]
+pub fn init(data_dir: Option<String>) -> Result<(), PhemyError> {
+    let config = crate::api::Config::from_data_dir(data_dir.as_deref(), None);
+    match crate::api::init(config) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(PhemyError::Init("Failed to initialize phemy-core".to_string())),
+    }
+}
+
+/// See phemy_shutdown().
+#[uniffi::export]
+pub fn shutdown() {
+    crate::shutdown();
+}
+
+/// Current settings as JSON, as phemy_get_settings() returns them.
+#[uniffi::export]
+pub fn get_settings_json() -> String {
+    serde_json::to_string(&crate::load_settings_with_vocabulary()).unwrap_or_default()
+}
+
+/// Validate and save settings from JSON, as phemy_save_settings() does.
+#[uniffi::export]
+pub fn save_settings_json(json: String) -> Result<(), PhemyError> {
+    match crate::save_settings_json(Some(&json)) {
+        Ok(_) => Ok(()),
+        Err((code, errors)) => {
+            let message = crate::settings::describe_errors(&errors);
+            Err(match code {
+                crate::ffi::ErrorCode::InvalidArgument => PhemyError::InvalidArgument(message),
+                _ => PhemyError::Settings(message),
+            })
+        }
+    }
+}
+
+#[uniffi::export]
+pub fn list_audio_devices() -> Result<Vec<AudioDevice>, PhemyError> {
+    crate::audio::device::list_input_devices().map_err(err(PhemyError::Audio))
+}
+
+/// Start recording from `device`, or the default device if None.
+#[uniffi::export]
+pub fn start_recording(device: Option<String>) -> Result<(), PhemyError> {
+    let settings = crate::settings::Settings::load();
+    crate::audio::capture::start_recording(device.as_deref(), Default::default(), false, &settings.audio)
+        .map_err(err(PhemyError::Audio))
+}
+
+#[uniffi::export]
+pub fn is_recording() -> bool {
+    crate::audio::capture::is_recording()
+}
+
+/// See phemy_cancel_recording().
+#[uniffi::export]
+pub fn cancel_recording() -> bool {
+    crate::audio::capture::cancel_recording()
+}
+
+/// See phemy_stop_and_process().
+#[uniffi::export]
+pub fn stop_and_process() -> Result<ProcessResult, PhemyError> {
+    let (samples, sample_rate) = crate::audio::capture::stop_recording().map_err(err(PhemyError::Audio))?;
+    process_samples(samples, sample_rate, PipelineOptions::default())
+}
+
+/// See phemy_process_samples().
+#[uniffi::export]
+pub fn process_samples(
+    samples: Vec<f32>,
+    sample_rate: u32,
+    options: PipelineOptions,
+) -> Result<ProcessResult, PhemyError> {
+    if samples.is_empty() {
+        return Err(PhemyError::InvalidArgument("No audio samples provided".to_string()));
+    }
+    if sample_rate == 0 {
+        return Err(PhemyError::InvalidArgument("Invalid sample rate: 0".to_string()));
+    }
+    crate::process_pipeline(&samples, sample_rate, &options, &crate::jobs::untracked(), None)
+        .map_err(err(PhemyError::Processing))
+}
+
+/// See phemy_process_text(); pasting is left to the caller.
+#[uniffi::export]
+pub fn process_text(text: String, options: PipelineOptions) -> Result<ProcessResult, PhemyError> {
+    if text.trim().is_empty() {
+        return Err(PhemyError::InvalidArgument("No text provided".to_string()));
+    }
+    crate::process_text_pipeline(&text, &options, &crate::jobs::untracked()).map_err(err(PhemyError::Processing))
+}
+
+#[uniffi::export]
+pub fn get_history(limit: u32, offset: u32) -> Result<Vec<HistoryEntry>, PhemyError> {
+    crate::db::get_history(limit as usize, offset as usize).map_err(err(PhemyError::Database))
+}
+
+/// Full-text search; the entries of phemy_search_history() without snippets.
+#[uniffi::export]
+pub fn search_history(query: String, limit: u32, offset: u32) -> Result<Vec<HistoryEntry>, PhemyError> {
+    let results = crate::db::search_history(&query, limit as usize, offset as usize)
+        .map_err(err(PhemyError::Database))?;
+    Ok(results.into_iter().map(|result| result.entry).collect())
+}
+
+#[uniffi::export]
+pub fn get_history_entry(id: String) -> Result<HistoryEntry, PhemyError> {
+    crate::db::get_history_entry(&id)
+        .map_err(err(PhemyError::Database))?
+        .ok_or_else(|| PhemyError::NotFound(format!("History entry {} not found", id)))
+}
+
+#[uniffi::export]
+pub fn delete_history_entry(id: String) -> Result<(), PhemyError> {
+    crate::db::delete_history_entry(&id).map_err(err(PhemyError::Database))
+}
+
+/// Clear history and its recordings; returns the number of entries deleted.
+#[uniffi::export]
+pub fn clear_history(keep_favorites: bool) -> Result<u64, PhemyError> {
+    let report = crate::db::clear_history(keep_favorites).map_err(err(PhemyError::Database))?;
+    Ok(report.deleted as u64)
+}
+
+#[uniffi::export]
+pub fn list_whisper_models() -> Result<Vec<WhisperModel>, PhemyError> {
+    crate::transcription::model_manager::list_models().map_err(err(PhemyError::Models))
+}
+
+/// Download a whisper model, blocking until done. See cancel_downloads().
+#[uniffi::export]
+pub fn download_whisper_model(name: String, listener: Option<Box<dyn DownloadListener>>) -> Result<(), PhemyError> {
+    use crate::transcription::model_manager;
+    let mut progress = forward_to(listener);
+    block_on(model_manager::download_model_with_progress(&name, |p| {
+        progress.send(p.downloaded_bytes, p.total_bytes, p.progress)
+    }))
+    .map_err(err(PhemyError::Models))
+}
+
+#[uniffi::export]
+pub fn delete_whisper_model(name: String) -> Result<(), PhemyError> {
+    crate::transcription::model_manager::delete_model(&name).map_err(err(PhemyError::Models))
+}
+
+#[uniffi::export]
+pub fn list_llm_models() -> Result<Vec<LlmModelInfo>, PhemyError> {
+    crate::llm::llm_model_manager::list_models().map_err(err(PhemyError::Models))
+}
+
+/// Download a local LLM model, blocking until done. See cancel_downloads().
+#[uniffi::export]
+pub fn download_llm_model(name: String, listener: Option<Box<dyn DownloadListener>>) -> Result<(), PhemyError> {
+    use crate::llm::llm_model_manager;
+    let mut progress = forward_to(listener);
+    block_on(llm_model_manager::download_model_with_progress(&name, |p| {
+        progress.send(p.downloaded_bytes, p.total_bytes, p.progress)
+    }))
+    .map_err(err(PhemyError::Models))
+}
+
+#[uniffi::export]
+pub fn delete_llm_model(name: String) -> Result<(), PhemyError> {
+    crate::llm::llm_model_manager::delete_model(&name).map_err(err(PhemyError::Models))
+}
+
+/// Stop any whisper or LLM model download; the download call then fails.
+#[uniffi::export]
+pub fn cancel_downloads() {
+    crate::ops::cancel_kind(crate::ops::OpKind::Download);
+}
+
+/// Stop any whisper model download. Returns false if none was running.
+#[uniffi::export]
+pub fn cancel_whisper_download() -> bool {
+    crate::transcription::model_manager::cancel_download()
+}
+
+/// Stop any local LLM model download. Returns false if none was running.
+#[uniffi::export]
+pub fn cancel_llm_download() -> bool {
+    crate::llm::llm_model_manager::cancel_download()
+}
+
+/// Run `download` on the runtime; its progress hook runs on this thread
+fn block_on(download: impl Future<Output = anyhow::Result<()>>) -> anyhow::Result<()> {
+    crate::runtime()?.block_on(download)
+}
+
+/// Progress forwarder for an optional listener
+fn forward_to(listener: Option<Box<dyn DownloadListener>>) -> ProgressForwarder {
+    ProgressForwarder::new(move |downloaded, total, _| {
+        if let Some(listener) = &listener {
+            listener.on_progress(downloaded, total);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    //! Calls go through the generated scaffolding functions, lowering
+    //! arguments and lifting results the way the foreign bindings do.
+
+    use super::*;
+    use uniffi::{Lift, Lower, RustBuffer, RustCallStatus, RustCallStatusCode};
+
+    type Tag = crate::UniFfiTag;
+
+    fn lower<T: Lower<Tag>>(value: T) -> T::FfiType {
+        T::lower(value)
+    }
+
+    fn lift<T: Lift<Tag>>(value: T::FfiType) -> T {
+        T::try_lift(value).expect("lift")
+    }
+
+    /// Run a scaffolding call. An error comes back as the flat
+    /// `PhemyError`'s variant (1-based, in declaration order) and message.
+    fn call<R>(f: impl FnOnce(&mut RustCallStatus) -> R) -> Result<R, (i32, String)> {
+        let mut status = RustCallStatus::default();
+        let value = f(&mut status);
+        match status.code {
+            RustCallStatusCode::Success => Ok(value),
+            RustCallStatusCode::Error => {
+                let buf = std::mem::ManuallyDrop::into_inner(status.error_buf);
+                let bytes = buf.destroy_into_vec();
+                let variant = i32::from_be_bytes(bytes[..4].try_into().unwrap());
+                let message = <String as Lift<Tag>>::try_read(&mut &bytes[4..]).unwrap();
+                Err((variant, message))
+            }
+            code => panic!("call failed unexpectedly: {:?}", code),
+        }
+    }
+
+    #[test]
+    fn exports_round_trip_through_the_scaffolding() {
+        let _lock = crate::test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = Some(dir.path().to_str().unwrap().to_string());
+        call(|s| uniffi_phemy_core_fn_func_init(lower(data_dir), s)).unwrap();
+
+        let json: RustBuffer = call(|s| uniffi_phemy_core_fn_func_get_settings_json(s)).unwrap();
+        let settings: serde_json::Value = serde_json::from_str(&lift::<String>(json)).unwrap();
+        assert!(settings["hotkey"].is_string());
+
+        let mut entry =
+            crate::db::new_history_entry("hello".into(), None, "raw".into(), None, 1.0, None);
+        entry.favorite = true;
+        crate::db::insert_history(&entry, None).unwrap();
+        let id = lower(entry.id.clone());
+        let found = call(|s| uniffi_phemy_core_fn_func_get_history_entry(id, s));
+        let found: HistoryEntry = lift(found.unwrap());
+        assert_eq!(found.raw_transcript, "hello");
+        assert!(found.favorite);
+
+        let deleted = call(|s| uniffi_phemy_core_fn_func_clear_history(lower(true), s));
+        assert_eq!(deleted, Ok(0));
+
+        call(|s| uniffi_phemy_core_fn_func_shutdown(s)).unwrap();
+    }
+
+    #[test]
+    fn errors_arrive_as_their_variant() {
+        let _lock = crate::test_support::lock();
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = Some(dir.path().to_str().unwrap().to_string());
+        call(|s| uniffi_phemy_core_fn_func_init(lower(data_dir), s)).unwrap();
+
+        let id = lower("nope".to_string());
+        let missing = call(|s| uniffi_phemy_core_fn_func_get_history_entry(id, s));
+        let (variant, message) = missing.unwrap_err();
+        assert_eq!(variant, 3, "NotFound");
+        assert!(message.contains("nope"), "{}", message);
+
+        let invalid = r#"{ "paste_delay_ms": 999999 }"#.to_string();
+        let (variant, _) = call(|s| uniffi_phemy_core_fn_func_save_settings_json(lower(invalid), s))
+            .unwrap_err();
+        assert_eq!(variant, 2, "Settings");
+
+        call(|s| uniffi_phemy_core_fn_func_shutdown(s)).unwrap();
+    }
+}
@@ -13,6 +13,18 @@ pub fn models_dir() -> anyhow::Result<PathBuf> {
     Ok(dir)
 }
 
+/// Get the directory where saved recordings are kept.
+pub fn recordings_dir() -> anyhow::Result<PathBuf> {
+    let base = crate::settings::get_data_dir().unwrap_or_else(|| {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("phemy")
+    });
+    let dir = base.join("recordings");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
 /// Convert f32 PCM samples to WAV bytes (for cloud API uploads)
 pub fn samples_to_wav(samples: &[f32], sample_rate: u32) -> anyhow::Result<Vec<u8>> {
     let spec = hound::WavSpec {
@@ -34,3 +46,65 @@ pub fn samples_to_wav(samples: &[f32], sample_rate: u32) -> anyhow::Result<Vec<u
 
     Ok(cursor.into_inner())
 }
+
+/// Read a WAV file as mono f32 samples (channels averaged) and its sample
+/// rate
+pub fn read_wav(path: &std::path::Path) -> anyhow::Result<(Vec<f32>, u32)> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let samples = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+    Ok((samples, spec.sample_rate))
+}
+
+/// Error for a call refused because another call holds what it needs (the
+/// local LLM, say) and waiting could take arbitrarily long. Reported to the
+/// host as the `busy` error code; see `is_busy`.
+#[derive(Debug)]
+pub struct Busy(pub String);
+
+impl std::fmt::Display for Busy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Busy: {}", self.0)
+    }
+}
+
+impl std::error::Error for Busy {}
+
+/// Whether `e`, or anything it wraps, is a `Busy` error
+pub fn is_busy(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Busy>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_round_trips_through_samples_to_wav() {
+        let samples = [0.0, 0.5, -0.5, 0.25];
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.wav");
+        std::fs::write(&path, samples_to_wav(&samples, 16_000).unwrap()).unwrap();
+
+        let (read, sample_rate) = read_wav(&path).unwrap();
+        assert_eq!(sample_rate, 16_000);
+        assert_eq!(read.len(), samples.len());
+        for (read, written) in read.iter().zip(samples) {
+            assert!((read - written).abs() < 1e-3, "{} != {}", read, written);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Runs the phemy-cli binary (the `cli` feature) against a fresh data
+//! directory and checks its output and exit codes.
+
+#![cfg(feature = "cli")]
+
+use std::process::{Command, Output};
+
+fn phemy_cli(data_dir: &std::path::Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_phemy-cli"))
+        .arg("--data-dir")
+        .arg(data_dir)
+        .arg("--in-memory")
+        .args(args)
+        .output()
+        .expect("phemy-cli runs")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn empty_history_lists_as_text_and_json() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let text = phemy_cli(dir.path(), &["history", "list"]);
+    assert_eq!(text.status.code(), Some(0));
+    assert_eq!(stdout(&text), "No history entries.\n");
+
+    let json = phemy_cli(dir.path(), &["--json", "history", "search", "anything"]);
+    assert_eq!(json.status.code(), Some(0));
+    assert_eq!(stdout(&json), "[]\n");
+}
+
+#[test]
+fn models_list_shows_nothing_downloaded_in_a_new_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = phemy_cli(dir.path(), &["models", "list"]);
+    assert_eq!(output.status.code(), Some(0));
+    let listing = stdout(&output);
+    let mut lines = listing.lines();
+    let header = lines.next().unwrap();
+    assert!(header.starts_with("KIND     NAME ") && header.ends_with("  DOWNLOADED"), "{}", listing);
+    assert!(lines.next().unwrap().starts_with("whisper  tiny "), "{}", listing);
+    assert!(lines.all(|line| line.ends_with("  no")), "{}", listing);
+}
+
+#[test]
+fn failures_and_bad_arguments_have_their_own_exit_codes() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let unknown = phemy_cli(dir.path(), &["models", "delete", "no-such-model"]);
+    assert_eq!(unknown.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&unknown.stderr), "Error: Unknown model: no-such-model\n");
+
+    let json = phemy_cli(dir.path(), &["--json", "transcribe", "missing.wav"]);
+    assert_eq!(json.status.code(), Some(1));
+    let error: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert!(error["error"]["message"].as_str().unwrap().starts_with("Failed to read missing.wav"), "{}", error);
+
+    let usage = phemy_cli(dir.path(), &["optimize", "--mode", "poetic", "text"]);
+    assert_eq!(usage.status.code(), Some(2));
+}
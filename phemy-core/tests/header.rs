@@ -0,0 +1,34 @@
+//! Checks on the committed C header, include/phemy_core.h.
+
+use std::path::Path;
+use std::process::Command;
+
+const COMMITTED: &str = include_str!("../include/phemy_core.h");
+const GENERATED: &str = include_str!(concat!(env!("OUT_DIR"), "/phemy_core.h"));
+
+#[test]
+fn committed_header_matches_cbindgen() {
+    assert!(
+        COMMITTED == GENERATED,
+        "include/phemy_core.h is stale; regenerate it with \
+         `PHEMY_UPDATE_HEADER=1 cargo build` and commit the result"
+    );
+}
+
+#[test]
+fn c_file_compiles_against_header() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let output = Command::new(&cc)
+        .args(["-std=c11", "-Wall", "-Wextra", "-Werror", "-fsyntax-only"])
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(root.join("tests/header_check.c"))
+        .output()
+        .unwrap_or_else(|e| panic!("couldn't run {}: {}", cc, e));
+    assert!(
+        output.status.success(),
+        "tests/header_check.c doesn't compile against phemy_core.h:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
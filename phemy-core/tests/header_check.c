@@ -0,0 +1,84 @@
+/* Compiled against include/phemy_core.h by tests/header.rs, so a change to an
+ * exported signature or callback type fails there instead of in a host app. */
+
+#include "phemy_core.h"
+
+/* Signatures hosts rely on; any drift is an incompatible pointer error */
+bool (*check_init)(const char *) = phemy_init;
+bool (*check_init_with_key)(const char *, const char *) = phemy_init_with_key;
+bool (*check_init_ex)(const char *) = phemy_init_ex;
+bool (*check_is_initialized)(void) = phemy_is_initialized;
+char *(*check_get_init_status)(void) = phemy_get_init_status;
+void (*check_shutdown)(void) = phemy_shutdown;
+void (*check_free_string)(char *) = phemy_free_string;
+void (*check_free_samples)(float *, uintptr_t) = phemy_free_samples;
+char *(*check_get_settings)(void) = phemy_get_settings;
+bool (*check_save_settings)(const char *) = phemy_save_settings;
+int32_t (*check_get_settings_buf)(char *, uintptr_t) = phemy_get_settings_buf;
+bool (*check_start_recording)(const char *, MicLevelCallback) = phemy_start_recording;
+bool (*check_start_recording_ex)(const char *, MicLevelCallback, bool) = phemy_start_recording_ex;
+bool (*check_start_recording_with_user_data)(const char *, MicLevelUserDataCallback, void *, bool) = phemy_start_recording_with_user_data;
+char *(*check_stop_recording)(void) = phemy_stop_recording;
+bool (*check_stop_recording_with_samples)(float **, uintptr_t *, uint32_t *) = phemy_stop_recording_with_samples;
+bool (*check_cancel_recording)(void) = phemy_cancel_recording;
+char *(*check_stop_and_process)(void) = phemy_stop_and_process;
+char *(*check_stop_and_process_ex)(const char *) = phemy_stop_and_process_ex;
+char *(*check_stop_and_process_with_progress)(PhaseCallback, void *) = phemy_stop_and_process_with_progress;
+uint64_t (*check_stop_and_process_async)(void) = phemy_stop_and_process_async;
+uint64_t (*check_stop_and_process_with_user_data)(const char *, CompletionCallback, void *) =
+    phemy_stop_and_process_with_user_data;
+char *(*check_poll_job)(uint64_t) = phemy_poll_job;
+bool (*check_cancel_job)(uint64_t) = phemy_cancel_job;
+uint64_t (*check_batch_process_directory)(const char *, const char *) = phemy_batch_process_directory;
+char *(*check_process_samples)(const float *, uintptr_t, uint32_t, const char *) = phemy_process_samples;
+char *(*check_process_text)(const char *, bool) = phemy_process_text;
+char *(*check_finish_recording)(void) = phemy_finish_recording;
+char *(*check_session_transcribe)(uint64_t) = phemy_session_transcribe;
+char *(*check_session_optimize)(uint64_t) = phemy_session_optimize;
+char *(*check_session_commit)(uint64_t) = phemy_session_commit;
+bool (*check_session_discard)(uint64_t) = phemy_session_discard;
+char *(*check_get_last_pipeline_metrics)(void) = phemy_get_last_pipeline_metrics;
+char *(*check_transcribe)(const float *, uintptr_t, uint32_t) = phemy_transcribe;
+bool (*check_download_whisper_model)(const char *) = phemy_download_whisper_model;
+bool (*check_download_whisper_model_cb)(const char *, DownloadProgressCallback, DownloadDoneCallback) =
+    phemy_download_whisper_model_cb;
+bool (*check_download_llm_model_cb)(const char *, DownloadProgressCallback, DownloadDoneCallback) =
+    phemy_download_llm_model_cb;
+uint64_t (*check_download_whisper_model_async)(const char *, DownloadProgressCallback, DownloadDoneCallback) =
+    phemy_download_whisper_model_async;
+uint64_t (*check_download_llm_model_async)(const char *, DownloadProgressCallback, DownloadDoneCallback) =
+    phemy_download_llm_model_async;
+uint64_t (*check_download_whisper_model_with_user_data)(const char *, CompletionCallback, void *) =
+    phemy_download_whisper_model_with_user_data;
+uint64_t (*check_download_llm_model_with_user_data)(const char *, CompletionCallback, void *) =
+    phemy_download_llm_model_with_user_data;
+bool (*check_cancel)(uint64_t) = phemy_cancel;
+char *(*check_list_operations)(void) = phemy_list_operations;
+char *(*check_health_check)(bool) = phemy_health_check;
+char *(*check_get_last_error)(void) = phemy_get_last_error;
+char *(*check_get_build_info)(void) = phemy_get_build_info;
+void (*check_cancel_downloads)(void) = phemy_cancel_downloads;
+bool (*check_cancel_download)(int32_t) = phemy_cancel_download;
+char *(*check_get_history)(int32_t, int32_t) = phemy_get_history;
+bool (*check_paste_text)(const char *) = phemy_paste_text;
+bool (*check_paste_text_async)(const char *, const char *, PasteProgressCallback, PasteDoneCallback) =
+    phemy_paste_text_async;
+void (*check_set_log_callback)(LogCallback, int32_t) = phemy_set_log_callback;
+void (*check_set_settings_changed_callback)(SettingsChangedCallback) = phemy_set_settings_changed_callback;
+
+/* Callback typedefs */
+static void on_mic_level(float rms, float peak) { (void)rms; (void)peak; }
+static void on_log(int32_t level, const char *target, const char *message) { (void)level; (void)target; (void)message; }
+static void on_settings_changed(void) {}
+static void on_paste_progress(uintptr_t typed, uintptr_t total) { (void)typed; (void)total; }
+static void on_paste_done(bool success, const char *result_json) { (void)success; (void)result_json; }
+static void on_download_progress(uint64_t downloaded, uint64_t total, double progress) { (void)downloaded; (void)total; (void)progress; }
+static void on_download_done(bool success, const char *error_json) { (void)success; (void)error_json; }
+
+MicLevelCallback check_mic_level = on_mic_level;
+LogCallback check_log = on_log;
+SettingsChangedCallback check_settings_changed = on_settings_changed;
+PasteProgressCallback check_paste_progress = on_paste_progress;
+PasteDoneCallback check_paste_done = on_paste_done;
+DownloadProgressCallback check_download_progress = on_download_progress;
+DownloadDoneCallback check_download_done = on_download_done;